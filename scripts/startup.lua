@@ -8,6 +8,21 @@ KNOWNBLOCKS = {
 READY = {
   type = "ready",
 }
+-- Version of the messages this script understands. Sent with the commands and requests it handles
+-- so the wrangler doesn't send it anything it would ignore.
+PROTOCOL = 1
+COMMANDS = {
+  "request", "move", "forward", "back", "turn_left", "turn_right", "reboot", "update_position",
+  "inspect", "equip_left", "equip_right", "transfer_to", "craft", "attack", "attack_up",
+  "attack_down", "place", "place_up", "place_down", "deploy", "rollback", "dig", "dig_up",
+  "dig_down", "drop", "drop_up", "drop_down",
+}
+REQUESTS = {
+  "inspect", "ping", "locate", "peripheral_list", "inventory_list", "item_list", "eval",
+}
+-- Where this script is so deploys can replace it.
+SCRIPT_PATH = "/" .. shell.getRunningProgram()
+VERSION_PATH = "/version"
 
 local function hasValue(table, value) 
   for _, v in ipairs(table) do
@@ -51,6 +66,100 @@ local function setPosition(coords)
   handle.close()
 end
 
+-- Turtles can't tell which dimension they are in so the wrangler tells them.
+-- The file holds the dimension name and optionally the world on the next line.
+local function getDimension()
+  local handle = io.open("/dimension", "r")
+  if handle == nil then
+    return nil
+  end
+
+  local lines = {}
+  for line in handle:lines() do
+    table.insert(lines, line)
+  end
+
+  handle:close()
+
+  if lines[1] == nil then
+    return nil
+  end
+
+  return {
+    name = lines[1],
+    world = lines[2],
+  }
+end
+
+local function setDimension(dimension)
+  local handle = fs.open("dimension", "w")
+  local formatted = dimension.name
+  if dimension.world ~= nil then
+    formatted = formatted .. "\n" .. dimension.world
+  end
+  handle.write(formatted)
+  handle.close()
+end
+
+-- Gets the version the wrangler gave this script when it deployed it.
+-- Returns nil if the script was installed by hand.
+local function getVersion()
+  local handle = io.open(VERSION_PATH, "r")
+  if handle == nil then
+    return nil
+  end
+
+  local version = handle:read("l")
+  handle:close()
+  return version
+end
+
+-- Moves a file to a new path replacing anything there. Does nothing if the file doesn't exist.
+local function replaceFile(from, to)
+  if not fs.exists(from) then
+    return
+  end
+
+  if fs.exists(to) then
+    fs.delete(to)
+  end
+  fs.move(from, to)
+end
+
+-- Writes a new script and version from the wrangler. The current ones are kept with .old on
+-- the end for a rollback.
+local function deploy(script, version)
+  replaceFile(SCRIPT_PATH, SCRIPT_PATH .. ".old")
+  if fs.exists(VERSION_PATH .. ".old") then
+    fs.delete(VERSION_PATH .. ".old")
+  end
+  replaceFile(VERSION_PATH, VERSION_PATH .. ".old")
+
+  local handle = fs.open(SCRIPT_PATH, "w")
+  handle.write(script)
+  handle.close()
+
+  handle = fs.open(VERSION_PATH, "w")
+  handle.write(version)
+  handle.close()
+end
+
+-- Swaps the script and version with the ones from before the last deploy.
+local function rollback()
+  if not fs.exists(SCRIPT_PATH .. ".old") then
+    print("No script to roll back to")
+    return
+  end
+
+  replaceFile(SCRIPT_PATH, SCRIPT_PATH .. ".new")
+  replaceFile(SCRIPT_PATH .. ".old", SCRIPT_PATH)
+  replaceFile(SCRIPT_PATH .. ".new", SCRIPT_PATH .. ".old")
+
+  replaceFile(VERSION_PATH, VERSION_PATH .. ".new")
+  replaceFile(VERSION_PATH .. ".old", VERSION_PATH)
+  replaceFile(VERSION_PATH .. ".new", VERSION_PATH .. ".old")
+end
+
 local function updatePosition(position)
   local current = getPosition()
   if current == nil then
@@ -209,9 +318,14 @@ function connect(url)
     return false
   end
 
-  local id = os.getComputerID()
+  -- The version after the id lets the wrangler tell if this script is outdated.
+  local hello = tostring(math.floor(os.getComputerID()))
+  local version = getVersion()
+  if version ~= nil then
+    hello = hello .. " " .. version
+  end
 
-  local status, result = pcall(ws.send, math.floor(id)) 
+  local status, result = pcall(ws.send, hello)
   if not status then 
     print("Error sending id: ", result)
     return false
@@ -225,6 +339,19 @@ function connect(url)
 
   Name = result
   os.setComputerLabel(result)
+
+  local hello = {
+    type = "hello",
+    protocol = PROTOCOL,
+    commands = COMMANDS,
+    requests = REQUESTS,
+  }
+  local status, result = pcall(ws.send, textutils.serializeJSON(hello))
+  if not status then
+    print("Error saying hello: ", result)
+    return false
+  end
+
   return ws
 end
 
@@ -250,11 +377,48 @@ function report(ws)
     position = position,
     heading = heading,
     fuel = fuel,
+    dimension = getDimension(),
+    upgrades = getUpgrades(),
   }
 
   ws.send(textutils.serializeJSON(report))
 end
 
+-- Gets the names of the items equipped on each side.
+-- Returns nil on ComputerCraft versions that can't tell.
+function getUpgrades()
+  if turtle.getEquippedLeft == nil then
+    return nil
+  end
+
+  local upgrades = {}
+  local left = turtle.getEquippedLeft()
+  if left ~= nil then
+    upgrades.left = left.name
+  end
+  local right = turtle.getEquippedRight()
+  if right ~= nil then
+    upgrades.right = right.name
+  end
+
+  return upgrades
+end
+
+-- Equips the item in a slot on a side, putting anything already equipped back in the slot.
+function equip(slot, side)
+  turtle.select(slot)
+  local success, reason
+  if side == "left" then
+    success, reason = turtle.equipLeft()
+  else
+    success, reason = turtle.equipRight()
+  end
+
+  if not success then
+    print("Failed to equip " .. side .. ": " .. tostring(reason))
+  end
+end
+
 function inspect()
   local exists, block = turtle.inspect() 
   if not exists then
@@ -276,6 +440,73 @@ function inspect()
   -- ws.send(textutils.serializeJSON(inspection))
 end
 
+-- Lists every peripheral attached directly to the turtle and its types.
+function listPeripherals()
+  local peripherals = {}
+  for _, side in ipairs(redstone.getSides()) do
+    if peripheral.isPresent(side) then
+      table.insert(peripherals, {
+        side = side,
+        types = { peripheral.getType(side) },
+      })
+    end
+  end
+
+  if #peripherals == 0 then
+    return textutils.empty_json_array
+  end
+  return peripherals
+end
+
+-- Lists the slots with items in the turtle's own inventory.
+function listItems()
+  local slots = {}
+  for slot = 1, 16 do
+    local item = turtle.getItemDetail(slot)
+    if item ~= nil then
+      table.insert(slots, {
+        slot = slot,
+        name = item.name,
+        count = item.count,
+      })
+    end
+  end
+  if #slots == 0 then
+    slots = textutils.empty_json_array
+  end
+
+  return {
+    size = 16,
+    slots = slots,
+  }
+end
+
+-- Lists the slots with items in the inventory on a side of the turtle.
+-- Returns nil and the reason if there is no inventory on that side.
+function listInventory(side)
+  if not peripheral.hasType(side, "inventory") then
+    return nil, "no inventory on " .. tostring(side)
+  end
+
+  local inventory = peripheral.wrap(side)
+  local slots = {}
+  for slot, item in pairs(inventory.list()) do
+    table.insert(slots, {
+      slot = slot,
+      name = item.name,
+      count = item.count,
+    })
+  end
+  if #slots == 0 then
+    slots = textutils.empty_json_array
+  end
+
+  return {
+    size = inventory.size(),
+    slots = slots,
+  }
+end
+
 --#endregion
 
 -- function splitMessage(message)
@@ -304,6 +535,63 @@ function move(ws, direction)
   end
 end
 
+-- Runs a chunk of Lua from the wrangler. It can only use the turtle and libraries that don't touch
+-- files or the connection. Gives up after timeout seconds if the chunk yields.
+-- Returns the serialized values the chunk returned or nil and why it failed.
+function eval(code, timeout)
+  local env = {
+    turtle = turtle,
+    textutils = textutils,
+    vector = vector,
+    math = math,
+    string = string,
+    table = table,
+    os = {
+      clock = os.clock,
+      day = os.day,
+      epoch = os.epoch,
+      time = os.time,
+      getComputerID = os.getComputerID,
+    },
+    sleep = sleep,
+    print = print,
+    error = error,
+    ipairs = ipairs,
+    next = next,
+    pairs = pairs,
+    pcall = pcall,
+    select = select,
+    tonumber = tonumber,
+    tostring = tostring,
+    type = type,
+    unpack = table.unpack,
+  }
+
+  local chunk, reason = load(code, "eval", "t", env)
+  if chunk == nil then
+    return nil, reason
+  end
+
+  local results = nil
+  parallel.waitForAny(
+    function() results = table.pack(pcall(chunk)) end,
+    function() sleep(timeout) end
+  )
+  if results == nil then
+    return nil, "timed out after " .. timeout .. " seconds"
+  end
+  if not results[1] then
+    return nil, tostring(results[2])
+  end
+
+  local ok, serialized = pcall(textutils.serialize, { table.unpack(results, 2, results.n) })
+  if not ok then
+    return nil, "can't serialize result: " .. tostring(serialized)
+  end
+
+  return serialized
+end
+
 function interpretRequest(ws, id, request) 
   local response = nil
   if request.type == "inspect" then 
@@ -316,8 +604,61 @@ function interpretRequest(ws, id, request)
     response = {
       type = "pong"
     }
+  elseif request.type == "locate" then
+    response = {
+      type = "location",
+    }
+    local x, y, z = gps.locate(2)
+    if x ~= nil then
+      response.position = {
+        x = math.floor(x),
+        y = math.floor(y),
+        z = math.floor(z),
+      }
+    end
+  elseif request.type == "peripheral_list" then
+    response = {
+      type = "peripherals",
+      peripherals = listPeripherals(),
+    }
+  elseif request.type == "item_list" then
+    response = {
+      type = "inventory",
+      inventory = listItems(),
+    }
+  elseif request.type == "inventory_list" then
+    local inventory, reason = listInventory(request.side)
+    if inventory == nil then
+      response = {
+        type = "error",
+        reason = reason,
+      }
+    else
+      response = {
+        type = "inventory",
+        inventory = inventory,
+      }
+    end
+  elseif request.type == "eval" then
+    print("Evaluating", request.code)
+    local result, reason = eval(request.code, request.timeout_ms / 1000)
+    if result == nil then
+      response = {
+        type = "error",
+        reason = reason,
+      }
+    else
+      response = {
+        type = "evaluated",
+        result = result,
+      }
+    end
   else
     print("Error unknown request:", request.type)
+    response = {
+      type = "error",
+      reason = "unknown request " .. tostring(request.type),
+    }
   end
 
   if response ~= nil then
@@ -362,6 +703,9 @@ function interpretCommand(ws, command)
     local new = command.coords
     new.heading = command.heading
     setPosition(new)
+    if command.dimension ~= nil then
+      setDimension(command.dimension)
+    end
   elseif command.type == "inspect" then
     print("Inspecting")
     local block = inspect()
@@ -370,6 +714,56 @@ function interpretCommand(ws, command)
       block = block,
     }
     ws.send(textutils.serializeJSON(event))
+  elseif command.type == "equip_left" then
+    equip(command.slot, "left")
+  elseif command.type == "equip_right" then
+    equip(command.slot, "right")
+  elseif command.type == "transfer_to" then
+    turtle.select(command.from)
+    if not turtle.transferTo(command.to, command.count) then
+      print("Failed to move items from " .. command.from .. " to " .. command.to)
+    end
+  elseif command.type == "attack" then
+    turtle.attack()
+  elseif command.type == "attack_up" then
+    turtle.attackUp()
+  elseif command.type == "attack_down" then
+    turtle.attackDown()
+  elseif command.type == "dig" then
+    turtle.dig()
+  elseif command.type == "dig_up" then
+    turtle.digUp()
+  elseif command.type == "dig_down" then
+    turtle.digDown()
+  elseif command.type == "place" then
+    turtle.select(command.slot)
+    turtle.place()
+  elseif command.type == "place_up" then
+    turtle.select(command.slot)
+    turtle.placeUp()
+  elseif command.type == "place_down" then
+    turtle.select(command.slot)
+    turtle.placeDown()
+  elseif command.type == "drop" then
+    turtle.select(command.slot)
+    turtle.drop()
+  elseif command.type == "drop_up" then
+    turtle.select(command.slot)
+    turtle.dropUp()
+  elseif command.type == "drop_down" then
+    turtle.select(command.slot)
+    turtle.dropDown()
+  elseif command.type == "deploy" then
+    print("Deploying script version " .. command.version)
+    deploy(command.script, command.version)
+  elseif command.type == "rollback" then
+    print("Rolling back script")
+    rollback()
+  elseif command.type == "craft" then
+    local success, reason = turtle.craft(command.count)
+    if not success then
+      print("Failed to craft: " .. tostring(reason))
+    end
   else
     print("Unknown command")
   end
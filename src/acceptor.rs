@@ -10,6 +10,8 @@ mod acceptor_message;
 
 mod client_connector;
 
+mod metrics_connector;
+
 mod tcp_handler;
 
 mod turtle_connector;
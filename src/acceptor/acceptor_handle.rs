@@ -1,8 +1,11 @@
 use crate::acceptor::client_connector::ClientConnector;
+use crate::acceptor::metrics_connector::MetricsConnector;
 use crate::acceptor::tcp_handler::TcpHandler;
 use crate::acceptor::turtle_connector::TurtleConnector;
 use crate::client_manager::ClientManagerHandle;
-use sqlx::SqlitePool;
+use crate::metrics::Metrics;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
 
@@ -42,17 +45,37 @@ impl AcceptorHandle {
         Self::new(addr, handler)
     }
 
+    /// Clients that log in with `admin_token` can use admin commands. No client can if it is
+    /// None.
     pub fn new_client(
         addr: String,
         client_manager: ClientManagerHandle,
         turtle_manager: TurtleManagerHandle,
-        pool: SqlitePool,
+        admin_token: Option<String>,
     ) -> Self {
-        let handler = ClientConnector::new(client_manager, turtle_manager, pool);
+        let handler = ClientConnector::new(client_manager, turtle_manager, admin_token);
 
         Self::new(addr, handler)
     }
 
+    /// Serves the metrics endpoint for `metrics` on `addr`.
+    pub fn new_metrics(addr: String, metrics: Arc<Metrics>) -> Self {
+        Self::new(addr, MetricsConnector::new(metrics))
+    }
+
+    /// Gets the address the AcceptorInner is listening on.
+    /// Returns None if the AcceptorInner has closed.
+    pub async fn local_addr(&self) -> Option<SocketAddr> {
+        let (tx, rx) = oneshot::channel();
+
+        if let Err(e) = self.tx.send(AcceptorMessage::LocalAddr(tx)).await {
+            error!("Error getting listener address: {e}");
+            return None;
+        }
+
+        rx.await.ok()
+    }
+
     /// Sends a close message to AcceptorInner and waits for the AcceptorInner to close.
     pub async fn close(&self) {
         let (tx, rx) = oneshot::channel();
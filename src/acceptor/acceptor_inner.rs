@@ -54,6 +54,11 @@ where
                                 close_tx = Some(tx);
                                 break;
                             }
+                            AcceptorMessage::LocalAddr(tx) => {
+                                if let Ok(addr) = listener.local_addr() {
+                                    let _ = tx.send(addr);
+                                }
+                            }
                         }
                     } else {
                         // If we get None from self.rx then all handles have been dropped and we
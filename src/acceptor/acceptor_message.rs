@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use tokio::sync::oneshot;
 
 /// Messages that can be sent by an AcceptorHandle to its AcceptorInner.
@@ -6,4 +8,8 @@ pub enum AcceptorMessage {
     /// Tells the AcceptorInner to stop listening for connections and exit.
     /// Contains a channel for the AcceptorInner to send a message on when it is closed.
     Close(oneshot::Sender<()>),
+
+    /// Gets the address the AcceptorInner is listening on.
+    /// Useful when listening on port 0 to find out which port was picked.
+    LocalAddr(oneshot::Sender<SocketAddr>),
 }
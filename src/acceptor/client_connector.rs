@@ -1,13 +1,12 @@
 use crate::acceptor::tcp_handler::TcpHandler;
 use crate::client_manager::{ClientConnectionHandle, ClientManagerHandle};
 use crate::turtle_manager::TurtleManagerHandle;
-use sqlx::SqlitePool;
 use tokio::net::TcpStream;
 
 pub struct ClientConnector {
     client_manager: ClientManagerHandle,
     turtle_manager: TurtleManagerHandle,
-    pool: SqlitePool,
+    admin_token: Option<String>,
     next_id: usize,
 }
 
@@ -15,12 +14,12 @@ impl ClientConnector {
     pub fn new(
         client_manager: ClientManagerHandle,
         turtle_manager: TurtleManagerHandle,
-        pool: SqlitePool,
+        admin_token: Option<String>,
     ) -> Self {
         ClientConnector {
             client_manager,
             turtle_manager,
-            pool,
+            admin_token,
             next_id: 0,
         }
     }
@@ -32,8 +31,12 @@ impl TcpHandler for ClientConnector {
         let id = self.next_id;
         self.next_id += 1;
 
-        let client =
-            ClientConnectionHandle::new(stream, self.turtle_manager.clone(), self.pool.clone(), id);
+        let client = ClientConnectionHandle::new(
+            stream,
+            self.turtle_manager.clone(),
+            id,
+            self.admin_token.clone(),
+        );
         self.client_manager.new_client(client).await;
     }
 }
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::acceptor::tcp_handler::TcpHandler;
+use crate::metrics::Metrics;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+/// Answers http requests for `/metrics` with every metric in the prometheus text format.
+pub struct MetricsConnector {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsConnector {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        MetricsConnector { metrics }
+    }
+}
+
+#[async_trait::async_trait]
+impl TcpHandler for MetricsConnector {
+    async fn handle_tcp(&mut self, stream: TcpStream) {
+        // Serve each scrape on its own task so a slow scraper can not hold up the acceptor.
+        tokio::spawn(serve(stream, self.metrics.clone()));
+    }
+}
+
+async fn serve(mut stream: TcpStream, metrics: Arc<Metrics>) {
+    let request =
+        match tokio::time::timeout(Duration::from_secs(5), read_request(&mut stream)).await {
+            Ok(Some(r)) => r,
+            _ => {
+                warn!("Problem reading metrics request");
+                return;
+            }
+        };
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    debug!("Got metrics request for {path}");
+
+    let response = if path == "/metrics" {
+        let body = metrics.render();
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+            Content-Type: text/plain; version=0.0.4\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        warn!("Problem sending metrics {e}");
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// Reads an http request up to the end of its headers.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = vec![];
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let mut buffer = [0; 1024];
+        let n = stream.read(&mut buffer).await.ok()?;
+        if n == 0 || request.len() > 8192 {
+            return None;
+        }
+        request.extend_from_slice(&buffer[0..n]);
+    }
+
+    String::from_utf8(request).ok()
+}
@@ -31,6 +31,16 @@ pub enum Block {
     },
 }
 
+impl Block {
+    /// Whether the block is a turtle. Turtles can't tell which turtle it is.
+    pub fn is_turtle(&self) -> bool {
+        matches!(
+            self,
+            Block::TurtleNormal { .. } | Block::TurtleAdvanced { .. }
+        )
+    }
+}
+
 /// Data that a block can contain.
 /// Type S is the type of the state data.
 /// For Block::Other S is a HashMap<String, String>.
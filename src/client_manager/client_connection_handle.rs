@@ -1,7 +1,6 @@
 use crate::client_manager::client_connection_inner::ClientConnectionInner;
 use crate::client_manager::client_connection_message::ClientConnectionMessage;
 use crate::turtle_manager::TurtleManagerHandle;
-use sqlx::SqlitePool;
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot};
 
@@ -13,12 +12,12 @@ impl ClientConnectionHandle {
     pub fn new(
         stream: TcpStream,
         turtle_manager: TurtleManagerHandle,
-        pool: SqlitePool,
         id: usize,
+        admin_token: Option<String>,
     ) -> Self {
         let (tx, rx) = mpsc::channel(1);
 
-        let inner = ClientConnectionInner::new(rx, stream, turtle_manager, pool, id);
+        let inner = ClientConnectionInner::new(rx, stream, turtle_manager, id, admin_token);
         tokio::spawn(inner.run());
 
         ClientConnectionHandle { tx }
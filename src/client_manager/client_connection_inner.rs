@@ -1,10 +1,11 @@
 use crate::client_manager::client_connection_message::ClientConnectionMessage;
 use crate::client_scheme::{Command, Event};
-use crate::db::turtle_operations;
-use crate::scheme::Direction;
+use crate::error::Error;
+use crate::scheme::{Dimension, Direction, Tool};
+use crate::selector::Selector;
 use crate::turtle_manager::{ConnectionMessageType, TurtleConnectionMessage, TurtleManagerHandle};
 use futures_util::sink::drain;
-use sqlx::SqlitePool;
+use std::future::Future;
 use tokio::io;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, Interest};
 use tokio::net::TcpStream;
@@ -15,10 +16,20 @@ pub struct ClientConnectionInner {
     rx: mpsc::Receiver<ClientConnectionMessage>,
     stream: TcpStream,
     turtle_manager: TurtleManagerHandle,
-    pool: SqlitePool,
     // connection_manager: Connecti
     message_buffer: Vec<u8>,
     id: usize,
+
+    /// Token a client logs in with to become an admin. Nobody can if it is None.
+    admin_token: Option<String>,
+
+    /// Set once the client has logged in with the admin token.
+    admin: bool,
+
+    /// Events from commands running in the background. Sent on by the run loop so slow commands
+    /// don't stop the client's other commands being read.
+    background_tx: mpsc::UnboundedSender<Event>,
+    background_rx: mpsc::UnboundedReceiver<Event>,
 }
 
 impl ClientConnectionInner {
@@ -26,16 +37,20 @@ impl ClientConnectionInner {
         rx: mpsc::Receiver<ClientConnectionMessage>,
         stream: TcpStream,
         turtle_manager: TurtleManagerHandle,
-        pool: SqlitePool,
         id: usize,
+        admin_token: Option<String>,
     ) -> Self {
+        let (background_tx, background_rx) = mpsc::unbounded_channel();
         ClientConnectionInner {
             rx,
             stream,
             turtle_manager,
-            pool,
             message_buffer: vec![],
             id,
+            admin_token,
+            admin: false,
+            background_tx,
+            background_rx,
         }
     }
 
@@ -45,6 +60,7 @@ impl ClientConnectionInner {
         // let mut reader = BufReader::new(reader);
         let (tx, mut turtle_event_rx) = mpsc::unbounded_channel();
         self.turtle_manager.client_subscribe(tx).await;
+        self.turtle_manager.metrics().client_connected();
 
         loop {
             let mut buffer = [0; 1024];
@@ -63,6 +79,9 @@ impl ClientConnectionInner {
                 Some(message) = turtle_event_rx.recv() => {
                     self.handle_turtle_connection_message(message).await;
                 }
+                Some(event) = self.background_rx.recv() => {
+                    self.send_event(&event).await;
+                }
                 read_result = self.stream.read(&mut buffer) =>  {
                     match read_result {
                         Ok(n) if n == 0 => {
@@ -82,6 +101,7 @@ impl ClientConnectionInner {
         }
 
         self.stream.shutdown().await;
+        self.turtle_manager.metrics().client_disconnected();
         debug!("Client connection closing");
 
         if let Some(tx) = close_tx {
@@ -100,16 +120,273 @@ impl ClientConnectionInner {
         }
     }
 
+    /// Runs a command from the client. The client is sent an error event if it fails.
     async fn handle_request(&mut self, request: Command) {
-        match request {
-            Command::GetTurtles => {
-                debug!("Sending all turtles to client");
-                self.send_turtles().await;
+        let result = match request.clone() {
+            Command::GetTurtles { dimension, tool } => {
+                debug!(
+                    "Sending turtles in {:?} with {:?} to client",
+                    dimension, tool
+                );
+                self.send_turtles(dimension, tool).await
+            }
+            // Moves can wait on reserved blocks so are done in the background. Turtles that stay
+            // blocked are reported with an error event once the wait runs out.
+            Command::Move { target, direction } => {
+                debug!("Moving {target} in direction {:?}", direction);
+                self.spawn_move(request.clone(), target, direction);
+                Ok(())
+            }
+            // Single turtles report why they could not be paused. Groups skip turtles that can't
+            // like pausing every turtle does.
+            Command::Pause { target } => match target {
+                Some(Selector::Name(name)) => self.turtle_manager.pause(name).await,
+                target => {
+                    let target = target.unwrap_or(Selector::All);
+                    self.turtle_manager.pause_selected(target).await;
+                    Ok(())
+                }
+            },
+            Command::Resume { target } => match target {
+                Some(Selector::Name(name)) => self.turtle_manager.resume(name).await,
+                target => {
+                    let target = target.unwrap_or(Selector::All);
+                    self.turtle_manager.resume_selected(target).await;
+                    Ok(())
+                }
+            },
+            Command::EmergencyStop { target } => match target {
+                Some(Selector::Name(name)) => self.turtle_manager.emergency_stop(name).await,
+                target => {
+                    let target = target.unwrap_or(Selector::All);
+                    self.turtle_manager.emergency_stop_selected(target).await;
+                    Ok(())
+                }
+            },
+            Command::Send { command, .. } if command.needs_admin() && !self.admin => {
+                Err(Error::NotAdmin)
+            }
+            Command::Send { target, command } => match command.direction() {
+                // Moves reserve the block they move into like Move does.
+                Some(direction) => {
+                    self.spawn_move(request.clone(), target, direction);
+                    Ok(())
+                }
+                None => {
+                    self.for_each_selected(target, |name, tm| {
+                        let command = command.clone();
+                        async move {
+                            match tm.get_turtle(name.as_str()).await {
+                                Some(turtle) => turtle.send(command).await,
+                                None => Err(Error::UnknownTurtle { name }),
+                            }
+                        }
+                    })
+                    .await
+                }
+            },
+            Command::AddLabel { name, label } => self.turtle_manager.add_label(name, label).await,
+            Command::RemoveLabel { name, label } => {
+                self.turtle_manager.remove_label(name, label).await
+            }
+            Command::GetLabels => match self.turtle_manager.get_labels().await {
+                Ok(labels) => {
+                    self.send_event(&Event::Labels { labels }).await;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            Command::ListPeripherals { target } => {
+                let listed = self
+                    .map_selected(target, |name, tm| async move {
+                        let peripherals = match tm.get_turtle(name.as_str()).await {
+                            Some(turtle) => turtle.list_peripherals().await?,
+                            None => return Err(Error::UnknownTurtle { name }),
+                        };
+                        Ok(Event::Peripherals { name, peripherals })
+                    })
+                    .await;
+                self.send_each(listed).await
+            }
+            Command::ListInventory { target, side } => {
+                let listed = self
+                    .map_selected(target, |name, tm| async move {
+                        let (position, dimension, inventory) =
+                            match tm.get_turtle(name.as_str()).await {
+                                Some(turtle) => turtle.list_inventory(side).await?,
+                                None => return Err(Error::UnknownTurtle { name }),
+                            };
+                        Ok(Event::Inventory {
+                            position,
+                            dimension,
+                            inventory: Some(inventory),
+                        })
+                    })
+                    .await;
+                self.send_each(listed).await
+            }
+            Command::Craft {
+                target,
+                item,
+                count,
+            } => {
+                self.for_each_selected(target, |name, tm| {
+                    let item = item.clone();
+                    async move { tm.craft(name, item, count).await }
+                })
+                .await
+            }
+            Command::Guard { target } => {
+                self.for_each_selected(target, |name, tm| async move { tm.guard(name).await })
+                    .await
+            }
+            Command::StopGuard { target } => {
+                self.for_each_selected(target, |name, tm| async move { tm.stop_guard(name).await })
+                    .await
+            }
+            // Scripts run as the turtle so only admins can change them, like eval.
+            Command::Deploy { .. } | Command::Rollback { .. } if !self.admin => {
+                Err(Error::NotAdmin)
+            }
+            Command::Deploy {
+                target: Some(target),
+                force,
+            } => {
+                let deployed = self
+                    .map_selected(target, |name, tm| async move {
+                        tm.deploy(name.as_str(), force).await.map(|()| name)
+                    })
+                    .await;
+                match deployed {
+                    // Turtles that were deployed to are reported even if others failed.
+                    Ok(deployed) => {
+                        let mut names = vec![];
+                        let mut error = None;
+                        for result in deployed {
+                            match result {
+                                Ok(name) => names.push(name),
+                                Err(e) => {
+                                    error.get_or_insert(e);
+                                }
+                            }
+                        }
+                        self.send_event(&Event::Deployed { names }).await;
+                        error.map_or(Ok(()), Err)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::Deploy { target: None, .. } => {
+                let names = self.turtle_manager.deploy_all().await;
+                self.send_event(&Event::Deployed { names }).await;
+                Ok(())
+            }
+            Command::Rollback { target } => {
+                self.for_each_selected(target, |name, tm| async move { tm.rollback(name).await })
+                    .await
+            }
+            Command::Login { token } => {
+                if self.admin_token.as_ref().is_some_and(|t| *t == token) {
+                    info!("Client {} logged in as admin", self.id);
+                    self.admin = true;
+                    self.send_event(&Event::LoggedIn).await;
+                    Ok(())
+                } else {
+                    Err(Error::NotAdmin)
+                }
+            }
+            // Evals can run for as long as the eval timeout so are answered in the background.
+            Command::Eval { target, code } if self.admin => {
+                let manager = self.turtle_manager.clone();
+                self.spawn_command(request.clone(), async move {
+                    map_selected(&manager, target, |name, tm| {
+                        let code = code.clone();
+                        async move {
+                            let result = tm.eval(name.as_str(), code).await?;
+                            Ok(Event::Evaluated { name, result })
+                        }
+                    })
+                    .await
+                });
+                Ok(())
+            }
+            Command::Eval { .. } => Err(Error::NotAdmin),
+            Command::MoveFormation {
+                names,
+                formation,
+                destination,
+                heading,
+            } => match self.select_all(names).await {
+                Ok(names) => {
+                    self.turtle_manager
+                        .move_formation(names, formation, destination, heading)
+                        .await
+                }
+                Err(e) => Err(e),
+            },
+            Command::GetReservations => {
+                let reservations = self.turtle_manager.get_reservations().await;
+                self.send_event(&Event::Reservations { reservations }).await;
+                Ok(())
+            }
+            Command::StartJob { job, .. } if job.needs_admin() && !self.admin => {
+                Err(Error::NotAdmin)
+            }
+            Command::StartJob { job, target } => {
+                match self.turtle_manager.start_job(job, target).await {
+                    Ok(id) => {
+                        self.send_event(&Event::JobStarted { id }).await;
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetJobs => {
+                let jobs = self.turtle_manager.get_jobs().await;
+                self.send_event(&Event::Jobs { jobs }).await;
+                Ok(())
+            }
+            Command::GetInventory {
+                position,
+                dimension,
+            } => {
+                let inventory = self
+                    .turtle_manager
+                    .get_inventory(position, dimension.clone())
+                    .await;
+                self.send_event(&Event::Inventory {
+                    position,
+                    dimension,
+                    inventory,
+                })
+                .await;
+                Ok(())
+            }
+            Command::SetWaypoint { waypoint } => self.turtle_manager.set_waypoint(waypoint).await,
+            Command::RemoveWaypoint { name } => self.turtle_manager.remove_waypoint(name).await,
+            Command::GetWaypoints => match self.turtle_manager.get_waypoints().await {
+                Ok(waypoints) => {
+                    self.send_event(&Event::Waypoints { waypoints }).await;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            Command::SetHome { name, waypoint } => {
+                self.turtle_manager.set_home(name, waypoint).await
             }
-            Command::Move { name, direction } => {
-                debug!("Moving turtle {name} in direction {:?}", direction);
-                self.move_turtle(name, direction).await;
+            Command::ReturnHome { target } => {
+                self.for_each_selected(target, |name, tm| async move { tm.return_home(name).await })
+                    .await
             }
+        };
+
+        if let Err(error) = result {
+            debug!("Client command {:?} failed {error}", request);
+            self.send_event(&Event::Error {
+                command: request,
+                error,
+            })
+            .await;
         }
     }
 
@@ -125,19 +402,65 @@ impl ClientConnectionInner {
             ConnectionMessageType::Disconnected => {
                 self.send_event(&Event::TurtleDisconnected { name }).await;
             }
+            ConnectionMessageType::Paused => {
+                self.send_event(&Event::TurtlePaused { name }).await;
+            }
+            ConnectionMessageType::Resumed => {
+                self.send_event(&Event::TurtleResumed { name }).await;
+            }
+            ConnectionMessageType::Unresponsive => {
+                self.send_event(&Event::TurtleUnresponsive { name }).await;
+            }
+            ConnectionMessageType::Reconnected => {
+                self.send_event(&Event::TurtleReconnected { name }).await;
+            }
+            ConnectionMessageType::Drops(items) => {
+                self.send_event(&Event::Drops { name, items }).await;
+            }
+            ConnectionMessageType::JobProgress(progress) => {
+                self.send_event(&Event::JobProgress { progress }).await;
+            }
+            ConnectionMessageType::ReturnedHome { waypoint, error } => {
+                self.send_event(&Event::ReturnedHome {
+                    name,
+                    waypoint,
+                    error,
+                })
+                .await;
+            }
+            ConnectionMessageType::FormationMoved { names, error } => {
+                self.send_event(&Event::FormationMoved { names, error })
+                    .await;
+            }
+            ConnectionMessageType::Crafted { item, error } => {
+                self.send_event(&Event::Crafted { name, item, error }).await;
+            }
         }
     }
 
-    async fn send_turtles(&mut self) {
-        let turtles = match turtle_operations::get_turtles(&self.pool).await {
-            Ok(t) => t,
-            Err(e) => {
-                error!("Problem getting turtles from database");
-                return;
+    /// Sends the client every turtle in `dimension` that has `tool` equipped.
+    /// Turtles are not filtered by either if it is None.
+    async fn send_turtles(
+        &mut self,
+        dimension: Option<Dimension>,
+        tool: Option<Tool>,
+    ) -> Result<(), Error> {
+        let mut turtles = match self.turtle_manager.get_turtles().await {
+            Some(t) => t,
+            None => {
+                error!("Problem getting turtles from turtle manager");
+                return Err(Error::Shutdown);
             }
         };
+        if let Some(dimension) = dimension {
+            turtles.retain(|t| t.dimension == dimension);
+        }
+        if let Some(tool) = tool {
+            turtles.retain(|t| t.upgrades.has(tool));
+        }
 
         self.send_event(&Event::Turtles { turtles }).await;
+        Ok(())
     }
 
     async fn send_event(&mut self, event: &Event) {
@@ -148,12 +471,118 @@ impl ClientConnectionInner {
         };
     }
 
-    async fn move_turtle(&self, name: String, direction: Direction) {
-        let turtle = match self.turtle_manager.get_turtle(name).await {
-            Some(t) => t,
-            None => return,
-        };
+    /// Gets the names of every turtle any of `targets` picks out.
+    async fn select_all(&self, targets: Vec<Selector>) -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        for target in targets {
+            names.extend(self.turtle_manager.select(target).await?);
+        }
 
-        turtle.move_turtle(direction).await;
+        Ok(names)
     }
+
+    /// Runs `f` for every turtle `target` picks out at the same time so turtles in a group
+    /// waiting on each other's blocks can all move. Fails with the first error.
+    async fn for_each_selected<F, Fut>(&self, target: Selector, f: F) -> Result<(), Error>
+    where
+        F: Fn(String, TurtleManagerHandle) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        self.map_selected(target, f).await?.into_iter().collect()
+    }
+
+    /// Runs `f` for every turtle `target` picks out at the same time and gets what each one
+    /// returned.
+    async fn map_selected<F, Fut, T>(
+        &self,
+        target: Selector,
+        f: F,
+    ) -> Result<Vec<Result<T, Error>>, Error>
+    where
+        F: Fn(String, TurtleManagerHandle) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        map_selected(&self.turtle_manager, target, f).await
+    }
+
+    /// Runs `command` on its own task so the client's next commands are read while it waits.
+    /// The client is sent the event each turtle returned and an error event for the first turtle
+    /// that failed once they all finish.
+    fn spawn_command<Fut>(&self, command: Command, future: Fut)
+    where
+        Fut: Future<Output = Result<Vec<Result<Event, Error>>, Error>> + Send + 'static,
+    {
+        let tx = self.background_tx.clone();
+        tokio::spawn(async move {
+            let results = match future.await {
+                Ok(r) => r,
+                Err(e) => vec![Err(e)],
+            };
+
+            let mut error = None;
+            for result in results {
+                match result {
+                    Ok(event) => {
+                        let _ = tx.send(event);
+                    }
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+            if let Some(error) = error {
+                debug!("Client command {:?} failed {error}", command);
+                let _ = tx.send(Event::Error { command, error });
+            }
+        });
+    }
+
+    /// Moves every turtle `target` picks out in the background through the manager so the block
+    /// each one moves into is reserved first.
+    fn spawn_move(&self, command: Command, target: Selector, direction: Direction) {
+        let manager = self.turtle_manager.clone();
+        self.spawn_command(command, async move {
+            let moved = map_selected(&manager, target, |name, tm| async move {
+                tm.move_turtle(name, direction).await
+            })
+            .await?;
+            Ok(moved.into_iter().filter_map(Result::err).map(Err).collect())
+        });
+    }
+
+    /// Sends the client the event each turtle returned. Fails with the first error once the
+    /// events from the turtles that succeeded are sent.
+    async fn send_each(
+        &mut self,
+        results: Result<Vec<Result<Event, Error>>, Error>,
+    ) -> Result<(), Error> {
+        let mut error = None;
+        for result in results? {
+            match result {
+                Ok(event) => self.send_event(&event).await,
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+}
+
+/// Runs `f` for every turtle `target` picks out with `manager` at the same time and gets what each
+/// one returned.
+async fn map_selected<F, Fut, T>(
+    manager: &TurtleManagerHandle,
+    target: Selector,
+    f: F,
+) -> Result<Vec<Result<T, Error>>, Error>
+where
+    F: Fn(String, TurtleManagerHandle) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let names = manager.select(target).await?;
+    let futures = names.into_iter().map(|name| f(name, manager.clone()));
+
+    Ok(futures_util::future::join_all(futures).await)
 }
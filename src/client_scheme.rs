@@ -1,20 +1,311 @@
+use crate::crafting::ItemCount;
+use crate::dispatcher::{Job, JobProgress};
+use crate::error::Error;
+use crate::formation::Formation;
 use crate::scheme;
-use crate::scheme::Direction;
-use crate::turtle_scheme::TurtleEvents;
+use crate::scheme::{Coordinates, Dimension, Direction, Heading, Tool, Waypoint};
+use crate::selector::{Labels, Selector};
+use crate::turtle_manager::Reservation;
+use crate::turtle_scheme::{Inventory, Peripheral, Side, TurtleCommand, TurtleEvents};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Command {
-    GetTurtles,
-    Move { name: String, direction: Direction },
+    /// Gets every turtle or only the turtles in `dimension` that have `tool` equipped.
+    GetTurtles {
+        #[serde(default)]
+        dimension: Option<Dimension>,
+
+        #[serde(default)]
+        tool: Option<Tool>,
+    },
+    /// Moves every turtle `target` picks out. `target` is a turtle's name, `@group`, `#tag` or
+    /// `*` here and in every other command that takes one. Turtles that can't move into a
+    /// reserved block before the wait runs out are answered with an error.
+    Move {
+        #[serde(alias = "name")]
+        target: Selector,
+        direction: Direction,
+    },
+
+    /// Pauses the turtles `target` picks out or every turtle if there is no target.
+    Pause {
+        #[serde(default, alias = "name")]
+        target: Option<Selector>,
+    },
+    Resume {
+        #[serde(default, alias = "name")]
+        target: Option<Selector>,
+    },
+    EmergencyStop {
+        #[serde(default, alias = "name")]
+        target: Option<Selector>,
+    },
+
+    /// Sends a command to every turtle `target` picks out.
+    Send {
+        target: Selector,
+        command: TurtleCommand,
+    },
+
+    /// Adds a turtle to a group or tags it. `label` is `@group` or `#tag`.
+    AddLabel {
+        name: String,
+        label: Selector,
+    },
+
+    /// Takes a turtle out of a group or removes a tag from it.
+    RemoveLabel {
+        name: String,
+        label: Selector,
+    },
+
+    /// Gets every group and tag with the turtles in it.
+    GetLabels,
+
+    /// Lists the peripherals attached to each turtle `target` picks out.
+    ListPeripherals {
+        #[serde(alias = "name")]
+        target: Selector,
+    },
+
+    /// Has each turtle `target` picks out list the inventory on one of its sides and stores it.
+    ListInventory {
+        #[serde(alias = "name")]
+        target: Selector,
+        side: Side,
+    },
+
+    /// Has each turtle `target` picks out craft at least `count` of `item` from what is in its
+    /// inventory. Answered with an error for any turtle that can't start and Crafted once each
+    /// turtle is done.
+    Craft {
+        #[serde(alias = "name")]
+        target: Selector,
+        item: String,
+        count: u32,
+    },
+
+    /// Has a turtle attack whatever is in front of it until told to stop.
+    /// Anything it picks up is sent as drops.
+    Guard {
+        #[serde(alias = "name")]
+        target: Selector,
+    },
+
+    /// Stops a turtle guarding.
+    StopGuard {
+        #[serde(alias = "name")]
+        target: Selector,
+    },
+
+    /// Deploys the server's startup.lua to the turtles `target` picks out or to every connected
+    /// turtle running a different version if there is no target. Turtles that were rolled back
+    /// are only deployed to with `force` set. Only for admins.
+    Deploy {
+        #[serde(default, alias = "name")]
+        target: Option<Selector>,
+
+        #[serde(default)]
+        force: bool,
+    },
+
+    /// Has the turtles `target` picks out go back to the script they had before their last deploy.
+    /// Only for admins.
+    Rollback {
+        #[serde(alias = "name")]
+        target: Selector,
+    },
+
+    /// Logs the client in as an admin if `token` matches the server's admin token.
+    Login {
+        token: String,
+    },
+
+    /// Runs a chunk of Lua on each turtle `target` picks out. Only for admins and only if the
+    /// server allows it.
+    Eval {
+        #[serde(alias = "name")]
+        target: Selector,
+        code: String,
+    },
+
+    /// Moves turtles together into `formation` with its first place at `destination`.
+    /// Every turtle goes back to where it started if one of them stays blocked. Answered with an
+    /// error if the turtles can't set off and FormationMoved once they stop.
+    MoveFormation {
+        names: Vec<Selector>,
+        formation: Formation,
+        destination: Coordinates,
+        heading: Heading,
+    },
+
+    /// Gets every block reserved for a turtle that is about to move into it.
+    GetReservations,
+
+    /// Splits a job into chunks and hands them to idle turtles `target` picks out, or any idle
+    /// turtle if there is no target. Progress is sent to every client as it changes.
+    StartJob {
+        job: Job,
+
+        #[serde(default)]
+        target: Selector,
+    },
+
+    /// Gets the progress of every job that has been started.
+    GetJobs,
+
+    /// Gets the last stored contents of the inventory at `position`.
+    /// Defaults to the overworld when no dimension is given.
+    GetInventory {
+        position: Coordinates,
+        #[serde(default)]
+        dimension: Dimension,
+    },
+
+    /// Stores a waypoint, replacing any waypoint with the same name.
+    SetWaypoint {
+        waypoint: Waypoint,
+    },
+
+    /// Removes a waypoint. Turtles that had it as their home are left without one.
+    RemoveWaypoint {
+        name: String,
+    },
+
+    GetWaypoints,
+
+    /// Sets the waypoint a turtle goes back to or clears it if there is no waypoint.
+    SetHome {
+        name: String,
+        #[serde(default)]
+        waypoint: Option<String>,
+    },
+
+    /// Sends the turtles `target` picks out back to their homes. Answered with an error for any
+    /// turtle that can't set off and ReturnedHome once each trip ends.
+    ReturnHome {
+        target: Selector,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
-    Turtles { turtles: Vec<scheme::Turtle> },
-    TurtleEvent { name: String, event: TurtleEvents },
-    TurtleConnected { name: String },
-    TurtleDisconnected { name: String },
+    Turtles {
+        turtles: Vec<scheme::Turtle>,
+    },
+    TurtleEvent {
+        name: String,
+        event: TurtleEvents,
+    },
+    TurtleConnected {
+        name: String,
+    },
+    TurtleDisconnected {
+        name: String,
+    },
+    TurtlePaused {
+        name: String,
+    },
+    TurtleResumed {
+        name: String,
+    },
+    TurtleUnresponsive {
+        name: String,
+    },
+    TurtleReconnected {
+        name: String,
+    },
+
+    Peripherals {
+        name: String,
+        peripherals: Vec<Peripheral>,
+    },
+
+    /// Contents of the inventory at `position`. None if it has never been listed.
+    Inventory {
+        position: Coordinates,
+        dimension: Dimension,
+        inventory: Option<Inventory>,
+    },
+
+    /// Items a guarding turtle picked up after attacking.
+    Drops {
+        name: String,
+        items: Vec<ItemCount>,
+    },
+
+    /// Turtles the server's startup.lua was deployed to.
+    Deployed {
+        names: Vec<String>,
+    },
+
+    /// The client is now an admin.
+    LoggedIn,
+
+    /// What a chunk of Lua evaluated on a turtle returned, serialized by the turtle.
+    Evaluated {
+        name: String,
+        result: String,
+    },
+
+    /// Every group and tag with the turtles in it.
+    Labels {
+        labels: Labels,
+    },
+
+    /// Blocks reserved for turtles that are about to move into them.
+    Reservations {
+        reservations: Vec<Reservation>,
+    },
+
+    /// A job from this client was started with `id`.
+    JobStarted {
+        id: u64,
+    },
+
+    /// How far along a job is. Sent whenever a task is done or a turtle starts or stops working
+    /// on it.
+    JobProgress {
+        progress: JobProgress,
+    },
+
+    /// Progress of every job that has been started.
+    Jobs {
+        jobs: Vec<JobProgress>,
+    },
+
+    /// Every waypoint sorted by name.
+    Waypoints {
+        waypoints: Vec<Waypoint>,
+    },
+
+    /// A turtle's trip back to its home at `waypoint` ended. `error` is None if it got there.
+    ReturnedHome {
+        name: String,
+        waypoint: String,
+        error: Option<Error>,
+    },
+
+    /// The formation of `names` stopped moving. `error` is None if every turtle got in place and
+    /// otherwise why they went back to where they started.
+    FormationMoved {
+        names: Vec<String>,
+        error: Option<Error>,
+    },
+
+    /// A turtle finished crafting `item`. `error` is None if it made everything it was asked to.
+    Crafted {
+        name: String,
+        item: String,
+        error: Option<Error>,
+    },
+
+    /// A command from the client could not be done.
+    Error {
+        command: Command,
+        error: Error,
+    },
 }
@@ -1,16 +1,23 @@
-use crate::db::turtle_operations;
-use crate::scheme::{Coordinates, Heading, TurtleType};
-use crate::turtle_manager::TurtleManagerHandle;
-use crate::turtle_scheme::{RequestType, TurtleCommand};
-use sqlx::SqlitePool;
+use crate::db;
+use crate::dispatcher::Job;
+use crate::error::Error;
+use crate::formation::Formation;
+use crate::metrics::Metrics;
+use crate::scheme::{Coordinates, Dimension, Heading, TurtleType, Waypoint};
+use crate::selector::Selector;
+use crate::turtle_manager::{self, TurtleManagerConfig, TurtleManagerHandle};
+use crate::turtle_scheme::{RequestType, Side, TurtleCommand};
+use std::future::Future;
 use std::io;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
 use tokio::runtime::Handle;
 use tokio::sync::oneshot;
 use tracing::{error, info, warn};
 
-fn interpret_command(command: &str) -> Option<TurtleCommand> {
+/// `slot` is only used by the equip commands.
+fn interpret_command(command: &str, slot: Option<u32>) -> Option<TurtleCommand> {
     match command.to_uppercase().as_str() {
         "FORWARD" => Some(TurtleCommand::Forward),
         "BACK" => Some(TurtleCommand::Back),
@@ -18,6 +25,20 @@ fn interpret_command(command: &str) -> Option<TurtleCommand> {
         "TURNRIGHT" => Some(TurtleCommand::TurnRight),
         "REBOOT" => Some(TurtleCommand::Reboot),
         "INSPECT" => Some(TurtleCommand::Inspect),
+        "EQUIPLEFT" => Some(TurtleCommand::EquipLeft { slot: slot? }),
+        "EQUIPRIGHT" => Some(TurtleCommand::EquipRight { slot: slot? }),
+        "ATTACK" => Some(TurtleCommand::Attack),
+        "ATTACKUP" => Some(TurtleCommand::AttackUp),
+        "ATTACKDOWN" => Some(TurtleCommand::AttackDown),
+        "DIG" => Some(TurtleCommand::Dig),
+        "DIGUP" => Some(TurtleCommand::DigUp),
+        "DIGDOWN" => Some(TurtleCommand::DigDown),
+        "PLACE" => Some(TurtleCommand::Place { slot: slot? }),
+        "PLACEUP" => Some(TurtleCommand::PlaceUp { slot: slot? }),
+        "PLACEDOWN" => Some(TurtleCommand::PlaceDown { slot: slot? }),
+        "DROP" => Some(TurtleCommand::Drop { slot: slot? }),
+        "DROPUP" => Some(TurtleCommand::DropUp { slot: slot? }),
+        "DROPDOWN" => Some(TurtleCommand::DropDown { slot: slot? }),
         _ => None,
     }
 }
@@ -38,12 +59,7 @@ fn read_number<F: FromStr>(trimmed_buffer: &str, i: usize) -> Option<F> {
     Some(c)
 }
 
-fn handle_input(
-    trimmed_buffer: &str,
-    turtle_manager: TurtleManagerHandle,
-    async_handle: &Handle,
-    pool: SqlitePool,
-) {
+fn handle_input(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
     let command = if let Some(c) = trimmed_buffer.chars().next() {
         c
     } else {
@@ -116,17 +132,24 @@ fn handle_input(
                 }
             };
 
+            // Dimension and world are optional. Turtles are in the overworld by default.
+            let dimension = match trimmed_buffer.split(' ').nth(7) {
+                Some(d) => Dimension::new(d, trimmed_buffer.split(' ').nth(8)),
+                None => Dimension::default(),
+            };
+
             async_handle.spawn(async move {
-                if let Err(e) = turtle_operations::add_turtle(
-                    turtle_name.as_str(),
-                    Coordinates { x, y, z },
-                    heading,
-                    turtle_type,
-                    &pool,
-                )
-                .await
+                if let Err(e) = turtle_manager
+                    .add_turtle(
+                        turtle_name.as_str(),
+                        Coordinates { x, y, z },
+                        dimension,
+                        heading,
+                        turtle_type,
+                    )
+                    .await
                 {
-                    error!("Problem creating new turtle {e}");
+                    error!("Problem adding {turtle_name}: {e}");
                 }
             });
         }
@@ -134,7 +157,7 @@ fn handle_input(
             send_request(trimmed_buffer, async_handle, turtle_manager);
         }
         'S' => {
-            get_status(async_handle, turtle_manager);
+            get_status(trimmed_buffer, async_handle, turtle_manager);
         }
         'P' => {
             set_coordinate(trimmed_buffer, async_handle, turtle_manager);
@@ -142,6 +165,54 @@ fn handle_input(
         'D' => {
             disconnect_turtle(trimmed_buffer, turtle_manager, async_handle);
         }
+        'H' => {
+            pause_turtle(trimmed_buffer, turtle_manager, async_handle, false);
+        }
+        'E' => {
+            pause_turtle(trimmed_buffer, turtle_manager, async_handle, true);
+        }
+        'U' => {
+            resume_turtle(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'Y' => {
+            replay(trimmed_buffer, async_handle);
+        }
+        'G' => {
+            locate_turtle(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'I' => {
+            list_inventory(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'F' => {
+            craft(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'A' => {
+            guard(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'V' => {
+            deploy(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'L' => {
+            eval(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'O' => {
+            move_formation(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'K' => {
+            list_reservations(turtle_manager, async_handle);
+        }
+        'T' => {
+            label(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'J' => {
+            jobs(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'W' => {
+            waypoints(trimmed_buffer, turtle_manager, async_handle);
+        }
+        'M' => {
+            home(trimmed_buffer, turtle_manager, async_handle);
+        }
         c if c != 'Q' => info!("Unknown command"),
         _ => {}
     }
@@ -160,7 +231,647 @@ fn disconnect_turtle(
         }
     };
 
-    async_handle.spawn(async move { turtle_manager.disconnect(name).await });
+    async_handle.spawn(async move {
+        if let Err(e) = turtle_manager.disconnect(name.as_str()).await {
+            error!("Problem disconnecting {name}: {e}");
+        }
+    });
+}
+
+/// Pauses the named turtle, a group or tag, or every turtle if nothing is given.
+/// If `emergency` is set the turtles also stop retrying their current command.
+fn pause_turtle(
+    trimmed_buffer: &str,
+    turtle_manager: TurtleManagerHandle,
+    async_handle: &Handle,
+    emergency: bool,
+) {
+    let target = trimmed_buffer
+        .split_whitespace()
+        .nth(1)
+        .map_or(Selector::All, Selector::parse);
+
+    async_handle.spawn(async move {
+        let result = match (&target, emergency) {
+            (Selector::Name(name), false) => turtle_manager.pause(name).await,
+            (Selector::Name(name), true) => turtle_manager.emergency_stop(name).await,
+            (_, false) => {
+                turtle_manager.pause_selected(target.clone()).await;
+                Ok(())
+            }
+            (_, true) => {
+                turtle_manager.emergency_stop_selected(target.clone()).await;
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            error!("Problem pausing {target}: {e}");
+        }
+    });
+}
+
+/// Resumes the named turtle, a group or tag, or every paused turtle if nothing is given.
+fn resume_turtle(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let target = trimmed_buffer
+        .split_whitespace()
+        .nth(1)
+        .map_or(Selector::All, Selector::parse);
+
+    async_handle.spawn(async move {
+        match target {
+            Selector::Name(name) => {
+                if let Err(e) = turtle_manager.resume(name.as_str()).await {
+                    error!("Problem resuming {name}: {e}");
+                }
+            }
+            target => turtle_manager.resume_selected(target).await,
+        }
+    });
+}
+
+/// Lists every group and tag. Given a turtle and `@group` or `#tag` adds the turtle to it, or
+/// takes the turtle out of it if the label starts with `-`.
+fn label(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let mut parts = trimmed_buffer.split_whitespace().skip(1);
+    let (name, label) = match (parts.next(), parts.next()) {
+        (Some(name), Some(label)) => (name.to_string(), label.to_string()),
+        (None, _) => {
+            async_handle.spawn(async move {
+                match turtle_manager.get_labels().await {
+                    Ok(labels) => {
+                        for (group, names) in labels.groups {
+                            info!("@{group}: {}", names.join(", "));
+                        }
+                        for (tag, names) in labels.tags {
+                            info!("#{tag}: {}", names.join(", "));
+                        }
+                    }
+                    Err(e) => error!("Problem getting groups and tags: {e}"),
+                }
+            });
+            return;
+        }
+        (Some(_), None) => {
+            error!("Invalid label command missing group or tag");
+            return;
+        }
+    };
+
+    async_handle.spawn(async move {
+        let result = match label.strip_prefix('-') {
+            Some(l) => {
+                turtle_manager
+                    .remove_label(name.as_str(), Selector::parse(l))
+                    .await
+            }
+            None => {
+                turtle_manager
+                    .add_label(name.as_str(), Selector::parse(&label))
+                    .await
+            }
+        };
+        if let Err(e) = result {
+            error!("Problem labelling {name} {label}: {e}");
+        }
+    });
+}
+
+/// Checks a turtle's position with GPS and sends it the result.
+fn locate_turtle(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let name = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(n) => n.to_string(),
+        None => {
+            error!("Invalid locate command missing turtle name");
+            return;
+        }
+    };
+
+    async_handle.spawn(async move { turtle_manager.send_turtle_position(name).await });
+}
+
+/// Runs `f` on its own task for every turtle `target` picks out.
+fn for_each_selected<F, Fut>(
+    target: Selector,
+    turtle_manager: TurtleManagerHandle,
+    async_handle: &Handle,
+    f: F,
+) where
+    F: Fn(String, TurtleManagerHandle) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handle = async_handle.clone();
+    async_handle.spawn(async move {
+        let names = match turtle_manager.select(target.clone()).await {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Problem selecting {target}: {e}");
+                return;
+            }
+        };
+        for name in names {
+            handle.spawn(f(name, turtle_manager.clone()));
+        }
+    });
+}
+
+/// Lists and stores the inventory on a side of each turtle a selector picks out.
+/// Lists the turtles' peripherals instead if no side is given.
+fn list_inventory(
+    trimmed_buffer: &str,
+    turtle_manager: TurtleManagerHandle,
+    async_handle: &Handle,
+) {
+    let target = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(t) => Selector::parse(t),
+        None => {
+            error!("Invalid inventory command missing turtle name");
+            return;
+        }
+    };
+    let side = match trimmed_buffer.split_whitespace().nth(2) {
+        Some(s) => match Side::from_str(s.to_lowercase().as_str()) {
+            Some(s) => Some(s),
+            None => {
+                error!("Invalid side {s}");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    for_each_selected(
+        target,
+        turtle_manager,
+        async_handle,
+        move |name, turtle_manager| async move {
+            let turtle = match turtle_manager.get_turtle(name.as_str()).await {
+                Some(t) => t,
+                None => {
+                    error!("{}", Error::UnknownTurtle { name });
+                    return;
+                }
+            };
+
+            match side {
+                Some(side) => match turtle.list_inventory(side).await {
+                    Ok((position, dimension, inventory)) => {
+                        info!("Inventory at {position} in {dimension}: {:?}", inventory)
+                    }
+                    Err(e) => error!("Problem listing inventory of {name}: {e}"),
+                },
+                None => match turtle.list_peripherals().await {
+                    Ok(peripherals) => info!("Peripherals of {name}: {:?}", peripherals),
+                    Err(e) => error!("Problem listing peripherals of {name}: {e}"),
+                },
+            }
+        },
+    );
+}
+
+/// Has each turtle a selector picks out craft an item. Crafts one if no count is given.
+fn craft(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let target = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(t) => Selector::parse(t),
+        None => {
+            error!("Invalid craft command missing turtle name");
+            return;
+        }
+    };
+    let item = match trimmed_buffer.split_whitespace().nth(2) {
+        Some(i) => i.to_string(),
+        None => {
+            error!("Invalid craft command missing item");
+            return;
+        }
+    };
+    let count = match trimmed_buffer.split_whitespace().nth(3) {
+        Some(c) => match c.parse::<u32>() {
+            Ok(c) => c,
+            Err(_) => {
+                error!("Invalid count {c}");
+                return;
+            }
+        },
+        None => 1,
+    };
+
+    for_each_selected(
+        target,
+        turtle_manager,
+        async_handle,
+        move |name, turtle_manager| {
+            let item = item.clone();
+            async move {
+                match turtle_manager
+                    .craft(name.as_str(), item.as_str(), count)
+                    .await
+                {
+                    Ok(()) => info!("{name} is crafting {count} {item}"),
+                    Err(e) => error!("Problem crafting {item} with {name}: {e}"),
+                }
+            }
+        },
+    );
+}
+
+/// Has turtles attack whatever is in front of them. Stops them instead if followed by stop.
+fn guard(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let target = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(t) => Selector::parse(t),
+        None => {
+            error!("Invalid guard command missing turtle name");
+            return;
+        }
+    };
+    let stop = trimmed_buffer
+        .split_whitespace()
+        .nth(2)
+        .is_some_and(|s| s.eq_ignore_ascii_case("stop"));
+
+    async_handle.spawn(async move {
+        let names = match turtle_manager.select(target).await {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Problem selecting turtles to guard: {e}");
+                return;
+            }
+        };
+        for name in names {
+            let result = if stop {
+                turtle_manager.stop_guard(name.as_str()).await
+            } else {
+                turtle_manager.guard(name.as_str()).await
+            };
+            if let Err(e) = result {
+                error!("Problem changing guard for {name}: {e}");
+            }
+        }
+    });
+}
+
+/// Deploys the server's startup.lua to the turtles a selector picks out or every outdated turtle
+/// if none is given. Rolls the turtles back to their previous script instead if followed by
+/// rollback. Followed by force it deploys over a rollback.
+fn deploy(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let target = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(t) => Selector::parse(t),
+        None => {
+            async_handle.spawn(async move {
+                let deployed = turtle_manager.deploy_all().await;
+                info!("Deployed to {} turtles: {:?}", deployed.len(), deployed);
+            });
+            return;
+        }
+    };
+    let option = trimmed_buffer
+        .split_whitespace()
+        .nth(2)
+        .map(str::to_ascii_lowercase);
+    let rollback = option.as_deref() == Some("rollback");
+    let force = option.as_deref() == Some("force");
+
+    for_each_selected(
+        target,
+        turtle_manager,
+        async_handle,
+        move |name, turtle_manager| async move {
+            if rollback {
+                if let Err(e) = turtle_manager.rollback(name.as_str()).await {
+                    error!("Problem rolling back {name}: {e}");
+                }
+            } else if let Err(e) = turtle_manager.deploy(name.as_str(), force).await {
+                error!("Problem deploying to {name}: {e}");
+            }
+        },
+    );
+}
+
+/// Runs the rest of the line as Lua on each turtle a selector picks out.
+fn eval(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let mut parts = trimmed_buffer.splitn(3, ' ');
+    let (target, code) = match (parts.nth(1), parts.next()) {
+        (Some(target), Some(code)) => (Selector::parse(target), code.to_string()),
+        _ => {
+            error!("Invalid eval command needs a turtle name and code");
+            return;
+        }
+    };
+
+    for_each_selected(
+        target,
+        turtle_manager,
+        async_handle,
+        move |name, turtle_manager| {
+            let code = code.clone();
+            async move {
+                match turtle_manager.eval(name.as_str(), code).await {
+                    Ok(result) => info!("{name} returned {result}"),
+                    Err(e) => error!("Problem evaluating on {name}: {e}"),
+                }
+            }
+        },
+    );
+}
+
+/// Moves turtles into a formation.
+/// Takes the shape (line, column or grid:<width>), the position and heading of the formation's
+/// first place and then the turtles' names, groups or tags.
+fn move_formation(
+    trimmed_buffer: &str,
+    turtle_manager: TurtleManagerHandle,
+    async_handle: &Handle,
+) {
+    let mut parts = trimmed_buffer.split_whitespace().skip(1);
+    let formation = match parts.next().map(|f| (f, Formation::from_str(f))) {
+        Some((_, Some(f))) => f,
+        Some((f, None)) => {
+            error!("Invalid formation {f}");
+            return;
+        }
+        None => {
+            error!("Invalid formation command missing shape");
+            return;
+        }
+    };
+
+    let mut coordinates = [0i64; 3];
+    for c in coordinates.iter_mut() {
+        *c = match parts.next().map(|c| c.parse()) {
+            Some(Ok(c)) => c,
+            _ => {
+                error!("Invalid formation command needs x, y and z");
+                return;
+            }
+        };
+    }
+    let [x, y, z] = coordinates;
+
+    let heading = match parts.next().map(Heading::from_str) {
+        Some(Some(h)) => h,
+        _ => {
+            error!("Invalid formation command needs a heading");
+            return;
+        }
+    };
+
+    let targets: Vec<Selector> = parts.map(Selector::parse).collect();
+    if targets.is_empty() {
+        error!("Invalid formation command missing turtle names");
+        return;
+    }
+
+    async_handle.spawn(async move {
+        let mut names = vec![];
+        for target in targets {
+            match turtle_manager.select(target).await {
+                Ok(n) => names.extend(n),
+                Err(e) => {
+                    error!("Problem selecting turtles for formation: {e}");
+                    return;
+                }
+            }
+        }
+
+        match turtle_manager
+            .move_formation(names, formation, Coordinates { x, y, z }, heading)
+            .await
+        {
+            Ok(()) => info!("Formation is moving"),
+            Err(e) => error!("Problem moving formation: {e}"),
+        }
+    });
+}
+
+/// Prints every block reserved for a turtle and how long until it runs out.
+fn list_reservations(turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    async_handle.spawn(async move {
+        let reservations = turtle_manager.get_reservations().await;
+        info!("{} reserved blocks", reservations.len());
+        for r in reservations {
+            let holder = r.name.as_deref().unwrap_or("unknown turtle");
+            info!(
+                "{} in {} for {holder} ({}ms left)",
+                r.position, r.dimension, r.expires_in_ms
+            );
+        }
+    });
+}
+
+/// Lists the progress of every job or with two corners excavates the box between them in the
+/// overworld. The excavation is only given to the turtles a selector after the corners picks out.
+fn jobs(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let mut numbers: Vec<&str> = trimmed_buffer.split_whitespace().skip(1).collect();
+    let target = match numbers.len() {
+        7 => numbers.pop().map_or(Selector::All, Selector::parse),
+        _ => Selector::All,
+    };
+    if numbers.is_empty() {
+        async_handle.spawn(async move {
+            for job in turtle_manager.get_jobs().await {
+                let state = if job.finished { "finished" } else { "running" };
+                info!(
+                    "Job {} {state}: {}/{} done {} failed. Turtles: {}",
+                    job.id,
+                    job.done,
+                    job.total,
+                    job.failed,
+                    job.turtles.join(", ")
+                );
+            }
+        });
+        return;
+    }
+
+    let corners: Vec<i64> = match numbers.iter().map(|n| n.parse()).collect() {
+        Ok(c) => c,
+        Err(_) => {
+            error!("Invalid excavate command needs x, y and z of two corners");
+            return;
+        }
+    };
+    let (from, to) = match corners[..] {
+        [x1, y1, z1, x2, y2, z2] => (
+            Coordinates {
+                x: x1,
+                y: y1,
+                z: z1,
+            },
+            Coordinates {
+                x: x2,
+                y: y2,
+                z: z2,
+            },
+        ),
+        _ => {
+            error!("Invalid excavate command needs x, y and z of two corners");
+            return;
+        }
+    };
+
+    let job = Job::Excavate {
+        from,
+        to,
+        dimension: Dimension::default(),
+    };
+    async_handle.spawn(async move {
+        match turtle_manager.start_job(job, target).await {
+            Ok(id) => info!("Started excavating from {from} to {to} as job {id}"),
+            Err(e) => error!("Problem starting excavation: {e}"),
+        }
+    });
+}
+
+/// Lists every waypoint. Given a name, coordinates and a heading stores a waypoint with an
+/// optional dimension and world, or removes the waypoint if the name starts with `-`.
+fn waypoints(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let parts: Vec<&str> = trimmed_buffer.split_whitespace().skip(1).collect();
+    let name = match parts.first() {
+        Some(n) => n.to_string(),
+        None => {
+            async_handle.spawn(async move {
+                match turtle_manager.get_waypoints().await {
+                    Ok(waypoints) => {
+                        for w in waypoints {
+                            info!(
+                                "{}: {} facing {} in {}",
+                                w.name, w.position, w.heading, w.dimension
+                            );
+                        }
+                    }
+                    Err(e) => error!("Problem getting waypoints: {e}"),
+                }
+            });
+            return;
+        }
+    };
+
+    if let Some(name) = name.strip_prefix('-') {
+        let name = name.to_string();
+        async_handle.spawn(async move {
+            if let Err(e) = turtle_manager.remove_waypoint(name.as_str()).await {
+                error!("Problem removing waypoint {name}: {e}");
+            }
+        });
+        return;
+    }
+
+    let coordinates: Option<Vec<i64>> = parts
+        .get(1..4)
+        .and_then(|c| c.iter().map(|n| n.parse().ok()).collect());
+    let position = match coordinates {
+        Some(c) => Coordinates {
+            x: c[0],
+            y: c[1],
+            z: c[2],
+        },
+        None => {
+            error!("Invalid waypoint command needs x, y and z");
+            return;
+        }
+    };
+    let heading = match parts.get(4).map(|h| Heading::from_str(h)) {
+        Some(Some(h)) => h,
+        _ => {
+            error!("Invalid waypoint command needs a heading");
+            return;
+        }
+    };
+    // Waypoints are in the overworld unless a dimension is given.
+    let dimension = match parts.get(5) {
+        Some(d) => Dimension::new(d, parts.get(6).copied()),
+        None => Dimension::default(),
+    };
+
+    let waypoint = Waypoint {
+        name,
+        position,
+        heading,
+        dimension,
+    };
+    async_handle.spawn(async move {
+        let name = waypoint.name.clone();
+        if let Err(e) = turtle_manager.set_waypoint(waypoint).await {
+            error!("Problem setting waypoint {name}: {e}");
+        }
+    });
+}
+
+/// Sends the turtles a selector picks out home. Given a turtle and a waypoint makes the waypoint
+/// the turtle's home, or clears the turtle's home if the waypoint is `-`.
+fn home(trimmed_buffer: &str, turtle_manager: TurtleManagerHandle, async_handle: &Handle) {
+    let mut parts = trimmed_buffer.split_whitespace().skip(1);
+    let target = match parts.next() {
+        Some(t) => t.to_string(),
+        None => {
+            error!("Invalid home command missing turtle name");
+            return;
+        }
+    };
+
+    if let Some(waypoint) = parts.next() {
+        let waypoint = (waypoint != "-").then(|| waypoint.to_string());
+        async_handle.spawn(async move {
+            if let Err(e) = turtle_manager.set_home(target.as_str(), waypoint).await {
+                error!("Problem setting home of {target}: {e}");
+            }
+        });
+        return;
+    }
+
+    let target = Selector::parse(&target);
+    for_each_selected(
+        target,
+        turtle_manager,
+        async_handle,
+        |name, turtle_manager| async move {
+            match turtle_manager.return_home(name.as_str()).await {
+                Ok(()) => info!("{name} is on its way home"),
+                Err(e) => error!("Problem sending {name} home: {e}"),
+            }
+        },
+    );
+}
+
+/// Replays a recorded turtle session file into a separate turtle manager with its own memory
+/// database and metrics so the replayed positions and fuel never reach the live database, turtles
+/// or metrics endpoint.
+fn replay(trimmed_buffer: &str, async_handle: &Handle) {
+    let path = match trimmed_buffer.split_whitespace().nth(1) {
+        Some(p) => PathBuf::from(p),
+        None => {
+            error!("Invalid replay command missing session file");
+            return;
+        }
+    };
+
+    let frames = match turtle_manager::read_session(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Problem reading session {} {e}", path.display());
+            return;
+        }
+    };
+
+    async_handle.spawn(async move {
+        let pool = match db::setup_replay_database().await {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Problem setting up database to replay into {e}");
+                return;
+            }
+        };
+        // Nothing serves these metrics so the replayed turtles are never counted.
+        let replay_manager = TurtleManagerHandle::with_metrics(
+            pool,
+            TurtleManagerConfig::default(),
+            Arc::new(Metrics::new()),
+        );
+        match turtle_manager::replay_session(&frames, &replay_manager).await {
+            Ok(sent) => info!("Replayed {}. Sent {} frames", path.display(), sent.len()),
+            Err(e) => error!("Problem replaying {} {e}", path.display()),
+        }
+        replay_manager.close().await;
+    });
 }
 
 fn set_coordinate(
@@ -230,21 +941,29 @@ fn set_coordinate(
         };
 
         let new_position = option(current_position, coordinate);
-        if let Err(e) = turtle.get_db().set_coordinates(new_position).await {
-            error!("Problem setting turtle position: {e}");
-        };
+        turtle_manager
+            .update_turtle_position(turtle_name.as_str(), new_position)
+            .await;
+
+        // The operator has given the position so it no longer needs confirming.
+        if let Err(e) = turtle.get_db().set_position_verified(true).await {
+            error!("Problem marking turtle position verified: {e}");
+        }
     });
 }
 
-fn get_status(async_handle: &Handle, turtle_manager: TurtleManagerHandle) {
+/// Prints the status of every turtle or, if a dimension and optional world are given, only the
+/// turtles in that dimension.
+fn get_status(trimmed_buffer: &str, async_handle: &Handle, turtle_manager: TurtleManagerHandle) {
+    let mut parts = trimmed_buffer.split_whitespace().skip(1);
+    let dimension = parts.next().map(|d| Dimension::new(d, parts.next()));
+
     async_handle.spawn(async move {
-        println!(
-            "{}",
-            turtle_manager
-                .get_status()
-                .await
-                .unwrap_or("Problem getting status".to_string())
-        );
+        let status = match dimension {
+            Some(dimension) => turtle_manager.get_dimension_status(dimension).await,
+            None => turtle_manager.get_status().await,
+        };
+        println!("{}", status.unwrap_or("Problem getting status".to_string()));
     });
 }
 
@@ -272,13 +991,14 @@ fn send_request(trimmed_buffer: &str, async_handle: &Handle, turtle_manager: Tur
     async_handle.spawn(async move {
         let try_turtle = turtle_manager.get_turtle(turtle_name.clone()).await;
         if let Some(turtle) = try_turtle {
-            match tokio::time::timeout(Duration::from_secs(10), turtle.request(request)).await {
-                Ok(Ok(response)) => {
+            match turtle.request(request).await {
+                Ok(response) => {
                     info!("Got response from {turtle_name}: {:?}", response)
                 }
-                Err(_) => error!("Timeout getting response from {turtle_name}"),
-                Ok(Err(_)) => error!("Problem getting response form {turtle_name}"),
+                Err(e) => error!("Problem getting response from {turtle_name}: {e}"),
             }
+        } else {
+            error!("{}", Error::UnknownTurtle { name: turtle_name });
         }
     });
 }
@@ -291,7 +1011,7 @@ fn send_command(trimmed_buffer: &str, async_handle: &Handle, turtle_manager: Tur
         return;
     };
     let command = if let Some(command) = trimmed_buffer.split(' ').nth(2) {
-        if let Some(command) = interpret_command(command) {
+        if let Some(command) = interpret_command(command, read_number(trimmed_buffer, 3)) {
             command
         } else {
             error!("Unknown turtle command {command}");
@@ -302,39 +1022,73 @@ fn send_command(trimmed_buffer: &str, async_handle: &Handle, turtle_manager: Tur
         return;
     };
 
+    // Groups and tags are sent the command like a broadcast.
+    let turtle_name = match Selector::parse(turtle_name.as_str()) {
+        Selector::Name(name) => name,
+        target => {
+            async_handle.spawn(async move { turtle_manager.broadcast(target, command).await });
+            return;
+        }
+    };
+
+    // Moves wait for the block the turtle is moving into to be reserved.
+    if let Some(direction) = command.direction() {
+        async_handle.spawn(async move {
+            if let Err(e) = turtle_manager
+                .move_turtle(turtle_name.as_str(), direction)
+                .await
+            {
+                warn!("Could not move {turtle_name} {e}");
+            }
+        });
+        return;
+    }
+
     async_handle.spawn(async move {
-        let try_turtle = turtle_manager.get_turtle(turtle_name).await;
+        let try_turtle = turtle_manager.get_turtle(turtle_name.clone()).await;
         if let Some(turtle) = try_turtle {
             if let Err(e) = turtle.send(command).await {
                 warn!("Could not send command to turtle {e}");
             }
+        } else {
+            error!("{}", Error::UnknownTurtle { name: turtle_name });
         }
     });
 }
 
+/// Sends a command to every turtle, or to the turtles a `@group`, `#tag` or `*` given before the
+/// command picks out.
 fn broadcast(trimmed_buffer: &str, async_handle: &Handle, turtle_manager: TurtleManagerHandle) {
-    let turtle_command_string = if let Some(c) = trimmed_buffer.split(' ').nth(1) {
+    // Turtle names could be taken for commands so only groups and tags are read as a target.
+    let (target, start) = match trimmed_buffer.split(' ').nth(1).map(Selector::parse) {
+        Some(Selector::Name(_)) | None => (Selector::All, 1),
+        Some(target) => (target, 2),
+    };
+
+    let turtle_command_string = if let Some(c) = trimmed_buffer.split(' ').nth(start) {
         c.to_string()
     } else {
         error!("Invalid command to run");
         return;
     };
 
-    let turtle_command = if let Some(command) = interpret_command(turtle_command_string.as_str()) {
+    let turtle_command = if let Some(command) = interpret_command(
+        turtle_command_string.as_str(),
+        read_number(trimmed_buffer, start + 1),
+    ) {
         command
     } else {
         error!("Unknown command {turtle_command_string}");
         return;
     };
 
-    async_handle.spawn(async move { turtle_manager.broadcast(turtle_command).await });
+    async_handle.spawn(async move { turtle_manager.broadcast(target, turtle_command).await });
 }
 
 pub fn read_input(
     close_tx: oneshot::Sender<()>,
     turtle_manager: TurtleManagerHandle,
     async_handle: Handle,
-    pool: SqlitePool,
 ) {
     let mut buffer = String::new();
     let mut trimmed_buffer = String::new();
@@ -348,7 +1102,6 @@ pub fn read_input(
             trimmed_buffer.as_str(),
             turtle_manager.clone(),
             &async_handle,
-            pool.clone(),
         );
     }
 
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::turtle_scheme::{Inventory, Side, TurtleCommand};
+
+/// Slots in a turtle's inventory. The inventory is 4 slots wide.
+const TURTLE_SLOTS: usize = 16;
+const TURTLE_WIDTH: usize = 4;
+
+/// Most items of one kind that fit in a slot.
+const STACK_SIZE: u32 = 64;
+
+/// A shaped crafting recipe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipe {
+    /// Number of items one craft makes.
+    #[serde(default = "one")]
+    pub count: u32,
+
+    /// Up to 3 rows of up to 3 item names. null leaves that part of the grid empty.
+    pub pattern: Vec<Vec<Option<String>>>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Recipe {
+    /// Gets the item each slot of the turtle's crafting grid needs.
+    /// The grid is the top left 3 by 3 slots of the inventory. Every other slot is None.
+    fn grid(&self) -> [Option<&str>; TURTLE_SLOTS] {
+        let mut grid = [None; TURTLE_SLOTS];
+        for (row, items) in self.pattern.iter().take(3).enumerate() {
+            for (column, item) in items.iter().take(3).enumerate() {
+                grid[row * TURTLE_WIDTH + column] = item.as_deref();
+            }
+        }
+
+        grid
+    }
+
+    /// Number of grid cells that take `item`.
+    pub fn cells(&self, item: &str) -> u32 {
+        self.grid().iter().filter(|i| **i == Some(item)).count() as u32
+    }
+}
+
+/// Number of one kind of item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemCount {
+    pub name: String,
+    pub count: u32,
+}
+
+/// Recipes the wrangler knows keyed by the item they make.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecipeBook {
+    recipes: HashMap<String, Recipe>,
+}
+
+impl RecipeBook {
+    /// Reads a json file of recipes keyed by the item they make.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = std::fs::read_to_string(path)?;
+        serde_json::from_str(&file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn get(&self, item: &str) -> Option<&Recipe> {
+        self.recipes.get(item)
+    }
+}
+
+/// Works out the commands that arrange a turtle's inventory for `recipe` and craft it enough
+/// times to make at least `count` items.
+///
+/// `turtle.craft` needs every slot outside the crafting grid to be empty so items that aren't
+/// part of the recipe are dropped into the inventory on the `stash` side first. Spare items of the
+/// recipe are left in the grid.
+pub fn plan(
+    recipe: &Recipe,
+    count: u32,
+    inventory: &Inventory,
+    stash: Option<Side>,
+) -> Result<Vec<TurtleCommand>, Error> {
+    let crafts = count.div_ceil(recipe.count.max(1));
+    if crafts > STACK_SIZE {
+        return Err(Error::CannotCraft {
+            reason: format!("can't craft more than {STACK_SIZE} times at once"),
+        });
+    }
+
+    let grid = recipe.grid();
+    let mut commands = vec![];
+    let mut slots: Vec<Option<ItemCount>> = vec![None; TURTLE_SLOTS];
+    for slot in inventory.slots.iter() {
+        let s = match (slot.slot as usize)
+            .checked_sub(1)
+            .and_then(|i| slots.get_mut(i))
+        {
+            Some(s) => s,
+            None => continue,
+        };
+
+        if !grid.contains(&Some(slot.name.as_str())) {
+            commands.push(
+                stash_command(stash, slot.slot).ok_or_else(|| Error::CannotCraft {
+                    reason: format!(
+                        "{} is not part of the recipe and there is no inventory to put it in",
+                        slot.name
+                    ),
+                })?,
+            );
+            continue;
+        }
+
+        *s = Some(ItemCount {
+            name: slot.name.clone(),
+            count: slot.count,
+        });
+    }
+
+    check_items(&grid, crafts, &slots)?;
+
+    let mut plan = Planner {
+        slots,
+        grid,
+        commands,
+    };
+    plan.clear_grid()?;
+    plan.fill_grid(crafts);
+    plan.commands.push(TurtleCommand::Craft { count: crafts });
+
+    Ok(plan.commands)
+}
+
+/// Gets the command that drops `slot` into the inventory on the `stash` side.
+/// Turtles can only drop in front, above and below.
+fn stash_command(stash: Option<Side>, slot: u32) -> Option<TurtleCommand> {
+    match stash? {
+        Side::Front => Some(TurtleCommand::Drop { slot }),
+        Side::Top => Some(TurtleCommand::DropUp { slot }),
+        Side::Bottom => Some(TurtleCommand::DropDown { slot }),
+        _ => None,
+    }
+}
+
+/// Checks the inventory has enough of every item in the recipe.
+fn check_items(
+    grid: &[Option<&str>; TURTLE_SLOTS],
+    crafts: u32,
+    slots: &[Option<ItemCount>],
+) -> Result<(), Error> {
+    let mut cells: HashMap<&str, u32> = HashMap::new();
+    for item in grid.iter().flatten() {
+        *cells.entry(item).or_default() += 1;
+    }
+
+    let mut have: HashMap<&str, u32> = HashMap::new();
+    for item in slots.iter().flatten() {
+        *have.entry(item.name.as_str()).or_default() += item.count;
+    }
+
+    let mut missing: Vec<ItemCount> = cells
+        .iter()
+        .filter_map(|(name, cells)| {
+            let need = cells * crafts;
+            let have = have.get(name).copied().unwrap_or_default();
+            (have < need).then(|| ItemCount {
+                name: name.to_string(),
+                count: need - have,
+            })
+        })
+        .collect();
+    if !missing.is_empty() {
+        missing.sort_by(|a, b| a.name.cmp(&b.name));
+        return Err(Error::MissingItems { items: missing });
+    }
+
+    for (name, have) in have {
+        if have > cells[name] * STACK_SIZE {
+            return Err(Error::CannotCraft {
+                reason: format!("too many {name} to fit in the crafting grid"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Tracks the turtle's inventory while transfers are planned.
+struct Planner<'a> {
+    slots: Vec<Option<ItemCount>>,
+    grid: [Option<&'a str>; TURTLE_SLOTS],
+    commands: Vec<TurtleCommand>,
+}
+
+impl<'a> Planner<'a> {
+    fn in_grid(slot: usize) -> bool {
+        slot % TURTLE_WIDTH < 3 && slot / TURTLE_WIDTH < 3
+    }
+
+    fn count(&self, slot: usize) -> u32 {
+        self.slots[slot]
+            .as_ref()
+            .map(|s| s.count)
+            .unwrap_or_default()
+    }
+
+    /// Whether `slot` is empty or holds `item` with room for more.
+    fn has_room(&self, slot: usize, item: &str) -> bool {
+        match &self.slots[slot] {
+            Some(s) => s.name == item && s.count < STACK_SIZE,
+            None => true,
+        }
+    }
+
+    fn transfer(&mut self, from: usize, to: usize, count: u32) {
+        let name = match &mut self.slots[from] {
+            Some(s) => {
+                s.count -= count;
+                s.name.clone()
+            }
+            None => return,
+        };
+        if self.count(from) == 0 {
+            self.slots[from] = None;
+        }
+
+        match &mut self.slots[to] {
+            Some(s) => s.count += count,
+            None => self.slots[to] = Some(ItemCount { name, count }),
+        }
+
+        self.commands.push(TurtleCommand::TransferTo {
+            from: from as u32 + 1,
+            to: to as u32 + 1,
+            count,
+        });
+    }
+
+    /// Moves items out of grid slots that need a different item or nothing.
+    /// They go straight to a grid slot that needs them if there is room, otherwise outside the
+    /// grid until they are needed.
+    fn clear_grid(&mut self) -> Result<(), Error> {
+        for slot in (0..TURTLE_SLOTS).filter(|s| Self::in_grid(*s)) {
+            while let Some(item) = self.slots[slot].clone() {
+                if self.grid[slot] == Some(item.name.as_str()) {
+                    break;
+                }
+
+                let wanted = (0..TURTLE_SLOTS).find(|s| {
+                    *s != slot
+                        && self.grid[*s] == Some(item.name.as_str())
+                        && self.has_room(*s, &item.name)
+                });
+                let spare =
+                    (0..TURTLE_SLOTS).find(|s| !Self::in_grid(*s) && self.has_room(*s, &item.name));
+                let to = wanted.or(spare).ok_or_else(|| Error::CannotCraft {
+                    reason: "no free slot to arrange the crafting grid".to_string(),
+                })?;
+
+                let count = item.count.min(STACK_SIZE - self.count(to));
+                self.transfer(slot, to, count);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts at least `crafts` items in every grid slot then moves everything left outside the
+    /// grid into the grid slots for that item.
+    fn fill_grid(&mut self, crafts: u32) {
+        for slot in (0..TURTLE_SLOTS).filter(|s| Self::in_grid(*s)) {
+            let item = match self.grid[slot] {
+                Some(i) => i,
+                None => continue,
+            };
+
+            while self.count(slot) < crafts {
+                let need = crafts - self.count(slot);
+                let spare = |s: usize, planner: &Self| {
+                    let count = planner.count(s);
+                    match &planner.slots[s] {
+                        Some(i) if i.name == item && s != slot => {
+                            if Self::in_grid(s) {
+                                count.saturating_sub(crafts)
+                            } else {
+                                count
+                            }
+                        }
+                        _ => 0,
+                    }
+                };
+
+                let from = match (0..TURTLE_SLOTS).find(|s| spare(*s, self) > 0) {
+                    Some(f) => f,
+                    None => break,
+                };
+                let count = need.min(spare(from, self));
+                self.transfer(from, slot, count);
+            }
+        }
+
+        for from in (0..TURTLE_SLOTS).filter(|s| !Self::in_grid(*s)) {
+            while let Some(item) = self.slots[from].clone() {
+                let to = (0..TURTLE_SLOTS).find(|s| {
+                    self.grid[*s] == Some(item.name.as_str()) && self.has_room(*s, &item.name)
+                });
+                match to {
+                    Some(to) => {
+                        let count = item.count.min(STACK_SIZE - self.count(to));
+                        self.transfer(from, to, count);
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,53 @@
 use sqlx::migrate::MigrateDatabase;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use sqlx::{ConnectOptions, SqlitePool};
+use sqlx::{ConnectOptions, Row, SqliteConnection, SqlitePool};
 use tracing::log::debug;
 
+pub mod group_operations;
+pub mod inventory_operations;
 pub mod turtle_operations;
+pub mod waypoint_operations;
 
 pub async fn setup_database(db_path: &str) -> Result<SqlitePool, sqlx::Error> {
     if !sqlx::Sqlite::database_exists(db_path).await? {
         create_db(db_path).await
     }
     let pool = SqlitePoolOptions::new().connect(db_path).await?;
+    migrate(&pool).await?;
 
     debug!("Database initialized");
     Ok(pool)
 }
 
+/// Creates a database for a test that only lives as long as the returned pool.
+#[cfg(test)]
+pub async fn setup_memory_database() -> Result<SqlitePool, sqlx::Error> {
+    memory_database().await
+}
+
+/// Creates a database to replay a recorded session into that only lives as long as the returned
+/// pool. Keeps the replayed positions and fuel out of the live database.
+pub async fn setup_replay_database() -> Result<SqlitePool, sqlx::Error> {
+    memory_database().await
+}
+
+/// The pool is limited to a single connection that is never closed because every sqlite memory
+/// connection is its own database.
+async fn memory_database() -> Result<SqlitePool, sqlx::Error> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await?;
+
+    let mut connection = pool.acquire().await?;
+    create_tables(&mut connection).await?;
+
+    debug!("Memory database initialized");
+    Ok(pool)
+}
+
 async fn create_db(db_path: &str) {
     debug!("Creating new database");
     let mut connection = SqliteConnectOptions::new()
@@ -24,17 +57,182 @@ async fn create_db(db_path: &str) {
         .await
         .unwrap();
 
+    create_tables(&mut connection).await.unwrap();
+}
+
+/// Brings a database made by an older version up to date with create_tables.
+async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let columns: Vec<String> = sqlx::query("SELECT name FROM pragma_table_info('turtles')")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    if !columns.iter().any(|c| c == "last_seen") {
+        debug!("Adding last_seen column to turtles");
+        sqlx::query("ALTER TABLE turtles ADD COLUMN last_seen INTEGER")
+            .execute(pool)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c == "position_verified") {
+        debug!("Adding position_verified column to turtles");
+        sqlx::query("ALTER TABLE turtles ADD COLUMN position_verified INTEGER NOT NULL DEFAULT 1")
+            .execute(pool)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c == "dimension") {
+        debug!("Adding dimension and world columns to turtles");
+        sqlx::query(
+            "ALTER TABLE turtles ADD COLUMN dimension TEXT NOT NULL DEFAULT 'minecraft:overworld'",
+        )
+        .execute(pool)
+        .await?;
+        sqlx::query("ALTER TABLE turtles ADD COLUMN world TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c == "left_upgrade") {
+        debug!("Adding upgrade columns to turtles");
+        sqlx::query("ALTER TABLE turtles ADD COLUMN left_upgrade TEXT")
+            .execute(pool)
+            .await?;
+        sqlx::query("ALTER TABLE turtles ADD COLUMN right_upgrade TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c == "home") {
+        debug!("Adding home column to turtles");
+        sqlx::query("ALTER TABLE turtles ADD COLUMN home TEXT")
+            .execute(pool)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c == "rolled_back") {
+        debug!("Adding rolled_back column to turtles");
+        sqlx::query("ALTER TABLE turtles ADD COLUMN rolled_back INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await?;
+    }
+
+    if !table_exists("inventories", pool).await? {
+        debug!("Adding inventories table");
+        create_inventories_table(&mut *pool.acquire().await?).await?;
+    }
+
+    if !table_exists("turtle_groups", pool).await? {
+        debug!("Adding group and tag tables");
+        create_group_tables(&mut *pool.acquire().await?).await?;
+    }
+
+    if !table_exists("waypoints", pool).await? {
+        debug!("Adding waypoints table");
+        create_waypoints_table(&mut *pool.acquire().await?).await?;
+    }
+
+    Ok(())
+}
+
+async fn table_exists(name: &str, pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)",
+        )
+        .bind(name)
+        .fetch_one(pool)
+        .await?
+        .get(0),
+    )
+}
+
+async fn create_tables(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     sqlx::query(
         "CREATE TABLE turtles (\
         name TEXT PRIMARY KEY, \
         x INTEGER NOT NULL, \
         y INTEGER NOT NULL, \
         z INTEGER NOT NULL, \
+        dimension TEXT NOT NULL DEFAULT 'minecraft:overworld', \
+        world TEXT, \
         heading TEXT NOT NULL,\
         type TEXT NOT NULL,\
-        fuel INTEGER NOT NULL)",
+        fuel INTEGER NOT NULL,\
+        last_seen INTEGER,\
+        position_verified INTEGER NOT NULL DEFAULT 1,\
+        left_upgrade TEXT,\
+        right_upgrade TEXT,\
+        home TEXT,\
+        rolled_back INTEGER NOT NULL DEFAULT 0)",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    create_inventories_table(&mut *connection).await?;
+    create_group_tables(&mut *connection).await?;
+    create_waypoints_table(connection).await
+}
+
+/// Inventories such as chests keyed by their position.
+/// World is never null so it can be part of the key. Inventories without a world use ''.
+async fn create_inventories_table(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE inventories (\
+        x INTEGER NOT NULL, \
+        y INTEGER NOT NULL, \
+        z INTEGER NOT NULL, \
+        dimension TEXT NOT NULL, \
+        world TEXT NOT NULL DEFAULT '', \
+        size INTEGER NOT NULL, \
+        slots TEXT NOT NULL, \
+        PRIMARY KEY (x, y, z, dimension, world))",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Groups of turtles and tags on turtles. Both can hold turtles that have not connected yet.
+async fn create_group_tables(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE turtle_groups (\
+        name TEXT NOT NULL, \
+        turtle TEXT NOT NULL, \
+        PRIMARY KEY (name, turtle))",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE turtle_tags (\
+        turtle TEXT NOT NULL, \
+        tag TEXT NOT NULL, \
+        PRIMARY KEY (turtle, tag))",
     )
-    .execute(&mut connection)
-    .await
-    .unwrap();
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Named places turtles can be sent to. A turtle's home is the name of one in turtles.
+async fn create_waypoints_table(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE waypoints (\
+        name TEXT PRIMARY KEY, \
+        x INTEGER NOT NULL, \
+        y INTEGER NOT NULL, \
+        z INTEGER NOT NULL, \
+        heading TEXT NOT NULL, \
+        dimension TEXT NOT NULL, \
+        world TEXT)",
+    )
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
 }
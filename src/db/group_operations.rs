@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::{Row, SqlitePool};
+
+use crate::metrics::{time_query, Metrics};
+
+/// Adds a turtle to a group. Does nothing if it is already in it.
+pub async fn add_to_group(
+    group: &str,
+    name: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "add_to_group",
+        sqlx::query("INSERT OR IGNORE INTO turtle_groups (name, turtle) VALUES (?, ?)")
+            .bind(group)
+            .bind(name)
+            .execute(pool),
+    )
+    .await
+}
+
+pub async fn remove_from_group(
+    group: &str,
+    name: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "remove_from_group",
+        sqlx::query("DELETE FROM turtle_groups WHERE name = ? AND turtle = ?")
+            .bind(group)
+            .bind(name)
+            .execute(pool),
+    )
+    .await
+}
+
+/// Gets the turtles in every group keyed by group name.
+pub async fn get_groups(
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<BTreeMap<String, Vec<String>>, sqlx::Error> {
+    let rows = time_query(
+        metrics,
+        "get_groups",
+        sqlx::query("SELECT name, turtle FROM turtle_groups ORDER BY name, turtle").fetch_all(pool),
+    )
+    .await?;
+
+    Ok(collect_pairs(
+        rows.iter().map(|row| (row.get(0), row.get(1))),
+    ))
+}
+
+/// Tags a turtle. Does nothing if it already has the tag.
+pub async fn add_tag(
+    name: &str,
+    tag: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "add_tag",
+        sqlx::query("INSERT OR IGNORE INTO turtle_tags (turtle, tag) VALUES (?, ?)")
+            .bind(name)
+            .bind(tag)
+            .execute(pool),
+    )
+    .await
+}
+
+pub async fn remove_tag(
+    name: &str,
+    tag: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "remove_tag",
+        sqlx::query("DELETE FROM turtle_tags WHERE turtle = ? AND tag = ?")
+            .bind(name)
+            .bind(tag)
+            .execute(pool),
+    )
+    .await
+}
+
+/// Gets the turtles with each tag keyed by tag.
+pub async fn get_tags(
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<BTreeMap<String, Vec<String>>, sqlx::Error> {
+    let rows = time_query(
+        metrics,
+        "get_tags",
+        sqlx::query("SELECT tag, turtle FROM turtle_tags ORDER BY tag, turtle").fetch_all(pool),
+    )
+    .await?;
+
+    Ok(collect_pairs(
+        rows.iter().map(|row| (row.get(0), row.get(1))),
+    ))
+}
+
+fn collect_pairs(pairs: impl Iterator<Item = (String, String)>) -> BTreeMap<String, Vec<String>> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (key, name) in pairs {
+        map.entry(key).or_default().push(name);
+    }
+
+    map
+}
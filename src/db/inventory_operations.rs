@@ -0,0 +1,76 @@
+use crate::metrics::{time_query, Metrics};
+use crate::scheme::{Coordinates, Dimension};
+use crate::turtle_scheme::{Inventory, ItemSlot};
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::{Row, SqlitePool};
+use tracing::error;
+
+/// Stores the contents of the inventory at `position`, replacing anything stored before.
+pub async fn set_inventory(
+    position: Coordinates,
+    dimension: &Dimension,
+    inventory: &Inventory,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    let slots = serde_json::to_string(&inventory.slots)
+        .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    time_query(
+        metrics,
+        "set_inventory",
+        sqlx::query(
+            "INSERT OR REPLACE INTO inventories \
+        (x, y, z, dimension, world, size, slots) \
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(position.x)
+        .bind(position.y)
+        .bind(position.z)
+        .bind(dimension.name.as_str())
+        .bind(dimension.world.as_deref().unwrap_or_default())
+        .bind(inventory.size)
+        .bind(slots)
+        .execute(pool),
+    )
+    .await
+}
+
+/// Gets the last stored contents of the inventory at `position`.
+/// Returns None if no turtle has listed the inventory.
+pub async fn get_inventory(
+    position: Coordinates,
+    dimension: &Dimension,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Option<Inventory> {
+    let row = time_query(
+        metrics,
+        "get_inventory",
+        sqlx::query(
+            "SELECT size, slots FROM inventories \
+            WHERE x = ? AND y = ? AND z = ? AND dimension = ? AND world = ?",
+        )
+        .bind(position.x)
+        .bind(position.y)
+        .bind(position.z)
+        .bind(dimension.name.as_str())
+        .bind(dimension.world.as_deref().unwrap_or_default())
+        .fetch_one(pool),
+    )
+    .await
+    .ok()?;
+
+    let slots: Vec<ItemSlot> = match serde_json::from_str(row.try_get("slots").ok()?) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Problem reading inventory slots at {position}: {e}");
+            return None;
+        }
+    };
+
+    Some(Inventory {
+        size: row.try_get("size").ok()?,
+        slots,
+    })
+}
@@ -1,19 +1,28 @@
+use crate::metrics::{time_query, Metrics};
 use crate::scheme;
-use crate::scheme::{Coordinates, Fuel, Heading, TurtleType};
+use crate::scheme::{Coordinates, Dimension, Fuel, Heading, TurtleType, Upgrades};
 use colored::Colorize;
-use sqlx::sqlite::SqliteQueryResult;
+use sqlx::sqlite::{SqliteQueryResult, SqliteRow};
 use sqlx::{Row, SqlitePool};
+use std::sync::Arc;
 use tracing::error;
 
 #[derive(Debug, Clone)]
 pub struct TurtleDB<'a> {
     name: &'a str,
     pool: SqlitePool,
+
+    /// Registry the turtle's queries are timed in.
+    metrics: Arc<Metrics>,
 }
 
 impl<'a> TurtleDB<'a> {
-    pub fn new(name: &'a str, pool: SqlitePool) -> Self {
-        TurtleDB { name, pool }
+    pub fn new(name: &'a str, pool: SqlitePool, metrics: Arc<Metrics>) -> Self {
+        TurtleDB {
+            name,
+            pool,
+            metrics,
+        }
     }
 
     ////////////////////////////////////////////////////
@@ -21,20 +30,59 @@ impl<'a> TurtleDB<'a> {
     ////////////////////////////////////////////////////
 
     pub async fn get_type(&self) -> Option<TurtleType> {
-        let row = sqlx::query("SELECT type FROM turtles WHERE name = ?")
-            .bind(self.name)
-            .fetch_one(&self.pool)
-            .await
-            .ok()?;
+        let row = time_query(
+            &self.metrics,
+            "get_type",
+            sqlx::query("SELECT type FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
 
         TurtleType::from_str(row.try_get(0).ok()?)
     }
 
+    pub async fn set_type(
+        &self,
+        turtle_type: TurtleType,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_type",
+            sqlx::query("UPDATE turtles SET type = ? WHERE name = ?")
+                .bind(turtle_type.as_str())
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
+
+    /// Gets everything stored about the turtle.
+    /// Returns None if the turtle is not in the database.
+    pub async fn get_turtle(&self) -> Option<scheme::Turtle> {
+        let row = time_query(
+            &self.metrics,
+            "get_turtle",
+            sqlx::query("SELECT * FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        turtle_from_row(&row).ok()
+    }
+
     pub async fn status(&self) -> String {
         let coordinates = match self.get_coordinates().await {
             Some(p) => p.to_string(),
             None => "Unknown".yellow().to_string(),
         };
+        let dimension = match self.get_dimension().await {
+            Some(d) => d.to_string(),
+            None => "Unknown".yellow().to_string(),
+        };
         let heading = match self.get_heading().await {
             Some(h) => h.as_str().to_string(),
             None => "Unknown".yellow().to_string(),
@@ -44,7 +92,22 @@ impl<'a> TurtleDB<'a> {
             None => "Unknown".yellow().to_string(),
         };
 
-        format!("Position: {coordinates} Heading: {heading}, Fuel: {fuel}")
+        let upgrades = match self.get_upgrades().await {
+            Some(u) => u.to_string(),
+            None => "Unknown".yellow().to_string(),
+        };
+
+        let last_seen = match self.get_last_seen().await {
+            Some(t) => format!("{}s ago", unix_time().saturating_sub(t)),
+            None => "Never".yellow().to_string(),
+        };
+
+        let coordinates = match self.is_position_verified().await {
+            Some(false) => format!("{coordinates} {}", "(unverified)".yellow()),
+            _ => coordinates,
+        };
+
+        format!("Position: {coordinates} in {dimension} Heading: {heading}, Fuel: {fuel}, Upgrades: {upgrades}, Last seen: {last_seen}")
     }
 
     ////////////////////////////////////////////////////
@@ -52,42 +115,153 @@ impl<'a> TurtleDB<'a> {
     ////////////////////////////////////////////////////
 
     pub async fn get_coordinates(&self) -> Option<Coordinates> {
-        sqlx::query_as::<_, Coordinates>("SELECT x, y, z FROM turtles WHERE name = ?")
-            .bind(self.name)
-            .fetch_one(&self.pool)
-            .await
-            .ok()
+        time_query(
+            &self.metrics,
+            "get_coordinates",
+            sqlx::query_as::<_, Coordinates>("SELECT x, y, z FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()
     }
 
     pub async fn set_coordinates(
         &self,
         coordinates: Coordinates,
     ) -> Result<SqliteQueryResult, sqlx::Error> {
-        sqlx::query("UPDATE turtles SET x = ?, y = ?, z = ? WHERE name = ?")
-            .bind(coordinates.x)
-            .bind(coordinates.y)
-            .bind(coordinates.z)
-            .bind(self.name)
-            .execute(&self.pool)
-            .await
+        time_query(
+            &self.metrics,
+            "set_coordinates",
+            sqlx::query("UPDATE turtles SET x = ?, y = ?, z = ? WHERE name = ?")
+                .bind(coordinates.x)
+                .bind(coordinates.y)
+                .bind(coordinates.z)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
+
+    pub async fn get_dimension(&self) -> Option<Dimension> {
+        let row = time_query(
+            &self.metrics,
+            "get_dimension",
+            sqlx::query("SELECT dimension, world FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        dimension_from_row(&row).ok()
+    }
+
+    pub async fn set_dimension(
+        &self,
+        dimension: &Dimension,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_dimension",
+            sqlx::query("UPDATE turtles SET dimension = ?, world = ? WHERE name = ?")
+                .bind(dimension.name.as_str())
+                .bind(dimension.world.as_deref())
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
+
+    /// Whether the position in the database is known to be right.
+    /// Turtles that registered themselves are unverified until their position is confirmed.
+    pub async fn is_position_verified(&self) -> Option<bool> {
+        let row = time_query(
+            &self.metrics,
+            "is_position_verified",
+            sqlx::query("SELECT position_verified FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        row.try_get(0).ok()
+    }
+
+    pub async fn set_position_verified(
+        &self,
+        verified: bool,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_position_verified",
+            sqlx::query("UPDATE turtles SET position_verified = ? WHERE name = ?")
+                .bind(verified)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
     }
 
     pub async fn get_heading(&self) -> Option<Heading> {
-        let heading = sqlx::query("SELECT heading FROM turtles WHERE name = ?")
-            .bind(self.name)
-            .fetch_one(&self.pool)
-            .await
-            .ok()?;
+        let heading = time_query(
+            &self.metrics,
+            "get_heading",
+            sqlx::query("SELECT heading FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
 
         Heading::from_str(heading.try_get(0).ok()?)
     }
 
     pub async fn set_heading(&self, heading: Heading) -> Result<SqliteQueryResult, sqlx::Error> {
-        sqlx::query("UPDATE turtles SET heading = ? WHERE name = ?")
-            .bind(heading.as_str())
-            .bind(self.name)
-            .execute(&self.pool)
-            .await
+        time_query(
+            &self.metrics,
+            "set_heading",
+            sqlx::query("UPDATE turtles SET heading = ? WHERE name = ?")
+                .bind(heading.as_str())
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
+
+    ////////////////////////////////////////////////////
+    // Upgrades
+    ////////////////////////////////////////////////////
+
+    pub async fn get_upgrades(&self) -> Option<Upgrades> {
+        let row = time_query(
+            &self.metrics,
+            "get_upgrades",
+            sqlx::query("SELECT left_upgrade, right_upgrade FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        upgrades_from_row(&row).ok()
+    }
+
+    pub async fn set_upgrades(
+        &self,
+        upgrades: &Upgrades,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_upgrades",
+            sqlx::query("UPDATE turtles SET left_upgrade = ?, right_upgrade = ? WHERE name = ?")
+                .bind(upgrades.left.as_deref())
+                .bind(upgrades.right.as_deref())
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
     }
 
     ////////////////////////////////////////////////////
@@ -95,11 +269,15 @@ impl<'a> TurtleDB<'a> {
     ////////////////////////////////////////////////////
 
     pub async fn get_fuel_level(&self) -> Option<u32> {
-        let level_row = sqlx::query("SELECT fuel FROM turtles WHERE name = ?")
-            .bind(self.name)
-            .fetch_one(&self.pool)
-            .await
-            .ok()?;
+        let level_row = time_query(
+            &self.metrics,
+            "get_fuel_level",
+            sqlx::query("SELECT fuel FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
 
         level_row.try_get(0).ok()
     }
@@ -111,11 +289,15 @@ impl<'a> TurtleDB<'a> {
     pub async fn get_fuel(&self) -> Option<Fuel> {
         let max = self.get_max_fuel().await?;
 
-        let level_row = sqlx::query("SELECT fuel FROM turtles WHERE name = ?")
-            .bind(self.name)
-            .fetch_one(&self.pool)
-            .await
-            .ok()?;
+        let level_row = time_query(
+            &self.metrics,
+            "get_fuel",
+            sqlx::query("SELECT fuel FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
 
         let level: u32 = level_row.try_get(0).ok()?;
 
@@ -123,86 +305,258 @@ impl<'a> TurtleDB<'a> {
     }
 
     pub async fn set_fuel(&self, fuel: u32) -> Result<SqliteQueryResult, sqlx::Error> {
-        sqlx::query("UPDATE turtles SET fuel = ? WHERE name = ?")
-            .bind(fuel)
-            .bind(self.name)
-            .execute(&self.pool)
-            .await
+        time_query(
+            &self.metrics,
+            "set_fuel",
+            sqlx::query("UPDATE turtles SET fuel = ? WHERE name = ?")
+                .bind(fuel)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
     }
-}
 
-pub async fn get_turtles(pool: &SqlitePool) -> Result<Vec<scheme::Turtle>, sqlx::Error> {
-    let rows = sqlx::query("SELECT * FROM turtles").fetch_all(pool).await?;
+    ////////////////////////////////////////////////////
+    // Liveness
+    ////////////////////////////////////////////////////
+
+    /// Gets when the turtle was last heard from as seconds since the unix epoch.
+    pub async fn get_last_seen(&self) -> Option<u64> {
+        let row = time_query(
+            &self.metrics,
+            "get_last_seen",
+            sqlx::query("SELECT last_seen FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        let last_seen: Option<i64> = row.try_get(0).ok()?;
+        Some(last_seen? as u64)
+    }
 
-    let mut turtles = Vec::with_capacity(rows.len());
+    /// Records that the turtle was heard from just now.
+    pub async fn set_last_seen_now(&self) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_last_seen",
+            sqlx::query("UPDATE turtles SET last_seen = ? WHERE name = ?")
+                .bind(unix_time() as i64)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
 
-    for row in rows.iter() {
-        let name: String = row.try_get("name")?;
+    ////////////////////////////////////////////////////
+    // Script
+    ////////////////////////////////////////////////////
 
-        let x: i64 = row.try_get("x")?;
-        let y: i64 = row.try_get("y")?;
-        let z: i64 = row.try_get("z")?;
-        let coordinates = Coordinates { x, y, z };
+    /// Whether the turtle's script was rolled back and has not been deployed over since.
+    pub async fn get_rolled_back(&self) -> bool {
+        let row = time_query(
+            &self.metrics,
+            "get_rolled_back",
+            sqlx::query("SELECT rolled_back FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await;
 
-        let heading = row.try_get("heading")?;
-        let heading = Heading::from_str(heading).unwrap();
+        row.is_ok_and(|r| r.try_get(0).unwrap_or_default())
+    }
 
-        let turtle_type = row.try_get("type")?;
-        let turtle_type = TurtleType::from_str(turtle_type).unwrap();
+    pub async fn set_rolled_back(
+        &self,
+        rolled_back: bool,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_rolled_back",
+            sqlx::query("UPDATE turtles SET rolled_back = ? WHERE name = ?")
+                .bind(rolled_back)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
+    }
 
-        let fuel_level = row.try_get("fuel")?;
-        let fuel = Fuel {
-            level: fuel_level,
-            max: turtle_type.get_max_fuel(),
-        };
+    ////////////////////////////////////////////////////
+    // Home
+    ////////////////////////////////////////////////////
 
-        let turtle = scheme::Turtle {
-            name,
-            coordinates,
-            heading,
-            turtle_type,
-            fuel,
-        };
+    /// Gets the name of the waypoint the turtle goes back to.
+    pub async fn get_home(&self) -> Option<String> {
+        let row = time_query(
+            &self.metrics,
+            "get_home",
+            sqlx::query("SELECT home FROM turtles WHERE name = ?")
+                .bind(self.name)
+                .fetch_one(&self.pool),
+        )
+        .await
+        .ok()?;
+
+        row.try_get(0).ok()?
+    }
 
-        turtles.push(turtle);
+    pub async fn set_home(&self, waypoint: Option<&str>) -> Result<SqliteQueryResult, sqlx::Error> {
+        time_query(
+            &self.metrics,
+            "set_home",
+            sqlx::query("UPDATE turtles SET home = ? WHERE name = ?")
+                .bind(waypoint)
+                .bind(self.name)
+                .execute(&self.pool),
+        )
+        .await
     }
+}
 
-    Ok(turtles)
+/// Gets every turtle in the database. Rows that can't be read are logged and skipped.
+pub async fn get_turtles(
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<Vec<scheme::Turtle>, sqlx::Error> {
+    let rows = time_query(
+        metrics,
+        "get_turtles",
+        sqlx::query("SELECT * FROM turtles").fetch_all(pool),
+    )
+    .await?;
+
+    // One bad row shouldn't stop every other turtle loading.
+    Ok(rows
+        .iter()
+        .filter_map(|row| match turtle_from_row(row) {
+            Ok(turtle) => Some(turtle),
+            Err(e) => {
+                error!("Skipping turtle that could not be read from the database {e}");
+                None
+            }
+        })
+        .collect())
 }
 
-pub async fn turtle_exists(name: &str, pool: &SqlitePool) -> bool {
-    let row = sqlx::query("SELECT name FROM turtles WHERE name = ?")
-        .bind(name)
-        .fetch_one(pool)
-        .await;
+/// Reads a whole row of the turtles table.
+fn turtle_from_row(row: &SqliteRow) -> Result<scheme::Turtle, sqlx::Error> {
+    let name: String = row.try_get("name")?;
+
+    let x: i64 = row.try_get("x")?;
+    let y: i64 = row.try_get("y")?;
+    let z: i64 = row.try_get("z")?;
+    let coordinates = Coordinates { x, y, z };
+    let dimension = dimension_from_row(row)?;
+
+    let heading: &str = row.try_get("heading")?;
+    let heading = Heading::from_str(heading)
+        .ok_or_else(|| sqlx::Error::Decode(format!("unknown heading {heading}").into()))?;
+
+    let turtle_type: &str = row.try_get("type")?;
+    let turtle_type = TurtleType::from_str(turtle_type)
+        .ok_or_else(|| sqlx::Error::Decode(format!("unknown turtle type {turtle_type}").into()))?;
+
+    let fuel_level = row.try_get("fuel")?;
+    let fuel = Fuel {
+        level: fuel_level,
+        max: turtle_type.get_max_fuel(),
+    };
+
+    Ok(scheme::Turtle {
+        name,
+        coordinates,
+        dimension,
+        heading,
+        turtle_type,
+        fuel,
+        upgrades: upgrades_from_row(row)?,
+    })
+}
 
-    match row {
-        Ok(r) => !r.is_empty(),
-        Err(_e) => {
-            error!("Problem checking if turtle {name} exists");
-            false
-        }
-    }
+fn upgrades_from_row(row: &SqliteRow) -> Result<Upgrades, sqlx::Error> {
+    Ok(Upgrades {
+        left: row.try_get("left_upgrade")?,
+        right: row.try_get("right_upgrade")?,
+    })
+}
+
+fn dimension_from_row(row: &SqliteRow) -> Result<Dimension, sqlx::Error> {
+    Ok(Dimension {
+        name: row.try_get("dimension")?,
+        world: row.try_get("world")?,
+    })
 }
 
 pub async fn add_turtle(
     name: &str,
     coordinates: Coordinates,
+    dimension: &Dimension,
+    heading: Heading,
+    turtle_type: TurtleType,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "add_turtle",
+        sqlx::query(
+            "INSERT INTO turtles\
+        (name, x, y, z, dimension, world, heading, type, fuel) \
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, 0)",
+        )
+        .bind(name)
+        .bind(coordinates.x)
+        .bind(coordinates.y)
+        .bind(coordinates.z)
+        .bind(dimension.name.as_str())
+        .bind(dimension.world.as_deref())
+        .bind(heading.as_str())
+        .bind(turtle_type.as_str())
+        .execute(pool),
+    )
+    .await
+}
+
+/// Adds a turtle that connected without being added first.
+/// Its position is marked unverified until it is confirmed.
+#[allow(clippy::too_many_arguments)]
+pub async fn register_turtle(
+    name: &str,
+    coordinates: Coordinates,
+    dimension: &Dimension,
     heading: Heading,
     turtle_type: TurtleType,
+    fuel: u32,
     pool: &SqlitePool,
+    metrics: &Metrics,
 ) -> Result<SqliteQueryResult, sqlx::Error> {
-    sqlx::query(
-        "INSERT INTO turtles\
-        (name, x, y, z, heading, type, fuel) \
-        VALUES (?, ?, ?, ?, ?, ?, 0)",
+    time_query(
+        metrics,
+        "register_turtle",
+        sqlx::query(
+            "INSERT INTO turtles\
+        (name, x, y, z, dimension, world, heading, type, fuel, position_verified) \
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 0)",
+        )
+        .bind(name)
+        .bind(coordinates.x)
+        .bind(coordinates.y)
+        .bind(coordinates.z)
+        .bind(dimension.name.as_str())
+        .bind(dimension.world.as_deref())
+        .bind(heading.as_str())
+        .bind(turtle_type.as_str())
+        .bind(fuel)
+        .execute(pool),
     )
-    .bind(name)
-    .bind(coordinates.x)
-    .bind(coordinates.y)
-    .bind(coordinates.z)
-    .bind(heading.as_str())
-    .bind(turtle_type.as_str())
-    .execute(pool)
     .await
 }
+
+/// Seconds since the unix epoch.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,109 @@
+use sqlx::sqlite::{SqliteQueryResult, SqliteRow};
+use sqlx::{Row, SqlitePool};
+
+use crate::metrics::{time_query, Metrics};
+use crate::scheme::{Coordinates, Dimension, Heading, Waypoint};
+
+/// Stores a waypoint, replacing any waypoint with the same name.
+pub async fn set_waypoint(
+    waypoint: &Waypoint,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<SqliteQueryResult, sqlx::Error> {
+    time_query(
+        metrics,
+        "set_waypoint",
+        sqlx::query(
+            "INSERT OR REPLACE INTO waypoints \
+            (name, x, y, z, heading, dimension, world) \
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(waypoint.name.as_str())
+        .bind(waypoint.position.x)
+        .bind(waypoint.position.y)
+        .bind(waypoint.position.z)
+        .bind(waypoint.heading.as_str())
+        .bind(waypoint.dimension.name.as_str())
+        .bind(waypoint.dimension.world.as_deref())
+        .execute(pool),
+    )
+    .await
+}
+
+/// Removes a waypoint and takes it away from every turtle that had it as home.
+/// Returns whether there was a waypoint with the name.
+pub async fn remove_waypoint(
+    name: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<bool, sqlx::Error> {
+    time_query(
+        metrics,
+        "clear_homes",
+        sqlx::query("UPDATE turtles SET home = NULL WHERE home = ?")
+            .bind(name)
+            .execute(pool),
+    )
+    .await?;
+
+    let result = time_query(
+        metrics,
+        "remove_waypoint",
+        sqlx::query("DELETE FROM waypoints WHERE name = ?")
+            .bind(name)
+            .execute(pool),
+    )
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_waypoint(
+    name: &str,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<Option<Waypoint>, sqlx::Error> {
+    let row = time_query(
+        metrics,
+        "get_waypoint",
+        sqlx::query("SELECT * FROM waypoints WHERE name = ?")
+            .bind(name)
+            .fetch_optional(pool),
+    )
+    .await?;
+
+    row.as_ref().map(waypoint_from_row).transpose()
+}
+
+/// Gets every waypoint sorted by name.
+pub async fn get_waypoints(
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<Vec<Waypoint>, sqlx::Error> {
+    let rows = time_query(
+        metrics,
+        "get_waypoints",
+        sqlx::query("SELECT * FROM waypoints ORDER BY name").fetch_all(pool),
+    )
+    .await?;
+
+    rows.iter().map(waypoint_from_row).collect()
+}
+
+fn waypoint_from_row(row: &SqliteRow) -> Result<Waypoint, sqlx::Error> {
+    let heading: String = row.try_get("heading")?;
+    Ok(Waypoint {
+        name: row.try_get("name")?,
+        position: Coordinates {
+            x: row.try_get("x")?,
+            y: row.try_get("y")?,
+            z: row.try_get("z")?,
+        },
+        heading: Heading::from_str(heading.as_str())
+            .ok_or_else(|| sqlx::Error::Decode(format!("unknown heading {heading}").into()))?,
+        dimension: Dimension {
+            name: row.try_get("dimension")?,
+            world: row.try_get("world")?,
+        },
+    })
+}
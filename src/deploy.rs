@@ -0,0 +1,55 @@
+/// A version of startup.lua that can be pushed to turtles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    pub source: String,
+
+    /// Hash of `source`. Turtles report it during the handshake.
+    pub version: String,
+}
+
+impl Script {
+    pub fn new(source: impl Into<String>) -> Self {
+        let source = source.into();
+        let version = version_hash(source.as_str());
+        Script { source, version }
+    }
+
+    /// The startup.lua this server was built with.
+    pub fn embedded() -> Self {
+        Script::new(include_str!("../scripts/startup.lua"))
+    }
+
+    /// Whether a turtle reporting `version` should be sent this script.
+    /// Turtles that don't report a version were installed by hand and are always outdated.
+    pub fn is_newer_than(&self, version: Option<&str>) -> bool {
+        version != Some(self.version.as_str())
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script::embedded()
+    }
+}
+
+/// 64 bit FNV-1a hash of `source` as hex.
+/// Used instead of std's hasher as its output can change between Rust versions.
+fn version_hash(source: &str) -> String {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let hash = source
+        .bytes()
+        .fold(OFFSET, |hash, b| (hash ^ b as u64).wrapping_mul(PRIME));
+    format!("{hash:016x}")
+}
+
+/// Splits the message a turtle sends at the start of the handshake into its computer id and the
+/// version of its script. Turtles from before deployment only send their id.
+pub fn parse_id_message(message: &str) -> Option<(u64, Option<String>)> {
+    let mut parts = message.split_whitespace();
+    let id = parts.next()?.parse::<f64>().ok()? as u64;
+    let version = parts.next().map(str::to_string);
+
+    Some((id, version))
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::scheme::{Coordinates, Dimension, Direction, Heading, Tool};
+use crate::selector::Selector;
+use crate::turtle_manager::{DispatchConfig, Turtle, TurtleManagerHandle};
+use crate::turtle_scheme::TurtleCommand;
+
+/// Work big enough to share between turtles.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Digs out every block in the box between two corners. Only turtles in `dimension` are
+    /// given any of it.
+    Excavate {
+        from: Coordinates,
+        to: Coordinates,
+
+        #[serde(default)]
+        dimension: Dimension,
+    },
+
+    /// Runs each list of commands on whichever turtle is free.
+    Tasks { tasks: Vec<Vec<TurtleCommand>> },
+}
+
+impl Job {
+    /// Splits the job into chunks of up to `size` tasks.
+    /// Excavations are split into columns row by row with every other row reversed so the
+    /// columns in a chunk are next to each other.
+    fn chunks(&self, size: usize) -> Vec<Chunk> {
+        let tasks: Vec<Task> = match self {
+            Job::Excavate { from, to, .. } => {
+                let (top, bottom) = (from.y.max(to.y), from.y.min(to.y));
+                let xs: Vec<i64> = (from.x.min(to.x)..=from.x.max(to.x)).collect();
+                (from.z.min(to.z)..=from.z.max(to.z))
+                    .enumerate()
+                    .flat_map(|(row, z)| {
+                        let mut row_xs = xs.clone();
+                        if row % 2 == 1 {
+                            row_xs.reverse();
+                        }
+                        row_xs
+                            .into_iter()
+                            .map(move |x| Task::Column { x, z, top, bottom })
+                    })
+                    .collect()
+            }
+            Job::Tasks { tasks } => tasks.iter().cloned().map(Task::Commands).collect(),
+        };
+
+        tasks
+            .chunks(size.max(1))
+            .map(|tasks| Chunk {
+                tasks: tasks.iter().cloned().collect(),
+                attempts: 0,
+            })
+            .collect()
+    }
+
+    /// Dimension the turtles working on the job have to be in. None if it does not matter.
+    fn dimension(&self) -> Option<Dimension> {
+        match self {
+            Job::Excavate { dimension, .. } => Some(dimension.clone()),
+            Job::Tasks { .. } => None,
+        }
+    }
+
+    /// Whether only admins can start the job because one of its commands needs an admin.
+    pub fn needs_admin(&self) -> bool {
+        match self {
+            Job::Excavate { .. } => false,
+            Job::Tasks { tasks } => tasks.iter().flatten().any(TurtleCommand::needs_admin),
+        }
+    }
+
+    /// Tool the turtles working on the job have to have equipped. None if it does not matter.
+    fn tool(&self) -> Option<Tool> {
+        match self {
+            Job::Excavate { .. } => Some(Tool::Pickaxe),
+            Job::Tasks { .. } => None,
+        }
+    }
+}
+
+/// How far along a job is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobProgress {
+    pub id: u64,
+
+    /// Tasks in the job. Each column of an excavation is a task.
+    pub total: usize,
+    pub done: usize,
+
+    /// Tasks given up on after every turtle given them failed.
+    pub failed: usize,
+
+    /// Turtles working on a chunk of the job.
+    pub turtles: Vec<String>,
+
+    pub finished: bool,
+}
+
+/// Part of a job handed to a single turtle.
+struct Chunk {
+    /// Tasks left to do.
+    tasks: VecDeque<Task>,
+
+    /// Times a turtle has failed to finish the chunk.
+    attempts: u32,
+}
+
+impl Chunk {
+    /// Fuel needed for the next task, which is all a turtle has to have to be given the chunk.
+    fn fuel_needs(&self) -> FuelNeeds {
+        self.tasks.front().map(Task::fuel_needs).unwrap_or_default()
+    }
+}
+
+/// Fuel a turtle needs for a task on top of the minimum it has to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FuelNeeds {
+    /// Where the task starts. Getting there takes fuel too.
+    pub start: Option<Coordinates>,
+
+    /// Moves the task takes from its start.
+    pub moves: u32,
+}
+
+impl FuelNeeds {
+    /// Gets the fuel a turtle at `position` needs.
+    pub fn from(&self, position: Coordinates) -> u32 {
+        let to_start = self.start.map_or(0, |s| distance(position, s) as u32);
+        self.moves + to_start
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Task {
+    /// Digs out a column from `top` down to `bottom`. The turtle ends up back at the top.
+    Column {
+        x: i64,
+        z: i64,
+        top: i64,
+        bottom: i64,
+    },
+
+    Commands(Vec<TurtleCommand>),
+}
+
+impl Task {
+    /// Commands don't say how far they move the turtle so they only need the minimum.
+    fn fuel_needs(&self) -> FuelNeeds {
+        match *self {
+            Task::Column { x, z, top, bottom } => FuelNeeds {
+                start: Some(Coordinates { x, y: top, z }),
+                moves: 2 * (top - bottom) as u32,
+            },
+            Task::Commands(_) => FuelNeeds::default(),
+        }
+    }
+}
+
+/// Splits `job` into chunks and hands them to idle turtles `target` picks out with enough fuel
+/// for their first task until every chunk is done or given up on. A chunk is handed out again if
+/// its turtle disconnects, runs low on fuel, fills its inventory or fails. Turtles low on fuel or
+/// with a full inventory are sent home. Only failures count towards giving up on a chunk, not turtles
+/// turning it down before starting a task. Progress is sent to the manager after every change.
+pub async fn run(
+    manager: TurtleManagerHandle,
+    id: u64,
+    job: Job,
+    target: Selector,
+    config: DispatchConfig,
+) {
+    let mut waiting: VecDeque<Chunk> = job.chunks(config.chunk_size).into();
+    let mut progress = JobProgress {
+        id,
+        total: waiting.iter().map(|c| c.tasks.len()).sum(),
+        done: 0,
+        failed: 0,
+        turtles: vec![],
+        finished: false,
+    };
+    info!("Starting job {id} with {} tasks", progress.total);
+    manager.report_job(progress.clone()).await;
+
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let mut workers = FuturesUnordered::new();
+    // Turtles sent home stay claimed until the job finishes so they are not handed another chunk
+    // of it.
+    let mut sent_home = vec![];
+    let mut poll = tokio::time::interval(config.poll_interval);
+    while !waiting.is_empty() || !workers.is_empty() {
+        tokio::select! {
+            _ = poll.tick(), if !waiting.is_empty() => {
+                let needs = waiting.iter().map(Chunk::fuel_needs).collect();
+                let mut claimed = manager
+                    .claim_idle(id, target.clone(), job.dimension(), job.tool(), config.min_fuel, needs)
+                    .await;
+                if claimed.is_empty() {
+                    continue;
+                }
+                // Taken out from the back so the indexes of the rest stay the same.
+                claimed.sort_by_key(|(i, _)| std::cmp::Reverse(*i));
+                for (i, turtle) in claimed {
+                    let chunk = match waiting.remove(i) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    info!("{} is working on job {id}", turtle.get_name());
+                    progress.turtles.push(turtle.get_name().to_string());
+                    workers.push(work(
+                        turtle,
+                        chunk,
+                        manager.clone(),
+                        config.clone(),
+                        done_tx.clone(),
+                    ));
+                }
+                manager.report_job(progress.clone()).await;
+            }
+            Some(()) = done_rx.recv() => {
+                progress.done += 1;
+                manager.report_job(progress.clone()).await;
+            }
+            Some((name, mut chunk, result)) = workers.next() => {
+                progress.turtles.retain(|t| *t != name);
+                // Both are checked before a task is started so the chunk was not attempted.
+                let refused =
+                    matches!(result, Err(Error::OutOfFuel { .. } | Error::InventoryFull { .. }));
+                if refused {
+                    sent_home.push(name.clone());
+                    let manager = manager.clone();
+                    let name = name.clone();
+                    tokio::spawn(async move { manager.return_home(name).await });
+                } else {
+                    manager.release(name.as_str()).await;
+                }
+                if let Err(e) = result {
+                    if !refused {
+                        chunk.attempts += 1;
+                    }
+                    if chunk.attempts > config.retries {
+                        warn!("Giving up on {} tasks of job {id}: {e}", chunk.tasks.len());
+                        progress.failed += chunk.tasks.len();
+                    } else {
+                        warn!("{name} stopped working on job {id}: {e}. Handing out its chunk");
+                        waiting.push_front(chunk);
+                    }
+                }
+                manager.report_job(progress.clone()).await;
+            }
+        }
+    }
+
+    for name in sent_home {
+        manager.release(name).await;
+    }
+
+    // Tasks finished by the last workers may still be waiting to be counted.
+    while let Ok(()) = done_rx.try_recv() {
+        progress.done += 1;
+    }
+    progress.finished = true;
+    info!(
+        "Finished job {id}. {} tasks done and {} failed",
+        progress.done, progress.failed
+    );
+    manager.report_job(progress).await;
+}
+
+/// Has a turtle do the tasks in a chunk, sending on `done` after each one.
+/// Gives the chunk back with the tasks it did not finish.
+async fn work(
+    turtle: Turtle,
+    mut chunk: Chunk,
+    manager: TurtleManagerHandle,
+    config: DispatchConfig,
+    done: mpsc::UnboundedSender<()>,
+) -> (String, Chunk, Result<(), Error>) {
+    let name = turtle.get_name().to_string();
+    let result = async {
+        let mut worker = Worker::new(turtle, manager, config).await?;
+        while let Some(task) = chunk.tasks.front() {
+            worker.run(task).await?;
+            chunk.tasks.pop_front();
+            let _ = done.send(());
+        }
+
+        Ok(())
+    }
+    .await;
+
+    (name, chunk, result)
+}
+
+/// A turtle working on a chunk of a job.
+struct Worker {
+    turtle: Turtle,
+    manager: TurtleManagerHandle,
+    config: DispatchConfig,
+    position: Coordinates,
+    heading: Heading,
+    fuel: u32,
+}
+
+impl Worker {
+    async fn new(
+        turtle: Turtle,
+        manager: TurtleManagerHandle,
+        config: DispatchConfig,
+    ) -> Result<Self, Error> {
+        let info = turtle
+            .get_info()
+            .await
+            .ok_or_else(|| Error::UnknownPosition {
+                name: turtle.get_name().to_string(),
+            })?;
+
+        Ok(Worker {
+            turtle,
+            manager,
+            config,
+            position: info.coordinates,
+            heading: info.heading,
+            fuel: info.fuel.level,
+        })
+    }
+
+    /// Does a task. Fails before starting if the turtle would be left with less than the minimum
+    /// fuel or has nowhere to put what it digs.
+    async fn run(&mut self, task: &Task) -> Result<(), Error> {
+        self.check_fuel(task.fuel_needs().from(self.position))?;
+        match *task {
+            Task::Column { x, z, top, bottom } => {
+                let start = Coordinates { x, y: top, z };
+                self.check_inventory().await?;
+
+                self.go_to(start).await?;
+                for y in (bottom..top).rev() {
+                    self.step(Coordinates { y, ..start }).await?;
+                }
+                for y in bottom + 1..=top {
+                    self.step(Coordinates { y, ..start }).await?;
+                }
+            }
+            Task::Commands(ref commands) => {
+                for command in commands {
+                    self.turtle.send(command.clone()).await?;
+                }
+                self.sync().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_fuel(&self, needed: u32) -> Result<(), Error> {
+        if self.fuel < self.config.min_fuel + needed {
+            return Err(Error::OutOfFuel {
+                name: self.turtle.get_name().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Turtles that can't list their inventory are assumed to have room.
+    async fn check_inventory(&self) -> Result<(), Error> {
+        match self.turtle.list_items().await {
+            Ok(inventory) if inventory.is_full() => Err(Error::InventoryFull {
+                name: self.turtle.get_name().to_string(),
+            }),
+            Ok(_) | Err(Error::Unsupported { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Digs its way to `destination`, climbing first and dropping down last.
+    async fn go_to(&mut self, destination: Coordinates) -> Result<(), Error> {
+        while self.position != destination {
+            let next = self.position.step_towards(destination);
+            self.step(next).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Digs out the block at `next` and moves into it. Fails if the turtle did not get there.
+    async fn step(&mut self, next: Coordinates) -> Result<(), Error> {
+        let (direction, heading) = self
+            .turtle
+            .turn_towards(self.position, self.heading, next)
+            .await?;
+        self.heading = heading;
+        let dig = match direction {
+            Direction::Up => TurtleCommand::DigUp,
+            Direction::Down => TurtleCommand::DigDown,
+            _ => TurtleCommand::Dig,
+        };
+
+        self.turtle.send(dig).await?;
+        self.manager
+            .move_turtle(self.turtle.get_name(), direction)
+            .await?;
+        if self.sync().await? != next {
+            return Err(Error::Blocked {
+                name: self.turtle.get_name().to_string(),
+                position: next,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the turtle to run everything sent to it and updates its position and fuel.
+    async fn sync(&mut self) -> Result<Coordinates, Error> {
+        let info = self.turtle.sync(&self.manager).await?;
+        self.position = info.coordinates;
+        self.fuel = info.fuel.level;
+        Ok(info.coordinates)
+    }
+}
+
+fn distance(a: Coordinates, b: Coordinates) -> i64 {
+    (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs()
+}
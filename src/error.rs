@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crafting::ItemCount;
+use crate::scheme::{Coordinates, Dimension};
+use crate::turtle_manager::{AlreadyDisconnectedError, NotConnectedError, NotPausedError};
+
+/// Why something asked of the wrangler or a turtle did not happen.
+/// Sent to clients as is so they can tell what is worth retrying.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Error {
+    /// No turtle has the name.
+    UnknownTurtle { name: String },
+
+    /// The turtle is not connected or its connection closed before it finished.
+    Disconnected,
+
+    /// The turtle reconnected and the connection that was working on it was dropped.
+    Replaced,
+
+    /// The turtle's position is not known so nothing next to it can be found.
+    UnknownPosition { name: String },
+
+    /// The turtle did not answer in time.
+    TimedOut,
+
+    /// The turtle tried and failed. Holds the reason the turtle gave.
+    TurtleFailed { reason: String },
+
+    /// No recipe makes the item.
+    UnknownRecipe { item: String },
+
+    /// The turtle does not have enough items to craft. Holds how many more of each it needs.
+    MissingItems { items: Vec<ItemCount> },
+
+    /// The turtle can't craft for a reason other than missing items.
+    CannotCraft { reason: String },
+
+    /// The turtle's startup.lua does not handle the command or request. Deploying a newer script
+    /// may add it.
+    Unsupported { name: String, command: String },
+
+    /// Evaluating Lua on turtles is turned off in the config.
+    EvalDisabled,
+
+    /// The client has to log in as an admin first.
+    NotAdmin,
+
+    /// The turtle could not move into `position`, or no way there was found, so the turtles it was
+    /// moving with went back.
+    Blocked { name: String, position: Coordinates },
+
+    /// `position` is reserved for or taken by another turtle. `by` is None for a turtle that was
+    /// only seen by an inspection.
+    Reserved {
+        position: Coordinates,
+        by: Option<String>,
+    },
+
+    /// The turtle does not have enough fuel left to do what it was asked.
+    OutOfFuel { name: String },
+
+    /// Every slot in the turtle's inventory has items in it.
+    InventoryFull { name: String },
+
+    /// No waypoint has the name.
+    UnknownWaypoint { name: String },
+
+    /// The turtle has not been given a home.
+    NoHome { name: String },
+
+    /// The turtle is not in `dimension` so it can't get to a waypoint or formation in it.
+    WrongDimension { name: String, dimension: Dimension },
+
+    /// A message was not what the protocol expected.
+    Protocol { message: String },
+
+    /// A database query failed.
+    Database { message: String },
+
+    /// The turtle's script was rolled back so it is only deployed to when forced.
+    RolledBack { name: String },
+
+    /// The turtle was not paused so it could not be resumed.
+    NotPaused,
+
+    /// The turtle was paused or stopped before it finished.
+    Paused,
+
+    /// The part of the wrangler that was needed has shut down.
+    Shutdown,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownTurtle { name } => write!(f, "There is no turtle named {name}"),
+            Error::Disconnected => write!(f, "Turtle is disconnected"),
+            Error::Replaced => write!(f, "Turtle reconnected before finishing"),
+            Error::UnknownPosition { name } => write!(f, "Position of {name} is not known"),
+            Error::TimedOut => write!(f, "Turtle did not answer in time"),
+            Error::TurtleFailed { reason } => write!(f, "Turtle failed: {reason}"),
+            Error::UnknownRecipe { item } => write!(f, "There is no recipe for {item}"),
+            Error::MissingItems { items } => {
+                write!(f, "Missing items:")?;
+                for item in items {
+                    write!(f, " {} {}", item.count, item.name)?;
+                }
+                Ok(())
+            }
+            Error::CannotCraft { reason } => write!(f, "Can't craft: {reason}"),
+            Error::Unsupported { name, command } => {
+                write!(f, "{name} does not support {command}")
+            }
+            Error::EvalDisabled => write!(f, "Evaluating Lua on turtles is turned off"),
+            Error::NotAdmin => write!(f, "Only admins can do that"),
+            Error::Blocked { name, position } => {
+                write!(f, "{name} is blocked from moving to {position}")
+            }
+            Error::Reserved { position, by } => match by {
+                Some(by) => write!(f, "{position} is reserved for {by}"),
+                None => write!(f, "{position} is taken by another turtle"),
+            },
+            Error::OutOfFuel { name } => write!(f, "{name} is low on fuel"),
+            Error::InventoryFull { name } => write!(f, "{name} has a full inventory"),
+            Error::UnknownWaypoint { name } => write!(f, "There is no waypoint named {name}"),
+            Error::NoHome { name } => write!(f, "{name} does not have a home"),
+            Error::WrongDimension { name, dimension } => write!(f, "{name} is not in {dimension}"),
+            Error::Protocol { message } => write!(f, "Protocol error: {message}"),
+            Error::Database { message } => write!(f, "Database error: {message}"),
+            Error::RolledBack { name } => {
+                write!(
+                    f,
+                    "{name} was rolled back so deploying to it has to be forced"
+                )
+            }
+            Error::NotPaused => write!(f, "Turtle is not paused"),
+            Error::Paused => write!(f, "Turtle was paused before it finished"),
+            Error::Shutdown => write!(f, "Turtle wrangler is shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        Error::Database {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<AlreadyDisconnectedError> for Error {
+    fn from(_: AlreadyDisconnectedError) -> Self {
+        Error::Disconnected
+    }
+}
+
+impl From<NotConnectedError> for Error {
+    fn from(_: NotConnectedError) -> Self {
+        Error::Disconnected
+    }
+}
+
+impl From<NotPausedError> for Error {
+    fn from(_: NotPausedError) -> Self {
+        Error::NotPaused
+    }
+}
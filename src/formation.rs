@@ -0,0 +1,316 @@
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+
+use crate::error::Error;
+use crate::route_planner::{self, distance};
+use crate::scheme::{Coordinates, Direction, Heading};
+use crate::turtle_manager::{FormationConfig, Turtle, TurtleManagerHandle};
+
+/// Shape a group of turtles takes at a destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum Formation {
+    /// Side by side, like turtles mining a wide tunnel.
+    Line,
+
+    /// One behind another.
+    Column,
+
+    /// Rows of `width` turtles side by side, each row behind the last.
+    Grid { width: u32 },
+}
+
+impl Formation {
+    /// Gets the position of each place in the formation. The first is `destination` and the rest
+    /// fill to its right and then behind it when facing `heading`.
+    pub fn slots(
+        &self,
+        count: usize,
+        destination: Coordinates,
+        heading: Heading,
+    ) -> Vec<Coordinates> {
+        let width = match self {
+            Formation::Line => count.max(1),
+            Formation::Column => 1,
+            Formation::Grid { width } => (*width as usize).max(1),
+        };
+
+        (0..count)
+            .map(|i| {
+                let across = (0..i % width).fold(destination, |p, _| p.step(heading.right()));
+                (0..i / width).fold(across, |p, _| p.step(heading.opposite()))
+            })
+            .collect()
+    }
+
+    /// Parses `line`, `column` or `grid:<width>`.
+    pub fn from_str(s: &str) -> Option<Formation> {
+        match s.split_once(':') {
+            Some(("grid", width)) => Some(Formation::Grid {
+                width: width.parse().ok()?,
+            }),
+            None if s == "line" => Some(Formation::Line),
+            None if s == "column" => Some(Formation::Column),
+            _ => None,
+        }
+    }
+}
+
+/// Moves the named turtles into `formation` at `destination` facing `heading`.
+///
+/// Paths that keep the turtles out of each other's way are planned up front. Turtles then move a
+/// time step of their paths at a time and every turtle finishes a step before any starts the
+/// next. A turtle blocked by anything else is retried as set in `config`. If it stays blocked
+/// every turtle goes back the way it came.
+///
+/// `started` is taken and answered once the paths are planned and the turtles set off. It is left
+/// for the caller to answer with the error if the formation can't be moved at all.
+pub async fn move_formation(
+    manager: &TurtleManagerHandle,
+    names: &[String],
+    formation: Formation,
+    destination: Coordinates,
+    heading: Heading,
+    config: &FormationConfig,
+    started: &mut Option<oneshot::Sender<Result<(), Error>>>,
+) -> Result<(), Error> {
+    let mut members: Vec<Member> = vec![];
+    let mut dimension = None;
+    for name in names {
+        if members.iter().any(|m| m.turtle.get_name() == name) {
+            continue;
+        }
+        let turtle = manager
+            .get_turtle(name.as_str())
+            .await
+            .ok_or_else(|| Error::UnknownTurtle { name: name.clone() })?;
+        let info = turtle
+            .get_info()
+            .await
+            .ok_or_else(|| Error::UnknownPosition { name: name.clone() })?;
+        // Positions only line up within a dimension so the whole formation has to be in one.
+        let dimension = dimension.get_or_insert_with(|| info.dimension.clone());
+        if info.dimension != *dimension {
+            return Err(Error::WrongDimension {
+                name: name.clone(),
+                dimension: dimension.clone(),
+            });
+        }
+        members.push(Member::new(turtle, info.coordinates, info.heading));
+    }
+
+    let starts: Vec<Coordinates> = members.iter().map(|m| m.position).collect();
+    let slots = assign(
+        &starts,
+        &formation.slots(members.len(), destination, heading),
+    );
+    let moves: Vec<(Coordinates, Coordinates)> = starts.into_iter().zip(slots).collect();
+    let paths = route_planner::plan(&moves).map_err(|i| Error::Blocked {
+        name: members[i].turtle.get_name().to_string(),
+        position: moves[i].1,
+    })?;
+    for (member, path) in members.iter_mut().zip(paths) {
+        member.path = path;
+    }
+
+    info!(
+        "Moving {} turtles in a {:?} to {destination} facing {heading}",
+        members.len(),
+        formation
+    );
+    if let Some(tx) = started.take() {
+        let _ = tx.send(Ok(()));
+    }
+    if let Err((time, e)) = run(&mut members, manager, config).await {
+        warn!("Formation failed: {e}. Moving turtles back");
+        for member in members.iter_mut() {
+            member.retrace(time);
+        }
+        if let Err((_, e)) = run(&mut members, manager, config).await {
+            error!("Problem moving formation back {e}");
+        }
+        return Err(e);
+    }
+
+    for result in join_all(members.iter_mut().map(|m| m.face(heading))).await {
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Moves every member along its path a time step at a time until they have all arrived.
+/// Fails with the time step it was on if a member stays blocked.
+async fn run(
+    members: &mut [Member],
+    manager: &TurtleManagerHandle,
+    config: &FormationConfig,
+) -> Result<(), (usize, Error)> {
+    let steps = members
+        .iter()
+        .map(|m| m.path.len())
+        .max()
+        .unwrap_or_default();
+    for time in 1..steps {
+        // Members that moved stay put while the blocked ones are retried so nobody gets ahead of
+        // the plan.
+        while members.iter().any(|m| m.position != m.at(time)) {
+            let moves = members
+                .iter_mut()
+                .filter(|m| m.position != m.at(time))
+                .map(|m| m.step(m.at(time), manager));
+            for result in join_all(moves).await {
+                result.map_err(|e| (time, e))?;
+            }
+
+            if let Some(member) = members.iter().find(|m| m.blocked > config.retries) {
+                return Err((
+                    time,
+                    Error::Blocked {
+                        name: member.turtle.get_name().to_string(),
+                        position: member.at(time),
+                    },
+                ));
+            }
+            if members.iter().any(|m| m.blocked > 0) {
+                tokio::time::sleep(config.retry_wait).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives each turtle a place in the formation. Closest pairs are matched first, then any two
+/// turtles that would travel less in total by swapping places swap, so turtles don't have to cross
+/// each other's paths.
+fn assign(starts: &[Coordinates], slots: &[Coordinates]) -> Vec<Coordinates> {
+    let mut pairs: Vec<(usize, usize)> = (0..starts.len())
+        .flat_map(|turtle| (0..slots.len()).map(move |slot| (turtle, slot)))
+        .collect();
+    pairs.sort_by_key(|(turtle, slot)| distance(starts[*turtle], slots[*slot]));
+
+    let mut assigned = vec![None; starts.len()];
+    let mut taken = vec![false; slots.len()];
+    for (turtle, slot) in pairs {
+        if assigned[turtle].is_none() && !taken[slot] {
+            assigned[turtle] = Some(slots[slot]);
+            taken[slot] = true;
+        }
+    }
+
+    let mut assigned: Vec<Coordinates> = assigned.into_iter().flatten().collect();
+    let mut swapped = true;
+    while swapped {
+        swapped = false;
+        for a in 0..assigned.len() {
+            for b in a + 1..assigned.len() {
+                let now = distance(starts[a], assigned[a]) + distance(starts[b], assigned[b]);
+                let after = distance(starts[a], assigned[b]) + distance(starts[b], assigned[a]);
+                if after < now {
+                    assigned.swap(a, b);
+                    swapped = true;
+                }
+            }
+        }
+    }
+
+    assigned
+}
+
+/// A turtle moving with a formation.
+struct Member {
+    turtle: Turtle,
+    position: Coordinates,
+    heading: Heading,
+
+    /// Where the turtle should be at each time step starting with where it began.
+    path: Vec<Coordinates>,
+
+    /// Times in a row the turtle has failed to move.
+    blocked: u32,
+}
+
+impl Member {
+    fn new(turtle: Turtle, position: Coordinates, heading: Heading) -> Self {
+        Member {
+            turtle,
+            position,
+            heading,
+            path: vec![position],
+            blocked: 0,
+        }
+    }
+
+    /// Where the turtle should be at `time`. Turtles that have arrived stay where they are.
+    fn at(&self, time: usize) -> Coordinates {
+        self.path
+            .get(time)
+            .or(self.path.last())
+            .copied()
+            .unwrap_or(self.position)
+    }
+
+    /// Moves the turtle into `next` next to it, turning first if it has to.
+    /// Counts it as blocked if it is not there afterwards.
+    async fn step(
+        &mut self,
+        next: Coordinates,
+        manager: &TurtleManagerHandle,
+    ) -> Result<(), Error> {
+        let direction = if self.position.heading_to(next) == Some(self.heading.opposite()) {
+            Direction::Back
+        } else {
+            let (direction, heading) = self
+                .turtle
+                .turn_towards(self.position, self.heading, next)
+                .await?;
+            self.heading = heading;
+            direction
+        };
+        // A turtle outside the formation is in or moving into the block so treat it like any
+        // other block in the way.
+        match manager.reserve(self.turtle.get_name(), next).await {
+            Err(Error::Reserved { .. }) => {
+                self.blocked += 1;
+                return Ok(());
+            }
+            result => result?,
+        }
+        self.turtle.move_turtle(direction).await?;
+
+        if self.sync(manager).await? == next {
+            self.position = next;
+            self.blocked = 0;
+        } else {
+            self.blocked += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Turns the turtle until it faces `heading`.
+    async fn face(&mut self, heading: Heading) -> Result<(), Error> {
+        self.turtle.face(self.heading, heading).await?;
+        self.heading = heading;
+        Ok(())
+    }
+
+    /// Waits for the turtle to run everything sent to it and gets its position.
+    /// The turtle reports its position before it answers the ping so the manager has stored it by
+    /// the time it answers for the turtle.
+    async fn sync(&self, manager: &TurtleManagerHandle) -> Result<Coordinates, Error> {
+        Ok(self.turtle.sync(manager).await?.coordinates)
+    }
+
+    /// Sets the turtle's path back the way it came from the step before `time`.
+    /// Turtles that already made the step at `time` undo it first while the rest wait, so every
+    /// turtle runs its path backwards in step with the others.
+    fn retrace(&mut self, time: usize) {
+        let back = (0..time).rev().map(|t| self.at(t));
+        self.path = std::iter::once(self.position).chain(back).collect();
+        self.blocked = 0;
+    }
+}
@@ -0,0 +1,59 @@
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::scheme::Waypoint;
+use crate::turtle_manager::{HomeConfig, Turtle, TurtleManagerHandle};
+
+/// Moves a turtle to `waypoint` and turns it to the waypoint's heading.
+/// The turtle climbs first if the waypoint is higher, goes along x then z and drops down last so
+/// it spends as little time as it can in the ground around the waypoint. Nothing is dug on the
+/// way. A blocked turtle tries again up to `config.retries` times before failing.
+pub async fn go_to(
+    manager: &TurtleManagerHandle,
+    turtle: &Turtle,
+    waypoint: &Waypoint,
+    config: &HomeConfig,
+) -> Result<(), Error> {
+    let name = turtle.get_name();
+    let info = turtle.sync(manager).await?;
+    if info.dimension != waypoint.dimension {
+        return Err(Error::WrongDimension {
+            name: name.to_string(),
+            dimension: waypoint.dimension.clone(),
+        });
+    }
+
+    info!("{name} is going to {}", waypoint.name);
+    let (mut position, mut heading) = (info.coordinates, info.heading);
+    let mut blocked = 0;
+    while position != waypoint.position {
+        let next = position.step_towards(waypoint.position);
+        let (direction, _) = turtle.turn_towards(position, heading, next).await?;
+        let moved = manager.move_turtle(name, direction).await;
+        let info = turtle.sync(manager).await?;
+        (position, heading) = (info.coordinates, info.heading);
+        match moved {
+            Ok(()) if position == next => blocked = 0,
+            Ok(()) | Err(Error::Reserved { .. }) if blocked < config.retries => {
+                blocked += 1;
+                warn!(
+                    "{name} is blocked at {position} on its way to {}",
+                    waypoint.name
+                );
+                tokio::time::sleep(config.retry_wait).await;
+            }
+            Ok(()) => {
+                return Err(Error::Blocked {
+                    name: name.to_string(),
+                    position: next,
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    turtle.face(heading, waypoint.heading).await?;
+    info!("{name} got to {}", waypoint.name);
+
+    Ok(())
+}
@@ -13,8 +13,33 @@ mod client_scheme;
 
 mod command_interpreter;
 
+/// Recipes turtles can craft and how to arrange a turtle's inventory to craft them.
+mod crafting;
+
 mod db;
 
+/// Versions of startup.lua and pushing them to turtles.
+mod deploy;
+
+/// The error type shared by the turtle manager, the command line and clients.
+mod error;
+
+/// Splitting big jobs into chunks and handing them to idle turtles.
+mod dispatcher;
+
+/// Moving groups of turtles together in formations.
+mod formation;
+
+/// Planning paths for groups of turtles that keep them out of each other's way.
+mod route_planner;
+
+/// Sending turtles back to named waypoints.
+mod home;
+
+/// Counters, gauges and histograms about turtles, clients and the database.
+/// Served in the prometheus text format by the metrics acceptor.
+mod metrics;
+
 /// Manages turtle websocket connections.
 mod turtle_manager;
 
@@ -23,13 +48,23 @@ mod turtle_scheme;
 
 mod scheme;
 
+/// Picking out turtles by name, group or tag.
+mod selector;
+
+/// In process tests that run the whole server against simulated turtles and clients.
+#[cfg(test)]
+mod tests;
+
+use std::path::{Path, PathBuf};
+
 use tokio::{runtime::Handle, sync::oneshot};
 
 use crate::client_manager::ClientManagerHandle;
-use tracing::{error, info};
+use crate::crafting::RecipeBook;
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::turtle_manager::TurtleManagerHandle;
+use crate::turtle_manager::{TurtleManagerConfig, TurtleManagerHandle};
 
 #[tokio::main]
 async fn main() {
@@ -63,7 +98,32 @@ async fn start() {
         }
     };
 
-    let turtle_manager = TurtleManagerHandle::new(pool.clone());
+    // Turtles can only craft what is in the RECIPES file if it is set.
+    let recipes = match std::env::var("RECIPES") {
+        Ok(path) => match RecipeBook::load(Path::new(&path)) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Problem loading recipes from {path} {e}");
+                return;
+            }
+        },
+        Err(_) => RecipeBook::default(),
+    };
+
+    // Turtle sessions are only recorded if RECORD_DIR is set.
+    let mut config = TurtleManagerConfig {
+        record_dir: std::env::var("RECORD_DIR").ok().map(PathBuf::from),
+        recipes,
+        ..Default::default()
+    };
+    // Lua can only be evaluated on turtles if ALLOW_EVAL is set. Clients also need to log in
+    // with ADMIN_TOKEN.
+    config.eval.enabled = std::env::var("ALLOW_EVAL").is_ok();
+    if let Err(e) = config.validate() {
+        error!("Invalid turtle manager config {e}");
+        return;
+    }
+    let turtle_manager = TurtleManagerHandle::new(pool, config);
     let turtle_acceptor =
         acceptor::AcceptorHandle::new_websocket("0.0.0.0:8080".to_string(), turtle_manager.clone());
 
@@ -72,18 +132,39 @@ async fn start() {
         "0.0.0.0:8081".to_string(),
         client_manager.clone(),
         turtle_manager.clone(),
-        pool.clone(),
+        std::env::var("ADMIN_TOKEN").ok(),
     );
 
+    let metrics_addr = std::env::var("METRICS_ADDR").unwrap_or("0.0.0.0:8082".to_string());
+    let metrics_acceptor =
+        acceptor::AcceptorHandle::new_metrics(metrics_addr, turtle_manager.metrics().clone());
+
+    if let Some(addr) = turtle_acceptor.local_addr().await {
+        info!("Listening for turtles on {addr}");
+    }
+    if let Some(addr) = client_acceptor.local_addr().await {
+        info!("Listening for clients on {addr}");
+    }
+    if let Some(addr) = metrics_acceptor.local_addr().await {
+        info!("Serving metrics on http://{addr}/metrics");
+    }
+
     let (tx, rx) = oneshot::channel();
     let manager = turtle_manager.clone();
 
     let handle = Handle::current();
-    std::thread::spawn(move || command_interpreter::read_input(tx, manager, handle, pool));
+    std::thread::spawn(move || command_interpreter::read_input(tx, manager, handle));
     rx.await.unwrap();
 
+    // Turtles can take up to the configured shutdown wait to get home so Ctrl+C stops waiting.
+    info!("Sending turtles home. Press Ctrl+C to stop waiting for them");
+    tokio::select! {
+        _ = turtle_manager.return_all_home() => {}
+        _ = tokio::signal::ctrl_c() => warn!("Shutting down without waiting for turtles to get home"),
+    }
     turtle_acceptor.close().await;
     turtle_manager.close().await;
     client_acceptor.close().await;
     client_manager.close().await;
+    metrics_acceptor.close().await;
 }
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds in seconds of the buckets every histogram uses.
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// Counts how many observations fell into each bucket in the same way as a prometheus histogram.
+#[derive(Debug)]
+pub struct Histogram {
+    /// Count of observations for each bucket in BUCKETS. Not cumulative.
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+
+    /// Sum of every observation in microseconds.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+
+        Histogram {
+            buckets: [ZERO; BUCKETS.len()],
+            count: ZERO,
+            sum: ZERO,
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|b| seconds <= *b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Writes the histogram's series. `labels` are added to every series.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        let mut cumulative = 0;
+        for (bucket, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bucket}\"}} {cumulative}"
+            );
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {count}"
+        );
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name}_sum {sum}");
+            let _ = writeln!(out, "{name}_count {count}");
+        } else {
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+            let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+/// Why a turtle timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimeoutKind {
+    /// The turtle did not send an ok for a command in time.
+    Command,
+
+    /// A lock on the turtle's sender was not released in time.
+    Lock,
+
+    /// The turtle did not send a response to a request in time.
+    Request,
+}
+
+impl TimeoutKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TimeoutKind::Command => "command",
+            TimeoutKind::Lock => "lock",
+            TimeoutKind::Request => "request",
+        }
+    }
+}
+
+/// Every metric a turtle manager keeps. Owned by the manager and rendered by the metrics endpoint.
+/// Per turtle metrics are keyed by the turtle's name.
+#[derive(Debug)]
+pub struct Metrics {
+    turtles_connected: AtomicI64,
+    turtles_paused: AtomicI64,
+    turtles_unresponsive: AtomicI64,
+    turtles_disconnected: AtomicI64,
+    clients_connected: AtomicI64,
+
+    turtle_fuel: Mutex<BTreeMap<String, u32>>,
+    queue_depth: Mutex<BTreeMap<String, usize>>,
+    command_retries: Mutex<BTreeMap<String, u64>>,
+    timeouts: Mutex<BTreeMap<(String, TimeoutKind), u64>>,
+
+    /// Time between sending a request and getting its response.
+    request_duration: Histogram,
+
+    /// Time taken by each kind of db query.
+    db_query_duration: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Metrics {
+            turtles_connected: AtomicI64::new(0),
+            turtles_paused: AtomicI64::new(0),
+            turtles_unresponsive: AtomicI64::new(0),
+            turtles_disconnected: AtomicI64::new(0),
+            clients_connected: AtomicI64::new(0),
+            turtle_fuel: Mutex::new(BTreeMap::new()),
+            queue_depth: Mutex::new(BTreeMap::new()),
+            command_retries: Mutex::new(BTreeMap::new()),
+            timeouts: Mutex::new(BTreeMap::new()),
+            request_duration: Histogram::new(),
+            db_query_duration: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_turtle_counts(
+        &self,
+        connected: i64,
+        paused: i64,
+        unresponsive: i64,
+        disconnected: i64,
+    ) {
+        self.turtles_connected.store(connected, Ordering::Relaxed);
+        self.turtles_paused.store(paused, Ordering::Relaxed);
+        self.turtles_unresponsive
+            .store(unresponsive, Ordering::Relaxed);
+        self.turtles_disconnected
+            .store(disconnected, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.clients_connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.clients_connected.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set_fuel(&self, name: &str, fuel: u32) {
+        self.turtle_fuel
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), fuel);
+    }
+
+    pub fn set_queue_depth(&self, name: &str, depth: usize) {
+        self.queue_depth
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), depth);
+    }
+
+    pub fn command_retried(&self, name: &str) {
+        *self
+            .command_retries
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn timed_out(&self, name: &str, kind: TimeoutKind) {
+        *self
+            .timeouts
+            .lock()
+            .unwrap()
+            .entry((name.to_string(), kind))
+            .or_default() += 1;
+    }
+
+    pub fn observe_request(&self, duration: Duration) {
+        self.request_duration.observe(duration);
+    }
+
+    pub fn observe_query(&self, query: &'static str, duration: Duration) {
+        self.db_query_duration
+            .lock()
+            .unwrap()
+            .entry(query)
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Renders every metric in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "turtle_wrangler_turtles",
+            "gauge",
+            "Number of known turtles by connection status.",
+        );
+        for (status, gauge) in [
+            ("connected", &self.turtles_connected),
+            ("paused", &self.turtles_paused),
+            ("unresponsive", &self.turtles_unresponsive),
+            ("disconnected", &self.turtles_disconnected),
+        ] {
+            let _ = writeln!(
+                out,
+                "turtle_wrangler_turtles{{status=\"{status}\"}} {}",
+                gauge.load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "turtle_wrangler_clients_connected",
+            "gauge",
+            "Number of connected clients.",
+        );
+        let _ = writeln!(
+            out,
+            "turtle_wrangler_clients_connected {}",
+            self.clients_connected.load(Ordering::Relaxed)
+        );
+
+        header(
+            &mut out,
+            "turtle_wrangler_turtle_fuel",
+            "gauge",
+            "Fuel level last reported by each turtle.",
+        );
+        for (name, fuel) in self.turtle_fuel.lock().unwrap().iter() {
+            let _ = writeln!(out, "turtle_wrangler_turtle_fuel{{name=\"{name}\"}} {fuel}");
+        }
+
+        header(
+            &mut out,
+            "turtle_wrangler_command_queue_depth",
+            "gauge",
+            "Commands waiting to be sent to each turtle.",
+        );
+        for (name, depth) in self.queue_depth.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "turtle_wrangler_command_queue_depth{{name=\"{name}\"}} {depth}"
+            );
+        }
+
+        header(
+            &mut out,
+            "turtle_wrangler_command_retries_total",
+            "counter",
+            "Commands sent again because the turtle did not send an ok in time.",
+        );
+        for (name, retries) in self.command_retries.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "turtle_wrangler_command_retries_total{{name=\"{name}\"}} {retries}"
+            );
+        }
+
+        header(
+            &mut out,
+            "turtle_wrangler_timeouts_total",
+            "counter",
+            "Timeouts waiting on each turtle by kind.",
+        );
+        for ((name, kind), timeouts) in self.timeouts.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "turtle_wrangler_timeouts_total{{name=\"{name}\",kind=\"{}\"}} {timeouts}",
+                kind.as_str()
+            );
+        }
+
+        header(
+            &mut out,
+            "turtle_wrangler_request_duration_seconds",
+            "histogram",
+            "Time between sending a request to a turtle and getting its response.",
+        );
+        self.request_duration
+            .render(&mut out, "turtle_wrangler_request_duration_seconds", "");
+
+        header(
+            &mut out,
+            "turtle_wrangler_db_query_duration_seconds",
+            "histogram",
+            "Time taken by database queries.",
+        );
+        for (query, histogram) in self.db_query_duration.lock().unwrap().iter() {
+            histogram.render(
+                &mut out,
+                "turtle_wrangler_db_query_duration_seconds",
+                format!("query=\"{query}\"").as_str(),
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+}
+
+/// Runs a db query and records how long it took under `query` in `metrics`.
+pub async fn time_query<F: Future>(metrics: &Metrics, query: &'static str, future: F) -> F::Output {
+    let start = Instant::now();
+    let output = future.await;
+    metrics.observe_query(query, start.elapsed());
+
+    output
+}
@@ -0,0 +1,258 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::scheme::Coordinates;
+
+/// Blocks searched around the starts and goals so turtles can step aside or go over each other.
+const MARGIN: i64 = 2;
+
+/// Blocks planned turtles are in at each time step.
+///
+/// A turtle keeps its block taken for the step after it leaves as turtles in a formation move at
+/// the same time and the one in front may not be gone yet. Once a turtle reaches its goal the
+/// goal stays taken.
+#[derive(Debug, Default)]
+pub struct ReservationTable {
+    taken: HashSet<(Coordinates, usize)>,
+
+    /// Goals keyed by the time step the turtle stops there.
+    parked: HashMap<Coordinates, usize>,
+
+    /// Last time step each block is taken before it is free for good.
+    latest: HashMap<Coordinates, usize>,
+}
+
+impl ReservationTable {
+    pub fn new() -> Self {
+        ReservationTable::default()
+    }
+
+    /// Whether a turtle can be in `position` at `time` and still be there for the step after
+    /// while it leaves.
+    pub fn is_free(&self, position: Coordinates, time: usize) -> bool {
+        (time..=time + 1).all(|t| {
+            !self.taken.contains(&(position, t))
+                && self.parked.get(&position).is_none_or(|p| t < *p)
+        })
+    }
+
+    /// Whether a turtle can stop in `position` from `time` on.
+    fn can_park(&self, position: Coordinates, time: usize) -> bool {
+        !self.parked.contains_key(&position) && self.latest.get(&position).is_none_or(|t| *t < time)
+    }
+
+    /// Takes every block on `path`, where the turtle is at `path[t]` at time step `t`, and parks
+    /// the turtle at the end.
+    pub fn reserve(&mut self, path: &[Coordinates]) {
+        for (time, position) in path.iter().enumerate() {
+            for t in time..=time + 1 {
+                self.taken.insert((*position, t));
+                let latest = self.latest.entry(*position).or_default();
+                *latest = (*latest).max(t);
+            }
+        }
+        if let Some(position) = path.last() {
+            self.parked.insert(*position, path.len() - 1);
+        }
+    }
+
+    /// Time step after which nothing is moving.
+    fn horizon(&self) -> usize {
+        self.latest.values().copied().max().unwrap_or_default()
+    }
+}
+
+/// Plans a path for every turtle from its start to its goal that never has two turtles in the
+/// same block or a turtle moving into a block another is leaving. Each path has the turtle's
+/// position at every time step, repeating it while the turtle waits.
+///
+/// Turtles are planned one at a time, each around the ones before it, with a space time A*.
+/// Turtles not planned yet are only in the way for the first step and have to get out of the
+/// way of the ones planned before them. If a turtle can't get to its goal the
+/// turtle sitting on its goal is planned before it, or it is planned first if there is none, and
+/// everything is tried again. Returns the index of the turtle that could not be planned if no
+/// order works.
+pub fn plan(moves: &[(Coordinates, Coordinates)]) -> Result<Vec<Vec<Coordinates>>, usize> {
+    let mut order: Vec<usize> = (0..moves.len()).collect();
+    // Turtles closest to their goals go first as they are the ones in front.
+    order.sort_by_key(|i| distance(moves[*i].0, moves[*i].1));
+
+    let mut failed = 0;
+    for _ in 0..=moves.len() * moves.len() {
+        let i = match plan_in_order(moves, &order) {
+            Ok(paths) => return Ok(paths),
+            Err(i) => i,
+        };
+        failed = i;
+
+        let position = order.iter().position(|o| *o == i).unwrap_or_default();
+        let in_the_way = order[position + 1..]
+            .iter()
+            .position(|o| moves[*o].0 == moves[i].1)
+            .map(|p| p + position + 1);
+        match in_the_way {
+            Some(p) => {
+                let blocker = order.remove(p);
+                order.insert(position, blocker);
+            }
+            None => {
+                order.remove(position);
+                order.insert(0, i);
+            }
+        }
+    }
+
+    Err(failed)
+}
+
+fn plan_in_order(
+    moves: &[(Coordinates, Coordinates)],
+    order: &[usize],
+) -> Result<Vec<Vec<Coordinates>>, usize> {
+    let bounds = Bounds::around(moves.iter().flat_map(|(start, goal)| [*start, *goal]));
+    let mut table = ReservationTable::new();
+    let mut paths = vec![vec![]; moves.len()];
+    for (planned, i) in order.iter().enumerate() {
+        let (start, goal) = moves[*i];
+        let waiting: HashSet<Coordinates> =
+            order[planned + 1..].iter().map(|o| moves[*o].0).collect();
+        paths[*i] = search(start, goal, &table, &waiting, &bounds).ok_or(*i)?;
+        table.reserve(&paths[*i]);
+    }
+
+    Ok(paths)
+}
+
+/// Finds the quickest path from `start` to `goal` around the turtles in `table` with A*. Waiting
+/// in place is a move that takes a time step. The `waiting` blocks are taken for the first step
+/// by turtles that have not been planned yet.
+pub fn search(
+    start: Coordinates,
+    goal: Coordinates,
+    table: &ReservationTable,
+    waiting: &HashSet<Coordinates>,
+    bounds: &Bounds,
+) -> Option<Vec<Coordinates>> {
+    // Long enough to wait for everything planned to finish and then cross the whole search area.
+    let limit = table.horizon() + bounds.volume() + 1;
+
+    // Each node is a position, time step and the node it came from.
+    let mut nodes: Vec<(Coordinates, usize, Option<usize>)> = vec![(start, 0, None)];
+    let mut open = BinaryHeap::new();
+    let mut closed: HashSet<(Coordinates, usize)> = HashSet::new();
+    open.push(Reverse((distance(start, goal), 0)));
+
+    while let Some(Reverse((_, node))) = open.pop() {
+        let (position, time, _) = nodes[node];
+        if !closed.insert((position, time)) {
+            continue;
+        }
+        let parks = table.can_park(goal, time) && (time > 1 || !waiting.contains(&goal));
+        if position == goal && parks {
+            let mut path = vec![];
+            let mut next = Some(node);
+            while let Some(n) = next {
+                path.push(nodes[n].0);
+                next = nodes[n].2;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if time >= limit {
+            continue;
+        }
+
+        for next in neighbours(position) {
+            let free = next == position || bounds.contains(next);
+            let left = time + 1 > 1 || !waiting.contains(&next);
+            if !free
+                || !left
+                || !table.is_free(next, time + 1)
+                || closed.contains(&(next, time + 1))
+            {
+                continue;
+            }
+
+            nodes.push((next, time + 1, Some(node)));
+            let cost = time as i64 + 1 + distance(next, goal);
+            open.push(Reverse((cost, nodes.len() - 1)));
+        }
+    }
+
+    None
+}
+
+/// The six blocks next to `position` and `position` itself for waiting.
+fn neighbours(position: Coordinates) -> [Coordinates; 7] {
+    let Coordinates { x, y, z } = position;
+    [
+        position,
+        Coordinates { x: x + 1, y, z },
+        Coordinates { x: x - 1, y, z },
+        Coordinates { x, y: y + 1, z },
+        Coordinates { x, y: y - 1, z },
+        Coordinates { x, y, z: z + 1 },
+        Coordinates { x, y, z: z - 1 },
+    ]
+}
+
+pub fn distance(a: Coordinates, b: Coordinates) -> i64 {
+    (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs()
+}
+
+/// Box of blocks a search stays inside.
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    min: Coordinates,
+    max: Coordinates,
+}
+
+impl Bounds {
+    /// Smallest box around `positions` grown by `MARGIN` on every side.
+    pub fn around(positions: impl Iterator<Item = Coordinates>) -> Self {
+        let mut positions = positions.peekable();
+        let first = positions
+            .peek()
+            .copied()
+            .unwrap_or(Coordinates { x: 0, y: 0, z: 0 });
+        let (min, max) = positions.fold((first, first), |(min, max), p| {
+            (
+                Coordinates {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                    z: min.z.min(p.z),
+                },
+                Coordinates {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                    z: max.z.max(p.z),
+                },
+            )
+        });
+
+        Bounds {
+            min: Coordinates {
+                x: min.x - MARGIN,
+                y: min.y - MARGIN,
+                z: min.z - MARGIN,
+            },
+            max: Coordinates {
+                x: max.x + MARGIN,
+                y: max.y + MARGIN,
+                z: max.z + MARGIN,
+            },
+        }
+    }
+
+    fn contains(&self, p: Coordinates) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x)
+            && (self.min.y..=self.max.y).contains(&p.y)
+            && (self.min.z..=self.max.z).contains(&p.z)
+    }
+
+    fn volume(&self) -> usize {
+        ((self.max.x - self.min.x + 1)
+            * (self.max.y - self.min.y + 1)
+            * (self.max.z - self.min.z + 1)) as usize
+    }
+}
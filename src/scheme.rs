@@ -22,13 +22,124 @@ pub enum Direction {
     Down,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+impl Direction {
+    /// Gets where a turtle at `position` facing `heading` ends up after moving this way.
+    /// Returns None for turns as the turtle stays where it is.
+    pub fn target(&self, position: Coordinates, heading: Heading) -> Option<Coordinates> {
+        match self {
+            Direction::Forward => Some(position.step(heading)),
+            Direction::Back => Some(position.step(heading.opposite())),
+            Direction::Up => Some(Coordinates {
+                y: position.y + 1,
+                ..position
+            }),
+            Direction::Down => Some(Coordinates {
+                y: position.y - 1,
+                ..position
+            }),
+            Direction::Left | Direction::Right => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, FromRow)]
 pub struct Coordinates {
     pub x: i64,
     pub y: i64,
     pub z: i64,
 }
 
+impl Coordinates {
+    /// Gets the heading of a single horizontal step from here to `other`.
+    /// Returns None if `other` is not one block north, south, east or west of here.
+    pub fn heading_to(&self, other: Coordinates) -> Option<Heading> {
+        match (other.x - self.x, other.y - self.y, other.z - self.z) {
+            (0, 0, -1) => Some(Heading::North),
+            (0, 0, 1) => Some(Heading::South),
+            (1, 0, 0) => Some(Heading::East),
+            (-1, 0, 0) => Some(Heading::West),
+            _ => None,
+        }
+    }
+
+    /// Gets the next block on the way from here to `destination` one block at a time.
+    /// Climbs first if `destination` is higher, goes along x then z and drops down last so the way
+    /// stays above the ground for as long as it can.
+    pub fn step_towards(&self, destination: Coordinates) -> Coordinates {
+        if destination.y > self.y {
+            Coordinates {
+                y: self.y + 1,
+                ..*self
+            }
+        } else if self.x != destination.x {
+            Coordinates {
+                x: self.x + (destination.x - self.x).signum(),
+                ..*self
+            }
+        } else if self.z != destination.z {
+            Coordinates {
+                z: self.z + (destination.z - self.z).signum(),
+                ..*self
+            }
+        } else {
+            Coordinates {
+                y: self.y - 1,
+                ..*self
+            }
+        }
+    }
+
+    /// Gets the position one block away in `heading`.
+    pub fn step(&self, heading: Heading) -> Coordinates {
+        match heading {
+            Heading::North => Coordinates {
+                z: self.z - 1,
+                ..*self
+            },
+            Heading::South => Coordinates {
+                z: self.z + 1,
+                ..*self
+            },
+            Heading::East => Coordinates {
+                x: self.x + 1,
+                ..*self
+            },
+            Heading::West => Coordinates {
+                x: self.x - 1,
+                ..*self
+            },
+        }
+    }
+}
+
+/// A Minecraft dimension such as `minecraft:the_nether`.
+/// Coordinates are only unique within a dimension.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Dimension {
+    pub name: String,
+
+    /// World or server the dimension is on. None when turtles only run on a single world.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world: Option<String>,
+}
+
+impl Dimension {
+    pub const OVERWORLD: &'static str = "minecraft:overworld";
+
+    pub fn new(name: &str, world: Option<&str>) -> Self {
+        Dimension {
+            name: name.to_string(),
+            world: world.map(str::to_string),
+        }
+    }
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Dimension::new(Self::OVERWORLD, None)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Heading {
     #[serde(rename = "n")]
@@ -59,6 +170,30 @@ impl Heading {
         }
     }
 
+    pub fn opposite(&self) -> Heading {
+        match self {
+            Heading::North => Heading::South,
+            Heading::South => Heading::North,
+            Heading::East => Heading::West,
+            Heading::West => Heading::East,
+        }
+    }
+
+    /// Gets the heading after turning left.
+    pub fn left(&self) -> Heading {
+        match self {
+            Heading::North => Heading::West,
+            Heading::West => Heading::South,
+            Heading::South => Heading::East,
+            Heading::East => Heading::North,
+        }
+    }
+
+    /// Gets the heading after turning right.
+    pub fn right(&self) -> Heading {
+        self.left().opposite()
+    }
+
     pub fn from_str(s: &str) -> Option<Heading> {
         match s {
             Self::NORTH => Some(Heading::North),
@@ -103,6 +238,15 @@ impl TurtleType {
         }
     }
 
+    /// Works out the type of a turtle from the fuel limit it reports.
+    pub fn from_max_fuel(max: u32) -> Option<Self> {
+        match max {
+            Self::NORMAL_FUEL => Some(TurtleType::Normal),
+            Self::ADVANCED_FUEL => Some(TurtleType::Advanced),
+            _ => None,
+        }
+    }
+
     pub fn get_max_fuel(&self) -> u32 {
         match self {
             TurtleType::Normal => Self::NORMAL_FUEL,
@@ -111,13 +255,78 @@ impl TurtleType {
     }
 }
 
+/// Items equipped on each side of a turtle such as `minecraft:diamond_pickaxe`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upgrades {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right: Option<String>,
+}
+
+impl Upgrades {
+    /// Whether either side has `tool` equipped.
+    pub fn has(&self, tool: Tool) -> bool {
+        [&self.left, &self.right]
+            .into_iter()
+            .flatten()
+            .any(|item| tool.matches(item))
+    }
+}
+
+/// Kinds of upgrade that work can need a turtle to have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tool {
+    Pickaxe,
+    Axe,
+    Shovel,
+    Hoe,
+    Sword,
+    Modem,
+    CraftingTable,
+}
+
+impl Tool {
+    /// Whether the item `name` is this kind of tool. Any material counts.
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Tool::Pickaxe => name.ends_with("_pickaxe"),
+            Tool::Axe => name.ends_with("_axe"),
+            Tool::Shovel => name.ends_with("_shovel"),
+            Tool::Hoe => name.ends_with("_hoe"),
+            Tool::Sword => name.ends_with("_sword"),
+            Tool::Modem => name.contains("modem"),
+            Tool::CraftingTable => name == "minecraft:crafting_table",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Turtle {
     pub name: String,
     pub coordinates: Coordinates,
+    pub dimension: Dimension,
     pub heading: Heading,
     pub turtle_type: TurtleType,
     pub fuel: Fuel,
+
+    #[serde(default)]
+    pub upgrades: Upgrades,
+}
+
+/// A named place turtles can be sent to, such as a turtle's home.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub name: String,
+    pub position: Coordinates,
+
+    /// Way turtles face once they get there.
+    pub heading: Heading,
+
+    #[serde(default)]
+    pub dimension: Dimension,
 }
 
 // pub struct TurtleData {
@@ -131,6 +340,15 @@ impl std::fmt::Display for Coordinates {
     }
 }
 
+impl std::fmt::Display for Dimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.world {
+            Some(world) => write!(f, "{} on {world}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
 impl std::fmt::Display for Heading {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let h = match self {
@@ -144,6 +362,17 @@ impl std::fmt::Display for Heading {
     }
 }
 
+impl std::fmt::Display for Upgrades {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {}",
+            self.left.as_deref().unwrap_or("none"),
+            self.right.as_deref().unwrap_or("none")
+        )
+    }
+}
+
 impl std::fmt::Display for Fuel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.level, self.max)
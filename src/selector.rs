@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Picks out which turtles something is for.
+/// Written as `*` for every turtle, `@group`, `#tag` or a turtle's name. Turtle names never
+/// start with one of those characters.
+/// Picks out every turtle by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Selector {
+    #[default]
+    All,
+    Name(String),
+    Group(String),
+    Tag(String),
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Selector {
+        if s == "*" {
+            Selector::All
+        } else if let Some(group) = s.strip_prefix('@') {
+            Selector::Group(group.to_string())
+        } else if let Some(tag) = s.strip_prefix('#') {
+            Selector::Tag(tag.to_string())
+        } else {
+            Selector::Name(s.to_string())
+        }
+    }
+}
+
+impl From<String> for Selector {
+    fn from(s: String) -> Self {
+        Selector::parse(s.as_str())
+    }
+}
+
+impl From<Selector> for String {
+    fn from(selector: Selector) -> Self {
+        selector.to_string()
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::All => write!(f, "*"),
+            Selector::Name(name) => write!(f, "{name}"),
+            Selector::Group(group) => write!(f, "@{group}"),
+            Selector::Tag(tag) => write!(f, "#{tag}"),
+        }
+    }
+}
+
+/// Every group and tag with the names of the turtles in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Labels {
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub tags: BTreeMap<String, Vec<String>>,
+}
@@ -0,0 +1,32 @@
+/// Starts the server on ephemeral ports and connects simulated turtles and clients to it.
+mod harness;
+
+/// Tests for the client protocol.
+mod client_tests;
+
+/// Tests for turtle connections, commands and the database.
+mod turtle_tests;
+
+/// Tests for the metrics endpoint.
+mod metrics_tests;
+
+/// Tests for recording turtle sessions and replaying them.
+mod replay_tests;
+
+/// Tests for planning and running crafts.
+mod crafting_tests;
+
+/// Tests for moving turtles in formations.
+mod formation_tests;
+
+/// Tests for reserving the blocks turtles move into.
+mod reservation_tests;
+
+/// Tests for groups, tags and picking out turtles with them.
+mod selector_tests;
+
+/// Tests for splitting jobs between idle turtles.
+mod dispatcher_tests;
+
+/// Tests for waypoints and sending turtles home.
+mod home_tests;
@@ -0,0 +1,648 @@
+use std::time::Duration;
+
+use turtle_sim::{Entity, Fault, ItemStack, TurtleConfig};
+
+use super::harness::{eventually, from_sim, to_sim, TestClient, TestServer, ADMIN_TOKEN, TIMEOUT};
+use crate::client_scheme::{Command, Event};
+use crate::crafting::ItemCount;
+use crate::dispatcher::{FuelNeeds, Job};
+use crate::error::Error;
+use crate::scheme::{Coordinates, Dimension, Direction, Heading, TurtleType};
+use crate::selector::Selector;
+use crate::turtle_manager::TurtleManagerConfig;
+use crate::turtle_scheme::{ItemSlot, Request, RequestType, Side, TurtleCommand, TurtleEvents};
+
+// Check that clients are told when turtles connect and disconnect.
+#[tokio::test]
+async fn check_connection_events() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::DisconnectAfter(1));
+    config.reconnect = Some(Duration::from_millis(100));
+    let (_sim, name) = server.connect_turtle(config).await;
+
+    let connected = client
+        .wait_for_event(|e| matches!(e, Event::TurtleConnected { .. }))
+        .await;
+    assert!(matches!(connected, Some(Event::TurtleConnected { name: n }) if n == name));
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle
+        .send(crate::turtle_scheme::TurtleCommand::TurnLeft)
+        .await
+        .unwrap();
+
+    let disconnected = client
+        .wait_for_event(|e| matches!(e, Event::TurtleDisconnected { .. }))
+        .await;
+    assert!(matches!(disconnected, Some(Event::TurtleDisconnected { name: n }) if n == name));
+
+    let reconnected = client
+        .wait_for_event(|e| matches!(e, Event::TurtleConnected { .. }))
+        .await;
+    assert!(matches!(reconnected, Some(Event::TurtleConnected { name: n }) if n == name));
+
+    server.close().await;
+}
+
+// Check that events from turtles are forwarded to clients.
+#[tokio::test]
+async fn check_turtle_events() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    let event = client
+        .wait_for_event(|e| {
+            matches!(
+                e,
+                Event::TurtleEvent {
+                    event: TurtleEvents::Report { .. },
+                    ..
+                }
+            )
+        })
+        .await;
+    assert!(matches!(event, Some(Event::TurtleEvent { name: n, .. }) if n == name));
+
+    server.close().await;
+}
+
+// Check that clients can list the turtles the manager knows about.
+#[tokio::test]
+async fn check_get_turtles() {
+    let server = TestServer::start().await;
+    let position = Coordinates { x: 1, y: 2, z: 3 };
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            position,
+            Dimension::default(),
+            Heading::South,
+            TurtleType::Advanced,
+        )
+        .await
+        .unwrap();
+
+    let mut client = server.connect_client().await;
+    client
+        .send(&Command::GetTurtles {
+            dimension: None,
+            tool: None,
+        })
+        .await;
+
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Turtles { .. }))
+        .await;
+    let turtles = match event {
+        Some(Event::Turtles { turtles }) => turtles,
+        _ => panic!("Did not get turtles"),
+    };
+    assert_eq!(turtles.len(), 1);
+    assert_eq!(turtles[0].name, "Aaren");
+    assert_eq!(turtles[0].coordinates, position);
+    assert_eq!(turtles[0].fuel.max, TurtleType::ADVANCED_FUEL);
+
+    server.close().await;
+}
+
+// Check that clients can list only the turtles in one dimension.
+#[tokio::test]
+async fn check_get_turtles_in_dimension() {
+    let server = TestServer::start().await;
+    let position = Coordinates { x: 1, y: 2, z: 3 };
+    let nether = Dimension::new("minecraft:the_nether", None);
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            position,
+            Dimension::default(),
+            Heading::South,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aarika",
+            position,
+            nether.clone(),
+            Heading::South,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+
+    let mut client = server.connect_client().await;
+    client
+        .send(&Command::GetTurtles {
+            dimension: Some(nether.clone()),
+            tool: None,
+        })
+        .await;
+
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Turtles { .. }))
+        .await;
+    let turtles = match event {
+        Some(Event::Turtles { turtles }) => turtles,
+        _ => panic!("Did not get turtles"),
+    };
+    assert_eq!(turtles.len(), 1);
+    assert_eq!(turtles[0].name, "Aarika");
+    assert_eq!(turtles[0].dimension, nether);
+
+    let status = server
+        .turtle_manager
+        .get_dimension_status(nether)
+        .await
+        .unwrap();
+    assert!(status.contains("Aarika") && !status.contains("Aaren"));
+
+    server.close().await;
+}
+
+// Check that clients can move turtles.
+#[tokio::test]
+async fn check_move() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    client
+        .send(&Command::Move {
+            target: Selector::Name(name),
+            direction: Direction::Up,
+        })
+        .await;
+
+    let state = sim
+        .wait_for(TIMEOUT, |s| !s.commands.is_empty())
+        .await
+        .expect("Turtle was not moved");
+    assert_eq!(state.commands[0]["type"], "move");
+    assert_eq!(from_sim(state.position), Coordinates { x: 0, y: 1, z: 0 });
+
+    server.close().await;
+}
+
+// Check that clients can see the peripherals next to a turtle and what is in a chest it faces.
+#[tokio::test]
+async fn check_list_inventory() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+
+    // The turtle faces north so the chest in front of it is at z - 1.
+    let chest = Coordinates { x: 0, y: 0, z: -1 };
+    let mut slots = vec![None; 27];
+    slots[0] = Some(ItemStack {
+        name: "minecraft:oak_log".to_string(),
+        count: 12,
+    });
+    server
+        .world
+        .lock()
+        .unwrap()
+        .set_inventory(to_sim(chest), "minecraft:chest", slots);
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    client
+        .send(&Command::ListPeripherals {
+            target: Selector::Name(name.clone()),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Peripherals { .. }))
+        .await;
+    let peripherals = match event {
+        Some(Event::Peripherals { peripherals, .. }) => peripherals,
+        _ => panic!("Did not get peripherals"),
+    };
+    assert_eq!(peripherals.len(), 1);
+    assert_eq!(peripherals[0].side, Side::Front);
+    assert!(peripherals[0].types.iter().any(|t| t == "inventory"));
+
+    let logs = vec![ItemSlot {
+        slot: 1,
+        name: "minecraft:oak_log".to_string(),
+        count: 12,
+    }];
+    client
+        .send(&Command::ListInventory {
+            target: Selector::Name(name),
+            side: Side::Front,
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Inventory { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Inventory { position, inventory: Some(i), .. }) if position == chest && i.size == 27 && i.slots == logs
+    ));
+
+    // The listed inventory was stored so it can be read without the turtle.
+    client
+        .send(&Command::GetInventory {
+            position: chest,
+            dimension: Dimension::default(),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Inventory { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Inventory { inventory: Some(i), .. }) if i.slots == logs
+    ));
+
+    server.close().await;
+}
+
+// Check that clients get an error event for commands that fail.
+#[tokio::test]
+async fn check_error_event() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+
+    let command = Command::Move {
+        target: Selector::Name("Nobody".to_string()),
+        direction: Direction::Up,
+    };
+    client.send(&command).await;
+
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Error {
+            command: Command::Move { .. },
+            error: Error::UnknownTurtle { name },
+        }) if name == "Nobody"
+    ));
+
+    // Resuming a turtle that is not paused tells the client why.
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    client
+        .send(&Command::Resume {
+            target: Some(Selector::Name(name.clone())),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Error {
+            error: Error::NotPaused,
+            ..
+        })
+    ));
+
+    server.close().await;
+}
+
+// Check that clients are told when a turtle stops responding and is disconnected.
+#[tokio::test]
+async fn check_unresponsive_events() {
+    let mut manager_config = TurtleManagerConfig::default();
+    manager_config.connection.heartbeat_interval = Duration::from_millis(50);
+    manager_config.connection.unresponsive_after = 2;
+    manager_config.connection.disconnect_after = 4;
+    let server = TestServer::start_with_config(manager_config).await;
+    let mut client = server.connect_client().await;
+
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::HangAfter(1));
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle
+        .send(crate::turtle_scheme::TurtleCommand::TurnLeft)
+        .await
+        .unwrap();
+
+    let unresponsive = client
+        .wait_for_event(|e| matches!(e, Event::TurtleUnresponsive { .. }))
+        .await;
+    assert!(matches!(unresponsive, Some(Event::TurtleUnresponsive { name: n }) if n == name));
+
+    let disconnected = client
+        .wait_for_event(|e| matches!(e, Event::TurtleDisconnected { .. }))
+        .await;
+    assert!(matches!(disconnected, Some(Event::TurtleDisconnected { name: n }) if n == name));
+
+    sim.close().await;
+    server.close().await;
+}
+
+// Check that a guarding turtle reports drops and stops attacking when told to.
+#[tokio::test]
+async fn check_guard() {
+    let server = TestServer::start_with_config(TurtleManagerConfig {
+        guard_interval: Duration::from_millis(20),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.connect_client().await;
+
+    // The turtle faces north so the zombie in front of it is at z - 1.
+    let front = to_sim(Coordinates { x: 0, y: 0, z: -1 });
+    let zombie = || Entity {
+        name: "minecraft:zombie".to_string(),
+        drops: vec![ItemStack {
+            name: "minecraft:rotten_flesh".to_string(),
+            count: 2,
+        }],
+    };
+    server.world.lock().unwrap().spawn_entity(front, zombie());
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    client
+        .send(&Command::Guard {
+            target: Selector::Name(name.clone()),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Drops { .. }))
+        .await;
+    let items = match event {
+        Some(Event::Drops { name: n, items }) if n == name => items,
+        _ => panic!("Did not get drops"),
+    };
+    assert_eq!(
+        items,
+        vec![ItemCount {
+            name: "minecraft:rotten_flesh".to_string(),
+            count: 2,
+        }]
+    );
+
+    server.turtle_manager.stop_guard(&name).await.unwrap();
+    // Let an attack that was already queued go through before the next zombie turns up.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    server.world.lock().unwrap().spawn_entity(front, zombie());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(
+        server.world.lock().unwrap().kill_entity(front).is_some(),
+        "Turtle kept attacking after it was stopped"
+    );
+
+    server.close().await;
+}
+
+// Check that a turtle whose guard task ended because it disconnected can be given work again.
+#[tokio::test]
+async fn check_guard_ends_on_disconnect() {
+    let server = TestServer::start_with_config(TurtleManagerConfig {
+        guard_interval: Duration::from_millis(20),
+        ..Default::default()
+    })
+    .await;
+
+    // Drops the connection after an item list, an attack and another item list.
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::DisconnectAfter(3));
+    config.reconnect = Some(Duration::from_millis(500));
+    let (sim, name) = server.connect_turtle(config).await;
+
+    server.turtle_manager.guard(&name).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| !s.connected).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| s.connected).await.unwrap();
+
+    assert!(
+        eventually(|| async {
+            !server
+                .turtle_manager
+                .claim_idle(0, Selector::All, None, None, 0, vec![FuelNeeds::default()])
+                .await
+                .is_empty()
+        })
+        .await,
+        "Turtle was still marked as guarding"
+    );
+
+    server.close().await;
+}
+
+// Check that only admins can evaluate Lua and only when it is turned on.
+#[tokio::test]
+async fn check_eval() {
+    let disabled = TestServer::start().await;
+    let (_sim, name) = disabled.connect_turtle(TurtleConfig::new(0)).await;
+    assert_eq!(
+        disabled.turtle_manager.eval(&name, "return 1").await,
+        Err(Error::EvalDisabled)
+    );
+    disabled.close().await;
+
+    let mut config = TurtleManagerConfig::default();
+    config.eval.enabled = true;
+    let server = TestServer::start_with_config(config).await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let eval = Command::Eval {
+        target: Selector::Name(name.clone()),
+        code: "return turtle.getFuelLevel()".to_string(),
+    };
+    let error = async |client: &mut TestClient| match client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await
+    {
+        Some(Event::Error { error, .. }) => error,
+        _ => panic!("Did not get an error"),
+    };
+
+    client.send(&eval).await;
+    assert_eq!(error(&mut client).await, Error::NotAdmin);
+    client
+        .send(&Command::Login {
+            token: "wrong".to_string(),
+        })
+        .await;
+    assert_eq!(error(&mut client).await, Error::NotAdmin);
+
+    client
+        .send(&Command::Login {
+            token: ADMIN_TOKEN.to_string(),
+        })
+        .await;
+    assert!(client
+        .wait_for_event(|e| matches!(e, Event::LoggedIn))
+        .await
+        .is_some());
+
+    // The simulator can't run Lua so the request fails on the turtle.
+    client.send(&eval).await;
+    assert!(matches!(
+        error(&mut client).await,
+        Error::TurtleFailed { .. }
+    ));
+    let state = sim
+        .wait_for(TIMEOUT, |s| {
+            s.commands.iter().any(|c| c["request"]["type"] == "eval")
+        })
+        .await
+        .expect("Eval request was not sent");
+    assert!(state
+        .commands
+        .iter()
+        .any(|c| c["request"]["code"] == "return turtle.getFuelLevel()"));
+
+    server.close().await;
+}
+
+// Check that clients can't get around the admin only eval by sending the request themselves.
+#[tokio::test]
+async fn check_send_eval_needs_admin() {
+    let mut config = TurtleManagerConfig::default();
+    config.eval.enabled = true;
+    let server = TestServer::start_with_config(config).await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    client
+        .send(&Command::Send {
+            target: Selector::Name(name),
+            command: TurtleCommand::Request(Request {
+                id: 1,
+                request: RequestType::Eval {
+                    code: "return 1".to_string(),
+                    timeout_ms: 1000,
+                },
+            }),
+        })
+        .await;
+    assert!(matches!(
+        client
+            .wait_for_event(|e| matches!(e, Event::Error { .. }))
+            .await,
+        Some(Event::Error {
+            error: Error::NotAdmin,
+            ..
+        })
+    ));
+    assert!(sim.get_state().commands.is_empty());
+
+    server.close().await;
+}
+
+// Check that only admins can deploy scripts to turtles or roll them back.
+#[tokio::test]
+async fn check_deploy_needs_admin() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    for command in [
+        Command::Deploy {
+            target: Some(Selector::Name(name.clone())),
+            force: true,
+        },
+        Command::Rollback {
+            target: Selector::Name(name.clone()),
+        },
+    ] {
+        client.send(&command).await;
+        assert!(matches!(
+            client
+                .wait_for_event(|e| matches!(e, Event::Error { .. }))
+                .await,
+            Some(Event::Error {
+                error: Error::NotAdmin,
+                ..
+            })
+        ));
+    }
+    assert!(sim.get_state().commands.is_empty());
+
+    server.close().await;
+}
+
+// Check that a job can't be used to send turtles commands only admins can send.
+#[tokio::test]
+async fn check_job_tasks_need_admin() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, _name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    let eval = TurtleCommand::Request(Request {
+        id: 1,
+        request: RequestType::Eval {
+            code: "return 1".to_string(),
+            timeout_ms: 1000,
+        },
+    });
+    for command in [eval, TurtleCommand::Rollback] {
+        client
+            .send(&Command::StartJob {
+                job: Job::Tasks {
+                    tasks: vec![vec![TurtleCommand::Forward, command]],
+                },
+                target: Selector::All,
+            })
+            .await;
+        assert!(matches!(
+            client
+                .wait_for_event(|e| matches!(e, Event::Error { .. } | Event::JobStarted { .. }))
+                .await,
+            Some(Event::Error {
+                error: Error::NotAdmin,
+                ..
+            })
+        ));
+    }
+    assert!(sim.get_state().commands.is_empty());
+
+    server.close().await;
+}
+
+// Check that the client's other commands are answered while a slow eval waits on the turtle.
+#[tokio::test]
+async fn check_eval_in_background() {
+    let mut config = TurtleManagerConfig::default();
+    config.eval.enabled = true;
+    let server = TestServer::start_with_config(config).await;
+    let mut client = server.connect_client().await;
+    let mut turtle = TurtleConfig::new(0);
+    turtle.latency = Duration::from_millis(500);
+    let (_sim, name) = server.connect_turtle(turtle).await;
+
+    client
+        .send(&Command::Login {
+            token: ADMIN_TOKEN.to_string(),
+        })
+        .await;
+    assert!(client
+        .wait_for_event(|e| matches!(e, Event::LoggedIn))
+        .await
+        .is_some());
+
+    client
+        .send(&Command::Eval {
+            target: Selector::Name(name),
+            code: "return 1".to_string(),
+        })
+        .await;
+    client.send(&Command::GetReservations).await;
+    let answered = |e: &Event| matches!(e, Event::Reservations { .. } | Event::Error { .. });
+    assert!(matches!(
+        client.wait_for_event(answered).await,
+        Some(Event::Reservations { .. })
+    ));
+    assert!(matches!(
+        client.wait_for_event(answered).await,
+        Some(Event::Error {
+            error: Error::TurtleFailed { .. },
+            ..
+        })
+    ));
+
+    server.close().await;
+}
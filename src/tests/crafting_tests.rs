@@ -0,0 +1,276 @@
+use turtle_sim::{CraftingRecipe, ItemStack, TurtleConfig};
+
+use super::harness::{to_sim, TestClient, TestServer, TIMEOUT};
+use crate::client_scheme::Event;
+use crate::crafting::{self, ItemCount, Recipe};
+use crate::error::Error;
+use crate::scheme::Coordinates;
+use crate::turtle_manager::TurtleManagerConfig;
+use crate::turtle_scheme::{Inventory, ItemSlot, Side, TurtleCommand};
+
+const PLANKS: &str = "minecraft:oak_planks";
+const COAL: &str = "minecraft:coal";
+const STICK: &str = "minecraft:stick";
+const TORCH: &str = "minecraft:torch";
+
+fn chest() -> Recipe {
+    let p = || Some(PLANKS.to_string());
+    Recipe {
+        count: 1,
+        pattern: vec![
+            vec![p(), p(), p()],
+            vec![p(), None, p()],
+            vec![p(), p(), p()],
+        ],
+    }
+}
+
+fn torch() -> Recipe {
+    Recipe {
+        count: 4,
+        pattern: vec![vec![Some(COAL.to_string())], vec![Some(STICK.to_string())]],
+    }
+}
+
+/// Starts a server that knows the torch recipe.
+async fn start_server() -> TestServer {
+    TestServer::start_with_config(TurtleManagerConfig {
+        recipes: serde_json::from_value(serde_json::json!({ TORCH: torch() })).unwrap(),
+        ..Default::default()
+    })
+    .await
+}
+
+/// A turtle with a crafting table that knows how to make torches, with one coal in slot 2 and
+/// one stick in slot 16.
+fn crafting_turtle() -> TurtleConfig {
+    let mut config = TurtleConfig::new(0);
+    config.upgrades.left = Some("minecraft:crafting_table".to_string());
+    config.recipes = vec![CraftingRecipe {
+        pattern: vec![vec![Some(COAL.to_string())], vec![Some(STICK.to_string())]],
+        output: ItemStack {
+            name: TORCH.to_string(),
+            count: 4,
+        },
+    }];
+    config.inventory[1] = Some(ItemStack {
+        name: COAL.to_string(),
+        count: 1,
+    });
+    config.inventory[15] = Some(ItemStack {
+        name: STICK.to_string(),
+        count: 1,
+    });
+    config
+}
+
+/// Waits for the client to be told a turtle finished crafting and gets why it failed if it did.
+async fn crafted(client: &mut TestClient) -> Option<Error> {
+    match client
+        .wait_for_event(|e| matches!(e, Event::Crafted { .. }))
+        .await
+    {
+        Some(Event::Crafted { error, .. }) => error,
+        _ => panic!("Turtle did not finish crafting"),
+    }
+}
+
+fn inventory(slots: &[(u32, &str, u32)]) -> Inventory {
+    Inventory {
+        size: 16,
+        slots: slots
+            .iter()
+            .map(|(slot, name, count)| ItemSlot {
+                slot: *slot,
+                name: name.to_string(),
+                count: *count,
+            })
+            .collect(),
+    }
+}
+
+// Check that a shortfall reports how many of each item are missing.
+#[test]
+fn check_missing_items() {
+    let result = crafting::plan(
+        &torch(),
+        8,
+        &inventory(&[(1, COAL, 1), (5, STICK, 2)]),
+        None,
+    );
+    assert_eq!(
+        result,
+        Err(Error::MissingItems {
+            items: vec![ItemCount {
+                name: COAL.to_string(),
+                count: 1,
+            }],
+        })
+    );
+}
+
+// Check that items which aren't in the recipe are dropped into the stash and stop the craft when
+// there is nowhere to put them.
+#[test]
+fn check_extra_items() {
+    let items = inventory(&[(1, COAL, 1), (5, STICK, 1), (9, PLANKS, 3)]);
+    let result = crafting::plan(&torch(), 4, &items, None);
+    assert!(matches!(result, Err(Error::CannotCraft { .. })));
+
+    let commands =
+        crafting::plan(&torch(), 4, &items, Some(Side::Top)).expect("Planks should be stashed");
+    assert_eq!(
+        commands,
+        vec![
+            TurtleCommand::DropUp { slot: 9 },
+            TurtleCommand::Craft { count: 1 },
+        ]
+    );
+}
+
+// Check that items are moved into the grid and the craft is the last command.
+#[test]
+fn check_plan_arranges_grid() {
+    // Coal is where the stick goes and the sticks are outside the grid.
+    let commands = crafting::plan(
+        &torch(),
+        8,
+        &inventory(&[(5, COAL, 2), (16, STICK, 2)]),
+        None,
+    )
+    .expect("Torches should be craftable");
+    assert_eq!(
+        commands,
+        vec![
+            TurtleCommand::TransferTo {
+                from: 5,
+                to: 1,
+                count: 2,
+            },
+            TurtleCommand::TransferTo {
+                from: 16,
+                to: 5,
+                count: 2,
+            },
+            TurtleCommand::Craft { count: 2 },
+        ]
+    );
+
+    // Planks are spread over every cell of the chest except the middle.
+    let commands = crafting::plan(&chest(), 2, &inventory(&[(6, PLANKS, 20)]), None)
+        .expect("Chests should be craftable");
+    assert_eq!(commands.last(), Some(&TurtleCommand::Craft { count: 2 }));
+    assert!(!commands
+        .iter()
+        .any(|c| matches!(c, TurtleCommand::TransferTo { to: 6, .. })));
+}
+
+// Check that crafting through the manager arranges the simulated turtle's inventory and crafts.
+#[tokio::test]
+async fn check_craft() {
+    let server = start_server().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(crafting_turtle()).await;
+
+    assert!(matches!(
+        server
+            .turtle_manager
+            .craft(&name, "minecraft:cake", 1)
+            .await,
+        Err(Error::UnknownRecipe { .. })
+    ));
+    server
+        .turtle_manager
+        .craft(&name, TORCH, 4)
+        .await
+        .expect("Torches should be craftable");
+    assert_eq!(crafted(&mut client).await, None);
+
+    let state = sim.get_state();
+    let transfers: Vec<_> = state
+        .commands
+        .iter()
+        .filter(|c| c["type"] == "transfer_to")
+        .map(|c| (c["from"].as_u64(), c["to"].as_u64()))
+        .collect();
+    assert_eq!(transfers, vec![(Some(2), Some(1)), (Some(16), Some(5))]);
+    assert_eq!(
+        state.inventory[0],
+        Some(ItemStack {
+            name: TORCH.to_string(),
+            count: 4,
+        })
+    );
+
+    server.close().await;
+}
+
+// Check that items which aren't part of the recipe are put in the chest in front of the turtle.
+#[tokio::test]
+async fn check_craft_stashes_extra_items() {
+    let server = start_server().await;
+    // The turtle faces north so the chest in front of it is at z - 1.
+    let chest = to_sim(Coordinates { x: 0, y: 0, z: -1 });
+    server
+        .world
+        .lock()
+        .unwrap()
+        .set_inventory(chest, "minecraft:chest", vec![None; 27]);
+
+    let mut config = crafting_turtle();
+    config.inventory[8] = Some(ItemStack {
+        name: PLANKS.to_string(),
+        count: 3,
+    });
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle(config).await;
+
+    server
+        .turtle_manager
+        .craft(&name, TORCH, 4)
+        .await
+        .expect("Torches should be craftable");
+    assert_eq!(crafted(&mut client).await, None);
+
+    let stashed = server.world.lock().unwrap().get_inventory(chest).unwrap()[0].clone();
+    assert_eq!(
+        stashed,
+        Some(ItemStack {
+            name: PLANKS.to_string(),
+            count: 3,
+        })
+    );
+    assert!(sim
+        .get_state()
+        .inventory
+        .iter()
+        .flatten()
+        .all(|i| i.name == TORCH));
+
+    server.close().await;
+}
+
+// Check that a craft the turtle could not do is reported once the inventory is listed again.
+#[tokio::test]
+async fn check_failed_craft() {
+    let server = start_server().await;
+    let mut client = server.connect_client().await;
+    let mut config = crafting_turtle();
+    config.recipes.clear();
+    let (sim, name) = server.connect_turtle(config).await;
+
+    server
+        .turtle_manager
+        .craft(&name, TORCH, 4)
+        .await
+        .expect("Crafting commands should be queued");
+    assert!(matches!(
+        crafted(&mut client).await,
+        Some(Error::CannotCraft { .. })
+    ));
+    sim.wait_for(TIMEOUT, |s| s.commands.iter().any(|c| c["type"] == "craft"))
+        .await
+        .expect("Turtle did not try to craft");
+
+    server.close().await;
+}
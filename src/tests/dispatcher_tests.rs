@@ -0,0 +1,234 @@
+use turtle_sim::{Fault, TurtleConfig};
+
+use super::harness::{at, dispatch_config, miner, TestServer};
+use crate::client_scheme::{Command, Event};
+use crate::dispatcher::Job;
+use crate::scheme::Dimension;
+use crate::selector::Selector;
+
+// Check an excavation is shared between turtles and every block in it is dug out.
+#[tokio::test]
+async fn check_excavate() {
+    let server = TestServer::start_with_config(dispatch_config()).await;
+    let mut client = server.connect_client().await;
+    let (from, to) = (at(0, -1, -1), at(1, -2, -2));
+    server.fill(from, to);
+    let (_first_sim, first) = server.connect_turtle_with(at(0, 0, 1), miner(0)).await;
+    let (_second_sim, second) = server.connect_turtle_with(at(1, 0, 1), miner(1)).await;
+
+    client
+        .send(&Command::StartJob {
+            job: Job::Excavate {
+                from,
+                to,
+                dimension: Dimension::default(),
+            },
+            target: Selector::All,
+        })
+        .await;
+    assert!(client
+        .wait_for_event(|e| matches!(e, Event::JobStarted { .. }))
+        .await
+        .is_some());
+
+    let mut seen = vec![];
+    let started =
+        |e: &Event| matches!(e, Event::JobProgress { progress } if progress.turtles.len() == 2);
+    if let Some(Event::JobProgress { progress }) = client.wait_for_event(started).await {
+        seen = progress.turtles;
+    }
+    seen.sort();
+    let mut expected = vec![first, second];
+    expected.sort();
+    assert_eq!(seen, expected);
+
+    let progress = client.wait_for_finish().await;
+    assert_eq!((progress.done, progress.failed, progress.total), (4, 0, 4));
+    assert!(server.is_empty(from, to));
+
+    client.send(&Command::GetJobs).await;
+    let jobs = client
+        .wait_for_event(|e| matches!(e, Event::Jobs { .. }))
+        .await;
+    assert!(matches!(jobs, Some(Event::Jobs { jobs }) if jobs == vec![progress]));
+
+    server.close().await;
+}
+
+// Check a job aimed at a group is only given to the turtles in the group.
+#[tokio::test]
+async fn check_excavate_group() {
+    let server = TestServer::start_with_config(dispatch_config()).await;
+    let mut client = server.connect_client().await;
+    let (from, to) = (at(0, -1, -1), at(1, -2, -2));
+    server.fill(from, to);
+    let (_other_sim, _other) = server.connect_turtle_with(at(4, 0, 4), miner(0)).await;
+    let (_miner_sim, miner) = server.connect_turtle_with(at(1, 0, 1), miner(1)).await;
+    let miners = Selector::Group("miners".to_string());
+    server
+        .turtle_manager
+        .add_label(miner.as_str(), miners.clone())
+        .await
+        .unwrap();
+
+    client
+        .send(&Command::StartJob {
+            job: Job::Excavate {
+                from,
+                to,
+                dimension: Dimension::default(),
+            },
+            target: miners,
+        })
+        .await;
+    let progress = loop {
+        match client
+            .wait_for_event(|e| matches!(e, Event::JobProgress { .. }))
+            .await
+        {
+            Some(Event::JobProgress { progress }) if progress.finished => break progress,
+            Some(Event::JobProgress { progress }) => {
+                assert!(progress.turtles.iter().all(|t| *t == miner));
+            }
+            _ => panic!("Job did not finish"),
+        }
+    };
+    assert_eq!((progress.done, progress.failed, progress.total), (4, 0, 4));
+    assert!(server.is_empty(from, to));
+
+    server.close().await;
+}
+
+// Check turtles without a pickaxe are not given excavations.
+#[tokio::test]
+async fn check_excavate_needs_pickaxe() {
+    let server = TestServer::start_with_config(dispatch_config()).await;
+    let mut client = server.connect_client().await;
+    let (from, to) = (at(0, -1, -1), at(1, -2, -1));
+    server.fill(from, to);
+    let (_unarmed_sim, _unarmed) = server
+        .connect_turtle_with(at(4, 0, 4), TurtleConfig::new(0))
+        .await;
+    server
+        .turtle_manager
+        .start_job(
+            Job::Excavate {
+                from,
+                to,
+                dimension: Dimension::default(),
+            },
+            Selector::All,
+        )
+        .await
+        .unwrap();
+    let (_miner_sim, miner) = server.connect_turtle_with(at(3, 0, 0), miner(1)).await;
+
+    let progress = loop {
+        match client
+            .wait_for_event(|e| matches!(e, Event::JobProgress { .. }))
+            .await
+        {
+            Some(Event::JobProgress { progress }) if progress.finished => break progress,
+            Some(Event::JobProgress { progress }) => {
+                assert!(progress.turtles.iter().all(|t| *t == miner));
+            }
+            _ => panic!("Job did not finish"),
+        }
+    };
+    assert_eq!((progress.done, progress.failed), (2, 0));
+    assert!(server.is_empty(from, to));
+
+    server.close().await;
+}
+
+// Check a turtle that runs low on fuel gives its chunk back for another turtle to finish.
+#[tokio::test]
+async fn check_low_fuel_reassigned() {
+    let server = TestServer::start_with_config(dispatch_config()).await;
+    let mut client = server.connect_client().await;
+    let (from, to) = (at(0, -1, -1), at(1, -2, -1));
+    server.fill(from, to);
+
+    // Enough for the first column and the minimum but not the second column.
+    let mut config = miner(0);
+    config.fuel = 5;
+    let (_tired_sim, tired) = server.connect_turtle_with(at(0, 0, 0), config).await;
+    server
+        .turtle_manager
+        .start_job(
+            Job::Excavate {
+                from,
+                to,
+                dimension: Dimension::default(),
+            },
+            Selector::All,
+        )
+        .await
+        .unwrap();
+
+    let gave_back = |e: &Event| matches!(e, Event::JobProgress { progress } if progress.done == 1 && progress.turtles.is_empty());
+    assert!(client.wait_for_event(gave_back).await.is_some());
+    let (_fresh_sim, fresh) = server.connect_turtle_with(at(3, 0, 0), miner(1)).await;
+
+    let progress = client.wait_for_finish().await;
+    assert_eq!((progress.done, progress.failed), (2, 0));
+    assert!(server.is_empty(from, to));
+
+    let tired_info = server.turtle_manager.get_turtle(tired).await.unwrap();
+    let fresh_info = server.turtle_manager.get_turtle(fresh).await.unwrap();
+    assert_eq!(
+        tired_info.get_info().await.unwrap().coordinates,
+        at(0, -1, -1)
+    );
+    assert_eq!(
+        fresh_info.get_info().await.unwrap().coordinates,
+        at(1, -1, -1)
+    );
+
+    server.close().await;
+}
+
+// Check a chunk is finished by another turtle when its turtle disconnects part way through it.
+#[tokio::test]
+async fn check_disconnect_reassigned() {
+    let server = TestServer::start_with_config(dispatch_config()).await;
+    let mut client = server.connect_client().await;
+    let (from, to) = (at(0, -1, -1), at(1, -2, -1));
+    server.fill(from, to);
+
+    // Drops the connection once the first column is dug and the turtle is back at its top.
+    let mut config = miner(0);
+    config.fault = Some(Fault::DisconnectAfter(10));
+    let (dropped_sim, dropped) = server.connect_turtle_with(at(0, 0, -1), config).await;
+    server
+        .turtle_manager
+        .start_job(
+            Job::Excavate {
+                from,
+                to,
+                dimension: Dimension::default(),
+            },
+            Selector::All,
+        )
+        .await
+        .unwrap();
+
+    let gave_back = |e: &Event| matches!(e, Event::JobProgress { progress } if progress.done == 1 && progress.turtles.is_empty());
+    assert!(client.wait_for_event(gave_back).await.is_some());
+    assert!(
+        !dropped_sim.get_state().connected,
+        "{dropped} did not disconnect"
+    );
+    let (_sim, finisher) = server.connect_turtle_with(at(1, 0, -1), miner(1)).await;
+
+    let progress = client.wait_for_finish().await;
+    assert_eq!((progress.done, progress.failed), (2, 0));
+    assert!(server.is_empty(from, to));
+    let finisher = server.turtle_manager.get_turtle(finisher).await.unwrap();
+    assert_eq!(
+        finisher.get_info().await.unwrap().coordinates,
+        at(1, -1, -1)
+    );
+
+    server.close().await;
+}
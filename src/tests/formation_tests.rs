@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use super::harness::{at, from_sim, to_sim, to_sim_heading, TestClient, TestServer, TIMEOUT};
+use crate::client_scheme::Event;
+use crate::error::Error;
+use crate::formation::Formation;
+use crate::route_planner::{self, distance};
+use crate::scheme::{Coordinates, Dimension, Heading, TurtleType};
+use crate::turtle_manager::TurtleManagerConfig;
+
+// Check the places in each formation fill to the right and then backwards.
+#[test]
+fn check_slots() {
+    let origin = at(0, 64, 0);
+    assert_eq!(
+        Formation::Line.slots(3, origin, Heading::North),
+        vec![at(0, 64, 0), at(1, 64, 0), at(2, 64, 0)]
+    );
+    assert_eq!(
+        Formation::Column.slots(3, origin, Heading::East),
+        vec![at(0, 64, 0), at(-1, 64, 0), at(-2, 64, 0)]
+    );
+    assert_eq!(
+        Formation::Grid { width: 2 }.slots(3, origin, Heading::South),
+        vec![at(0, 64, 0), at(-1, 64, 0), at(0, 64, -1)]
+    );
+    assert_eq!(
+        Formation::from_str("grid:2"),
+        Some(Formation::Grid { width: 2 })
+    );
+    assert_eq!(Formation::from_str("blob"), None);
+}
+
+/// Checks every path goes from its start to its goal a block at a time and that no turtle is ever
+/// in, or moves into, a block another turtle is in.
+fn check_paths(moves: &[(Coordinates, Coordinates)], paths: &[Vec<Coordinates>]) {
+    let steps = paths.iter().map(Vec::len).max().unwrap();
+    let at = |path: &Vec<Coordinates>, t: usize| path[t.min(path.len() - 1)];
+    for ((start, goal), path) in moves.iter().zip(paths) {
+        assert_eq!(path.first(), Some(start));
+        assert_eq!(path.last(), Some(goal));
+        assert!(path.windows(2).all(|w| distance(w[0], w[1]) <= 1));
+    }
+
+    for t in 0..steps {
+        for (a, path) in paths.iter().enumerate() {
+            for (b, other) in paths.iter().enumerate().filter(|(b, _)| *b != a) {
+                assert_ne!(at(path, t), at(other, t), "{a} and {b} collide at step {t}");
+                assert!(
+                    t == 0 || at(path, t) != at(other, t - 1),
+                    "{a} moves into the block {b} is leaving at step {t}"
+                );
+            }
+        }
+    }
+}
+
+// Check planned paths keep turtles apart when they have to pass, follow or make way for each
+// other.
+#[test]
+fn check_planned_paths() {
+    let scenarios = [
+        // Head on along a line.
+        vec![(at(0, 0, 0), at(3, 0, 0)), (at(3, 0, 0), at(0, 0, 0))],
+        // A column moving up by one so each turtle's goal is where the one in front started.
+        vec![
+            (at(0, 0, 0), at(0, 0, -1)),
+            (at(0, 0, 1), at(0, 0, 0)),
+            (at(0, 0, 2), at(0, 0, 1)),
+        ],
+        // A column spreading into a line in front of it.
+        vec![
+            (at(0, 0, 0), at(0, 0, -3)),
+            (at(0, 0, 1), at(1, 0, -3)),
+            (at(0, 0, 2), at(2, 0, -3)),
+        ],
+        // Crossing paths.
+        vec![(at(0, 0, 0), at(2, 0, 2)), (at(2, 0, 0), at(0, 0, 2))],
+    ];
+
+    for moves in scenarios {
+        let paths = route_planner::plan(&moves).expect("Paths should be found");
+        check_paths(&moves, &paths);
+    }
+}
+
+/// Waits for the client to be told a formation stopped moving and gets its turtles and why it
+/// failed if it did.
+async fn formation_moved(client: &mut TestClient) -> (Vec<String>, Option<Error>) {
+    match client
+        .wait_for_event(|e| matches!(e, Event::FormationMoved { .. }))
+        .await
+    {
+        Some(Event::FormationMoved { names, error }) => (names, error),
+        _ => panic!("Formation did not stop moving"),
+    }
+}
+
+// Check a column of turtles can spread out into a line without running into each other.
+#[tokio::test]
+async fn check_column_to_line() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let mut turtles = vec![];
+    for (id, z) in [(0, 0), (1, 1), (2, 2)] {
+        turtles.push(server.connect_turtle_at(id, at(0, 0, z)).await);
+    }
+    let names: Vec<String> = turtles.iter().map(|(_, name)| name.clone()).collect();
+
+    server
+        .turtle_manager
+        .move_formation(names.clone(), Formation::Line, at(0, 0, -3), Heading::North)
+        .await
+        .unwrap();
+    assert_eq!(formation_moved(&mut client).await, (names, None));
+
+    let mut positions = vec![];
+    for (sim, _) in turtles.iter() {
+        let state = sim
+            .wait_for(TIMEOUT, |s| {
+                s.heading == to_sim_heading(Heading::North) && s.position.z == -3
+            })
+            .await
+            .expect("Turtle did not end up in line facing north");
+        positions.push(from_sim(state.position));
+    }
+    positions.sort_by_key(|p| p.x);
+    assert_eq!(positions, vec![at(0, 0, -3), at(1, 0, -3), at(2, 0, -3)]);
+
+    server.close().await;
+}
+
+// Check every turtle goes back to where it started if one of them stays blocked.
+#[tokio::test]
+async fn check_blocked_formation_goes_back() {
+    let mut config = TurtleManagerConfig::default();
+    config.formation.retries = 1;
+    config.formation.retry_wait = Duration::from_millis(10);
+    let server = TestServer::start_with_config(config).await;
+    let mut client = server.connect_client().await;
+
+    server
+        .world
+        .lock()
+        .unwrap()
+        .set_block(to_sim(at(1, 0, -2)), "minecraft:stone");
+    let (left, left_name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    let (right, right_name) = server.connect_turtle_at(1, at(1, 0, 0)).await;
+
+    let names = vec![left_name, right_name.clone()];
+    server
+        .turtle_manager
+        .move_formation(names.clone(), Formation::Line, at(0, 0, -3), Heading::North)
+        .await
+        .expect("Turtles should set off");
+    assert_eq!(
+        formation_moved(&mut client).await,
+        (
+            names,
+            Some(Error::Blocked {
+                name: right_name,
+                position: at(1, 0, -2),
+            })
+        )
+    );
+
+    for (sim, start) in [(left, at(0, 0, 0)), (right, at(1, 0, 0))] {
+        assert_eq!(from_sim(sim.get_state().position), start);
+    }
+
+    server.close().await;
+}
+
+// Check turtles in different dimensions can't be put in the same formation.
+#[tokio::test]
+async fn check_formation_in_one_dimension() {
+    let server = TestServer::start().await;
+    let nether = Dimension::new("minecraft:the_nether", None);
+    for (name, dimension) in [("Aaren", Dimension::default()), ("Aarika", nether)] {
+        server
+            .turtle_manager
+            .add_turtle(
+                name,
+                at(0, 0, 0),
+                dimension,
+                Heading::North,
+                TurtleType::Normal,
+            )
+            .await
+            .unwrap();
+    }
+
+    let result = server
+        .turtle_manager
+        .move_formation(
+            vec!["Aaren".to_string(), "Aarika".to_string()],
+            Formation::Line,
+            at(0, 0, -3),
+            Heading::North,
+        )
+        .await;
+    assert_eq!(
+        result,
+        Err(Error::WrongDimension {
+            name: "Aarika".to_string(),
+            dimension: Dimension::default(),
+        })
+    );
+
+    server.close().await;
+}
@@ -0,0 +1,371 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use sqlx::SqlitePool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use turtle_sim::{SharedWorld, SimTurtleHandle, TurtleConfig, World};
+
+use crate::acceptor::AcceptorHandle;
+use crate::client_manager::ClientManagerHandle;
+use crate::client_scheme::{Command, Event};
+use crate::db;
+use crate::dispatcher::JobProgress;
+use crate::scheme::{Coordinates, Heading};
+use crate::turtle_manager::{TurtleManagerConfig, TurtleManagerHandle};
+
+/// How long tests wait for something to happen before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Token clients log in with to become admins.
+pub const ADMIN_TOKEN: &str = "admin";
+
+/// The whole server running in process with an in memory database.
+pub struct TestServer {
+    pub pool: SqlitePool,
+    pub turtle_manager: TurtleManagerHandle,
+    pub turtle_acceptor: AcceptorHandle,
+    pub client_manager: ClientManagerHandle,
+    pub client_acceptor: AcceptorHandle,
+    pub metrics_acceptor: AcceptorHandle,
+
+    /// Websocket url simulated turtles connect to.
+    pub turtle_url: String,
+
+    /// Address clients connect to.
+    pub client_addr: SocketAddr,
+
+    /// Address the metrics endpoint is served on.
+    pub metrics_addr: SocketAddr,
+
+    /// World shared by every turtle spawned through this server.
+    pub world: SharedWorld,
+}
+
+impl TestServer {
+    /// Starts the server the same way main does but on ephemeral ports.
+    pub async fn start() -> Self {
+        Self::start_with_config(TurtleManagerConfig::default()).await
+    }
+
+    /// Starts the server with the turtle manager using `config`.
+    pub async fn start_with_config(config: TurtleManagerConfig) -> Self {
+        let pool = db::setup_memory_database()
+            .await
+            .expect("Problem setting up memory database");
+
+        let turtle_manager = TurtleManagerHandle::new(pool.clone(), config);
+        let turtle_acceptor =
+            AcceptorHandle::new_websocket("127.0.0.1:0".to_string(), turtle_manager.clone());
+
+        let client_manager = ClientManagerHandle::new();
+        let client_acceptor = AcceptorHandle::new_client(
+            "127.0.0.1:0".to_string(),
+            client_manager.clone(),
+            turtle_manager.clone(),
+            Some(ADMIN_TOKEN.to_string()),
+        );
+
+        let metrics_acceptor = AcceptorHandle::new_metrics(
+            "127.0.0.1:0".to_string(),
+            turtle_manager.metrics().clone(),
+        );
+
+        let turtle_addr = turtle_acceptor
+            .local_addr()
+            .await
+            .expect("Turtle acceptor did not start");
+        let client_addr = client_acceptor
+            .local_addr()
+            .await
+            .expect("Client acceptor did not start");
+        let metrics_addr = metrics_acceptor
+            .local_addr()
+            .await
+            .expect("Metrics acceptor did not start");
+
+        TestServer {
+            pool,
+            turtle_manager,
+            turtle_acceptor,
+            client_manager,
+            client_acceptor,
+            metrics_acceptor,
+            turtle_url: format!("ws://{turtle_addr}"),
+            client_addr,
+            metrics_addr,
+            world: World::shared(),
+        }
+    }
+
+    /// Connects a simulated turtle to the server.
+    pub fn spawn_turtle(&self, config: TurtleConfig) -> SimTurtleHandle {
+        SimTurtleHandle::new(self.turtle_url.clone(), config, self.world.clone())
+    }
+
+    /// Connects a simulated turtle and waits for it to be given a name.
+    pub async fn connect_turtle(&self, config: TurtleConfig) -> (SimTurtleHandle, String) {
+        let turtle = self.spawn_turtle(config);
+        let state = turtle
+            .wait_for(TIMEOUT, |s| s.connected)
+            .await
+            .expect("Turtle did not connect");
+        let name = state.name.unwrap();
+
+        // The manager adds the turtle once the handshake finishes in the background.
+        let added = eventually(|| async {
+            self.turtle_manager
+                .get_turtle(name.as_str())
+                .await
+                .is_some_and(|mut t| t.get_connection_mut().get_connection().is_some())
+        })
+        .await;
+        assert!(added, "{name} was not added");
+
+        (turtle, name)
+    }
+
+    /// Connects a simulated turtle at `position` facing north that knows where it is and waits for
+    /// the server to store its position.
+    pub async fn connect_turtle_at(
+        &self,
+        id: u64,
+        position: Coordinates,
+    ) -> (SimTurtleHandle, String) {
+        self.connect_turtle_with(position, TurtleConfig::new(id))
+            .await
+    }
+
+    /// Connects a simulated turtle set up by `config` at `position` facing north that knows where
+    /// it is and waits for the server to store its position and fuel.
+    pub async fn connect_turtle_with(
+        &self,
+        position: Coordinates,
+        mut config: TurtleConfig,
+    ) -> (SimTurtleHandle, String) {
+        config.position = to_sim(position);
+        config.position_file = Some((to_sim(position), to_sim_heading(Heading::North)));
+        let fuel = config.fuel;
+        let (sim, name) = self.connect_turtle(config).await;
+
+        let registered = eventually(|| async {
+            match self.turtle_manager.get_turtle(name.as_str()).await {
+                Some(turtle) => turtle
+                    .get_info()
+                    .await
+                    .is_some_and(|t| t.coordinates == position && t.fuel.level == fuel),
+                None => false,
+            }
+        })
+        .await;
+        assert!(registered, "{name} was not registered");
+
+        (sim, name)
+    }
+
+    pub async fn connect_client(&self) -> TestClient {
+        TestClient::connect(self.client_addr).await
+    }
+
+    /// Shuts the server down in the same order main does.
+    pub async fn close(&self) {
+        self.turtle_manager.return_all_home().await;
+        self.turtle_acceptor.close().await;
+        self.turtle_manager.close().await;
+        self.client_acceptor.close().await;
+        self.client_manager.close().await;
+        self.metrics_acceptor.close().await;
+    }
+
+    /// Makes a http GET request to the metrics endpoint.
+    /// Returns the whole response including the status line and headers.
+    pub async fn get_metrics(&self, path: &str) -> String {
+        let mut stream = TcpStream::connect(self.metrics_addr)
+            .await
+            .expect("Problem connecting to metrics endpoint");
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        tokio::time::timeout(TIMEOUT, stream.read_to_string(&mut response))
+            .await
+            .expect("Timeout reading metrics")
+            .unwrap();
+
+        response
+    }
+
+    /// Puts stone in every block in the box between two corners.
+    pub fn fill(&self, from: Coordinates, to: Coordinates) {
+        let mut world = self.world.lock().unwrap();
+        for position in positions(from, to) {
+            world.set_block(to_sim(position), "minecraft:stone");
+        }
+    }
+
+    /// Whether every block in the box between two corners is empty.
+    pub fn is_empty(&self, from: Coordinates, to: Coordinates) -> bool {
+        let world = self.world.lock().unwrap();
+        positions(from, to)
+            .into_iter()
+            .all(|p| world.get_block(to_sim(p)).is_none())
+    }
+}
+
+/// A client connected to the server over tcp.
+pub struct TestClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    events: Vec<Event>,
+}
+
+impl TestClient {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr)
+            .await
+            .expect("Problem connecting client");
+
+        TestClient {
+            stream,
+            buffer: vec![],
+            events: vec![],
+        }
+    }
+
+    pub async fn send(&mut self, command: &Command) {
+        let data = turtle_tcp::message_to_bytes(command).unwrap();
+        self.stream
+            .write_all(&data)
+            .await
+            .expect("Problem sending command");
+    }
+
+    /// Waits for the next event from the server.
+    pub async fn next_event(&mut self, timeout: Duration) -> Option<Event> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        while self.events.is_empty() {
+            let mut data = [0; 1024];
+            let n = match tokio::time::timeout_at(deadline, self.stream.read(&mut data)).await {
+                Ok(Ok(n)) if n > 0 => n,
+                _ => return None,
+            };
+
+            self.buffer.extend_from_slice(&data[0..n]);
+            self.events
+                .extend(turtle_tcp::parse_buffer::<Event>(&mut self.buffer));
+        }
+
+        Some(self.events.remove(0))
+    }
+
+    /// Skips events until one matches `predicate`.
+    pub async fn wait_for_event(&mut self, predicate: impl Fn(&Event) -> bool) -> Option<Event> {
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let event = self.next_event(remaining).await?;
+            if predicate(&event) {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Waits for a job to finish and gets how it went.
+    pub async fn wait_for_finish(&mut self) -> JobProgress {
+        let finished =
+            |e: &Event| matches!(e, Event::JobProgress { progress } if progress.finished);
+        match self.wait_for_event(finished).await {
+            Some(Event::JobProgress { progress }) => progress,
+            _ => panic!("Job did not finish"),
+        }
+    }
+}
+
+/// Config where failing moves give up on reservations quickly so they don't hold tests up.
+pub fn reservation_config() -> TurtleManagerConfig {
+    let mut config = TurtleManagerConfig::default();
+    config.reservations.wait = Duration::from_millis(200);
+    config.reservations.retry_interval = Duration::from_millis(20);
+    config
+}
+
+/// Config where jobs are split into small chunks that are handed out quickly.
+pub fn dispatch_config() -> TurtleManagerConfig {
+    let mut config = TurtleManagerConfig::default();
+    config.dispatch.chunk_size = 2;
+    config.dispatch.min_fuel = 1;
+    config.dispatch.poll_interval = Duration::from_millis(20);
+    config
+}
+
+/// Config for a simulated turtle with a pickaxe so it can be given excavations.
+pub fn miner(id: u64) -> TurtleConfig {
+    let mut config = TurtleConfig::new(id);
+    config.upgrades.left = Some("minecraft:diamond_pickaxe".to_string());
+    config
+}
+
+pub fn at(x: i64, y: i64, z: i64) -> Coordinates {
+    Coordinates { x, y, z }
+}
+
+/// Gets every position in the box between two corners.
+pub fn positions(from: Coordinates, to: Coordinates) -> Vec<Coordinates> {
+    let mut positions = vec![];
+    for x in from.x.min(to.x)..=from.x.max(to.x) {
+        for y in from.y.min(to.y)..=from.y.max(to.y) {
+            for z in from.z.min(to.z)..=from.z.max(to.z) {
+                positions.push(at(x, y, z));
+            }
+        }
+    }
+    positions
+}
+
+/// Polls `check` until it returns true or the test timeout passes.
+pub async fn eventually<F, Fut>(mut check: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+
+    while tokio::time::Instant::now() < deadline {
+        if check().await {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    false
+}
+
+/// Converts coordinates into the simulator's own coordinate type.
+pub fn to_sim(coordinates: Coordinates) -> turtle_sim::protocol::Coordinates {
+    turtle_sim::protocol::Coordinates {
+        x: coordinates.x,
+        y: coordinates.y,
+        z: coordinates.z,
+    }
+}
+
+/// Converts coordinates from the simulator's own coordinate type.
+pub fn from_sim(coordinates: turtle_sim::protocol::Coordinates) -> Coordinates {
+    Coordinates {
+        x: coordinates.x,
+        y: coordinates.y,
+        z: coordinates.z,
+    }
+}
+
+/// Converts a heading into the simulator's own heading type.
+pub fn to_sim_heading(heading: Heading) -> turtle_sim::protocol::Heading {
+    match heading {
+        Heading::North => turtle_sim::protocol::Heading::North,
+        Heading::East => turtle_sim::protocol::Heading::East,
+        Heading::South => turtle_sim::protocol::Heading::South,
+        Heading::West => turtle_sim::protocol::Heading::West,
+    }
+}
@@ -0,0 +1,298 @@
+use std::time::Duration;
+
+use turtle_sim::{ItemStack, SimTurtleHandle, TurtleConfig};
+
+use super::harness::{at, miner, to_sim, to_sim_heading, TestServer, TIMEOUT};
+use crate::client_scheme::{Command, Event};
+use crate::dispatcher::Job;
+use crate::error::Error;
+use crate::scheme::{Coordinates, Dimension, Direction, Heading, Waypoint};
+use crate::selector::Selector;
+use crate::turtle_manager::TurtleManagerConfig;
+use crate::turtle_scheme::{RequestType, ResponseType};
+
+fn waypoint(name: &str, position: Coordinates, heading: Heading) -> Waypoint {
+    Waypoint {
+        name: name.to_string(),
+        position,
+        heading,
+        dimension: Dimension::default(),
+    }
+}
+
+/// Waits for the simulated turtle to be at `waypoint` facing its heading.
+async fn wait_until_at(sim: &SimTurtleHandle, waypoint: &Waypoint) -> bool {
+    let (position, heading) = (to_sim(waypoint.position), to_sim_heading(waypoint.heading));
+    sim.wait_for(TIMEOUT, |s| s.position == position && s.heading == heading)
+        .await
+        .is_some()
+}
+
+/// Turtle with a pickaxe whose every slot is full.
+fn full_turtle(id: u64) -> TurtleConfig {
+    let mut config = miner(id);
+    config.inventory = std::array::from_fn(|_| {
+        Some(ItemStack {
+            name: "minecraft:dirt".to_string(),
+            count: 64,
+        })
+    });
+    config
+}
+
+// Check waypoints can be stored, listed and removed and removing one clears the homes using it.
+#[tokio::test]
+async fn check_waypoints() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (_sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+
+    let mine = waypoint("mine", at(5, -20, 5), Heading::West);
+    let base = waypoint("base", at(1, 2, 3), Heading::East);
+    for waypoint in [mine.clone(), base.clone()] {
+        client.send(&Command::SetWaypoint { waypoint }).await;
+    }
+    client.send(&Command::GetWaypoints).await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Waypoints { .. }))
+        .await;
+    assert!(matches!(event, Some(Event::Waypoints { waypoints }) if waypoints == vec![base, mine]));
+
+    client
+        .send(&Command::SetHome {
+            name: name.clone(),
+            waypoint: Some("nowhere".to_string()),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Error {
+            error: Error::UnknownWaypoint { name },
+            ..
+        }) if name == "nowhere"
+    ));
+
+    client
+        .send(&Command::SetHome {
+            name: name.clone(),
+            waypoint: Some("base".to_string()),
+        })
+        .await;
+    client
+        .send(&Command::RemoveWaypoint {
+            name: "base".to_string(),
+        })
+        .await;
+    client
+        .send(&Command::ReturnHome {
+            target: Selector::Name(name.clone()),
+        })
+        .await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Error {
+            command: Command::ReturnHome { .. },
+            error: Error::NoHome { name: n },
+        }) if n == name
+    ));
+
+    server.close().await;
+}
+
+// Check a turtle sent home climbs over, goes across and ends up facing the waypoint's heading.
+#[tokio::test]
+async fn check_return_home() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    server
+        .world
+        .lock()
+        .unwrap()
+        .set_block(to_sim(at(0, 0, -1)), "minecraft:stone");
+
+    let base = waypoint("base", at(2, 1, -2), Heading::East);
+    let manager = &server.turtle_manager;
+    manager.set_waypoint(base.clone()).await.unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    client
+        .send(&Command::ReturnHome {
+            target: Selector::Name(name.clone()),
+        })
+        .await;
+    let returned = client
+        .wait_for_event(|e| matches!(e, Event::ReturnedHome { .. }))
+        .await;
+    assert!(matches!(
+        returned,
+        Some(Event::ReturnedHome { name: n, waypoint, error: None }) if n == name && waypoint == "base"
+    ));
+    assert!(wait_until_at(&sim, &base).await);
+    // Going home does not dig.
+    let dug = server
+        .world
+        .lock()
+        .unwrap()
+        .get_block(to_sim(at(0, 0, -1)))
+        .is_none();
+    assert!(!dug);
+
+    server.close().await;
+}
+
+// Check an idle turtle is sent home when its fuel drops below the low fuel level.
+#[tokio::test]
+async fn check_low_fuel_return_home() {
+    let mut config = TurtleManagerConfig::default();
+    config.home.low_fuel = 100;
+    let server = TestServer::start_with_config(config).await;
+
+    let mut turtle_config = TurtleConfig::new(0);
+    turtle_config.fuel = 102;
+    let (sim, name) = server.connect_turtle_with(at(0, 0, 0), turtle_config).await;
+
+    let base = waypoint("base", at(0, 0, 0), Heading::South);
+    let manager = &server.turtle_manager;
+    manager.set_waypoint(base.clone()).await.unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    let turtle = manager.get_turtle(name.as_str()).await.unwrap();
+    for _ in 0..3 {
+        turtle.move_turtle(Direction::Forward).await.unwrap();
+    }
+    assert!(sim
+        .wait_for(TIMEOUT, |s| s.position == to_sim(at(0, 0, -3)))
+        .await
+        .is_some());
+
+    assert!(wait_until_at(&sim, &base).await);
+
+    server.close().await;
+}
+
+// Check a turtle with a full inventory gives its chunk back and goes home.
+#[tokio::test]
+async fn check_full_inventory_return_home() {
+    let mut config = TurtleManagerConfig::default();
+    config.dispatch.min_fuel = 1;
+    config.dispatch.poll_interval = Duration::from_millis(20);
+    let server = TestServer::start_with_config(config).await;
+    let mut client = server.connect_client().await;
+
+    let (sim, name) = server
+        .connect_turtle_with(at(0, 0, 1), full_turtle(0))
+        .await;
+
+    let base = waypoint("base", at(0, 0, 3), Heading::North);
+    let manager = &server.turtle_manager;
+    manager.set_waypoint(base.clone()).await.unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    manager
+        .start_job(
+            Job::Excavate {
+                from: at(0, -1, -1),
+                to: at(0, -1, -1),
+                dimension: Dimension::default(),
+            },
+            Selector::All,
+        )
+        .await
+        .unwrap();
+    let gave_back = |e: &Event| matches!(e, Event::JobProgress { progress } if progress.done == 0 && progress.turtles.is_empty() && progress.total == 1);
+    let started =
+        |e: &Event| matches!(e, Event::JobProgress { progress } if !progress.turtles.is_empty());
+    assert!(client.wait_for_event(started).await.is_some());
+    assert!(client.wait_for_event(gave_back).await.is_some());
+
+    assert!(wait_until_at(&sim, &base).await);
+
+    server.close().await;
+}
+
+// Check an idle turtle is sent home when it lists its inventory and finds it full.
+#[tokio::test]
+async fn check_idle_full_inventory_return_home() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server
+        .connect_turtle_with(at(0, 0, 1), full_turtle(0))
+        .await;
+
+    let base = waypoint("base", at(0, 0, 3), Heading::North);
+    let manager = &server.turtle_manager;
+    manager.set_waypoint(base.clone()).await.unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    let turtle = manager.get_turtle(name.as_str()).await.unwrap();
+    assert!(turtle.list_items().await.unwrap().is_full());
+    let returned = client
+        .wait_for_event(|e| matches!(e, Event::ReturnedHome { .. }))
+        .await;
+    assert!(matches!(
+        returned,
+        Some(Event::ReturnedHome { error: None, .. })
+    ));
+    assert!(wait_until_at(&sim, &base).await);
+
+    server.close().await;
+}
+
+// Check a turtle that finds its inventory full while it is locked is still sent home.
+#[tokio::test]
+async fn check_locked_full_inventory_return_home() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server
+        .connect_turtle_with(at(0, 0, 1), full_turtle(0))
+        .await;
+
+    let base = waypoint("base", at(0, 0, 3), Heading::North);
+    let manager = &server.turtle_manager;
+    manager.set_waypoint(base.clone()).await.unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    let mut turtle = manager.get_turtle(name.as_str()).await.unwrap();
+    let connection = turtle
+        .get_connection_mut()
+        .get_connection()
+        .unwrap()
+        .clone();
+    let lock = connection.lock().await.unwrap();
+    let listed = lock.request(RequestType::ItemList).await;
+    assert!(matches!(listed, Ok(ResponseType::Inventory { inventory }) if inventory.is_full()));
+    lock.unlock().await;
+
+    let returned = client
+        .wait_for_event(|e| matches!(e, Event::ReturnedHome { .. }))
+        .await;
+    assert!(matches!(
+        returned,
+        Some(Event::ReturnedHome { error: None, .. })
+    ));
+    assert!(wait_until_at(&sim, &base).await);
+
+    server.close().await;
+}
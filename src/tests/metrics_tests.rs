@@ -0,0 +1,92 @@
+use turtle_sim::TurtleConfig;
+
+use super::harness::{eventually, TestServer};
+use crate::scheme::{Coordinates, Dimension, Heading, Waypoint};
+use crate::turtle_scheme::{RequestType, TurtleCommand};
+
+// Check that the metrics endpoint reports turtles, their fuel and request durations.
+#[tokio::test]
+async fn check_metrics() {
+    let server = TestServer::start().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.request(RequestType::Ping).await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+
+    let fuel = format!(
+        "turtle_wrangler_turtle_fuel{{name=\"{name}\"}} {}",
+        TurtleConfig::NORMAL_FUEL_LIMIT - 1
+    );
+    assert!(eventually(|| async { server.get_metrics("/metrics").await.contains(&fuel) }).await);
+
+    let response = server.get_metrics("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(&format!(
+        "turtle_wrangler_command_queue_depth{{name=\"{name}\"}} 0"
+    )));
+    assert!(response.contains("# TYPE turtle_wrangler_request_duration_seconds histogram"));
+    assert!(!response.contains("turtle_wrangler_request_duration_seconds_count 0\n"));
+    assert!(
+        response.contains("turtle_wrangler_db_query_duration_seconds_count{query=\"set_fuel\"}")
+    );
+
+    server.close().await;
+}
+
+// Check that db queries are only counted by the manager that made them.
+#[tokio::test]
+async fn check_db_metrics_per_manager() {
+    let server = TestServer::start().await;
+    let other = TestServer::start().await;
+    server
+        .turtle_manager
+        .set_waypoint(Waypoint {
+            name: "base".to_string(),
+            position: Coordinates { x: 1, y: 2, z: 3 },
+            heading: Heading::East,
+            dimension: Dimension::default(),
+        })
+        .await
+        .unwrap();
+
+    let set_waypoint = "turtle_wrangler_db_query_duration_seconds_count{query=\"set_waypoint\"}";
+    assert!(server.get_metrics("/metrics").await.contains(set_waypoint));
+    assert!(!other.get_metrics("/metrics").await.contains(set_waypoint));
+
+    server.close().await;
+    other.close().await;
+}
+
+// Check that the turtle counts follow a turtle connecting and disconnecting.
+#[tokio::test]
+async fn check_metrics_turtle_counts() {
+    let server = TestServer::start().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    assert!(eventually(|| has_turtle_counts(&server, 1, 0)).await);
+
+    server.turtle_manager.disconnect(&name).await.unwrap();
+    assert!(eventually(|| has_turtle_counts(&server, 0, 1)).await);
+
+    server.close().await;
+}
+
+async fn has_turtle_counts(server: &TestServer, connected: u32, disconnected: u32) -> bool {
+    let response = server.get_metrics("/metrics").await;
+    response.contains(&format!(
+        "turtle_wrangler_turtles{{status=\"connected\"}} {connected}\n"
+    )) && response.contains(&format!(
+        "turtle_wrangler_turtles{{status=\"disconnected\"}} {disconnected}\n"
+    ))
+}
+
+// Check that anything other than /metrics is not found.
+#[tokio::test]
+async fn check_metrics_not_found() {
+    let server = TestServer::start().await;
+
+    let response = server.get_metrics("/").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+    server.close().await;
+}
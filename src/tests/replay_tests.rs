@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use turtle_sim::TurtleConfig;
+
+use super::harness::{eventually, TestServer, TIMEOUT};
+use crate::db::turtle_operations::TurtleDB;
+use crate::scheme::{Coordinates, Dimension, Heading, TurtleType};
+use crate::turtle_manager::{read_session, replay_session, FrameDirection, TurtleManagerConfig};
+use crate::turtle_scheme::TurtleCommand;
+
+/// Directory for a test's recordings that no other test uses.
+fn record_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("turtle-wrangler-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Finds the only session recorded in `dir`.
+fn recorded_session(dir: &Path) -> PathBuf {
+    let mut sessions: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(sessions.len(), 1);
+    sessions.remove(0)
+}
+
+async fn add_turtle(server: &TestServer) {
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            Coordinates { x: 0, y: 0, z: 0 },
+            Dimension::default(),
+            Heading::North,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+}
+
+// Check that a recorded session replayed into a new server sends the turtle the same frames and
+// leaves the database in the same state.
+#[tokio::test]
+async fn check_record_and_replay() {
+    let dir = record_dir("replay");
+    let server = TestServer::start_with_config(TurtleManagerConfig {
+        record_dir: Some(dir.clone()),
+        ..Default::default()
+    })
+    .await;
+    add_turtle(&server).await;
+
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| s.commands.len() == 2)
+        .await
+        .unwrap();
+
+    let expected_position = Coordinates { x: 0, y: 0, z: -1 };
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_coordinates().await == Some(expected_position)
+                && db.get_heading().await == Some(Heading::West)
+        })
+        .await
+    );
+    sim.close().await;
+    server.close().await;
+
+    let frames = read_session(&recorded_session(&dir)).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let recorded: Vec<_> = frames
+        .iter()
+        .filter(|f| f.direction == FrameDirection::Out)
+        .map(|f| f.message.clone())
+        .collect();
+    assert_eq!(recorded[0], name);
+
+    let server = TestServer::start().await;
+    add_turtle(&server).await;
+    let manager = server.turtle_manager.clone();
+    let replay = tokio::spawn(async move { replay_session(&frames, &manager).await });
+
+    // Commands from outside the turtle are not in the recording so have to be sent again.
+    assert!(
+        eventually(|| async {
+            server
+                .turtle_manager
+                .get_status()
+                .await
+                .is_some_and(|s| s.contains("Connected"))
+        })
+        .await
+    );
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+
+    let sent = replay.await.unwrap().unwrap();
+    assert_eq!(sent, recorded);
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_coordinates().await == Some(expected_position)
+                && db.get_heading().await == Some(Heading::West)
+        })
+        .await
+    );
+
+    server.close().await;
+}
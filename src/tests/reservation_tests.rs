@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use turtle_sim::{TurtleBlock, TurtleConfig};
+
+use super::harness::{at, eventually, from_sim, reservation_config, to_sim, TestServer, TIMEOUT};
+use crate::client_scheme::{Command, Event};
+use crate::error::Error;
+use crate::scheme::Direction;
+use crate::selector::Selector;
+use crate::turtle_scheme::TurtleCommand;
+
+// Check a turtle waits for a block reserved for another turtle and moves once it is freed.
+#[tokio::test]
+async fn check_reserved_block() {
+    let server = TestServer::start_with_config(reservation_config()).await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    let (_other, other_name) = server.connect_turtle_at(1, at(5, 0, 0)).await;
+
+    let front = at(0, 0, -1);
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), front)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .turtle_manager
+            .move_turtle(name.as_str(), Direction::Forward)
+            .await,
+        Err(Error::Reserved {
+            position: front,
+            by: Some(other_name.clone()),
+        })
+    );
+
+    client.send(&Command::GetReservations).await;
+    let reservations = match client
+        .wait_for_event(|e| matches!(e, Event::Reservations { .. }))
+        .await
+    {
+        Some(Event::Reservations { reservations }) => reservations,
+        _ => panic!("Did not get reservations"),
+    };
+    assert_eq!(reservations.len(), 1);
+    assert_eq!(reservations[0].position, front);
+    assert_eq!(reservations[0].name.as_ref(), Some(&other_name));
+
+    // Reserving somewhere else frees the block in front.
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), at(5, 0, -1))
+        .await
+        .unwrap();
+    server
+        .turtle_manager
+        .move_turtle(name.as_str(), Direction::Forward)
+        .await
+        .unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| !s.commands.is_empty())
+        .await
+        .expect("Turtle did not move");
+    assert_eq!(from_sim(state.position), front);
+
+    server.close().await;
+}
+
+// Check turtles don't move into blocks other turtles are in, whether the server knows the turtle
+// or only saw it by inspecting.
+#[tokio::test]
+async fn check_occupied_block() {
+    let server = TestServer::start_with_config(reservation_config()).await;
+    let (_sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    let (_behind, behind_name) = server.connect_turtle_at(1, at(0, 0, 1)).await;
+
+    assert_eq!(
+        server
+            .turtle_manager
+            .move_turtle(name.as_str(), Direction::Back)
+            .await,
+        Err(Error::Reserved {
+            position: at(0, 0, 1),
+            by: Some(behind_name),
+        })
+    );
+
+    let front = at(0, 0, -1);
+    server.world.lock().unwrap().place_turtle(
+        to_sim(front),
+        TurtleBlock {
+            heading: turtle_sim::protocol::Heading::South,
+            advanced: false,
+        },
+    );
+    let turtle = server
+        .turtle_manager
+        .get_turtle(name.as_str())
+        .await
+        .unwrap();
+    turtle.send(TurtleCommand::Inspect).await.unwrap();
+
+    let seen = eventually(|| async {
+        server
+            .turtle_manager
+            .get_reservations()
+            .await
+            .iter()
+            .any(|r| r.position == front && r.name.is_none())
+    })
+    .await;
+    assert!(seen, "Inspected turtle was not reserved");
+    assert_eq!(
+        server
+            .turtle_manager
+            .move_turtle(name.as_str(), Direction::Forward)
+            .await,
+        Err(Error::Reserved {
+            position: front,
+            by: None,
+        })
+    );
+
+    server.close().await;
+}
+
+// Check that the client's other commands are answered while its move waits on a reserved block
+// and that the move is reported once it gives up.
+#[tokio::test]
+async fn check_client_move_reserved() {
+    let server = TestServer::start_with_config(reservation_config()).await;
+    let mut client = server.connect_client().await;
+    let (sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    let (_other, other_name) = server.connect_turtle_at(1, at(5, 0, 0)).await;
+
+    let front = at(0, 0, -1);
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), front)
+        .await
+        .unwrap();
+    client
+        .send(&Command::Move {
+            target: Selector::Name(name),
+            direction: Direction::Forward,
+        })
+        .await;
+    client.send(&Command::GetReservations).await;
+
+    let answered = |e: &Event| matches!(e, Event::Reservations { .. } | Event::Error { .. });
+    assert!(matches!(
+        client.wait_for_event(answered).await,
+        Some(Event::Reservations { .. })
+    ));
+    let error = match client.wait_for_event(answered).await {
+        Some(Event::Error {
+            command: Command::Move { .. },
+            error,
+        }) => error,
+        _ => panic!("Move did not fail"),
+    };
+    assert_eq!(
+        error,
+        Error::Reserved {
+            position: front,
+            by: Some(other_name),
+        }
+    );
+    assert!(sim.get_state().commands.is_empty());
+
+    server.close().await;
+}
+
+// Check broadcast moves wait for the block the turtle is moving into like any other move.
+#[tokio::test]
+async fn check_broadcast_move_reserved() {
+    let server = TestServer::start_with_config(reservation_config()).await;
+    let (sim, name) = server.connect_turtle_at(0, at(0, 0, 0)).await;
+    let (_other, other_name) = server.connect_turtle_at(1, at(5, 0, 0)).await;
+
+    let front = at(0, 0, -1);
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), front)
+        .await
+        .unwrap();
+    server
+        .turtle_manager
+        .broadcast(Selector::Name(name.clone()), TurtleCommand::Forward)
+        .await;
+
+    // Longer than the reservation wait so the move has given up.
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(sim.get_state().commands.is_empty());
+
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), at(5, 0, -1))
+        .await
+        .unwrap();
+    server
+        .turtle_manager
+        .broadcast(Selector::Name(name), TurtleCommand::Forward)
+        .await;
+    let state = sim
+        .wait_for(TIMEOUT, |s| !s.commands.is_empty())
+        .await
+        .expect("Turtle did not move");
+    assert_eq!(from_sim(state.position), front);
+
+    server.close().await;
+}
+
+// Check a move sent before the turtle reports where its last move left it reserves the block
+// the turtle really moves into next.
+#[tokio::test]
+async fn check_queued_moves_reserved() {
+    let server = TestServer::start_with_config(reservation_config()).await;
+    let mut config = TurtleConfig::new(0);
+    config.latency = Duration::from_millis(200);
+    let (sim, name) = server.connect_turtle_with(at(0, 0, 0), config).await;
+    let (_other, other_name) = server.connect_turtle_at(1, at(5, 0, 0)).await;
+
+    let second = at(0, 0, -2);
+    server
+        .turtle_manager
+        .reserve(other_name.as_str(), second)
+        .await
+        .unwrap();
+    server
+        .turtle_manager
+        .move_turtle(name.as_str(), Direction::Forward)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .turtle_manager
+            .move_turtle(name.as_str(), Direction::Forward)
+            .await,
+        Err(Error::Reserved {
+            position: second,
+            by: Some(other_name),
+        })
+    );
+    assert_eq!(from_sim(sim.get_state().position), at(0, 0, -1));
+
+    server.close().await;
+}
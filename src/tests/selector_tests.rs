@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use turtle_sim::TurtleConfig;
+
+use super::harness::{eventually, TestClient, TestServer, ADMIN_TOKEN, TIMEOUT};
+use crate::client_scheme::{Command, Event};
+use crate::deploy::Script;
+use crate::error::Error;
+use crate::selector::{Labels, Selector};
+use crate::turtle_manager::TurtleManagerConfig;
+use crate::turtle_scheme::TurtleCommand;
+
+async fn get_labels(client: &mut TestClient) -> Labels {
+    client.send(&Command::GetLabels).await;
+    match client
+        .wait_for_event(|e| matches!(e, Event::Labels { .. }))
+        .await
+    {
+        Some(Event::Labels { labels }) => labels,
+        _ => panic!("Did not get labels"),
+    }
+}
+
+// Check selectors are written the same way they are read and old commands with names still work.
+#[test]
+fn check_parse_selector() {
+    for (s, selector) in [
+        ("*", Selector::All),
+        ("Bob", Selector::Name("Bob".to_string())),
+        ("@miners", Selector::Group("miners".to_string())),
+        ("#farm-north", Selector::Tag("farm-north".to_string())),
+    ] {
+        assert_eq!(Selector::parse(s), selector);
+        assert_eq!(selector.to_string(), s);
+    }
+
+    let command: Command = serde_json::from_str(r#"{"type": "pause", "name": "Bob"}"#).unwrap();
+    assert!(matches!(
+        command,
+        Command::Pause {
+            target: Some(Selector::Name(name))
+        } if name == "Bob"
+    ));
+    let command: Command = serde_json::from_str(r#"{"type": "deploy", "name": "Bob"}"#).unwrap();
+    assert!(matches!(
+        command,
+        Command::Deploy {
+            target: Some(Selector::Name(name)),
+            force: false,
+        } if name == "Bob"
+    ));
+}
+
+// Check clients can put turtles in groups and tag them then command just those turtles.
+#[tokio::test]
+async fn check_labels() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let mut turtles = vec![];
+    for id in 0..3 {
+        turtles.push(server.connect_turtle(TurtleConfig::new(id)).await);
+    }
+    let name = |i: usize| turtles[i].1.clone();
+    let miners = Selector::Group("miners".to_string());
+    let farm = Selector::Tag("farm".to_string());
+
+    for (i, label) in [(0, &miners), (1, &miners), (2, &farm)] {
+        client
+            .send(&Command::AddLabel {
+                name: name(i),
+                label: label.clone(),
+            })
+            .await;
+    }
+    let labels = get_labels(&mut client).await;
+    let mut expected = vec![name(0), name(1)];
+    expected.sort();
+    assert_eq!(labels.groups["miners"], expected);
+    assert_eq!(labels.tags["farm"], vec![name(2)]);
+
+    client
+        .send(&Command::Send {
+            target: miners.clone(),
+            command: TurtleCommand::TurnLeft,
+        })
+        .await;
+    for (sim, _) in turtles[..2].iter() {
+        let state = sim
+            .wait_for(TIMEOUT, |s| !s.commands.is_empty())
+            .await
+            .expect("Miner did not get the command");
+        assert_eq!(state.commands[0]["type"], "turn_left");
+    }
+    assert!(turtles[2]
+        .0
+        .wait_for(Duration::from_millis(300), |s| !s.commands.is_empty())
+        .await
+        .is_none());
+
+    client.send(&Command::Pause { target: Some(farm) }).await;
+    let paused = client
+        .wait_for_event(|e| matches!(e, Event::TurtlePaused { .. }))
+        .await;
+    assert!(matches!(paused, Some(Event::TurtlePaused { name: n }) if n == name(2)));
+
+    client
+        .send(&Command::RemoveLabel {
+            name: name(1),
+            label: miners.clone(),
+        })
+        .await;
+    assert_eq!(
+        get_labels(&mut client).await.groups["miners"],
+        vec![name(0)]
+    );
+
+    let command = Command::AddLabel {
+        name: "Nobody".to_string(),
+        label: miners,
+    };
+    client.send(&command).await;
+    let event = client
+        .wait_for_event(|e| matches!(e, Event::Error { .. }))
+        .await;
+    assert!(matches!(
+        event,
+        Some(Event::Error {
+            error: Error::UnknownTurtle { .. },
+            ..
+        })
+    ));
+
+    server.close().await;
+}
+
+// Check a group change that fails to save is not seen by selections.
+#[tokio::test]
+async fn check_failed_label() {
+    let server = TestServer::start().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let miners = Selector::Group("miners".to_string());
+    sqlx::query("DROP TABLE turtle_groups")
+        .execute(&server.pool)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        server.turtle_manager.add_label(&name, miners.clone()).await,
+        Err(Error::Database { .. })
+    ));
+    assert_eq!(server.turtle_manager.select(miners).await, Ok(vec![]));
+    assert!(server
+        .turtle_manager
+        .get_labels()
+        .await
+        .unwrap()
+        .groups
+        .is_empty());
+
+    server.close().await;
+}
+
+// Check deploys, rollbacks and listings go to every turtle a selector picks out.
+#[tokio::test]
+async fn check_selected_scripts() {
+    let script = Script::new("print('new')");
+    let server = TestServer::start_with_config(TurtleManagerConfig {
+        script: script.clone(),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.connect_client().await;
+    client
+        .send(&Command::Login {
+            token: ADMIN_TOKEN.to_string(),
+        })
+        .await;
+    assert!(client
+        .wait_for_event(|e| matches!(e, Event::LoggedIn))
+        .await
+        .is_some());
+    let old = Selector::Tag("old".to_string());
+    let mut turtles = vec![];
+    for id in 0..2 {
+        let mut config = TurtleConfig::new(id);
+        config.script_version = Some("old".to_string());
+        let (sim, name) = server.connect_turtle(config).await;
+        server
+            .turtle_manager
+            .add_label(name.as_str(), old.clone())
+            .await
+            .unwrap();
+        turtles.push((sim, name));
+    }
+    let mut names: Vec<String> = turtles.iter().map(|(_, n)| n.clone()).collect();
+    names.sort();
+
+    client
+        .send(&Command::ListPeripherals {
+            target: old.clone(),
+        })
+        .await;
+    let mut listed = vec![];
+    for _ in 0..2 {
+        match client
+            .wait_for_event(|e| matches!(e, Event::Peripherals { .. }))
+            .await
+        {
+            Some(Event::Peripherals { name, .. }) => listed.push(name),
+            _ => panic!("Did not get peripherals"),
+        }
+    }
+    listed.sort();
+    assert_eq!(listed, names);
+
+    client
+        .send(&Command::Deploy {
+            target: Some(old.clone()),
+            force: false,
+        })
+        .await;
+    match client
+        .wait_for_event(|e| matches!(e, Event::Deployed { .. }))
+        .await
+    {
+        Some(Event::Deployed {
+            names: mut deployed,
+        }) => {
+            deployed.sort();
+            assert_eq!(deployed, names);
+        }
+        _ => panic!("Did not deploy"),
+    }
+    for (sim, name) in turtles.iter() {
+        sim.wait_for(TIMEOUT, |s| {
+            s.connected && s.script_version.as_deref() == Some(script.version.as_str())
+        })
+        .await
+        .expect("Script was not deployed");
+        let reported = eventually(|| async {
+            server
+                .turtle_manager
+                .get_turtle(name.as_str())
+                .await
+                .is_some_and(|t| t.script_version() == Some(script.version.as_str()))
+        })
+        .await;
+        assert!(reported, "{name} did not report the new version");
+    }
+
+    client.send(&Command::Rollback { target: old }).await;
+    for (sim, _) in turtles.iter() {
+        sim.wait_for(TIMEOUT, |s| {
+            s.connected && s.script_version.as_deref() == Some("old")
+        })
+        .await
+        .expect("Script was not rolled back");
+    }
+
+    server.close().await;
+}
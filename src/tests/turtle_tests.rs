@@ -0,0 +1,1169 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use turtle_sim::{Fault, ItemStack, TurtleConfig};
+
+use super::harness::{eventually, from_sim, to_sim, to_sim_heading, TestServer, TIMEOUT};
+use crate::client_scheme::Event;
+use crate::db;
+use crate::db::turtle_operations::{self, TurtleDB};
+use crate::deploy::Script;
+use crate::error::Error;
+use crate::metrics::Metrics;
+use crate::scheme::{Coordinates, Dimension, Direction, Heading, Tool, TurtleType, Waypoint};
+use crate::turtle_manager::{TurtleManagerConfig, TurtleManagerHandle};
+use crate::turtle_scheme::{RequestType, ResponseType, TurtleCommand, PROTOCOL_VERSION};
+
+/// Gets the type of every command a simulated turtle has received.
+fn command_types(commands: &[serde_json::Value]) -> Vec<&str> {
+    commands
+        .iter()
+        .map(|c| c["type"].as_str().unwrap_or_default())
+        .collect()
+}
+
+// Check that a connecting turtle is named and shows up as connected.
+#[tokio::test]
+async fn check_turtle_connects() {
+    let server = TestServer::start().await;
+    let (_turtle, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    assert!(server.turtle_manager.get_turtle(&name).await.is_some());
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert!(status.contains(&name));
+    assert!(status.contains("Connected"));
+
+    server.close().await;
+}
+
+// Check that turtles in the database are known as disconnected before they connect.
+#[tokio::test]
+async fn check_load_turtles() {
+    let pool = db::setup_memory_database().await.unwrap();
+    let position = Coordinates { x: 4, y: 5, z: 6 };
+    turtle_operations::add_turtle(
+        "Aaren",
+        position,
+        &Dimension::default(),
+        Heading::East,
+        TurtleType::Normal,
+        &pool,
+        &Metrics::new(),
+    )
+    .await
+    .unwrap();
+    let turtle_manager = TurtleManagerHandle::new(pool, TurtleManagerConfig::default());
+
+    let status = turtle_manager.get_status().await.unwrap();
+    assert!(status.contains("Aaren") && status.contains("Disconnected"));
+    assert!(turtle_manager.get_turtle("Aaren").await.is_some());
+
+    let turtles = turtle_manager.get_turtles().await.unwrap();
+    assert_eq!(turtles.len(), 1);
+    assert_eq!(turtles[0].coordinates, position);
+    assert_eq!(turtles[0].heading, Heading::East);
+
+    turtle_manager.close().await;
+}
+
+// Check that a turtle that can't be read from the database is skipped rather than stopping the
+// rest loading.
+#[tokio::test]
+async fn check_load_bad_turtle() {
+    let pool = db::setup_memory_database().await.unwrap();
+    for name in ["Aaren", "Aarika"] {
+        turtle_operations::add_turtle(
+            name,
+            Coordinates { x: 4, y: 5, z: 6 },
+            &Dimension::default(),
+            Heading::East,
+            TurtleType::Normal,
+            &pool,
+            &Metrics::new(),
+        )
+        .await
+        .unwrap();
+    }
+    sqlx::query("UPDATE turtles SET heading = 'up' WHERE name = 'Aarika'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let turtle_manager = TurtleManagerHandle::new(pool, TurtleManagerConfig::default());
+
+    let turtles = turtle_manager.get_turtles().await.unwrap();
+    assert_eq!(turtles.len(), 1);
+    assert_eq!(turtles[0].name, "Aaren");
+
+    turtle_manager.close().await;
+}
+
+// Check that commands reach the turtle in the order they were sent.
+#[tokio::test]
+async fn check_command_order() {
+    let server = TestServer::start().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+
+    for command in [
+        TurtleCommand::Forward,
+        TurtleCommand::TurnRight,
+        TurtleCommand::Forward,
+        TurtleCommand::Back,
+    ] {
+        turtle.send(command).await.unwrap();
+    }
+
+    let state = sim
+        .wait_for(TIMEOUT, |s| s.commands.len() == 4)
+        .await
+        .expect("Turtle did not get every command");
+    assert_eq!(
+        command_types(&state.commands),
+        vec!["forward", "turn_right", "forward", "back"]
+    );
+    assert_eq!(from_sim(state.position), Coordinates { x: 0, y: 0, z: -1 });
+
+    server.close().await;
+}
+
+// Check that a turtle missing from the database is added during the handshake and its first report
+// fills in where it is and what type it is.
+#[tokio::test]
+async fn check_auto_registration() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.position = to_sim(Coordinates { x: 3, y: 4, z: 5 });
+    config.heading = to_sim_heading(Heading::East);
+    config.position_file = Some((config.position, config.heading));
+    config.fuel_limit = TurtleConfig::ADVANCED_FUEL_LIMIT;
+    config.upgrades.left = Some("minecraft:diamond_pickaxe".to_string());
+    let (_sim, name) = server.connect_turtle(config).await;
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_turtle().await.is_some_and(|t| {
+                t.coordinates == Coordinates { x: 3, y: 4, z: 5 }
+                    && t.heading == Heading::East
+                    && t.turtle_type == TurtleType::Advanced
+            })
+        })
+        .await
+    );
+    assert_eq!(db.is_position_verified().await, Some(false));
+
+    // The manager lists turtles from what it knows rather than the database.
+    assert!(
+        eventually(|| async {
+            server.turtle_manager.get_turtles().await == Some(vec![db.get_turtle().await.unwrap()])
+        })
+        .await
+    );
+    let turtles = server.turtle_manager.get_turtles().await.unwrap();
+    assert!(turtles[0].upgrades.has(Tool::Pickaxe));
+
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert!(status.contains("unverified"));
+
+    server.close().await;
+}
+
+// Check that a turtle that connected but has not reported yet can already be given a home.
+#[tokio::test]
+async fn check_registered_before_first_report() {
+    let server = TestServer::start().await;
+    let (mut ws, _) = tokio_tungstenite::connect_async(server.turtle_url.as_str())
+        .await
+        .unwrap();
+    ws.send(Message::Text("0".to_string())).await.unwrap();
+    let name = match ws.next().await {
+        Some(Ok(Message::Text(name))) => name,
+        message => panic!("Turtle was not named {message:?}"),
+    };
+
+    let manager = &server.turtle_manager;
+    assert!(eventually(|| async { manager.get_turtle(name.as_str()).await.is_some() }).await);
+    manager
+        .set_waypoint(Waypoint {
+            name: "base".to_string(),
+            position: Coordinates { x: 1, y: 2, z: 3 },
+            heading: Heading::East,
+            dimension: Dimension::default(),
+        })
+        .await
+        .unwrap();
+    manager
+        .set_home(name.as_str(), Some("base".to_string()))
+        .await
+        .unwrap();
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert_eq!(db.get_home().await, Some("base".to_string()));
+
+    server.close().await;
+}
+
+// Check that reports from the turtle are written to the database.
+#[tokio::test]
+async fn check_report_updates_db() {
+    let server = TestServer::start().await;
+    let config = TurtleConfig::new(0);
+    let (_sim, name) = server.connect_turtle(config.clone()).await;
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_coordinates().await == Some(Coordinates { x: 0, y: 0, z: -1 })
+                && db.get_heading().await == Some(Heading::West)
+        })
+        .await
+    );
+    assert_eq!(
+        db.get_fuel_level().await,
+        Some(TurtleConfig::NORMAL_FUEL_LIMIT - 1)
+    );
+
+    server.close().await;
+}
+
+// Check that a turtle that does not know its position is sent the position in the database while
+// its sender is locked and can still be commanded after the lock is released.
+#[tokio::test]
+async fn check_get_position_lock() {
+    let server = TestServer::start().await;
+    let position = Coordinates { x: 5, y: 64, z: -3 };
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            position,
+            Dimension::default(),
+            Heading::East,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+
+    let mut config = TurtleConfig::new(0);
+    config.position_file = None;
+    let sim = server.spawn_turtle(config);
+
+    sim.wait_for(TIMEOUT, |s| {
+        s.position_file == Some((to_sim(position), to_sim_heading(Heading::East)))
+    })
+    .await
+    .expect("Turtle was not sent its position");
+
+    let turtle = server.turtle_manager.get_turtle("Aaren").await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| {
+            command_types(&s.commands).last() == Some(&"forward")
+        })
+        .await
+        .expect("Turtle did not get a command after unlocking");
+    assert_eq!(
+        state.position_file,
+        Some((
+            to_sim(Coordinates { x: 6, y: 64, z: -3 }),
+            to_sim_heading(Heading::East)
+        ))
+    );
+
+    server.close().await;
+}
+
+// Check that a turtle with GPS is sent its real position and the database is corrected.
+#[tokio::test]
+async fn check_gps_corrects_drift() {
+    let server = TestServer::start().await;
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            Coordinates { x: 5, y: 64, z: -3 },
+            Dimension::default(),
+            Heading::East,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+
+    let real_position = Coordinates { x: 7, y: 60, z: 2 };
+    let mut config = TurtleConfig::new(0);
+    config.position_file = None;
+    config.position = to_sim(real_position);
+    config.heading = to_sim_heading(Heading::South);
+    config.gps = true;
+    let sim = server.spawn_turtle(config);
+
+    sim.wait_for(TIMEOUT, |s| {
+        s.position_file == Some((to_sim(real_position), to_sim_heading(Heading::South)))
+    })
+    .await
+    .expect("Turtle was not sent its GPS position");
+
+    let turtle = server.turtle_manager.get_turtle("Aaren").await.unwrap();
+    assert!(
+        eventually(|| async {
+            let info = turtle.get_info().await;
+            info.as_ref().map(|t| t.coordinates) == Some(real_position)
+                && info.map(|t| t.heading) == Some(Heading::South)
+        })
+        .await,
+        "Database was not corrected"
+    );
+
+    server.close().await;
+}
+
+// Check that the position a turtle with a modem and GPS reports when it connects is checked and
+// corrected even though it never asks for its position.
+#[tokio::test]
+async fn check_gps_checks_first_report() {
+    let server = TestServer::start().await;
+    let real_position = Coordinates { x: 7, y: 60, z: 2 };
+    let mut config = TurtleConfig::new(0);
+    config.position = to_sim(real_position);
+    config.heading = to_sim_heading(Heading::South);
+    config.position_file = Some((to_sim(Coordinates { x: 1, y: 2, z: 3 }), config.heading));
+    config.upgrades.left = Some("computercraft:wireless_modem_normal".to_string());
+    config.gps = true;
+    let (sim, name) = server.connect_turtle(config).await;
+
+    sim.wait_for(TIMEOUT, |s| {
+        s.position_file == Some((to_sim(real_position), to_sim_heading(Heading::South)))
+    })
+    .await
+    .expect("Turtle was not sent its GPS position");
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_coordinates().await == Some(real_position)
+                && db.is_position_verified().await == Some(true)
+        })
+        .await,
+        "Database was not corrected"
+    );
+
+    server.close().await;
+}
+
+// Check that a turtle is told its dimension with its position and reports it back.
+#[tokio::test]
+async fn check_dimension() {
+    let server = TestServer::start().await;
+    let nether = Dimension::new("minecraft:the_nether", Some("survival"));
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            Coordinates { x: 5, y: 64, z: -3 },
+            nether.clone(),
+            Heading::East,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+
+    let mut config = TurtleConfig::new(0);
+    config.position_file = None;
+    let sim = server.spawn_turtle(config);
+    sim.wait_for(TIMEOUT, |s| {
+        s.dimension_file
+            .as_ref()
+            .map(|d| (d.name.as_str(), d.world.as_deref()))
+            == Some(("minecraft:the_nether", Some("survival")))
+    })
+    .await
+    .expect("Turtle was not sent its dimension");
+
+    // A turtle that moved through a portal on its own reports the dimension it was given.
+    let mut config = TurtleConfig::new(1);
+    config.dimension_file = Some(turtle_sim::protocol::Dimension {
+        name: "minecraft:the_end".to_string(),
+        world: None,
+    });
+    let (_sim, name) = server.connect_turtle(config).await;
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async {
+            db.get_dimension().await == Some(Dimension::new("minecraft:the_end", None))
+        })
+        .await,
+        "Reported dimension was not saved"
+    );
+    assert!(TurtleDB::new(
+        "Aaren",
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone()
+    )
+    .status()
+    .await
+    .contains("minecraft:the_nether on survival"));
+
+    server.close().await;
+}
+
+// Check that the status can be limited to the turtles in one dimension.
+#[tokio::test]
+async fn check_dimension_status() {
+    let server = TestServer::start().await;
+    let nether = Dimension::new("minecraft:the_nether", None);
+    for (name, dimension) in [("Aaren", Dimension::default()), ("Aarika", nether.clone())] {
+        server
+            .turtle_manager
+            .add_turtle(
+                name,
+                Coordinates { x: 0, y: 0, z: 0 },
+                dimension,
+                Heading::North,
+                TurtleType::Normal,
+            )
+            .await
+            .unwrap();
+    }
+
+    let status = server
+        .turtle_manager
+        .get_dimension_status(nether)
+        .await
+        .unwrap();
+    assert!(status.contains("Aarika") && !status.contains("Aaren"));
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert_eq!(status.lines().count(), 2);
+
+    server.close().await;
+}
+
+// Check that equipped upgrades are reported, saved and updated after equipping.
+#[tokio::test]
+async fn check_upgrades() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.upgrades.left = Some("minecraft:diamond_pickaxe".to_string());
+    config.inventory[0] = Some(ItemStack {
+        name: "computercraft:wireless_modem_normal".to_string(),
+        count: 1,
+    });
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+
+    let has_tool = |tool| {
+        let turtle = turtle.clone();
+        async move {
+            turtle
+                .get_info()
+                .await
+                .is_some_and(|t| t.upgrades.has(tool))
+        }
+    };
+    assert!(
+        eventually(|| has_tool(Tool::Pickaxe)).await,
+        "Reported pickaxe was not saved"
+    );
+    assert!(!has_tool(Tool::Modem).await);
+
+    turtle
+        .send(TurtleCommand::EquipRight { slot: 1 })
+        .await
+        .unwrap();
+    sim.wait_for(TIMEOUT, |s| s.upgrades.right.is_some())
+        .await
+        .expect("Modem was not equipped");
+
+    // The next report before the turtle is ready again has the modem.
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    assert!(
+        eventually(|| has_tool(Tool::Modem)).await,
+        "Equipped modem was not saved"
+    );
+    let info = turtle.get_info().await.unwrap();
+    assert_eq!(
+        info.upgrades.right.as_deref(),
+        Some("computercraft:wireless_modem_normal")
+    );
+
+    server.close().await;
+}
+
+// Check that requests get their response.
+#[tokio::test]
+async fn check_request() {
+    let server = TestServer::start().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+
+    let response = tokio::time::timeout(TIMEOUT, turtle.request(RequestType::Ping))
+        .await
+        .expect("Timeout waiting for response");
+    assert_eq!(response, Ok(ResponseType::Pong));
+
+    server.close().await;
+}
+
+// Check that a request to a turtle that stopped responding fails once its type's timeout passes.
+#[tokio::test]
+async fn check_request_timeout() {
+    let mut manager_config = TurtleManagerConfig::default();
+    manager_config
+        .connection
+        .request_timeouts
+        .overrides
+        .insert(RequestType::Ping.kind(), Duration::from_millis(200));
+    let server = TestServer::start_with_config(manager_config).await;
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::HangAfter(1));
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| s.commands.len() == 1)
+        .await
+        .unwrap();
+
+    // Other request types still use the default so only pings time out this quickly.
+    let response = tokio::time::timeout(TIMEOUT, turtle.request(RequestType::Ping))
+        .await
+        .expect("Request was not timed out by the sender");
+    assert_eq!(response, Err(Error::TimedOut));
+
+    sim.close().await;
+    server.close().await;
+}
+
+// Check that an eval request waits for the code's own timeout on top of the request timeout.
+#[tokio::test]
+async fn check_eval_timeout() {
+    let mut manager_config = TurtleManagerConfig::default();
+    manager_config.eval.enabled = true;
+    manager_config.eval.timeout = Duration::from_millis(600);
+    manager_config
+        .connection
+        .request_timeouts
+        .overrides
+        .insert("eval", Duration::from_millis(100));
+    let server = TestServer::start_with_config(manager_config).await;
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::HangAfter(1));
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| s.commands.len() == 1)
+        .await
+        .unwrap();
+
+    let started = tokio::time::Instant::now();
+    let result = tokio::time::timeout(
+        TIMEOUT,
+        server.turtle_manager.eval(&name, "return 1".to_string()),
+    )
+    .await
+    .expect("Eval was not timed out by the sender");
+    assert_eq!(result, Err(Error::TimedOut));
+    assert!(started.elapsed() >= Duration::from_millis(700));
+
+    sim.close().await;
+    server.close().await;
+}
+
+// Check that a turtle that drops its connection can reconnect and be commanded again.
+#[tokio::test]
+async fn check_reconnect() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::DisconnectAfter(1));
+    config.reconnect = Some(Duration::from_millis(100));
+    let (sim, name) = server.connect_turtle(config).await;
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| !s.connected).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| s.connected).await.unwrap();
+
+    assert!(
+        eventually(|| async {
+            server
+                .turtle_manager
+                .get_status()
+                .await
+                .is_some_and(|s| s.contains("Connected") && !s.contains("Disconnected"))
+        })
+        .await
+    );
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnRight).await.unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| s.commands.len() == 2)
+        .await
+        .expect("Turtle did not get a command after reconnecting");
+    assert_eq!(
+        command_types(&state.commands),
+        vec!["turn_left", "turn_right"]
+    );
+
+    server.close().await;
+}
+
+// Check that a turtle connecting again while its old connection is still open replaces it.
+#[tokio::test]
+async fn check_replaced_connection() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::HangAfter(1));
+    let (old, name) = server.connect_turtle(config).await;
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    old.wait_for(TIMEOUT, |s| s.commands.len() == 1)
+        .await
+        .unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    turtle.send(TurtleCommand::Back).await.unwrap();
+    let request = tokio::spawn({
+        let turtle = turtle.clone();
+        async move { turtle.request(RequestType::Ping).await }
+    });
+
+    let mut config = TurtleConfig::new(0);
+    config.position.x = 1;
+    config.position_file = Some((config.position, config.heading));
+    let new = server.spawn_turtle(config);
+
+    let response = tokio::time::timeout(TIMEOUT, request)
+        .await
+        .expect("Request was not failed")
+        .unwrap();
+    assert_eq!(response, Err(Error::Replaced));
+
+    let reconnected = client
+        .wait_for_event(|e| matches!(e, Event::TurtleReconnected { .. }))
+        .await;
+    assert!(matches!(reconnected, Some(Event::TurtleReconnected { name: n }) if n == name));
+
+    let state = new
+        .wait_for(TIMEOUT, |s| s.commands.len() == 2)
+        .await
+        .expect("Queued commands were not moved to the new connection");
+    assert_eq!(command_types(&state.commands), vec!["forward", "back"]);
+
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert_eq!(status.lines().count(), 1);
+    assert!(status.contains("Connected") && !status.contains("Disconnected"));
+
+    old.close().await;
+    server.close().await;
+}
+
+// Check that a turtle reconnecting while its sender is locked has what was queued with the lock
+// moved to the new connection.
+#[tokio::test]
+async fn check_replaced_connection_while_locked() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    // Hangs once it answers the ping sent when locking.
+    config.fault = Some(Fault::HangAfter(1));
+    let (old, name) = server.connect_turtle(config).await;
+
+    let mut turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    let connection = turtle
+        .get_connection_mut()
+        .get_connection()
+        .unwrap()
+        .clone();
+    let lock = connection.lock().await.unwrap();
+    lock.send(TurtleCommand::Forward).await;
+    lock.send(TurtleCommand::Back).await;
+    // Gives the last command time to be queued.
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let mut config = TurtleConfig::new(0);
+    config.position.x = 1;
+    config.position_file = Some((config.position, config.heading));
+    let new = server.spawn_turtle(config);
+
+    let state = new
+        .wait_for(TIMEOUT, |s| s.commands.len() == 2)
+        .await
+        .expect("Commands queued with the lock were not moved to the new connection");
+    assert_eq!(command_types(&state.commands), vec!["forward", "back"]);
+    assert_eq!(
+        lock.request(RequestType::Ping).await,
+        Err(Error::Disconnected)
+    );
+
+    old.close().await;
+    server.close().await;
+}
+
+// Check that an emergency stopped turtle stays stopped when its connection is replaced.
+#[tokio::test]
+async fn check_emergency_stop_kept_on_reconnect() {
+    let server = TestServer::start().await;
+    let mut client = server.connect_client().await;
+    let (old, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    server.turtle_manager.emergency_stop(&name).await.unwrap();
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+
+    let new = server.spawn_turtle(TurtleConfig::new(0));
+    assert!(client
+        .wait_for_event(|e| matches!(e, Event::TurtleReconnected { .. }))
+        .await
+        .is_some());
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert!(status.contains("Paused"));
+    assert!(new
+        .wait_for(Duration::from_millis(500), |s| !s.commands.is_empty())
+        .await
+        .is_none());
+
+    server.turtle_manager.resume(&name).await.unwrap();
+    let state = new
+        .wait_for(TIMEOUT, |s| !s.commands.is_empty())
+        .await
+        .expect("Turtle did not get its queued command after resuming");
+    assert_eq!(command_types(&state.commands), vec!["turn_left"]);
+
+    old.close().await;
+    server.close().await;
+}
+
+// Check that paused turtles keep their commands until resumed.
+#[tokio::test]
+async fn check_pause_resume() {
+    let server = TestServer::start().await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    server.turtle_manager.pause(&name).await.unwrap();
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    turtle.send(TurtleCommand::Back).await.unwrap();
+
+    assert!(sim
+        .wait_for(Duration::from_millis(300), |s| !s.commands.is_empty())
+        .await
+        .is_none());
+    assert!(server
+        .turtle_manager
+        .get_status()
+        .await
+        .unwrap()
+        .contains("Paused"));
+
+    server.turtle_manager.resume(&name).await.unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| s.commands.len() == 2)
+        .await
+        .expect("Turtle did not get its commands after resuming");
+    assert_eq!(command_types(&state.commands), vec!["forward", "back"]);
+
+    server.close().await;
+}
+
+// Check that disconnecting says why it could not be done.
+#[tokio::test]
+async fn check_disconnect_errors() {
+    let server = TestServer::start().await;
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    assert_eq!(
+        server.turtle_manager.disconnect("Nobody").await,
+        Err(Error::UnknownTurtle {
+            name: "Nobody".to_string()
+        })
+    );
+    assert_eq!(server.turtle_manager.disconnect(&name).await, Ok(()));
+    assert_eq!(
+        server.turtle_manager.disconnect(&name).await,
+        Err(Error::Disconnected)
+    );
+
+    server.close().await;
+    assert_eq!(
+        server.turtle_manager.disconnect(&name).await,
+        Err(Error::Shutdown)
+    );
+}
+
+// Check that shutting down the server disconnects every turtle.
+#[tokio::test]
+async fn check_shutdown() {
+    let server = TestServer::start().await;
+    let (first, _) = server.connect_turtle(TurtleConfig::new(0)).await;
+    let mut config = TurtleConfig::new(1);
+    config.position.x = 1;
+    let (second, _) = server.connect_turtle(config).await;
+
+    tokio::time::timeout(TIMEOUT, server.close())
+        .await
+        .expect("Server did not shut down");
+
+    assert!(first.wait_for(TIMEOUT, |s| !s.connected).await.is_some());
+    assert!(second.wait_for(TIMEOUT, |s| !s.connected).await.is_some());
+}
+
+/// Heartbeats fast enough for tests.
+fn fast_heartbeat() -> TurtleManagerConfig {
+    let mut config = TurtleManagerConfig::default();
+    config.connection.heartbeat_interval = Duration::from_millis(50);
+    config.connection.unresponsive_after = 2;
+    config.connection.disconnect_after = 4;
+    config
+}
+
+// Check that a turtle has to be marked unresponsive before it can be disconnected.
+#[test]
+fn check_heartbeat_config_validation() {
+    assert!(fast_heartbeat().validate().is_ok());
+
+    let mut config = fast_heartbeat();
+    config.connection.unresponsive_after = 4;
+    assert!(config.validate().is_err());
+
+    config.connection.unresponsive_after = 5;
+    assert!(config.validate().is_err());
+}
+
+// Check that an idle turtle answering heartbeats stays connected and has its last seen time set.
+#[tokio::test]
+async fn check_heartbeat_idle_turtle() {
+    let server = TestServer::start_with_config(fast_heartbeat()).await;
+    server
+        .turtle_manager
+        .add_turtle(
+            "Aaren",
+            Coordinates { x: 0, y: 0, z: 0 },
+            Dimension::default(),
+            Heading::North,
+            TurtleType::Normal,
+        )
+        .await
+        .unwrap();
+    let (_sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert!(status.contains("Connected"));
+    assert!(!status.contains("Unresponsive") && !status.contains("Disconnected"));
+    // Last seen is stored in whole seconds so may have just ticked over.
+    assert!(status.contains("Last seen: 0s ago") || status.contains("Last seen: 1s ago"));
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(db.get_last_seen().await.is_some());
+
+    server.close().await;
+}
+
+// Check that a turtle that stops responding is marked unresponsive and then disconnected.
+#[tokio::test]
+async fn check_heartbeat_unresponsive() {
+    let server = TestServer::start_with_config(fast_heartbeat()).await;
+    let mut config = TurtleConfig::new(0);
+    config.fault = Some(Fault::HangAfter(1));
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+
+    assert!(
+        eventually(|| async {
+            server
+                .turtle_manager
+                .get_status()
+                .await
+                .is_some_and(|s| s.contains("Unresponsive"))
+        })
+        .await
+    );
+    assert!(
+        eventually(|| async {
+            server
+                .turtle_manager
+                .get_status()
+                .await
+                .is_some_and(|s| s.contains("Disconnected"))
+        })
+        .await
+    );
+
+    sim.close().await;
+    server.close().await;
+}
+
+// Check that outdated turtles are deployed to, reconnect with the new version and can roll back.
+#[tokio::test]
+async fn check_deploy() {
+    let script = Script::new("print('new')");
+    let server = TestServer::start_with_config(TurtleManagerConfig {
+        script: script.clone(),
+        ..Default::default()
+    })
+    .await;
+
+    let mut config = TurtleConfig::new(0);
+    config.script_version = Some("old".to_string());
+    let (sim, name) = server.connect_turtle(config).await;
+    let mut current = TurtleConfig::new(1);
+    current.script_version = Some(script.version.clone());
+    let (_current, _) = server.connect_turtle(current).await;
+
+    let status = server.turtle_manager.get_status().await.unwrap();
+    assert!(status.contains("old"));
+
+    let reported = |version: String| {
+        let turtle_manager = server.turtle_manager.clone();
+        let name = name.clone();
+        async move {
+            turtle_manager
+                .get_turtle(&name)
+                .await
+                .is_some_and(|t| t.script_version() == Some(version.as_str()))
+        }
+    };
+    assert!(eventually(|| reported("old".to_string())).await);
+
+    // Only the outdated turtle is deployed to.
+    assert_eq!(server.turtle_manager.deploy_all().await, vec![name.clone()]);
+    sim.wait_for(TIMEOUT, |s| {
+        s.connected && s.script_version.as_deref() == Some(script.version.as_str())
+    })
+    .await
+    .expect("Script was not deployed");
+    assert!(
+        eventually(|| reported(script.version.clone())).await,
+        "Turtle did not report the new version when it reconnected"
+    );
+    assert!(server.turtle_manager.deploy_all().await.is_empty());
+
+    server.turtle_manager.rollback(&name).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| {
+        s.connected && s.script_version.as_deref() == Some("old")
+    })
+    .await
+    .expect("Script was not rolled back");
+    assert!(eventually(|| reported("old".to_string())).await);
+
+    // The rollback sticks until the turtle is deployed to with force.
+    assert!(server.turtle_manager.deploy_all().await.is_empty());
+    assert_eq!(
+        server.turtle_manager.deploy(&name, false).await,
+        Err(Error::RolledBack { name: name.clone() })
+    );
+    server.turtle_manager.deploy(&name, true).await.unwrap();
+    assert!(
+        eventually(|| reported(script.version.clone())).await,
+        "Forced deploy did not replace the rolled back script"
+    );
+
+    server.close().await;
+}
+
+// Check that commands and requests a turtle did not list in its hello are rejected.
+#[tokio::test]
+async fn check_unsupported_command() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.commands = Some(vec!["turn_left".to_string()]);
+    config.requests = vec!["ping".to_string()];
+    let (sim, name) = server.connect_turtle(config).await;
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+
+    assert_eq!(
+        turtle.send(TurtleCommand::Attack).await,
+        Err(Error::Unsupported {
+            name: name.clone(),
+            command: "attack".to_string(),
+        })
+    );
+    assert!(matches!(
+        turtle.request(RequestType::ItemList).await,
+        Err(Error::Unsupported { .. })
+    ));
+    assert_eq!(
+        turtle.request(RequestType::Ping).await,
+        Ok(ResponseType::Pong)
+    );
+
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| s.heading == to_sim_heading(Heading::West))
+        .await
+        .expect("Supported command was not sent");
+    assert!(!command_types(&state.commands).contains(&"attack"));
+
+    server.close().await;
+}
+
+// Check that turtles from before the hello still register but only get the commands startup.lua
+// had then.
+#[tokio::test]
+async fn check_turtle_without_hello() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.commands = None;
+    let (sim, name) = server.connect_turtle(config).await;
+
+    let db = TurtleDB::new(
+        name.as_str(),
+        server.pool.clone(),
+        server.turtle_manager.metrics().clone(),
+    );
+    assert!(
+        eventually(|| async { db.get_fuel_level().await == Some(TurtleConfig::NORMAL_FUEL_LIMIT) })
+            .await,
+        "First report was lost"
+    );
+
+    let turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    assert!(matches!(
+        turtle.send(TurtleCommand::Attack).await,
+        Err(Error::Unsupported { .. })
+    ));
+    turtle.send(TurtleCommand::TurnLeft).await.unwrap();
+    let state = sim
+        .wait_for(TIMEOUT, |s| {
+            command_types(&s.commands).contains(&"turn_left")
+        })
+        .await
+        .expect("Command was not sent");
+    assert!(!command_types(&state.commands).contains(&"attack"));
+
+    server.close().await;
+}
+
+// Check that turtles speaking another protocol are disconnected during the handshake.
+#[tokio::test]
+async fn check_protocol_mismatch() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.protocol = PROTOCOL_VERSION + 1;
+    let sim = server.spawn_turtle(config);
+
+    let state = sim
+        .wait_for(TIMEOUT, |s| s.name.is_some() && !s.connected)
+        .await
+        .expect("Turtle was not disconnected");
+    let name = state.name.unwrap();
+    assert!(server.turtle_manager.get_turtle(&name).await.is_none());
+
+    sim.close().await;
+    server.close().await;
+}
+
+// Check that pausing a locked turtle releases the lock and keeps what was queued with it until
+// the turtle is resumed.
+#[tokio::test]
+async fn check_pause_releases_lock() {
+    let server = TestServer::start().await;
+    let mut config = TurtleConfig::new(0);
+    config.latency = Duration::from_millis(100);
+    let (sim, name) = server.connect_turtle(config).await;
+
+    let mut turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    let connection = turtle
+        .get_connection_mut()
+        .get_connection()
+        .unwrap()
+        .clone();
+    let lock = connection.lock().await.unwrap();
+    for _ in 0..10 {
+        lock.send(TurtleCommand::Move {
+            direction: Direction::Left,
+        })
+        .await;
+    }
+    // Gives the last command time to be queued.
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    server.turtle_manager.pause(&name).await.unwrap();
+
+    assert!(
+        eventually(|| async { lock.request(RequestType::Ping).await == Err(Error::Disconnected) })
+            .await
+    );
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let moves = |s: &turtle_sim::SimState| {
+        command_types(&s.commands)
+            .iter()
+            .filter(|t| **t == "move")
+            .count()
+    };
+    assert!(
+        moves(&sim.get_state()) < 10,
+        "every locked command ran while paused"
+    );
+
+    server.turtle_manager.resume(&name).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| moves(s) == 10)
+        .await
+        .expect("Commands queued with the lock were dropped");
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+    sim.wait_for(TIMEOUT, |s| {
+        command_types(&s.commands).last() == Some(&"forward")
+    })
+    .await
+    .expect("Turtle did not get a command after the lock was released");
+
+    server.close().await;
+}
+
+// Check that a lock that is never released times out after the configured lock timeout.
+#[tokio::test]
+async fn check_lock_timeout() {
+    let mut config = TurtleManagerConfig::default();
+    config.connection.lock_timeout = Duration::from_millis(200);
+    let server = TestServer::start_with_config(config).await;
+    let (sim, name) = server.connect_turtle(TurtleConfig::new(0)).await;
+
+    let mut turtle = server.turtle_manager.get_turtle(&name).await.unwrap();
+    let connection = turtle
+        .get_connection_mut()
+        .get_connection()
+        .unwrap()
+        .clone();
+    let lock = connection.lock().await.unwrap();
+    turtle.send(TurtleCommand::Forward).await.unwrap();
+
+    sim.wait_for(TIMEOUT, |s| command_types(&s.commands).contains(&"forward"))
+        .await
+        .expect("Lock was not released after its timeout");
+    assert_eq!(
+        lock.request(RequestType::Ping).await,
+        Err(Error::Disconnected)
+    );
+
+    server.close().await;
+}
@@ -3,6 +3,9 @@
 /// Communicates with a TurtleManagerInner.
 mod turtle_manager_handle;
 
+/// Settings for how a TurtleManager treats turtle connections.
+mod turtle_manager_config;
+
 /// The logic behind managing all the connected turtle websockets.
 mod turtle_manager_inner;
 
@@ -46,12 +49,31 @@ mod unknown_turtle_connection;
 
 mod turtle_connection_message;
 
+/// Records the websocket frames of turtle sessions to files.
+mod session_recorder;
+
+/// Replays recorded turtle sessions into a TurtleManager.
+mod session_replay;
+
 mod turtle;
 
+/// Finds a turtle's position and heading with GPS.
+mod gps;
+
+/// Blocks reserved for turtles that are about to move into them.
+mod reservations;
+
 // Exports
 
+pub use reservations::Reservation;
+pub use session_recorder::read_session;
+#[cfg(test)]
+pub use session_recorder::FrameDirection;
+pub use session_replay::replay_session;
 pub use turtle::Turtle;
 pub use turtle_connection_message::{ConnectionMessageType, TurtleConnectionMessage};
+pub use turtle_connection_status::{AlreadyDisconnectedError, NotConnectedError, NotPausedError};
+pub use turtle_manager_config::{DispatchConfig, FormationConfig, HomeConfig, TurtleManagerConfig};
 pub use turtle_manager_handle::TurtleManagerHandle;
 pub use turtle_receiver_handle::TurtleReceiverHandle;
 pub use turtle_sender_handle::TurtleSenderHandle;
@@ -0,0 +1,109 @@
+use tracing::{debug, warn};
+
+use crate::{
+    scheme::{Coordinates, Heading},
+    turtle_manager::TurtleManagerHandle,
+    turtle_scheme::{RequestType, ResponseType, TurtleCommand},
+};
+
+use super::turtle_sender_handle::LockedSenderHandle;
+
+/// Gets the turtle's GPS fix.
+/// Returns None if the turtle can't get a fix or did not respond.
+pub async fn locate(lock: &LockedSenderHandle) -> Option<Coordinates> {
+    match lock.request(RequestType::Locate).await {
+        Ok(ResponseType::Location { position }) => position,
+        Ok(response) => {
+            warn!("Got incorrect response type to locate: {:?}", response);
+            None
+        }
+        Err(e) => {
+            warn!("Problem locating turtle {e}");
+            None
+        }
+    }
+}
+
+/// Works out which way the turtle is facing by moving it a block and comparing GPS fixes.
+/// Tries forward and then back in case one is blocked and puts the turtle back where it was.
+/// Returns None if the turtle could not move either way or lost its fix.
+///
+/// # Arguments
+/// * `lock` - Lock on the turtle's sender.
+/// * `manager` - Reserves the blocks the turtle moves into.
+/// * `name` - Name of the turtle.
+/// * `fix` - Where the turtle is now.
+/// * `guess` - Heading given to the turtle so it is willing to move.
+pub async fn find_heading(
+    lock: &LockedSenderHandle,
+    manager: &TurtleManagerHandle,
+    name: &str,
+    fix: Coordinates,
+    guess: Heading,
+) -> Option<Heading> {
+    // startup.lua refuses to move without a position file so give it one to start from.
+    lock.send_position_update(fix, guess, None).await;
+
+    for (command, undo, heading) in [
+        (TurtleCommand::Forward, TurtleCommand::Back, guess),
+        (
+            TurtleCommand::Back,
+            TurtleCommand::Forward,
+            guess.opposite(),
+        ),
+    ] {
+        if !reserve_around(manager, name, fix, fix.step(heading)).await {
+            debug!("{name} can't move {:?} while finding heading", command);
+            continue;
+        }
+
+        lock.send(command.clone()).await;
+        let moved = match locate(lock).await {
+            Some(m) => m,
+            None => {
+                // Can't tell if it moved so try to put it back anyway.
+                lock.send(undo).await;
+                return None;
+            }
+        };
+
+        if moved == fix {
+            debug!("Turtle did not move {:?} while finding heading", command);
+            continue;
+        }
+
+        lock.send(undo).await;
+        let heading = fix.heading_to(moved)?;
+        return Some(match command {
+            TurtleCommand::Back => heading.opposite(),
+            _ => heading,
+        });
+    }
+
+    None
+}
+
+/// Reserves `target` for a turtle at `fix` that doesn't know its heading once none of the blocks
+/// around it are reserved for or taken by another turtle, as the turtle could move into any of
+/// them. Only one block is reserved for a turtle at a time so `target`, the block it moves into
+/// if its guessed heading is right, is reserved last.
+async fn reserve_around(
+    manager: &TurtleManagerHandle,
+    name: &str,
+    fix: Coordinates,
+    target: Coordinates,
+) -> bool {
+    let around = [Heading::North, Heading::East, Heading::South, Heading::West]
+        .map(|h| fix.step(h))
+        .into_iter()
+        .filter(|p| *p != target);
+
+    for position in around.chain([target]) {
+        if let Err(e) = manager.reserve(name, position).await {
+            debug!("{name} can't reserve {position} while finding heading: {e}");
+            return false;
+        }
+    }
+
+    true
+}
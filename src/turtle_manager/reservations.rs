@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::scheme::{Coordinates, Dimension};
+
+/// A block a turtle is about to move into or one a turtle was seen in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub position: Coordinates,
+    pub dimension: Dimension,
+
+    /// Turtle the block is reserved for. None if an inspection found a turtle there.
+    pub name: Option<String>,
+
+    /// Milliseconds until the reservation runs out.
+    pub expires_in_ms: u64,
+}
+
+/// Blocks turtles have reserved. Every reservation runs out after its lease so a turtle that
+/// never moves does not hold its block forever.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    blocks: HashMap<(Dimension, Coordinates), (Option<String>, Instant)>,
+}
+
+impl Reservations {
+    /// Reserves a block for `name` until `lease` passes and drops any other block reserved for
+    /// it. Returns who holds the block if it is reserved for anything else.
+    pub fn reserve(
+        &mut self,
+        name: &str,
+        dimension: Dimension,
+        position: Coordinates,
+        lease: Duration,
+    ) -> Result<(), Option<String>> {
+        self.prune();
+        let key = (dimension, position);
+        if let Some((holder, _)) = self.blocks.get(&key) {
+            if holder.as_deref() != Some(name) {
+                return Err(holder.clone());
+            }
+        }
+
+        self.release(name);
+        self.blocks
+            .insert(key, (Some(name.to_string()), Instant::now() + lease));
+        Ok(())
+    }
+
+    /// Marks a block as taken by a turtle an inspection found. Blocks reserved for a known turtle
+    /// are left as they are.
+    pub fn seen(&mut self, dimension: Dimension, position: Coordinates, lease: Duration) {
+        self.prune();
+        self.blocks
+            .entry((dimension, position))
+            .or_insert((None, Instant::now() + lease));
+    }
+
+    /// Drops every block reserved for `name`.
+    pub fn release(&mut self, name: &str) {
+        self.blocks
+            .retain(|_, (holder, _)| holder.as_deref() != Some(name));
+    }
+
+    /// Gets every reservation that has not run out.
+    pub fn list(&mut self) -> Vec<Reservation> {
+        self.prune();
+        let now = Instant::now();
+        self.blocks
+            .iter()
+            .map(|((dimension, position), (name, expires))| Reservation {
+                position: *position,
+                dimension: dimension.clone(),
+                name: name.clone(),
+                expires_in_ms: expires.duration_since(now).as_millis() as u64,
+            })
+            .collect()
+    }
+
+    fn prune(&mut self) {
+        let now = Instant::now();
+        self.blocks.retain(|_, (_, expires)| *expires > now);
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{error, info};
+
+/// Which way a recorded frame went over the websocket.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDirection {
+    /// Sent by the turtle to us.
+    In,
+
+    /// Sent by us to the turtle.
+    Out,
+}
+
+/// A single websocket frame written to a session file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+    /// Milliseconds since the session started.
+    pub time: u64,
+    pub direction: FrameDirection,
+    pub message: String,
+}
+
+/// Writes every text frame of a turtle's websocket session to a file, one json frame per line.
+/// Cloned into both the sender and receiver of a connection so they write to the same file.
+#[derive(Debug, Clone)]
+pub struct SessionRecorder {
+    file: Arc<Mutex<File>>,
+    start: Instant,
+}
+
+impl SessionRecorder {
+    /// Creates a new session file for `name` in `dir`.
+    /// The file is named after the turtle and the time the session started so every session gets
+    /// its own file.
+    pub fn new(dir: &Path, name: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = dir.join(format!("{name}-{started}.jsonl"));
+        let file = File::create(&path)?;
+        info!("Recording session for {name} to {}", path.display());
+
+        Ok(SessionRecorder {
+            file: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+        })
+    }
+
+    /// Writes a frame received from the turtle.
+    pub fn record_in(&self, message: &str) {
+        self.record(FrameDirection::In, message);
+    }
+
+    /// Writes a frame sent to the turtle.
+    pub fn record_out(&self, message: &str) {
+        self.record(FrameDirection::Out, message);
+    }
+
+    fn record(&self, direction: FrameDirection, message: &str) {
+        let frame = RecordedFrame {
+            time: self.start.elapsed().as_millis() as u64,
+            direction,
+            message: message.to_string(),
+        };
+        let mut line = serde_json::to_string(&frame).expect("Problem serializing frame");
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            error!("Problem recording frame {e}");
+        }
+    }
+}
+
+/// Creates a recorder for a new session if recording is turned on.
+pub fn start_recording(dir: Option<&PathBuf>, name: &str) -> Option<SessionRecorder> {
+    let dir = dir?;
+    match SessionRecorder::new(dir, name) {
+        Ok(recorder) => Some(recorder),
+        Err(e) => {
+            error!("Problem starting recording for {name} {e}");
+            None
+        }
+    }
+}
+
+/// Reads every frame from a session file written by a SessionRecorder.
+pub fn read_session(path: &Path) -> std::io::Result<Vec<RecordedFrame>> {
+    let file = File::open(path)?;
+    let mut frames = vec![];
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let frame = serde_json::from_str(line.as_str())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        frames.push(frame);
+    }
+
+    Ok(frames)
+}
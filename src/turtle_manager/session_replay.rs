@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, warn};
+
+use super::{
+    session_recorder::{FrameDirection, RecordedFrame},
+    unknown_turtle_connection::UnknownTurtleConnection,
+    TurtleManagerHandle,
+};
+
+/// How long to wait for each frame we sent in the recording before moving on without it.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(6);
+
+type ReplayStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Feeds a recorded session back into the turtle manager as if the turtle had connected again.
+///
+/// The session is replayed over a loopback websocket given to the manager as an
+/// UnknownTurtleConnection, so it goes through the same code as a real turtle.
+/// Frames the turtle sent are sent in their recorded order. Frames we sent are waited for before
+/// moving on so the replay follows the recording instead of the recorded timestamps.
+/// Commands that came from the command line or clients are not replayed. They have to be sent to
+/// the turtle again during the replay or the replay will time out waiting for them.
+///
+/// Returns every frame sent to the replayed turtle so it can be compared with the recording.
+pub async fn replay_session(
+    frames: &[RecordedFrame],
+    manager: &TurtleManagerHandle,
+) -> Result<Vec<String>, tokio_tungstenite::tungstenite::Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    let (client, server) = tokio::join!(
+        tokio_tungstenite::connect_async(format!("ws://{addr}")),
+        async {
+            let (stream, _) = listener.accept().await?;
+            tokio_tungstenite::accept_async(stream).await
+        }
+    );
+    let (mut ws, _) = client?;
+    manager
+        .new_unknown_turtle(UnknownTurtleConnection::new(server?))
+        .await;
+
+    let mut sent = vec![];
+    for frame in frames {
+        match frame.direction {
+            FrameDirection::In => {
+                debug!("Replaying frame {}", frame.message);
+                ws.send(Message::Text(frame.message.clone())).await?;
+            }
+            FrameDirection::Out => {
+                match tokio::time::timeout(FRAME_TIMEOUT, next_text(&mut ws)).await {
+                    Ok(Some(message)) => {
+                        if message != frame.message {
+                            warn!("Replay diverged. Expected {} got {message}", frame.message);
+                        }
+                        sent.push(message);
+                    }
+                    Ok(None) => {
+                        warn!("Replayed connection closed early");
+                        return Ok(sent);
+                    }
+                    Err(_) => warn!("Timeout waiting for {} during replay", frame.message),
+                }
+            }
+        }
+    }
+
+    let _ = ws.close(None).await;
+    Ok(sent)
+}
+
+/// Gets the next text frame sent to the replayed turtle.
+/// Returns None if the connection closed.
+async fn next_text(ws: &mut ReplayStream) -> Option<String> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => return Some(text),
+            Some(Ok(_)) => continue,
+            _ => return None,
+        }
+    }
+}
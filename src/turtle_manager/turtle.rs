@@ -1,24 +1,38 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use colored::Colorize;
 use sqlx::SqlitePool;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::Instant;
 
-use tracing::info;
+use tracing::{debug, error, info, warn};
 
+use crate::crafting::{self, ItemCount, Recipe};
+use crate::db::inventory_operations;
 use crate::db::turtle_operations::TurtleDB;
-use crate::scheme::Direction;
-use crate::turtle_manager::TurtleConnectionMessage;
-use crate::turtle_scheme::TurtleEvents;
+use crate::deploy::Script;
+use crate::error::Error;
+use crate::metrics::Metrics;
+use crate::scheme::{self, Dimension, Direction, Tool};
+use crate::turtle_manager::{TurtleConnectionMessage, TurtleManagerHandle};
+use crate::turtle_scheme::{Inventory, Peripheral, Side, TurtleEvents};
 use crate::{
     scheme::{Coordinates, Heading},
-    turtle_scheme::{RequestType, ResponseType, TurtleCommand},
+    turtle_scheme::{Capabilities, RequestType, ResponseType, TurtleCommand},
 };
 
-use super::turtle_connection_status::TurtleConnectionStatus;
-
-#[derive(Debug)]
-pub struct DisconnectedError;
+use super::{
+    gps, turtle_connection_status::TurtleConnectionStatus,
+    turtle_manager_config::ReservationConfig, turtle_manager_message::ResultSender,
+};
 
 pub enum TurtleStatus {
     Connected,
+    Paused,
+    Unresponsive,
     Disconnected,
 }
 
@@ -27,34 +41,122 @@ pub struct Turtle {
     name: &'static str,
     connection: TurtleConnectionStatus,
     db: TurtleDB<'static>,
+    pool: SqlitePool,
+
+    /// What the turtle said it supports when it last connected.
+    capabilities: Capabilities,
+
+    /// Registry the turtle's queries are timed in.
+    metrics: Arc<Metrics>,
+
+    /// Moves and turns sent to the turtle and how many of them it has reported back on.
+    moves: Arc<Moves>,
+}
+
+/// Counts a turtle's moves so a reserved move is only worked out once the manager knows where
+/// the moves before it left the turtle.
+#[derive(Debug, Default)]
+struct Moves {
+    /// Held while a reserved move is sent so they go out one at a time.
+    reserving: Mutex<()>,
+
+    /// Moves and turns sent.
+    sent: AtomicU64,
+
+    /// Moves and turns sent before the last ping the turtle answered. The turtle reports where
+    /// a move left it before answering anything sent after it.
+    confirmed: AtomicU64,
 }
 
 impl Turtle {
-    pub fn new(connection: TurtleConnectionStatus, pool: SqlitePool) -> Self {
+    pub fn new(
+        connection: TurtleConnectionStatus,
+        pool: SqlitePool,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         let name = connection.get_name();
         Turtle {
             name,
             connection,
-            db: TurtleDB::new(name, pool),
+            db: TurtleDB::new(name, pool.clone(), metrics.clone()),
+            pool,
+            capabilities: Capabilities::default(),
+            metrics,
+            moves: Arc::new(Moves::default()),
         }
     }
 
     pub fn get_status(&self) -> TurtleStatus {
         match &self.connection {
             TurtleConnectionStatus::Connected { .. } => TurtleStatus::Connected,
+            TurtleConnectionStatus::Paused { .. } => TurtleStatus::Paused,
+            TurtleConnectionStatus::Unresponsive { .. } => TurtleStatus::Unresponsive,
             TurtleConnectionStatus::Disconnected(_) => TurtleStatus::Disconnected,
         }
     }
 
-    pub async fn status_string(&self) -> String {
+    /// Formats the turtle's connection and last known state.
+    /// Connected turtles also show their script version and whether it is older than `script`.
+    pub async fn status_string(&self, script: &Script) -> String {
+        let version = match self.connection.get_connection() {
+            Some(c) if script.is_newer_than(c.get_script_version()) => format!(
+                " Script: {} {}",
+                c.get_script_version().unwrap_or("Unknown"),
+                "(outdated)".yellow()
+            ),
+            Some(c) => format!(" Script: {}", c.get_script_version().unwrap_or_default()),
+            None => String::new(),
+        };
+
         format!(
-            "{}:\t\tStatus: {} {}",
+            "{}:\t\tStatus: {} {}{version}",
             self.name,
             self.connection,
             self.db.status().await
         )
     }
 
+    /// Version of startup.lua the turtle reported when it connected.
+    /// None if it is disconnected or did not report one.
+    pub fn script_version(&self) -> Option<&str> {
+        self.connection.get_connection()?.get_script_version()
+    }
+
+    /// Sends the turtle `script` and reboots it into it.
+    /// A turtle that was rolled back keeps its script unless `force` is set.
+    pub async fn deploy(&self, script: &Script, force: bool) -> Result<(), Error> {
+        let rolled_back = self.db.get_rolled_back().await;
+        if rolled_back && !force {
+            return Err(Error::RolledBack {
+                name: self.name.to_string(),
+            });
+        }
+
+        info!(
+            "Deploying script {} to {} running {:?}",
+            script.version,
+            self.name,
+            self.script_version()
+        );
+        self.send(TurtleCommand::Deploy {
+            script: script.source.clone(),
+            version: script.version.clone(),
+        })
+        .await?;
+        if rolled_back {
+            self.db.set_rolled_back(false).await?;
+        }
+        self.send(TurtleCommand::Reboot).await
+    }
+
+    /// Has the turtle go back to the script it had before its last deploy and reboot into it.
+    pub async fn rollback(&self) -> Result<(), Error> {
+        info!("Rolling back script of {}", self.name);
+        self.send(TurtleCommand::Rollback).await?;
+        self.db.set_rolled_back(true).await?;
+        self.send(TurtleCommand::Reboot).await
+    }
+
     pub fn get_connection_mut(&mut self) -> &mut TurtleConnectionStatus {
         &mut self.connection
     }
@@ -63,79 +165,533 @@ impl Turtle {
         self.name
     }
 
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Fails if the turtle said it does not support `command`.
+    fn check_command(&self, command: &TurtleCommand) -> Result<(), Error> {
+        match command {
+            TurtleCommand::Request(r) => self.check_request(&r.request),
+            c if self.capabilities.supports(c) => Ok(()),
+            c => Err(self.unsupported(c.kind())),
+        }
+    }
+
+    /// Fails if the turtle said it does not answer `request`.
+    fn check_request(&self, request: &RequestType) -> Result<(), Error> {
+        if self.capabilities.supports_request(request) {
+            Ok(())
+        } else {
+            Err(self.unsupported(request.kind()))
+        }
+    }
+
+    fn unsupported(&self, kind: &str) -> Error {
+        Error::Unsupported {
+            name: self.name.to_string(),
+            command: kind.to_string(),
+        }
+    }
+
     pub fn get_db(&self) -> &TurtleDB {
         &self.db
     }
 
+    /// Gets the turtle's last known position, heading and fuel from the database.
+    pub async fn get_info(&self) -> Option<scheme::Turtle> {
+        self.db.get_turtle().await
+    }
+
+    /// Waits for the turtle to run everything sent to it and gets what it reported since.
+    pub async fn sync(&self, manager: &TurtleManagerHandle) -> Result<scheme::Turtle, Error> {
+        self.confirm_moves().await?;
+        let turtle = manager
+            .get_turtle(self.name)
+            .await
+            .ok_or_else(|| Error::UnknownTurtle {
+                name: self.name.to_string(),
+            })?;
+
+        turtle
+            .get_info()
+            .await
+            .ok_or_else(|| Error::UnknownPosition {
+                name: self.name.to_string(),
+            })
+    }
+
     pub async fn client_subscribe(
         &self,
         tx: mpsc::UnboundedSender<TurtleConnectionMessage<'static>>,
-    ) -> Result<(), DisconnectedError> {
-        if let TurtleConnectionStatus::Connected { connection, .. } = &self.connection {
+    ) -> Result<(), Error> {
+        if let Some(connection) = self.connection.get_connection() {
             connection.client_subscribe(tx).await;
         } else {
-            return Err(DisconnectedError);
+            return Err(Error::Disconnected);
         }
 
         Ok(())
     }
 
-    pub async fn send(&self, command: TurtleCommand) -> Result<(), DisconnectedError> {
-        if let TurtleConnectionStatus::Connected { connection, .. } = &self.connection {
-            connection.send(command).await;
+    /// Queues a command. Fails straight away if the turtle does not support it.
+    pub async fn send(&self, command: TurtleCommand) -> Result<(), Error> {
+        self.check_command(&command)?;
+        match self.connection.get_connection() {
+            Some(connection) => {
+                if command.direction().is_some() {
+                    self.moves.sent.fetch_add(1, Ordering::Relaxed);
+                }
+                connection.send(command).await
+            }
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// Sends a request and waits for its response. Fails straight away if the turtle does not
+    /// answer that type of request.
+    pub async fn request(&self, request: RequestType) -> Result<ResponseType, Error> {
+        self.check_request(&request)?;
+        if let Some(connection) = self.connection.get_connection() {
+            connection.request(request).await
         } else {
-            return Err(DisconnectedError);
+            Err(Error::Disconnected)
+        }
+    }
+
+    pub async fn move_turtle(&self, direction: Direction) -> Result<(), Error> {
+        self.send(TurtleCommand::Move { direction }).await
+    }
+
+    /// Turns the turtle facing `heading` until it faces `towards`.
+    pub async fn face(&self, mut heading: Heading, towards: Heading) -> Result<(), Error> {
+        while heading != towards {
+            if heading.right() == towards {
+                self.move_turtle(Direction::Right).await?;
+                heading = heading.right();
+            } else {
+                self.move_turtle(Direction::Left).await?;
+                heading = heading.left();
+            }
         }
 
         Ok(())
     }
 
-    pub async fn request(&self, request: RequestType) -> Result<ResponseType, ()> {
-        if let TurtleConnectionStatus::Connected { connection, .. } = &self.connection {
-            connection.request(request).await
-        } else {
-            Err(())
+    /// Gets the direction that moves the turtle at `position` facing `heading` into `next`.
+    /// The turtle is turned to face `next` first if it is on the same level. Returns the heading
+    /// the turtle faces afterwards as well.
+    pub async fn turn_towards(
+        &self,
+        position: Coordinates,
+        heading: Heading,
+        next: Coordinates,
+    ) -> Result<(Direction, Heading), Error> {
+        if next.y > position.y {
+            return Ok((Direction::Up, heading));
+        } else if next.y < position.y {
+            return Ok((Direction::Down, heading));
         }
+
+        let towards = position.heading_to(next).ok_or_else(|| Error::Protocol {
+            message: format!("{next} is not next to {position}"),
+        })?;
+        self.face(heading, towards).await?;
+        Ok((Direction::Forward, towards))
     }
 
-    pub async fn move_turtle(&self, direction: Direction) -> Result<(), DisconnectedError> {
-        if let TurtleConnectionStatus::Connected { connection, .. } = &self.connection {
-            connection.send(TurtleCommand::Move { direction }).await;
+    /// Moves the turtle once the block it is moving into is reserved for it, waiting up to
+    /// `config.wait` for the block to be freed. Reserved moves go out one at a time and the block
+    /// is worked out from the manager's known position once the turtle has reported where every
+    /// earlier move left it. Turns and turtles without a known position are sent straight away.
+    pub async fn move_reserved(
+        &self,
+        direction: Direction,
+        manager: &TurtleManagerHandle,
+        config: &ReservationConfig,
+    ) -> Result<(), Error> {
+        let _reserving = self.moves.reserving.lock().await;
+        if self.moves.confirmed.load(Ordering::Relaxed) < self.moves.sent.load(Ordering::Relaxed) {
+            self.confirm_moves().await?;
+        }
+
+        if let Some((position, heading)) = manager.get_position(self.name).await {
+            if let Some(target) = direction.target(position, heading) {
+                let deadline = Instant::now() + config.wait;
+                loop {
+                    match manager.reserve(self.name, target).await {
+                        Ok(()) => break,
+                        Err(Error::Reserved { .. })
+                            if Instant::now() + config.retry_interval < deadline =>
+                        {
+                            tokio::time::sleep(config.retry_interval).await;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        self.move_turtle(direction).await
+    }
+
+    /// Waits for the turtle to answer a ping, by which point the manager has every report from
+    /// the moves sent before it.
+    async fn confirm_moves(&self) -> Result<(), Error> {
+        let sent = self.moves.sent.load(Ordering::Relaxed);
+        self.request(RequestType::Ping).await?;
+        self.moves.confirmed.fetch_max(sent, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Arranges the turtle's inventory for `recipe` and crafts at least `count` of `item`.
+    /// Items that aren't part of the recipe are dropped into a chest in front, above or below.
+    /// The turtle is locked while its inventory is read, the commands are queued and the inventory
+    /// is listed again to check the craft so nothing else can move its items in between.
+    /// `queued` is taken and answered once the crafting commands are queued. It is left for the
+    /// caller to answer with the error if the craft fails before then.
+    pub async fn craft(
+        &self,
+        item: &str,
+        recipe: &Recipe,
+        count: u32,
+        queued: &mut Option<ResultSender>,
+    ) -> Result<(), Error> {
+        // Turtles on versions that can't report upgrades are trusted to have a crafting table.
+        if let Some(upgrades) = self.db.get_upgrades().await {
+            let reported = upgrades.left.is_some() || upgrades.right.is_some();
+            if reported && !upgrades.has(Tool::CraftingTable) {
+                return Err(Error::CannotCraft {
+                    reason: "no crafting table equipped".to_string(),
+                });
+            }
+        }
+
+        self.check_request(&RequestType::ItemList)?;
+        let connection = self
+            .connection
+            .get_connection()
+            .ok_or(Error::Disconnected)?;
+        let lock = connection.lock().await?;
+        let list_items = || async {
+            match lock.request(RequestType::ItemList).await? {
+                ResponseType::Inventory { inventory } => Ok(inventory),
+                r => Err(Error::Protocol {
+                    message: format!("expected inventory got {:?}", r),
+                }),
+            }
+        };
+        let inventory = list_items().await?;
+
+        // Items that aren't part of the recipe go into a chest next to the turtle.
+        let foreign = inventory.slots.iter().any(|s| recipe.cells(&s.name) == 0);
+        let stash = if foreign
+            && self
+                .capabilities
+                .supports_request(&RequestType::PeripheralList)
+        {
+            match lock.request(RequestType::PeripheralList).await? {
+                ResponseType::Peripherals { peripherals } => peripherals
+                    .into_iter()
+                    .filter(|p| p.types.iter().any(|t| t == "inventory"))
+                    .map(|p| p.side)
+                    .find(|s| matches!(s, Side::Front | Side::Top | Side::Bottom)),
+                r => {
+                    return Err(Error::Protocol {
+                        message: format!("expected peripherals got {:?}", r),
+                    })
+                }
+            }
         } else {
-            return Err(DisconnectedError);
+            None
+        };
+
+        let commands = crafting::plan(recipe, count, &inventory, stash)?;
+        for command in commands.iter() {
+            self.check_command(command)?;
+        }
+        for command in commands {
+            lock.send(command).await;
+        }
+        if let Some(tx) = queued.take() {
+            let _ = tx.send(Ok(()));
+        }
+
+        // turtle.craft only prints why it failed so the inventory is listed again to check.
+        let crafts = count.div_ceil(recipe.count.max(1));
+        let kept = match recipe.cells(item) {
+            0 => 0,
+            cells => inventory.count(item).saturating_sub(cells * crafts),
+        };
+        let expected = kept + crafts * recipe.count;
+        let made = list_items().await?.count(item);
+        if made < expected {
+            return Err(Error::CannotCraft {
+                reason: format!("turtle has {made} {item} after crafting but expected {expected}"),
+            });
         }
 
         Ok(())
     }
 
-    pub async fn send_position_update(&self) {
-        let position = match self.db.get_coordinates().await {
-            Some(p) => p,
-            None => Coordinates { x: 0, y: 0, z: 0 },
+    /// Attacks whatever is in front of the turtle every `interval` until `stop` is sent to or
+    /// dropped. Items that turn up in the turtle's inventory after an attack are reported to the
+    /// manager as drops.
+    pub async fn guard(
+        &self,
+        interval: Duration,
+        mut stop: oneshot::Receiver<()>,
+        turtle_manager: TurtleManagerHandle,
+    ) -> Result<(), Error> {
+        let mut before = self.list_items().await?;
+        loop {
+            self.send(TurtleCommand::Attack).await?;
+
+            // Answered after the attack so the inventory includes anything it picked up.
+            let after = self.list_items().await?;
+            let drops = new_items(&before, &after);
+            if !drops.is_empty() {
+                turtle_manager.report_drops(self.name, drops).await;
+            }
+            before = after;
+
+            tokio::select! {
+                _ = &mut stop => return Ok(()),
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    /// Lists the turtle's own inventory.
+    pub async fn list_items(&self) -> Result<Inventory, Error> {
+        match self.request(RequestType::ItemList).await? {
+            ResponseType::Inventory { inventory } => Ok(inventory),
+            r => Err(Error::Protocol {
+                message: format!("expected inventory got {:?}", r),
+            }),
+        }
+    }
+
+    /// Runs `code` on the turtle and returns what it returned serialized by the turtle.
+    /// Fails with the turtle's error if the code does not load, errors or runs for more than
+    /// `timeout`.
+    pub async fn eval(&self, code: String, timeout: Duration) -> Result<String, Error> {
+        let request = RequestType::Eval {
+            code,
+            timeout_ms: timeout.as_millis() as u64,
+        };
+        match self.request(request).await? {
+            ResponseType::Evaluated { result } => Ok(result),
+            r => Err(Error::Protocol {
+                message: format!("expected evaluated got {:?}", r),
+            }),
+        }
+    }
+
+    /// Lists the peripherals attached to each side of the turtle.
+    pub async fn list_peripherals(&self) -> Result<Vec<Peripheral>, Error> {
+        match self.request(RequestType::PeripheralList).await? {
+            ResponseType::Peripherals { peripherals } => Ok(peripherals),
+            r => Err(Error::Protocol {
+                message: format!("expected peripherals got {:?}", r),
+            }),
+        }
+    }
+
+    /// Lists the inventory on `side` of the turtle and stores it against the inventory's position.
+    /// The turtle's known position is used so it has to be known before the inventory is listed.
+    pub async fn list_inventory(
+        &self,
+        side: Side,
+    ) -> Result<(Coordinates, Dimension, Inventory), Error> {
+        let (position, heading) = match self.get_known_position().await {
+            (Some(p), Some(h)) => (p, h),
+            _ => {
+                return Err(Error::UnknownPosition {
+                    name: self.name.to_string(),
+                })
+            }
+        };
+        let dimension = self.db.get_dimension().await.unwrap_or_default();
+
+        let inventory = match self.request(RequestType::InventoryList { side }).await? {
+            ResponseType::Inventory { inventory } => inventory,
+            r => {
+                return Err(Error::Protocol {
+                    message: format!("expected inventory got {:?}", r),
+                })
+            }
+        };
+
+        let position = side.position_from(position, heading);
+        inventory_operations::set_inventory(
+            position,
+            &dimension,
+            &inventory,
+            &self.pool,
+            &self.metrics,
+        )
+        .await?;
+
+        Ok((position, dimension, inventory))
+    }
+
+    /// Gets the position and heading in the database.
+    pub async fn get_known_position(&self) -> (Option<Coordinates>, Option<Heading>) {
+        (self.db.get_coordinates().await, self.db.get_heading().await)
+    }
+
+    /// Sends the turtle its position and heading.
+    /// A GPS fix is preferred over the known position from the database. If the turtle gets a fix
+    /// its heading is found by moving it and both the database and the turtle are corrected if
+    /// they had drifted.
+    pub async fn send_position_update(
+        &self,
+        manager: &TurtleManagerHandle,
+        db_position: Option<Coordinates>,
+        db_heading: Option<Heading>,
+    ) {
+        let connection = match self.connection.get_connection() {
+            Some(c) => c,
+            None => return,
+        };
+        let lock = match connection.lock().await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("Problem locking {} to update its position {e}", self.name);
+                return;
+            }
+        };
+
+        // Turtles that can't locate themselves use the known position.
+        let fix = if self.capabilities.supports_request(&RequestType::Locate) {
+            gps::locate(&lock).await
+        } else {
+            None
         };
+        let (position, heading) = match fix {
+            Some(fix) => {
+                let guess = db_heading.unwrap_or(Heading::North);
+                let heading = gps::find_heading(&lock, manager, self.name, fix, guess).await;
+                self.correct_drift(manager, fix, heading, db_position, db_heading)
+                    .await;
 
-        let heading = match self.db.get_heading().await {
-            Some(h) => h,
-            None => Heading::North,
+                (fix, heading.or(db_heading).unwrap_or(Heading::North))
+            }
+            None => (
+                db_position.unwrap_or(Coordinates { x: 0, y: 0, z: 0 }),
+                db_heading.unwrap_or(Heading::North),
+            ),
         };
 
+        // Turtles can't tell which dimension they are in so it always comes from the database.
+        let dimension = self.db.get_dimension().await;
         info!(
             "Updating {}'s heading and position to {position}, {heading}",
             self.name
         );
+        lock.send_position_update(position, heading, dimension)
+            .await;
+    }
 
-        if let TurtleConnectionStatus::Connected { connection, .. } = &self.connection {
-            if let Ok(lock) = connection.lock().await {
-                lock.send_position_update(position, heading).await;
+    /// Checks the position a turtle reported when it connected against a GPS fix.
+    /// A matching fix marks the position verified. Otherwise the turtle's heading is found by
+    /// moving it and both the database and the turtle are corrected.
+    /// Nothing happens if the turtle can't locate itself or get a fix.
+    pub async fn verify_position(
+        &self,
+        manager: &TurtleManagerHandle,
+        reported: Coordinates,
+        heading: Heading,
+    ) {
+        if !self.capabilities.supports_request(&RequestType::Locate) {
+            return;
+        }
+        let connection = match self.connection.get_connection() {
+            Some(c) => c,
+            None => return,
+        };
+        let lock = match connection.lock().await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("Problem locking {} to verify its position {e}", self.name);
+                return;
+            }
+        };
+
+        let fix = match gps::locate(&lock).await {
+            Some(fix) => fix,
+            None => return,
+        };
+        if fix == reported {
+            debug!("GPS agrees with {}'s position {fix}", self.name);
+            if let Err(e) = self.db.set_position_verified(true).await {
+                error!("Problem marking turtle position verified: {e}");
             }
+            return;
         }
+
+        let found = gps::find_heading(&lock, manager, self.name, fix, heading).await;
+        self.correct_drift(manager, fix, found, Some(reported), Some(heading))
+            .await;
+        let dimension = self.db.get_dimension().await;
+        lock.send_position_update(fix, found.unwrap_or(heading), dimension)
+            .await;
     }
-}
 
-impl std::fmt::Display for DisconnectedError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Unable to run command. Turtle is disconnected")
+    /// Has the manager store a GPS fix, warning if it does not match what dead reckoning says.
+    /// The heading is only stored if it was found.
+    async fn correct_drift(
+        &self,
+        manager: &TurtleManagerHandle,
+        fix: Coordinates,
+        heading: Option<Heading>,
+        db_position: Option<Coordinates>,
+        db_heading: Option<Heading>,
+    ) {
+        if let Some(p) = db_position.filter(|p| *p != fix) {
+            warn!(
+                "{} has drifted. Dead reckoning says {p} but GPS says {fix}",
+                self.name
+            );
+        }
+        manager.update_turtle_position(self.name, fix).await;
+
+        if let Some(heading) = heading {
+            if let Some(h) = db_heading.filter(|h| *h != heading) {
+                warn!(
+                    "{} has drifted. Dead reckoning says it faces {h} but it faces {heading}",
+                    self.name
+                );
+            }
+            manager.update_turtle_heading(self.name, heading).await;
+        }
+
+        if let Err(e) = self.db.set_position_verified(true).await {
+            error!("Problem marking turtle position verified: {e}");
+        }
     }
 }
 
-impl std::error::Error for DisconnectedError {}
+/// Gets how many more of each item `after` has than `before` sorted by name.
+fn new_items(before: &Inventory, after: &Inventory) -> Vec<ItemCount> {
+    let totals = |inventory: &Inventory| {
+        let mut totals: BTreeMap<String, u32> = BTreeMap::new();
+        for slot in inventory.slots.iter() {
+            *totals.entry(slot.name.clone()).or_default() += slot.count;
+        }
+        totals
+    };
+
+    let before = totals(before);
+    totals(after)
+        .into_iter()
+        .filter_map(|(name, count)| {
+            let gained = count.saturating_sub(before.get(&name).copied().unwrap_or_default());
+            (gained > 0).then_some(ItemCount {
+                name,
+                count: gained,
+            })
+        })
+        .collect()
+}
@@ -1,16 +1,42 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use crate::turtle_manager::TurtleConnectionMessage;
 use futures_util::StreamExt;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio_tungstenite::WebSocketStream;
 
+use crate::error::Error;
 use crate::turtle_scheme::{RequestType, ResponseType, TurtleCommand, TurtleEvents};
 
 use super::{
+    session_recorder::SessionRecorder,
+    turtle_manager_config::{ConnectionConfig, TurtleManagerConfig},
     turtle_sender_handle::{self, LockedSenderHandle},
     TurtleManagerHandle, TurtleReceiverHandle, TurtleSenderHandle,
 };
 
+/// Id given to the next connection.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What the sender and receiver of one connection both need.
+#[derive(Debug, Clone)]
+pub struct ConnectionContext {
+    /// Told when the connection closes.
+    pub manager: TurtleManagerHandle,
+
+    /// Name of the turtle for logging purposes.
+    pub name: &'static str,
+
+    /// Id of the connection so a replaced connection can't disconnect its replacement.
+    pub id: u64,
+
+    /// Records every frame sent and received if the session is being recorded.
+    pub recorder: Option<SessionRecorder>,
+
+    pub config: ConnectionConfig,
+}
+
 /// Contains both the sender and receiver for a turtle websocket connection.
 #[derive(Debug, Clone)]
 pub struct TurtleConnection {
@@ -19,6 +45,13 @@ pub struct TurtleConnection {
 
     /// Sender of the turtle websocket.
     sender: TurtleSenderHandle,
+
+    /// Unique to this connection. Lets the manager tell a connection apart from the one that
+    /// replaced it.
+    id: u64,
+
+    /// Version of startup.lua the turtle reported during the handshake.
+    script_version: Option<String>,
 }
 
 impl TurtleConnection {
@@ -28,33 +61,83 @@ impl TurtleConnection {
     /// * `ws_connection` - WebSocket of the connected turtle.
     /// * `manager` - TurtleManagerHandle so that the sender and receiver can notify a close.
     /// * `name` - Name of the turtle.
+    /// * `script_version` - Version of startup.lua the turtle is running if it reported one.
+    /// * `recorder` - Records every frame sent and received if set.
+    /// * `first_message` - Frame read during the handshake that the receiver should handle first.
+    /// * `config` - Decides how often the receiver checks the turtle is still alive and how long
+    ///   requests wait for a response.
     pub fn new(
         ws_connection: WebSocketStream<TcpStream>,
         manager: TurtleManagerHandle,
         name: &'static str,
+        script_version: Option<String>,
+        recorder: Option<SessionRecorder>,
+        first_message: Option<String>,
+        config: &TurtleManagerConfig,
     ) -> Self {
         let (ws_sender, ws_receiver) = ws_connection.split();
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
 
-        let (sender, r_sender) = turtle_sender_handle::sender(ws_sender, manager.clone(), name);
+        let context = ConnectionContext {
+            manager,
+            name,
+            id,
+            recorder,
+            config: config.connection.clone(),
+        };
+        let (sender, r_sender) = turtle_sender_handle::sender(ws_sender, context.clone());
+        let receiver = TurtleReceiverHandle::new(ws_receiver, r_sender, first_message, context);
 
-        let receiver = TurtleReceiverHandle::new(ws_receiver, manager, r_sender, name);
+        TurtleConnection {
+            receiver,
+            sender,
+            id,
+            script_version,
+        }
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
 
-        TurtleConnection { receiver, sender }
+    pub fn get_script_version(&self) -> Option<&str> {
+        self.script_version.as_deref()
     }
 
     /// Send a message to the connected turtle
     ///
     /// # Arguments
     /// * `message` - The message to send.
-    pub async fn send(&self, command: TurtleCommand) {
-        self.sender.send(command).await;
+    pub async fn send(&self, command: TurtleCommand) -> Result<(), Error> {
+        self.sender.send(command).await
     }
 
-    pub async fn request(&self, request: RequestType) -> Result<ResponseType, ()> {
+    pub async fn request(&self, request: RequestType) -> Result<ResponseType, Error> {
         self.sender.request(request).await
     }
 
-    pub async fn lock(&self) -> Result<LockedSenderHandle, ()> {
+    /// Stops the sender from sending queued commands.
+    pub async fn pause(&self) {
+        self.sender.pause().await;
+    }
+
+    /// Lets the sender send queued commands again.
+    pub async fn resume(&self) {
+        self.sender.resume().await;
+    }
+
+    /// Stops the sender from sending anything, including retries of the last command.
+    pub async fn emergency_stop(&self) {
+        self.sender.emergency_stop().await;
+    }
+
+    /// Fails every request waiting on a response and takes every queued command.
+    /// Used to move a turtle's commands onto the connection replacing this one.
+    pub async fn drain(&self) -> Vec<TurtleCommand> {
+        self.sender.drain().await
+    }
+
+    pub async fn lock(&self) -> Result<LockedSenderHandle, Error> {
         self.sender.lock().await
     }
 
@@ -1,3 +1,6 @@
+use crate::crafting::ItemCount;
+use crate::dispatcher::JobProgress;
+use crate::error::Error;
 use crate::turtle_scheme::TurtleEvents;
 
 #[derive(Debug, Clone)]
@@ -11,4 +14,39 @@ pub enum ConnectionMessageType {
     TurtleEvent(TurtleEvents),
     Connected,
     Disconnected,
+    Paused,
+    Resumed,
+
+    /// The turtle has missed too many heartbeats. It is still connected but may be disconnected
+    /// soon.
+    Unresponsive,
+
+    /// The turtle connected again while its old connection was still open. The old connection
+    /// was closed and its queued commands moved to the new one.
+    Reconnected,
+
+    /// Items that turned up in a guarding turtle's inventory after it attacked.
+    Drops(Vec<ItemCount>),
+
+    /// How far along a job is. Not about a single turtle so it is sent with an empty name.
+    JobProgress(JobProgress),
+
+    /// The turtle's trip back to its home at `waypoint` ended. `error` is None if it got there.
+    ReturnedHome {
+        waypoint: String,
+        error: Option<Error>,
+    },
+
+    /// The formation of `names` stopped moving. `error` is None if every turtle got in place. Not
+    /// about a single turtle so it is sent with an empty name.
+    FormationMoved {
+        names: Vec<String>,
+        error: Option<Error>,
+    },
+
+    /// The turtle finished crafting `item`. `error` is None if it made everything it was asked to.
+    Crafted {
+        item: String,
+        error: Option<Error>,
+    },
 }
@@ -3,12 +3,17 @@ use colored::Colorize;
 use super::turtle_connection::TurtleConnection;
 
 #[derive(Debug)]
-pub struct AlreadyConnectedError {
+pub struct AlreadyDisconnectedError {
     name: &'static str,
 }
 
 #[derive(Debug)]
-pub struct AlreadyDisconnectedError {
+pub struct NotConnectedError {
+    name: &'static str,
+}
+
+#[derive(Debug)]
+pub struct NotPausedError {
     name: &'static str,
 }
 
@@ -18,6 +23,25 @@ pub enum TurtleConnectionStatus {
         name: &'static str,
         connection: TurtleConnection,
     },
+
+    /// The turtle is connected but its sender has been told to stop sending commands.
+    /// Commands sent while paused are queued until the turtle is resumed.
+    /// `emergency` is set if it was emergency stopped so the sent command is not retried either.
+    Paused {
+        name: &'static str,
+        connection: TurtleConnection,
+        emergency: bool,
+    },
+
+    /// The turtle has missed too many heartbeats but has not been disconnected yet.
+    /// `paused` and `emergency` are kept so the turtle goes back to the right status if it
+    /// responds again.
+    Unresponsive {
+        name: &'static str,
+        connection: TurtleConnection,
+        paused: bool,
+        emergency: bool,
+    },
     Disconnected(&'static str),
 }
 
@@ -25,29 +49,72 @@ impl TurtleConnectionStatus {
     pub fn get_name(&self) -> &'static str {
         match self {
             TurtleConnectionStatus::Connected { name, .. } => name,
+            TurtleConnectionStatus::Paused { name, .. } => name,
+            TurtleConnectionStatus::Unresponsive { name, .. } => name,
             TurtleConnectionStatus::Disconnected(name) => name,
         }
     }
 
-    pub fn connect(&mut self, connection: TurtleConnection) -> Result<(), AlreadyConnectedError> {
+    /// Gets the connection if the turtle is connected, paused or unresponsive.
+    pub fn get_connection(&self) -> Option<&TurtleConnection> {
         match self {
-            TurtleConnectionStatus::Connected {
+            TurtleConnectionStatus::Connected { connection, .. } => Some(connection),
+            TurtleConnectionStatus::Paused { connection, .. } => Some(connection),
+            TurtleConnectionStatus::Unresponsive { connection, .. } => Some(connection),
+            TurtleConnectionStatus::Disconnected(_) => None,
+        }
+    }
+
+    /// Connects the turtle with a new connection.
+    /// If the turtle already had a connection it is replaced and returned so it can be closed.
+    /// A paused or emergency stopped turtle stays that way on its new connection. An unresponsive
+    /// one goes back to connected or paused as the new connection has just authed.
+    pub async fn replace(&mut self, connection: TurtleConnection) -> Option<TurtleConnection> {
+        let (name, old, paused, emergency) = match self {
+            TurtleConnectionStatus::Connected { name, connection } => {
+                (*name, Some(connection.clone()), false, false)
+            }
+            TurtleConnectionStatus::Paused {
                 name,
-                connection: _,
-            } => {
-                return Err(AlreadyConnectedError { name });
+                connection,
+                emergency,
+            } => (*name, Some(connection.clone()), true, *emergency),
+            TurtleConnectionStatus::Unresponsive {
+                name,
+                connection,
+                paused,
+                emergency,
+            } => (*name, Some(connection.clone()), *paused, *emergency),
+            TurtleConnectionStatus::Disconnected(name) => (*name, None, false, false),
+        };
+
+        *self = if paused {
+            if emergency {
+                connection.emergency_stop().await;
+            } else {
+                connection.pause().await;
             }
-            TurtleConnectionStatus::Disconnected(name) => {
-                *self = TurtleConnectionStatus::Connected { name, connection };
+            TurtleConnectionStatus::Paused {
+                name,
+                connection,
+                emergency,
             }
-        }
+        } else {
+            TurtleConnectionStatus::Connected { name, connection }
+        };
 
-        Ok(())
+        old
     }
 
     pub async fn disconnect(&mut self) -> Result<(), AlreadyDisconnectedError> {
         match self {
-            TurtleConnectionStatus::Connected { name, connection } => {
+            TurtleConnectionStatus::Connected { name, connection }
+            | TurtleConnectionStatus::Paused {
+                name, connection, ..
+            }
+            | TurtleConnectionStatus::Unresponsive {
+                name, connection, ..
+            } => {
                 connection.close().await;
                 *self = TurtleConnectionStatus::Disconnected(name);
             }
@@ -58,12 +125,147 @@ impl TurtleConnectionStatus {
 
         Ok(())
     }
+
+    /// Pauses the turtle's sender.
+    /// If `emergency` is set the sender also stops retrying the command it is waiting on.
+    /// A turtle that was already emergency stopped stays emergency stopped.
+    pub async fn pause(&mut self, emergency: bool) -> Result<(), NotConnectedError> {
+        match self {
+            TurtleConnectionStatus::Connected { name, connection } => {
+                let emergency = pause_connection(connection, emergency, false).await;
+                *self = TurtleConnectionStatus::Paused {
+                    name,
+                    connection: connection.clone(),
+                    emergency,
+                };
+            }
+            TurtleConnectionStatus::Paused {
+                connection,
+                emergency: stopped,
+                ..
+            } => {
+                *stopped = pause_connection(connection, emergency, *stopped).await;
+            }
+            TurtleConnectionStatus::Unresponsive {
+                connection,
+                paused,
+                emergency: stopped,
+                ..
+            } => {
+                *stopped = pause_connection(connection, emergency, *stopped).await;
+                *paused = true;
+            }
+            TurtleConnectionStatus::Disconnected(name) => return Err(NotConnectedError { name }),
+        }
+
+        Ok(())
+    }
+
+    pub async fn resume(&mut self) -> Result<(), NotPausedError> {
+        match self {
+            TurtleConnectionStatus::Paused {
+                name, connection, ..
+            } => {
+                connection.resume().await;
+                *self = TurtleConnectionStatus::Connected {
+                    name,
+                    connection: connection.clone(),
+                };
+            }
+            TurtleConnectionStatus::Unresponsive {
+                connection,
+                paused: paused @ true,
+                emergency,
+                ..
+            } => {
+                connection.resume().await;
+                *paused = false;
+                *emergency = false;
+            }
+            TurtleConnectionStatus::Connected { name, .. }
+            | TurtleConnectionStatus::Unresponsive { name, .. }
+            | TurtleConnectionStatus::Disconnected(name) => return Err(NotPausedError { name }),
+        }
+
+        Ok(())
+    }
+
+    /// Marks a connected turtle as unresponsive or puts an unresponsive turtle back to connected
+    /// or paused.
+    pub fn set_responsive(&mut self, responsive: bool) -> Result<(), NotConnectedError> {
+        match (&*self, responsive) {
+            (TurtleConnectionStatus::Connected { name, connection }, false) => {
+                *self = TurtleConnectionStatus::Unresponsive {
+                    name,
+                    connection: connection.clone(),
+                    paused: false,
+                    emergency: false,
+                };
+            }
+            (
+                TurtleConnectionStatus::Paused {
+                    name,
+                    connection,
+                    emergency,
+                },
+                false,
+            ) => {
+                *self = TurtleConnectionStatus::Unresponsive {
+                    name,
+                    connection: connection.clone(),
+                    paused: true,
+                    emergency: *emergency,
+                };
+            }
+            (
+                TurtleConnectionStatus::Unresponsive {
+                    name,
+                    connection,
+                    paused,
+                    emergency,
+                },
+                true,
+            ) => {
+                let name = *name;
+                let connection = connection.clone();
+                *self = if *paused {
+                    TurtleConnectionStatus::Paused {
+                        name,
+                        connection,
+                        emergency: *emergency,
+                    }
+                } else {
+                    TurtleConnectionStatus::Connected { name, connection }
+                };
+            }
+            (TurtleConnectionStatus::Disconnected(name), _) => {
+                return Err(NotConnectedError { name })
+            }
+            // Already in the right state.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Pauses or emergency stops a connection's sender. Returns whether it is now emergency stopped.
+async fn pause_connection(connection: &TurtleConnection, emergency: bool, stopped: bool) -> bool {
+    if emergency {
+        connection.emergency_stop().await;
+    } else {
+        connection.pause().await;
+    }
+
+    emergency || stopped
 }
 
 impl std::fmt::Display for TurtleConnectionStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let status = match self {
             TurtleConnectionStatus::Connected { .. } => "Connected   ".green(),
+            TurtleConnectionStatus::Paused { .. } => "Paused      ".yellow(),
+            TurtleConnectionStatus::Unresponsive { .. } => "Unresponsive".magenta(),
             TurtleConnectionStatus::Disconnected(_) => "Disconnected".red(),
         };
         // write!(f, "{status} {}", self.get_name())
@@ -71,17 +273,24 @@ impl std::fmt::Display for TurtleConnectionStatus {
     }
 }
 
-impl std::fmt::Display for AlreadyConnectedError {
+impl std::fmt::Display for AlreadyDisconnectedError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Turtle {} is already connected", self.name)
+        write!(f, "Turtle {} is already disconnected", self.name)
     }
 }
 
-impl std::fmt::Display for AlreadyDisconnectedError {
+impl std::fmt::Display for NotConnectedError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Turtle {} is already disconnected", self.name)
+        write!(f, "Turtle {} is not connected", self.name)
+    }
+}
+
+impl std::fmt::Display for NotPausedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Turtle {} is not paused", self.name)
     }
 }
 
-impl std::error::Error for AlreadyConnectedError {}
 impl std::error::Error for AlreadyDisconnectedError {}
+impl std::error::Error for NotConnectedError {}
+impl std::error::Error for NotPausedError {}
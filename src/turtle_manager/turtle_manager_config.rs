@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::crafting::RecipeBook;
+use crate::deploy::Script;
+use crate::turtle_scheme::RequestType;
+
+/// Settings for how a TurtleManager treats turtle connections.
+#[derive(Debug, Clone)]
+pub struct TurtleManagerConfig {
+    /// If set every turtle session is recorded to a file in this directory.
+    pub record_dir: Option<PathBuf>,
+
+    /// Heartbeats and request timeouts for every turtle connection.
+    pub connection: ConnectionConfig,
+
+    /// Recipes turtles can be asked to craft.
+    pub recipes: RecipeBook,
+
+    /// How long a guarding turtle waits between attacks.
+    pub guard_interval: Duration,
+
+    /// Script deployed to turtles that are running a different version.
+    pub script: Script,
+
+    /// Whether Lua can be evaluated on turtles and for how long.
+    pub eval: EvalConfig,
+
+    /// How long turtles moving in a formation wait for something in their way.
+    pub formation: FormationConfig,
+
+    /// How long blocks stay reserved for the turtles moving into them.
+    pub reservations: ReservationConfig,
+
+    /// How jobs are split up and handed to idle turtles.
+    pub dispatch: DispatchConfig,
+
+    /// When turtles are sent back to their home waypoint and how long they try to get there.
+    pub home: HomeConfig,
+}
+
+impl Default for TurtleManagerConfig {
+    fn default() -> Self {
+        TurtleManagerConfig {
+            record_dir: None,
+            connection: ConnectionConfig::default(),
+            recipes: RecipeBook::default(),
+            guard_interval: Duration::from_secs(1),
+            script: Script::default(),
+            eval: EvalConfig::default(),
+            formation: FormationConfig::default(),
+            reservations: ReservationConfig::default(),
+            dispatch: DispatchConfig::default(),
+            home: HomeConfig::default(),
+        }
+    }
+}
+
+impl TurtleManagerConfig {
+    /// Checks the settings make sense together.
+    /// Returns why they don't if they don't.
+    pub fn validate(&self) -> Result<(), String> {
+        self.connection.validate()
+    }
+}
+
+/// Timeouts and limits each turtle connection runs with.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// How often each turtle is checked for liveness and sent a heartbeat.
+    pub heartbeat_interval: Duration,
+
+    /// Heartbeats a turtle can miss in a row before it is marked unresponsive.
+    pub unresponsive_after: u32,
+
+    /// Heartbeats a turtle can miss in a row before it is disconnected.
+    pub disconnect_after: u32,
+
+    /// How long each request waits for its response before failing with a timeout.
+    pub request_timeouts: RequestTimeouts,
+
+    /// How long one task can keep a turtle locked before the lock is released for it.
+    pub lock_timeout: Duration,
+}
+
+impl ConnectionConfig {
+    /// A turtle has to be marked unresponsive before it is disconnected or it would never be.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.unresponsive_after >= self.disconnect_after {
+            return Err(format!(
+                "unresponsive_after ({}) must be less than disconnect_after ({})",
+                self.unresponsive_after, self.disconnect_after
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            heartbeat_interval: Duration::from_secs(10),
+            unresponsive_after: 3,
+            disconnect_after: 6,
+            request_timeouts: RequestTimeouts::default(),
+            lock_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// How long a request can wait for its response from the turtle.
+#[derive(Debug, Clone)]
+pub struct RequestTimeouts {
+    /// Used for every request type without an override.
+    pub default: Duration,
+
+    /// Timeouts for single request types keyed by `RequestType::kind`.
+    pub overrides: HashMap<&'static str, Duration>,
+}
+
+impl RequestTimeouts {
+    /// Gets the timeout for a request.
+    /// Evals also get the time the turtle was told to let the code run for, so the timeout of the
+    /// eval type only has to cover the round trip.
+    pub fn get(&self, request: &RequestType) -> Duration {
+        let timeout = self
+            .overrides
+            .get(request.kind())
+            .copied()
+            .unwrap_or(self.default);
+
+        match request {
+            RequestType::Eval { timeout_ms, .. } => timeout + Duration::from_millis(*timeout_ms),
+            _ => timeout,
+        }
+    }
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        RequestTimeouts {
+            default: Duration::from_secs(10),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+/// Settings for evaluating Lua on turtles.
+#[derive(Debug, Clone)]
+pub struct EvalConfig {
+    /// Off by default as evaluated code can do anything the turtle can.
+    pub enabled: bool,
+
+    /// How long the turtle runs a chunk before giving up on it. The eval request waits this long
+    /// on top of its own timeout so the turtle's error is returned rather than a timeout.
+    pub timeout: Duration,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        EvalConfig {
+            enabled: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Settings for moving turtles in a formation.
+#[derive(Debug, Clone)]
+pub struct FormationConfig {
+    /// Times a blocked turtle tries to move again before the formation goes back.
+    pub retries: u32,
+
+    /// How long to wait before trying again. Gives mobs and other turtles time to move away.
+    pub retry_wait: Duration,
+}
+
+impl Default for FormationConfig {
+    fn default() -> Self {
+        FormationConfig {
+            retries: 5,
+            retry_wait: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Settings for reserving the blocks turtles move into.
+#[derive(Debug, Clone)]
+pub struct ReservationConfig {
+    /// How long a block stays reserved after a turtle reserves it or a turtle is seen in it.
+    pub lease: Duration,
+
+    /// How long a move waits for a block reserved for something else before failing.
+    pub wait: Duration,
+
+    /// How often a waiting move tries to reserve the block again.
+    pub retry_interval: Duration,
+}
+
+impl Default for ReservationConfig {
+    fn default() -> Self {
+        ReservationConfig {
+            lease: Duration::from_secs(5),
+            wait: Duration::from_secs(10),
+            retry_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Settings for splitting jobs between idle turtles.
+#[derive(Debug, Clone)]
+pub struct DispatchConfig {
+    /// Most tasks handed to a turtle at once. Each column of an excavation is a task.
+    pub chunk_size: usize,
+
+    /// Fuel a turtle has to keep after a task. Turtles with less are not given chunks and give
+    /// back the one they are working on.
+    pub min_fuel: u32,
+
+    /// Times a chunk is handed out again after a turtle fails to finish it.
+    pub retries: u32,
+
+    /// How often waiting chunks are offered to idle turtles.
+    pub poll_interval: Duration,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        DispatchConfig {
+            chunk_size: 8,
+            min_fuel: 100,
+            retries: 3,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Settings for sending turtles back to their home waypoint.
+#[derive(Debug, Clone)]
+pub struct HomeConfig {
+    /// Fuel level below which an idle turtle is sent home.
+    pub low_fuel: u32,
+
+    /// Times a blocked turtle tries to move again before giving up on getting home.
+    pub retries: u32,
+
+    /// How long to wait before trying again.
+    pub retry_wait: Duration,
+
+    /// How long the server waits for turtles to get home when shutting down.
+    pub shutdown_wait: Duration,
+}
+
+impl Default for HomeConfig {
+    fn default() -> Self {
+        HomeConfig {
+            low_fuel: 100,
+            retries: 5,
+            retry_wait: Duration::from_secs(1),
+            shutdown_wait: Duration::from_secs(60),
+        }
+    }
+}
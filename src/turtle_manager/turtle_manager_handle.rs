@@ -1,18 +1,32 @@
+use std::sync::Arc;
+
 use sqlx::SqlitePool;
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
 
+use crate::crafting::ItemCount;
+use crate::dispatcher::{FuelNeeds, Job, JobProgress};
+use crate::formation::Formation;
+use crate::metrics::Metrics;
+use crate::selector::{Labels, Selector};
 use crate::turtle_manager::TurtleConnectionMessage;
 use crate::turtle_scheme::TurtleEvents;
 use crate::{
-    scheme::{Coordinates, Fuel, Heading},
-    turtle_scheme::TurtleCommand,
+    error::Error,
+    scheme::{
+        self, Coordinates, Dimension, Direction, Fuel, Heading, Tool, TurtleType, Upgrades,
+        Waypoint,
+    },
+    turtle_scheme::{Inventory, TurtleCommand},
 };
 
 use super::{
-    turtle::Turtle, turtle_manager_inner::TurtleManagerInner,
-    turtle_manager_message::TurtleManagerMessage,
-    unknown_turtle_connection::UnknownTurtleConnection,
+    reservations::Reservation,
+    turtle::Turtle,
+    turtle_manager_config::TurtleManagerConfig,
+    turtle_manager_inner::TurtleManagerInner,
+    turtle_manager_message::{ResultSender, TurtleManagerMessage},
+    unknown_turtle_connection::{AuthedTurtle, UnknownTurtleConnection},
 };
 
 /// Handle for communicating with a TurtleManagerInner.
@@ -20,18 +34,48 @@ use super::{
 pub struct TurtleManagerHandle {
     /// Sender to send TurtleManagerMessages to a TurtleManagerInner.
     tx: mpsc::Sender<TurtleManagerMessage>,
+
+    /// Metrics for every turtle and client of this manager.
+    metrics: Arc<Metrics>,
 }
 
 impl TurtleManagerHandle {
     /// Creates a new TurtleManagerInner and starts it.
     /// Returns a handle to communicate to the TurtleManagerInner.
-    pub fn new(pool: SqlitePool) -> Self {
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Database turtles are stored in.
+    /// * `config` - How turtle connections are recorded and checked for liveness.
+    pub fn new(pool: SqlitePool, config: TurtleManagerConfig) -> Self {
+        Self::with_metrics(pool, config, Arc::new(Metrics::new()))
+    }
+
+    /// Creates a new TurtleManagerInner that keeps its metrics in `metrics` and starts it.
+    /// Returns a handle to communicate to the TurtleManagerInner.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - Database turtles are stored in.
+    /// * `config` - How turtle connections are recorded and checked for liveness.
+    /// * `metrics` - Registry the manager's turtles and clients are counted in.
+    pub fn with_metrics(
+        pool: SqlitePool,
+        config: TurtleManagerConfig,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         let (tx, rx) = mpsc::channel(100);
+        let handle = TurtleManagerHandle { tx, metrics };
 
-        let inner = TurtleManagerInner::new(rx, TurtleManagerHandle { tx: tx.clone() }, pool);
+        let inner = TurtleManagerInner::new(rx, handle.clone(), pool, config);
         tokio::spawn(inner.run());
 
-        TurtleManagerHandle { tx }
+        handle
+    }
+
+    /// Metrics for this manager's turtles and clients.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
     }
 
     /// Closes the TurtleManager.
@@ -56,31 +100,63 @@ impl TurtleManagerHandle {
         }
     }
 
+    /// Called once a new turtle finishes the handshake.
+    pub async fn authed(&self, turtle: AuthedTurtle) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Authed(turtle))
+            .await
+            .is_err()
+        {
+            error!("Problem sending authed turtle to turtle manager");
+        }
+    }
+
     /// Disconnects a turtle.
     ///
     /// # Arguments
     ///
     /// * `name` - Name of the turtle to disconnect.
-    pub async fn disconnect(&self, name: impl Into<String>) {
+    pub async fn disconnect(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("disconnect", |tx| TurtleManagerMessage::Disconnect {
+            name,
+            tx,
+        })
+        .await
+    }
+
+    /// Tells the manager a turtle's connection has shut down.
+    /// Only disconnects the turtle if `id` is still its current connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the turtle the connection belonged to.
+    /// * `id` - Id of the connection that shut down.
+    pub async fn connection_closed(&self, name: impl Into<String>, id: u64) {
         if self
             .tx
-            .send(TurtleManagerMessage::Disconnect(name.into()))
+            .send(TurtleManagerMessage::ConnectionClosed {
+                name: name.into(),
+                id,
+            })
             .await
             .is_err()
         {
-            error!("Problem sending disconnect to turtle manager");
+            error!("Problem sending connection closed to turtle manager");
         }
     }
 
-    /// Sends a message to all connected turtles.
+    /// Sends a command to every turtle `selector` picks out.
     ///
     /// # Arguments
     ///
-    /// * `message` - Message to send to all turtles.
-    pub async fn broadcast(&self, command: TurtleCommand) {
+    /// * `selector` - Turtles to send the command to.
+    /// * `command` - Command to send.
+    pub async fn broadcast(&self, selector: Selector, command: TurtleCommand) {
         if self
             .tx
-            .send(TurtleManagerMessage::Broadcast(command))
+            .send(TurtleManagerMessage::Broadcast { selector, command })
             .await
             .is_err()
         {
@@ -88,13 +164,174 @@ impl TurtleManagerHandle {
         }
     }
 
+    /// Pauses a turtle. The turtle finishes its current command and then stops taking new ones.
+    /// Commands sent while paused are queued until the turtle is resumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the turtle to pause.
+    pub async fn pause(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("pause", |tx| TurtleManagerMessage::Pause { name, tx })
+            .await
+    }
+
+    /// Resumes a paused or emergency stopped turtle.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the turtle to resume.
+    pub async fn resume(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("resume", |tx| TurtleManagerMessage::Resume { name, tx })
+            .await
+    }
+
+    /// Pauses a turtle and stops it from retrying its current command.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the turtle to stop.
+    pub async fn emergency_stop(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("emergency stop", |tx| TurtleManagerMessage::EmergencyStop {
+            name,
+            tx,
+        })
+        .await
+    }
+
+    /// Pauses every connected turtle `selector` picks out.
+    pub async fn pause_selected(&self, selector: Selector) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::PauseSelected(selector))
+            .await
+            .is_err()
+        {
+            error!("Problem sending pause selected to turtle manager");
+        }
+    }
+
+    /// Resumes every paused turtle `selector` picks out.
+    pub async fn resume_selected(&self, selector: Selector) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::ResumeSelected(selector))
+            .await
+            .is_err()
+        {
+            error!("Problem sending resume selected to turtle manager");
+        }
+    }
+
+    /// Emergency stops every connected turtle `selector` picks out.
+    pub async fn emergency_stop_selected(&self, selector: Selector) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::EmergencyStopSelected(selector))
+            .await
+            .is_err()
+        {
+            error!("Problem sending emergency stop selected to turtle manager");
+        }
+    }
+
+    /// Gets the names of the turtles `selector` picks out.
+    /// Fails if a single turtle is named and the manager does not know it.
+    pub async fn select(&self, selector: Selector) -> Result<Vec<String>, Error> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::Select { selector, tx })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Select message to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.unwrap_or(Err(Error::Shutdown))
+    }
+
+    /// Adds a turtle to the group or tag in `label`, such as `@miners` or `#farm-north`.
+    pub async fn add_label(&self, name: impl Into<String>, label: Selector) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Label", |tx| TurtleManagerMessage::Label {
+            name,
+            label,
+            add: true,
+            tx,
+        })
+        .await
+    }
+
+    /// Takes a turtle out of the group or tag in `label`.
+    pub async fn remove_label(
+        &self,
+        name: impl Into<String>,
+        label: Selector,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Label", |tx| TurtleManagerMessage::Label {
+            name,
+            label,
+            add: false,
+            tx,
+        })
+        .await
+    }
+
+    /// Called once a change to a turtle's groups or tags is saved so selections see it.
+    pub async fn labelled(&self, name: String, label: Selector, add: bool, tx: ResultSender) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Labelled {
+                name,
+                label,
+                add,
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Labelled to turtle manager");
+        }
+    }
+
+    /// Gets every group and tag with the turtles in it.
+    pub async fn get_labels(&self) -> Result<Labels, Error> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetLabels(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetLabels message to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.unwrap_or(Err(Error::Shutdown))
+    }
+
     /// Gets the status of all turtles.
     /// Returns None if the TurtleManagerInner fails to send the status.
     pub async fn get_status(&self) -> Option<String> {
+        self.status(None).await
+    }
+
+    /// Gets the status of the turtles in `dimension`.
+    /// Returns None if the TurtleManagerInner fails to send the status.
+    pub async fn get_dimension_status(&self, dimension: Dimension) -> Option<String> {
+        self.status(Some(dimension)).await
+    }
+
+    async fn status(&self, dimension: Option<Dimension>) -> Option<String> {
         let (tx, rx) = oneshot::channel();
         if self
             .tx
-            .send(TurtleManagerMessage::Status(tx))
+            .send(TurtleManagerMessage::Status { dimension, tx })
             .await
             .is_err()
         {
@@ -128,6 +365,543 @@ impl TurtleManagerHandle {
             .flatten()
     }
 
+    /// Gets a turtle's last reported position and heading without reading the database.
+    /// Returns None if the position isn't known.
+    pub async fn get_position(&self, name: impl Into<String>) -> Option<(Coordinates, Heading)> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetPosition {
+                name: name.into(),
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetPosition message to turtle manager");
+            return None;
+        }
+
+        rx.await.ok().flatten()
+    }
+
+    /// Gets the last known state of every turtle in the database.
+    /// Returns None if the TurtleManagerInner fails to send them.
+    pub async fn get_turtles(&self) -> Option<Vec<scheme::Turtle>> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetTurtles(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetTurtles message to turtle manager");
+            return None;
+        }
+
+        rx.await.ok()
+    }
+
+    /// Has a turtle craft at least `count` of `item`.
+    /// Returns once the crafting commands are queued. Fails with the items the turtle is missing
+    /// if it does not have enough to craft. Clients are sent Crafted once the turtle is done.
+    pub async fn craft(
+        &self,
+        name: impl Into<String>,
+        item: impl Into<String>,
+        count: u32,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        let item = item.into();
+        self.send_for_result("Craft", |tx| TurtleManagerMessage::Craft {
+            name,
+            item,
+            count,
+            tx,
+        })
+        .await
+    }
+
+    /// Moves turtles together into `formation` with its first place at `destination`.
+    /// Returns once the turtles set off. Clients are sent FormationMoved once every turtle is in
+    /// place or, if one of them stayed blocked, once they have all gone back to where they started.
+    pub async fn move_formation(
+        &self,
+        names: Vec<String>,
+        formation: Formation,
+        destination: Coordinates,
+        heading: Heading,
+    ) -> Result<(), Error> {
+        self.send_for_result("MoveFormation", |tx| TurtleManagerMessage::MoveFormation {
+            names,
+            formation,
+            destination,
+            heading,
+            tx,
+        })
+        .await
+    }
+
+    /// Has a turtle attack whatever is in front of it until `stop_guard` is called.
+    /// Items it picks up are sent to client subscribers.
+    pub async fn guard(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Guard", |tx| TurtleManagerMessage::Guard { name, tx })
+            .await
+    }
+
+    /// Stops a turtle guarding.
+    pub async fn stop_guard(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("StopGuard", |tx| TurtleManagerMessage::StopGuard {
+            name,
+            tx,
+        })
+        .await
+    }
+
+    /// Lets the manager know a turtle's guard task ended.
+    pub async fn guard_stopped(&self, name: impl Into<String>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::GuardStopped { name: name.into() })
+            .await
+            .is_err()
+        {
+            error!("Problem sending GuardStopped to turtle manager");
+        }
+    }
+
+    /// Tells client subscribers what a guarding turtle picked up.
+    pub async fn report_drops(&self, name: impl Into<String>, items: Vec<ItemCount>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Drops {
+                name: name.into(),
+                items,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending drops to turtle manager");
+        }
+    }
+
+    /// Sends a turtle the script from the config and reboots it into it.
+    /// A turtle that was rolled back is only deployed to if `force` is set.
+    pub async fn deploy(&self, name: impl Into<String>, force: bool) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Deploy", |tx| TurtleManagerMessage::Deploy {
+            name,
+            force,
+            tx,
+        })
+        .await
+    }
+
+    /// Deploys the script from the config to every connected turtle that is running a
+    /// different version and was not rolled back. Returns the names of the turtles it was
+    /// deployed to.
+    pub async fn deploy_all(&self) -> Vec<String> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::DeployAll(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending DeployAll message to turtle manager");
+            return vec![];
+        }
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Has a turtle go back to the script it had before its last deploy and reboot into it.
+    /// The turtle is skipped by deploy_all until it is deployed to with force.
+    pub async fn rollback(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Rollback", |tx| TurtleManagerMessage::Rollback { name, tx })
+            .await
+    }
+
+    /// Runs a chunk of Lua on a turtle and returns the values it returned serialized.
+    /// Fails with EvalDisabled unless eval is turned on in the config.
+    pub async fn eval(
+        &self,
+        name: impl Into<String>,
+        code: impl Into<String>,
+    ) -> Result<String, Error> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::Eval {
+                name: name.into(),
+                code: code.into(),
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Eval to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.unwrap_or(Err(Error::Shutdown))
+    }
+
+    /// Moves a turtle once the block it is moving into is reserved for it.
+    /// Waits for blocks reserved for or taken by other turtles to be freed before failing.
+    pub async fn move_turtle(
+        &self,
+        name: impl Into<String>,
+        direction: Direction,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("MoveTurtle", |tx| TurtleManagerMessage::MoveTurtle {
+            name,
+            direction,
+            tx,
+        })
+        .await
+    }
+
+    /// Reserves `position` for a turtle that is about to move into it.
+    /// Fails straight away if it is reserved for or taken by another turtle.
+    pub async fn reserve(
+        &self,
+        name: impl Into<String>,
+        position: Coordinates,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("Reserve", |tx| TurtleManagerMessage::Reserve {
+            name,
+            position,
+            tx,
+        })
+        .await
+    }
+
+    /// Called by a turtle's receiver when the turtle inspected another turtle in front of it.
+    pub async fn turtle_seen(&self, name: impl Into<String>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::TurtleSeen { name: name.into() })
+            .await
+            .is_err()
+        {
+            error!("Problem sending seen turtle to turtle manager");
+        }
+    }
+
+    /// Gets every block that is reserved and who for.
+    pub async fn get_reservations(&self) -> Vec<Reservation> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetReservations(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetReservations message to turtle manager");
+            return vec![];
+        }
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Splits a job into chunks and hands them to the idle turtles `target` picks out until it
+    /// is done. Returns the job's id straight away. Progress is sent to client subscribers.
+    pub async fn start_job(&self, job: Job, target: Selector) -> Result<u64, Error> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::StartJob { job, target, tx })
+            .await
+            .is_err()
+        {
+            error!("Problem sending StartJob to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.map_err(|_| Error::Shutdown)?
+    }
+
+    /// Marks an idle turtle as working on a job for each chunk of it there is a turtle with
+    /// enough fuel for. Returns each turtle with the index of the chunk it was claimed for.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - Id of the job the turtles are working on.
+    /// * `target` - Turtles that can be claimed.
+    /// * `dimension` - Dimension the turtles have to be in. Any dimension if None.
+    /// * `tool` - Tool the turtles have to have equipped. Any turtle if None.
+    /// * `min_fuel` - Least fuel the turtles have to keep.
+    /// * `needs` - Fuel needed on top of `min_fuel` for each chunk.
+    pub async fn claim_idle(
+        &self,
+        job: u64,
+        target: Selector,
+        dimension: Option<Dimension>,
+        tool: Option<Tool>,
+        min_fuel: u32,
+        needs: Vec<FuelNeeds>,
+    ) -> Vec<(usize, Turtle)> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::ClaimIdle {
+                job,
+                target,
+                dimension,
+                tool,
+                min_fuel,
+                needs,
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending ClaimIdle to turtle manager");
+            return vec![];
+        }
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Marks a turtle claimed for a job as idle again.
+    pub async fn release(&self, name: impl Into<String>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Release { name: name.into() })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Release to turtle manager");
+        }
+    }
+
+    /// Stores how far along a job is and sends it to client subscribers.
+    pub async fn report_job(&self, progress: JobProgress) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::ReportJob(progress))
+            .await
+            .is_err()
+        {
+            error!("Problem sending job progress to turtle manager");
+        }
+    }
+
+    /// Gets the progress of every job that has been started.
+    pub async fn get_jobs(&self) -> Vec<JobProgress> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetJobs(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetJobs message to turtle manager");
+            return vec![];
+        }
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Stores a waypoint, replacing any waypoint with the same name.
+    pub async fn set_waypoint(&self, waypoint: Waypoint) -> Result<(), Error> {
+        self.send_for_result("SetWaypoint", |tx| TurtleManagerMessage::SetWaypoint {
+            waypoint,
+            tx,
+        })
+        .await
+    }
+
+    /// Removes a waypoint. Turtles that had it as their home are left without one.
+    pub async fn remove_waypoint(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("RemoveWaypoint", |tx| {
+            TurtleManagerMessage::RemoveWaypoint { name, tx }
+        })
+        .await
+    }
+
+    /// Gets every waypoint sorted by name.
+    pub async fn get_waypoints(&self) -> Result<Vec<Waypoint>, Error> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetWaypoints(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetWaypoints message to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.unwrap_or(Err(Error::Shutdown))
+    }
+
+    /// Sets the waypoint a turtle goes back to. None clears it.
+    pub async fn set_home(
+        &self,
+        name: impl Into<String>,
+        waypoint: Option<String>,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("SetHome", |tx| TurtleManagerMessage::SetHome {
+            name,
+            waypoint,
+            tx,
+        })
+        .await
+    }
+
+    /// Sends a turtle back to its home and waits for it to set off.
+    /// Clients are sent ReturnedHome once it gets there or gives up.
+    pub async fn return_home(&self, name: impl Into<String>) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("ReturnHome", |tx| TurtleManagerMessage::ReturnHome {
+            name,
+            tx,
+        })
+        .await
+    }
+
+    /// Marks a turtle as no longer on its way home and tells clients how its trip to `waypoint`
+    /// went. Clients are not told about turtles without a home.
+    pub async fn returned(
+        &self,
+        name: impl Into<String>,
+        waypoint: Option<String>,
+        error: Option<Error>,
+    ) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Returned {
+                name: name.into(),
+                waypoint,
+                error,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Returned to turtle manager");
+        }
+    }
+
+    /// Tells clients a formation stopped moving. `error` is None if every turtle got in place.
+    pub async fn formation_moved(&self, names: Vec<String>, error: Option<Error>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::FormationMoved { names, error })
+            .await
+            .is_err()
+        {
+            error!("Problem sending FormationMoved to turtle manager");
+        }
+    }
+
+    /// Tells clients a turtle finished crafting `item`. `error` is None if it made everything.
+    pub async fn crafted(&self, name: impl Into<String>, item: String, error: Option<Error>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Crafted {
+                name: name.into(),
+                item,
+                error,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending Crafted to turtle manager");
+        }
+    }
+
+    /// Tells the manager a turtle listed its own inventory and found it full.
+    pub async fn inventory_full(&self, name: impl Into<String>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::InventoryFull(name.into()))
+            .await
+            .is_err()
+        {
+            error!("Problem sending InventoryFull to turtle manager");
+        }
+    }
+
+    /// Sends every connected turtle with a home back to it.
+    /// Waits until they all get there or the configured shutdown wait runs out.
+    pub async fn return_all_home(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::ReturnAllHome(tx))
+            .await
+            .is_err()
+        {
+            error!("Problem sending ReturnAllHome to turtle manager");
+            return;
+        }
+
+        let _ = rx.await;
+    }
+
+    /// Gets the last stored contents of the inventory at `position` in `dimension`.
+    /// Returns None if no turtle has listed the inventory.
+    pub async fn get_inventory(
+        &self,
+        position: Coordinates,
+        dimension: Dimension,
+    ) -> Option<Inventory> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .send(TurtleManagerMessage::GetInventory {
+                position,
+                dimension,
+                tx,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending GetInventory message to turtle manager");
+            return None;
+        }
+
+        rx.await.ok().flatten()
+    }
+
+    /// Adds a new turtle to the database so it is known before it first connects.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name the turtle will be given when it connects.
+    /// * `position` - Where the turtle is.
+    /// * `dimension` - Dimension the turtle is in.
+    /// * `heading` - Which way the turtle is facing.
+    /// * `turtle_type` - Whether the turtle is advanced.
+    pub async fn add_turtle(
+        &self,
+        name: impl Into<String>,
+        position: Coordinates,
+        dimension: Dimension,
+        heading: Heading,
+        turtle_type: TurtleType,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        self.send_for_result("AddTurtle", |tx| TurtleManagerMessage::AddTurtle {
+            name,
+            position,
+            dimension,
+            heading,
+            turtle_type,
+            tx,
+        })
+        .await
+    }
+
     pub async fn update_turtle_position(&self, name: impl Into<String>, position: Coordinates) {
         if self
             .tx
@@ -142,6 +916,34 @@ impl TurtleManagerHandle {
         }
     }
 
+    pub async fn update_turtle_dimension(&self, name: impl Into<String>, dimension: Dimension) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::UpdateDimension {
+                name: name.into(),
+                dimension,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending turtle dimension update to turtle manager");
+        }
+    }
+
+    pub async fn update_turtle_upgrades(&self, name: impl Into<String>, upgrades: Upgrades) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::UpdateUpgrades {
+                name: name.into(),
+                upgrades,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending turtle upgrades update to turtle manager");
+        }
+    }
+
     pub async fn update_turtle_heading(&self, name: impl Into<String>, heading: Heading) {
         if self
             .tx
@@ -170,6 +972,37 @@ impl TurtleManagerHandle {
         }
     }
 
+    /// Finishes registering a turtle from its first report.
+    /// Turtles added during the handshake get their type from the reported fuel limit.
+    /// Turtles that were already in the database keep theirs.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the turtle.
+    /// * `fuel` - Fuel reported by the turtle. The fuel limit decides the turtle's type.
+    /// * `position` - Position and heading from the turtle's position file. Checked with GPS if
+    ///   the turtle can locate itself. None if the turtle asked for its position, which already
+    ///   checks it.
+    pub async fn register_turtle(
+        &self,
+        name: impl Into<String>,
+        fuel: Fuel,
+        position: Option<(Coordinates, Heading)>,
+    ) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::Register {
+                name: name.into(),
+                fuel,
+                position,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending register to turtle manager");
+        }
+    }
+
     pub async fn send_turtle_position(&self, name: impl Into<String>) {
         if self
             .tx
@@ -181,6 +1014,33 @@ impl TurtleManagerHandle {
         }
     }
 
+    /// Records the current time as when the turtle was last heard from.
+    pub async fn update_last_seen(&self, name: impl Into<String>) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::UpdateLastSeen(name.into()))
+            .await
+            .is_err()
+        {
+            error!("Problem sending last seen update to turtle manager");
+        }
+    }
+
+    /// Marks a turtle as unresponsive if `responsive` is false or as responding again if true.
+    pub async fn set_responsive(&self, name: impl Into<String>, responsive: bool) {
+        if self
+            .tx
+            .send(TurtleManagerMessage::SetResponsive {
+                name: name.into(),
+                responsive,
+            })
+            .await
+            .is_err()
+        {
+            error!("Problem sending responsive update to turtle manager");
+        }
+    }
+
     pub async fn client_subscribe(
         &self,
         tx: mpsc::UnboundedSender<TurtleConnectionMessage<'static>>,
@@ -194,4 +1054,25 @@ impl TurtleManagerHandle {
             error!("Problem sending client subscription to turtle manager");
         }
     }
+
+    /// Sends a message that answers with a result and waits for it.
+    /// Fails with Error::Shutdown if the TurtleManagerInner has closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - What the message does. Used when logging.
+    /// * `message` - Makes the message from the sender for the result.
+    async fn send_for_result(
+        &self,
+        action: &str,
+        message: impl FnOnce(ResultSender) -> TurtleManagerMessage,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(message(tx)).await.is_err() {
+            error!("Problem sending {action} to turtle manager");
+            return Err(Error::Shutdown);
+        }
+
+        rx.await.unwrap_or(Err(Error::Shutdown))
+    }
 }
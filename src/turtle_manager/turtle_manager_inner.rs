@@ -1,21 +1,44 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+
 use sqlx::SqlitePool;
 use tokio::sync::{mpsc, oneshot};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::crafting::ItemCount;
+use crate::db::turtle_operations::{self, TurtleDB};
+use crate::db::{group_operations, inventory_operations, waypoint_operations};
+use crate::dispatcher::{self, FuelNeeds, Job, JobProgress};
+use crate::error::Error;
+use crate::formation::{self, Formation};
+use crate::home;
+use crate::metrics::Metrics;
+use crate::selector::{Labels, Selector};
 use crate::turtle_manager::turtle::TurtleStatus;
 use crate::turtle_manager::{ConnectionMessageType, TurtleConnectionMessage};
 use crate::turtle_scheme::TurtleEvents;
 use crate::{
-    scheme::{Coordinates, Fuel, Heading},
+    scheme::{
+        self, Coordinates, Dimension, Direction, Fuel, Heading, Tool, TurtleType, Upgrades,
+        Waypoint,
+    },
     turtle_scheme::TurtleCommand,
 };
 
 use super::{
-    turtle::Turtle, turtle_connection_status::TurtleConnectionStatus,
-    turtle_manager_message::TurtleManagerMessage,
-    unknown_turtle_connection::UnknownTurtleConnection, TurtleManagerHandle,
+    reservations::Reservations,
+    turtle::Turtle,
+    turtle_connection_status::TurtleConnectionStatus,
+    turtle_manager_config::TurtleManagerConfig,
+    turtle_manager_message::{ResultSender, TurtleManagerMessage},
+    unknown_turtle_connection::{AuthedTurtle, UnknownTurtleConnection},
+    TurtleManagerHandle,
 };
 
+/// Change to a turtle's group or tag waiting to be written to the database. The sender is answered
+/// once it is written.
+type LabelWrite = (String, Selector, bool, ResultSender);
+
 /// Contains the logic behind managing the turtle websocket connections.
 pub struct TurtleManagerInner {
     /// Receives messages from TurtleManagerHandle.
@@ -30,7 +53,42 @@ pub struct TurtleManagerInner {
 
     client_subscriptions: Vec<mpsc::UnboundedSender<TurtleConnectionMessage<'static>>>,
 
+    /// Stops the guard task of each guarding turtle keyed by turtle name.
+    guards: HashMap<String, oneshot::Sender<()>>,
+
+    /// Blocks turtles are about to move into or that other turtles were seen in.
+    reservations: Reservations,
+
+    /// Id of the job each busy turtle is working on keyed by turtle name.
+    working: HashMap<&'static str, u64>,
+
+    /// Last progress of every job keyed by id.
+    jobs: BTreeMap<u64, JobProgress>,
+
+    /// Id given to the next job.
+    next_job: u64,
+
+    /// Turtles on their way to their home.
+    returning: HashSet<&'static str>,
+
+    /// Turtles added to the database during the handshake that have not sent their first report.
+    unreported: HashSet<&'static str>,
+
+    /// What the database has for each turtle's position, heading, dimension and fuel keyed by
+    /// turtle name. Updated along with the database so it can be read without a query.
+    known: HashMap<&'static str, KnownState>,
+
+    /// Every group and tag with the turtles in it. Loaded from the database at start up and
+    /// changed along with it so turtles can be selected without a query.
+    labels: Labels,
+
+    /// Writes changes to `labels` to the database in the order they were made.
+    label_writes: mpsc::UnboundedSender<LabelWrite>,
+
     pool: SqlitePool,
+
+    /// Passed on to every turtle connection.
+    config: TurtleManagerConfig,
 }
 
 impl TurtleManagerInner {
@@ -40,13 +98,32 @@ impl TurtleManagerInner {
         rx: mpsc::Receiver<TurtleManagerMessage>,
         own_handle: TurtleManagerHandle,
         pool: SqlitePool,
+        config: TurtleManagerConfig,
     ) -> Self {
+        let (label_writes, label_writes_rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_labels(
+            label_writes_rx,
+            pool.clone(),
+            own_handle.clone(),
+        ));
+
         TurtleManagerInner {
             rx,
             own_handle,
             turtles: Vec::new(),
             client_subscriptions: vec![],
+            guards: HashMap::new(),
+            reservations: Reservations::default(),
+            working: HashMap::new(),
+            jobs: BTreeMap::new(),
+            next_job: 0,
+            returning: HashSet::new(),
+            unreported: HashSet::new(),
+            known: HashMap::new(),
+            labels: Labels::default(),
+            label_writes,
             pool,
+            config,
         }
     }
 
@@ -56,6 +133,10 @@ impl TurtleManagerInner {
         // Used to notify when we have closed.
         let mut close_tx = None;
 
+        self.load_turtles().await;
+        self.load_labels().await;
+        self.update_turtle_counts();
+
         while let Some(message) = self.rx.recv().await {
             match message {
                 TurtleManagerMessage::Close(tx) => {
@@ -63,83 +144,464 @@ impl TurtleManagerInner {
                     break;
                 }
                 TurtleManagerMessage::UnknownTurtle(unknown_turtle) => {
-                    self.new_unknown_turtle(unknown_turtle).await;
-                }
-                TurtleManagerMessage::Disconnect(name) => self.disconnect_turtle(name).await,
-                TurtleManagerMessage::Broadcast(command) => self.broadcast(command).await,
-                TurtleManagerMessage::Status(tx) => {
-                    let names_futures = self
-                        .turtles
-                        .iter()
-                        .map(async move |t| t.status_string().await);
-                    let names = futures_util::future::join_all(names_futures)
-                        .await
-                        .join("\n");
-                    let names = names.trim().to_string();
-
-                    if tx.send(names).is_err() {
-                        error!("Problem sending status");
-                    };
+                    self.new_unknown_turtle(unknown_turtle);
+                }
+                TurtleManagerMessage::Authed(turtle) => self.authed_turtle(turtle).await,
+                TurtleManagerMessage::Disconnect { name, tx } => {
+                    let _ = tx.send(self.disconnect_turtle(name).await);
+                }
+                TurtleManagerMessage::ConnectionClosed { name, id } => {
+                    self.connection_closed(name, id).await;
+                }
+                TurtleManagerMessage::Broadcast { selector, command } => {
+                    self.broadcast(selector, command).await
+                }
+                TurtleManagerMessage::Pause { name, tx } => {
+                    let _ = tx.send(self.pause_turtle(name, false).await);
+                }
+                TurtleManagerMessage::Resume { name, tx } => {
+                    let _ = tx.send(self.resume_turtle(name).await);
+                }
+                TurtleManagerMessage::EmergencyStop { name, tx } => {
+                    let _ = tx.send(self.pause_turtle(name, true).await);
+                }
+                TurtleManagerMessage::PauseSelected(selector) => {
+                    self.pause_selected(selector, false).await
+                }
+                TurtleManagerMessage::ResumeSelected(selector) => {
+                    self.resume_selected(selector).await
+                }
+                TurtleManagerMessage::EmergencyStopSelected(selector) => {
+                    self.pause_selected(selector, true).await
+                }
+                TurtleManagerMessage::Select { selector, tx } => {
+                    let names = self.select(&selector);
+                    let _ = tx.send(names.map(|n| n.iter().map(|n| n.to_string()).collect()));
+                }
+                TurtleManagerMessage::Label {
+                    name,
+                    label,
+                    add,
+                    tx,
+                } => self.label(name, label, add, tx),
+                TurtleManagerMessage::Labelled {
+                    name,
+                    label,
+                    add,
+                    tx,
+                } => {
+                    self.labelled(name, label, add);
+                    let _ = tx.send(Ok(()));
+                }
+                TurtleManagerMessage::GetLabels(tx) => {
+                    let _ = tx.send(Ok(self.labels.clone()));
                 }
+                TurtleManagerMessage::Status { dimension, tx } => self.status(dimension, tx),
                 TurtleManagerMessage::GetTurtle { name, tx } => self.get_turtle(name.as_str(), tx),
+                TurtleManagerMessage::GetPosition { name, tx } => {
+                    let known = self.known.get(name.as_str());
+                    let _ = tx.send(known.map(|k| (k.position, k.heading)));
+                }
+                TurtleManagerMessage::GetTurtles(tx) => self.get_turtles(tx),
+                TurtleManagerMessage::Craft {
+                    name,
+                    item,
+                    count,
+                    tx,
+                } => self.craft(name, item, count, tx),
+                TurtleManagerMessage::Crafted { name, item, error } => {
+                    self.crafted(name, item, error)
+                }
+                TurtleManagerMessage::Guard { name, tx } => {
+                    let _ = tx.send(self.guard(name));
+                }
+                TurtleManagerMessage::StopGuard { name, tx } => {
+                    // Dropping the sender stops the task too.
+                    if let Some(stop) = self.guards.remove(&name) {
+                        let _ = stop.send(());
+                    }
+                    let _ = tx.send(Ok(()));
+                }
+                TurtleManagerMessage::GuardStopped { name } => {
+                    // A newer guard task's stop receiver is still open.
+                    if self.guards.get(&name).is_some_and(|s| s.is_closed()) {
+                        self.guards.remove(&name);
+                    }
+                }
+                TurtleManagerMessage::Drops { name, items } => self.send_drops(name, items),
+                TurtleManagerMessage::Eval { name, code, tx } => self.eval(name, code, tx),
+                TurtleManagerMessage::MoveFormation {
+                    names,
+                    formation,
+                    destination,
+                    heading,
+                    tx,
+                } => self.move_formation(names, formation, destination, heading, tx),
+                TurtleManagerMessage::FormationMoved { names, error } => {
+                    self.formation_moved(names, error)
+                }
+                TurtleManagerMessage::Deploy { name, force, tx } => self.deploy(name, force, tx),
+                TurtleManagerMessage::DeployAll(tx) => self.deploy_all(tx),
+                TurtleManagerMessage::Rollback { name, tx } => self.rollback(name, tx),
+                TurtleManagerMessage::MoveTurtle {
+                    name,
+                    direction,
+                    tx,
+                } => self.move_turtle(name, direction, tx),
+                TurtleManagerMessage::Reserve { name, position, tx } => {
+                    let _ = tx.send(self.reserve(name, position));
+                }
+                TurtleManagerMessage::TurtleSeen { name } => self.turtle_seen(name),
+                TurtleManagerMessage::GetReservations(tx) => {
+                    let _ = tx.send(self.reservations.list());
+                }
+                TurtleManagerMessage::StartJob { job, target, tx } => {
+                    let _ = tx.send(self.start_job(job, target));
+                }
+                TurtleManagerMessage::ClaimIdle {
+                    job,
+                    target,
+                    dimension,
+                    tool,
+                    min_fuel,
+                    needs,
+                    tx,
+                } => {
+                    let claimed = self.claim_idle(job, &target, dimension, tool, min_fuel, needs);
+                    let _ = tx.send(claimed);
+                }
+                TurtleManagerMessage::Release { name } => {
+                    self.working.remove(name.as_str());
+                }
+                TurtleManagerMessage::ReportJob(progress) => self.report_job(progress),
+                TurtleManagerMessage::GetJobs(tx) => {
+                    let _ = tx.send(self.jobs.values().cloned().collect());
+                }
+                TurtleManagerMessage::SetWaypoint { waypoint, tx } => {
+                    let pool = self.pool.clone();
+                    let metrics = self.own_handle.metrics().clone();
+                    tokio::spawn(async move {
+                        let result =
+                            waypoint_operations::set_waypoint(&waypoint, &pool, &metrics).await;
+                        let _ = tx.send(result.map(|_| ()).map_err(Error::from));
+                    });
+                }
+                TurtleManagerMessage::RemoveWaypoint { name, tx } => {
+                    let pool = self.pool.clone();
+                    let metrics = self.own_handle.metrics().clone();
+                    tokio::spawn(async move {
+                        let _ = tx.send(remove_waypoint(name, &pool, &metrics).await);
+                    });
+                }
+                TurtleManagerMessage::GetWaypoints(tx) => {
+                    let pool = self.pool.clone();
+                    let metrics = self.own_handle.metrics().clone();
+                    tokio::spawn(async move {
+                        let waypoints = waypoint_operations::get_waypoints(&pool, &metrics).await;
+                        let _ = tx.send(waypoints.map_err(Error::from));
+                    });
+                }
+                TurtleManagerMessage::SetHome { name, waypoint, tx } => {
+                    self.set_home(name, waypoint, tx)
+                }
+                TurtleManagerMessage::ReturnHome { name, tx } => self.return_home(name, tx),
+                TurtleManagerMessage::Returned {
+                    name,
+                    waypoint,
+                    error,
+                } => self.returned(name, waypoint, error),
+                TurtleManagerMessage::InventoryFull(name) => self.inventory_full(name),
+                TurtleManagerMessage::ReturnAllHome(tx) => self.return_all_home(tx),
+                TurtleManagerMessage::GetInventory {
+                    position,
+                    dimension,
+                    tx,
+                } => {
+                    let pool = self.pool.clone();
+                    let metrics = self.own_handle.metrics().clone();
+                    tokio::spawn(async move {
+                        let inventory = inventory_operations::get_inventory(
+                            position, &dimension, &pool, &metrics,
+                        )
+                        .await;
+                        let _ = tx.send(inventory);
+                    });
+                }
+                TurtleManagerMessage::AddTurtle {
+                    name,
+                    position,
+                    dimension,
+                    heading,
+                    turtle_type,
+                    tx,
+                } => {
+                    let result = self
+                        .add_turtle(name, position, dimension, heading, turtle_type)
+                        .await;
+                    let _ = tx.send(result);
+                }
                 TurtleManagerMessage::UpdatePosition { name, position } => {
                     self.update_turtle_position(name, position).await;
                 }
+                TurtleManagerMessage::UpdateDimension { name, dimension } => {
+                    self.update_turtle_dimension(name, dimension).await;
+                }
+                TurtleManagerMessage::UpdateUpgrades { name, upgrades } => {
+                    self.update_turtle_upgrades(name, upgrades).await;
+                }
                 TurtleManagerMessage::UpdateHeading { name, heading } => {
                     self.update_turtle_heading(name, heading).await;
                 }
                 TurtleManagerMessage::UpdateFuel { name, fuel } => {
                     self.update_turtle_fuel(name, fuel).await;
                 }
+                TurtleManagerMessage::Register {
+                    name,
+                    fuel,
+                    position,
+                } => {
+                    self.register_turtle(name, fuel, position).await;
+                }
                 TurtleManagerMessage::SendTurtlePosition(name) => {
-                    self.send_turtle_position(name).await;
+                    self.send_turtle_position(name);
                 }
                 TurtleManagerMessage::ClientSubscription(tx) => {
                     self.client_subscribe_all(tx).await;
                 }
+                TurtleManagerMessage::UpdateLastSeen(name) => self.update_last_seen(name),
+                TurtleManagerMessage::SetResponsive { name, responsive } => {
+                    self.set_responsive(name, responsive);
+                }
             }
+
+            self.update_turtle_counts();
         }
 
         info!("Turtle manager closing");
+        for turtle in self.turtles.iter_mut() {
+            let _ = turtle.get_connection_mut().disconnect().await;
+        }
+
         if let Some(tx) = close_tx {
             let _ = tx.send(());
         }
     }
 
-    /// Registers a new turtle that has not been identified.
-    /// Identifies the turtle and adds it to self.turtles.
-    async fn new_unknown_turtle(&mut self, unknown_turtle: UnknownTurtleConnection) {
-        if let Some((name, connection)) = unknown_turtle.auth(self.own_handle.clone()).await {
-            // Send the new turtle the client subscriptions so that the turtle connection can forward events to clients.
-            for tx in self.client_subscriptions.iter() {
-                debug!("Sending client subscription");
-                connection.client_subscribe(tx.clone()).await;
-            }
+    /// Adds every turtle in the database as disconnected so they are known before they connect.
+    async fn load_turtles(&mut self) {
+        let turtles =
+            match turtle_operations::get_turtles(&self.pool, self.own_handle.metrics()).await {
+                Ok(t) => t,
+                Err(e) => {
+                    error!("Problem loading turtles from database {e}");
+                    return;
+                }
+            };
 
-            debug!("Sending connected message to clients");
-            Self::send_subs_message(
-                &mut self.client_subscriptions,
-                TurtleConnectionMessage {
-                    name,
-                    message_type: ConnectionMessageType::Connected,
-                },
+        info!("Loaded {} turtles from database", turtles.len());
+        for turtle in turtles {
+            let name = self.add_disconnected(turtle.name.clone());
+            self.known.insert(name, KnownState::from(turtle));
+        }
+    }
+
+    /// Adds a turtle that has not connected yet unless it is already known.
+    /// Returns the name the turtle is known by.
+    fn add_disconnected(&mut self, name: String) -> &'static str {
+        if let Some(turtle) = self.get_turtle_by_name(name.as_str()) {
+            return turtle.get_name();
+        }
+
+        // Names of connected turtles are static so turtles are never removed. The same is done
+        // for turtles from the database so they can be replaced by a connection.
+        let name: &'static str = Box::leak(name.into_boxed_str());
+        self.turtles.push(Turtle::new(
+            TurtleConnectionStatus::Disconnected(name),
+            self.pool.clone(),
+            self.own_handle.metrics().clone(),
+        ));
+        name
+    }
+
+    /// Adds a new turtle to the database and the list of turtles.
+    async fn add_turtle(
+        &mut self,
+        name: String,
+        position: Coordinates,
+        dimension: Dimension,
+        heading: Heading,
+        turtle_type: TurtleType,
+    ) -> Result<(), Error> {
+        if let Err(e) = turtle_operations::add_turtle(
+            name.as_str(),
+            position,
+            &dimension,
+            heading,
+            turtle_type,
+            &self.pool,
+            self.own_handle.metrics(),
+        )
+        .await
+        {
+            error!("Problem creating new turtle {e}");
+            return Err(e.into());
+        }
+
+        info!("Added turtle {name}");
+        let name = self.add_disconnected(name);
+        self.known.insert(
+            name,
+            KnownState {
+                turtle_type,
+                ..KnownState::new(position, heading, dimension)
+            },
+        );
+        Ok(())
+    }
+
+    /// Adds a turtle that connected without being in the database.
+    /// Nothing is known about it until its first report so it is placed at the origin facing north
+    /// with its position unverified. The report fills in the rest.
+    /// Turtles in the database are loaded or added through the manager so are already known.
+    async fn add_connected_turtle(&mut self, name: &'static str) {
+        if self.known.contains_key(name) {
+            return;
+        }
+
+        if let Err(e) = turtle_operations::register_turtle(
+            name,
+            Coordinates { x: 0, y: 0, z: 0 },
+            &Dimension::default(),
+            Heading::North,
+            TurtleType::Normal,
+            0,
+            &self.pool,
+            self.own_handle.metrics(),
+        )
+        .await
+        {
+            error!("Problem registering turtle {name} {e}");
+            return;
+        }
+
+        info!("Registered {name}");
+        self.known.insert(
+            name,
+            KnownState::new(
+                Coordinates { x: 0, y: 0, z: 0 },
+                Heading::North,
+                Dimension::default(),
+            ),
+        );
+        self.unreported.insert(name);
+    }
+
+    /// Checks the position in a turtle's first report with GPS in the background and sets the type
+    /// of a turtle added during the handshake from the reported fuel limit.
+    async fn register_turtle(
+        &mut self,
+        name: String,
+        fuel: Fuel,
+        position: Option<(Coordinates, Heading)>,
+    ) {
+        if let (Some(turtle), Some((position, heading))) =
+            (self.get_turtle_by_name(name.as_str()), position)
+        {
+            let manager = self.own_handle.clone();
+            tokio::spawn(async move { turtle.verify_position(&manager, position, heading).await });
+        }
+
+        if !self.unreported.remove(name.as_str()) {
+            return;
+        }
+
+        let turtle_type = TurtleType::from_max_fuel(fuel.max).unwrap_or_else(|| {
+            warn!(
+                "{name} has unknown fuel limit {}. Assuming normal",
+                fuel.max
             );
+            TurtleType::Normal
+        });
+
+        if let Err(e) = TurtleDB::new(
+            name.as_str(),
+            self.pool.clone(),
+            self.own_handle.metrics().clone(),
+        )
+        .set_type(turtle_type)
+        .await
+        {
+            error!("Problem setting {name}'s type {e}");
+            return;
+        }
+        if let Some(known) = self.known.get_mut(name.as_str()) {
+            known.turtle_type = turtle_type;
+        }
+
+        info!("{name} is a {} turtle", turtle_type.as_str());
+    }
+
+    /// Identifies a new turtle in the background so slow handshakes don't hold up the manager.
+    /// The turtle is sent back to be added once it is done.
+    fn new_unknown_turtle(&self, unknown_turtle: UnknownTurtleConnection) {
+        let manager = self.own_handle.clone();
+        let record_dir = self.config.record_dir.clone();
+        tokio::spawn(async move {
+            if let Some(turtle) = unknown_turtle.auth(record_dir.as_ref()).await {
+                manager.authed(turtle).await;
+            }
+        });
+    }
+
+    /// Connects a turtle that finished the handshake and adds it to self.turtles.
+    async fn authed_turtle(&mut self, turtle: AuthedTurtle) {
+        let name = turtle.name;
+        let capabilities = turtle.capabilities.clone();
+        let connection = turtle.connect(self.own_handle.clone(), &self.config);
+        // Send the new turtle the client subscriptions so that the turtle connection can forward events to clients.
+        for tx in self.client_subscriptions.iter() {
+            debug!("Sending client subscription");
+            connection.client_subscribe(tx.clone()).await;
+        }
 
-            for turtle in self.turtles.iter_mut() {
-                if turtle.get_name() == name {
-                    if let Err(e) = turtle.get_connection_mut().connect(connection) {
-                        error!("Problem authing turtle {e}");
-                        let _ = turtle.get_connection_mut().disconnect().await;
+        self.add_connected_turtle(name).await;
+        self.set_last_seen(name);
+
+        let mut message_type = ConnectionMessageType::Connected;
+        if let Some(turtle) = self.get_turtle_mut_ref(name) {
+            if let Some(old) = turtle
+                .get_connection_mut()
+                .replace(connection.clone())
+                .await
+            {
+                warn!("{name} reconnected. Replacing its old connection");
+                let queued = old.drain().await;
+                old.close().await;
+
+                debug!("Moving {} queued commands to new connection", queued.len());
+                for command in queued {
+                    if let Err(e) = connection.send(command).await {
+                        error!("Problem moving queued command to {name}'s new connection {e}");
                     }
-                    return;
                 }
+                message_type = ConnectionMessageType::Reconnected;
             }
-
-            self.turtles.push(Turtle::new(
+            turtle.set_capabilities(capabilities);
+        } else {
+            let mut turtle = Turtle::new(
                 TurtleConnectionStatus::Connected { name, connection },
                 self.pool.clone(),
-            ));
-        };
+                self.own_handle.metrics().clone(),
+            );
+            turtle.set_capabilities(capabilities);
+            self.turtles.push(turtle);
+        }
+
+        debug!("Sending connected message to clients");
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage { name, message_type },
+        );
     }
 
     async fn client_subscribe_all(
@@ -155,6 +617,8 @@ impl TurtleManagerInner {
         for turtle in self.turtles.iter() {
             let message_type = match turtle.get_status() {
                 TurtleStatus::Connected => ConnectionMessageType::Connected,
+                TurtleStatus::Paused => ConnectionMessageType::Paused,
+                TurtleStatus::Unresponsive => ConnectionMessageType::Unresponsive,
                 TurtleStatus::Disconnected => ConnectionMessageType::Disconnected,
             };
 
@@ -167,10 +631,12 @@ impl TurtleManagerInner {
     }
 
     // Forces a turtle to disconnect.
-    async fn disconnect_turtle(&mut self, name: String) {
+    async fn disconnect_turtle(&mut self, name: String) -> Result<(), Error> {
         for turtle in self.turtles.iter_mut() {
             if turtle.get_name() == name {
-                if let Err(e) = turtle.get_connection_mut().disconnect().await {
+                self.reservations.release(name.as_str());
+                let result = turtle.get_connection_mut().disconnect().await;
+                if let Err(e) = &result {
                     error!("Problem disconnecting turtle {e}");
                 }
                 Self::send_subs_message(
@@ -180,11 +646,202 @@ impl TurtleManagerInner {
                         message_type: ConnectionMessageType::Disconnected,
                     },
                 );
-                return;
+                return result.map_err(Error::from);
             }
         }
 
         error!("Turtle named {name} attempted to disconnect without authing");
+        Err(Error::UnknownTurtle { name })
+    }
+
+    /// Disconnects a turtle when its connection shuts down unless it has already been replaced.
+    async fn connection_closed(&mut self, name: String, id: u64) {
+        let current = self
+            .get_turtle_mut_ref(name.as_str())
+            .and_then(|t| t.get_connection_mut().get_connection().map(|c| c.get_id()));
+
+        if current == Some(id) {
+            let _ = self.disconnect_turtle(name).await;
+        } else {
+            debug!("Ignoring close of old connection {id} for {name}");
+        }
+    }
+
+    /// Pauses a turtle's sender. If `emergency` is set the turtle also stops retrying its current
+    /// command.
+    async fn pause_turtle(&mut self, name: String, emergency: bool) -> Result<(), Error> {
+        if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
+            if let Err(e) = turtle.get_connection_mut().pause(emergency).await {
+                error!("Problem pausing turtle {e}");
+                return Err(e.into());
+            }
+            let name = turtle.get_name();
+            Self::send_subs_message(
+                &mut self.client_subscriptions,
+                TurtleConnectionMessage {
+                    name,
+                    message_type: ConnectionMessageType::Paused,
+                },
+            );
+            Ok(())
+        } else {
+            error!("Unknown turtle {name}");
+            Err(Error::UnknownTurtle { name })
+        }
+    }
+
+    async fn resume_turtle(&mut self, name: String) -> Result<(), Error> {
+        if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
+            if let Err(e) = turtle.get_connection_mut().resume().await {
+                error!("Problem resuming turtle {e}");
+                return Err(e.into());
+            }
+            let name = turtle.get_name();
+            Self::send_subs_message(
+                &mut self.client_subscriptions,
+                TurtleConnectionMessage {
+                    name,
+                    message_type: ConnectionMessageType::Resumed,
+                },
+            );
+            Ok(())
+        } else {
+            error!("Unknown turtle {name}");
+            Err(Error::UnknownTurtle { name })
+        }
+    }
+
+    /// Pauses every turtle `selector` picks out that is not disconnected. Turtles also stop retrying
+    /// their current command if `emergency` is set.
+    async fn pause_selected(&mut self, selector: Selector, emergency: bool) {
+        let selected = match self.select(&selector) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Problem selecting {selector} to pause: {e}");
+                return;
+            }
+        };
+        let names: Vec<&'static str> = self
+            .turtles
+            .iter()
+            .filter(|t| selected.contains(&t.get_name()))
+            .filter(|t| !matches!(t.get_status(), TurtleStatus::Disconnected))
+            .map(|t| t.get_name())
+            .collect();
+
+        for name in names {
+            let _ = self.pause_turtle(name.to_string(), emergency).await;
+        }
+    }
+
+    async fn resume_selected(&mut self, selector: Selector) {
+        let selected = match self.select(&selector) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Problem selecting {selector} to resume: {e}");
+                return;
+            }
+        };
+        let names: Vec<&'static str> = self
+            .turtles
+            .iter()
+            .filter(|t| selected.contains(&t.get_name()))
+            .filter(|t| matches!(t.get_status(), TurtleStatus::Paused))
+            .map(|t| t.get_name())
+            .collect();
+
+        for name in names {
+            let _ = self.resume_turtle(name.to_string()).await;
+        }
+    }
+
+    /// Gets the names of the turtles `selector` picks out.
+    /// Groups and tags can hold turtles this manager does not know which are left out.
+    fn select(&self, selector: &Selector) -> Result<Vec<&'static str>, Error> {
+        let names = match selector {
+            Selector::All => return Ok(self.turtles.iter().map(|t| t.get_name()).collect()),
+            Selector::Name(name) => {
+                return match self.get_turtle_by_name(name.as_str()) {
+                    Some(t) => Ok(vec![t.get_name()]),
+                    None => Err(Error::UnknownTurtle { name: name.clone() }),
+                }
+            }
+            Selector::Group(group) => self.labels.groups.get(group),
+            Selector::Tag(tag) => self.labels.tags.get(tag),
+        };
+        let names = names.map(Vec::as_slice).unwrap_or_default();
+
+        Ok(self
+            .turtles
+            .iter()
+            .map(|t| t.get_name())
+            .filter(|n| names.iter().any(|name| name == n))
+            .collect())
+    }
+
+    /// Loads every group and tag so turtles can be selected by them.
+    async fn load_labels(&mut self) {
+        let groups = group_operations::get_groups(&self.pool, self.own_handle.metrics()).await;
+        let tags = group_operations::get_tags(&self.pool, self.own_handle.metrics()).await;
+        match (groups, tags) {
+            (Ok(groups), Ok(tags)) => self.labels = Labels { groups, tags },
+            (Err(e), _) | (_, Err(e)) => error!("Problem loading groups and tags {e}"),
+        }
+    }
+
+    /// Adds a turtle to or removes it from the group or tag in `label`.
+    /// The change is written to the database first and only seen by selections once it is saved.
+    fn label(&self, name: String, label: Selector, add: bool, tx: ResultSender) {
+        if self.get_turtle_by_name(name.as_str()).is_none() {
+            let _ = tx.send(Err(Error::UnknownTurtle { name }));
+            return;
+        }
+        if !matches!(label, Selector::Group(_) | Selector::Tag(_)) {
+            let _ = tx.send(Err(Error::Protocol {
+                message: format!("{label} is not a group or tag"),
+            }));
+            return;
+        }
+
+        let _ = self.label_writes.send((name, label, add, tx));
+    }
+
+    /// Changes a turtle's groups and tags in `labels` once the change is saved to the database.
+    fn labelled(&mut self, name: String, label: Selector, add: bool) {
+        let (labels, key) = match &label {
+            Selector::Group(group) => (&mut self.labels.groups, group),
+            Selector::Tag(tag) => (&mut self.labels.tags, tag),
+            _ => return,
+        };
+
+        let names = labels.entry(key.clone()).or_default();
+        match (names.binary_search(&name), add) {
+            (Err(i), true) => names.insert(i, name),
+            (Ok(i), false) => {
+                names.remove(i);
+            }
+            _ => {}
+        }
+        if names.is_empty() {
+            labels.remove(key);
+        }
+    }
+
+    /// Updates the metrics for how many turtles are in each connection status.
+    fn update_turtle_counts(&self) {
+        let (mut connected, mut paused, mut unresponsive, mut disconnected) = (0, 0, 0, 0);
+        for turtle in self.turtles.iter() {
+            match turtle.get_status() {
+                TurtleStatus::Connected => connected += 1,
+                TurtleStatus::Paused => paused += 1,
+                TurtleStatus::Unresponsive => unresponsive += 1,
+                TurtleStatus::Disconnected => disconnected += 1,
+            }
+        }
+
+        self.own_handle
+            .metrics()
+            .set_turtle_counts(connected, paused, unresponsive, disconnected);
     }
 
     fn send_subs_message(
@@ -202,10 +859,116 @@ impl TurtleManagerInner {
         });
     }
 
-    /// Send a message to all connected turtles.
-    async fn broadcast(&mut self, command: TurtleCommand) {
+    /// Deploys the configured script to a turtle on its own task as it checks and clears whether
+    /// the turtle was rolled back in the database.
+    fn deploy(&self, name: String, force: bool, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+
+        let script = self.config.script.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(turtle.deploy(&script, force).await);
+        });
+    }
+
+    /// Deploys the configured script to every connected turtle running a different version.
+    /// Turtles that were rolled back are left alone. Every turtle is deployed to at once on a task
+    /// of its own and the names of the ones that were are sent once they all finish.
+    fn deploy_all(&self, tx: oneshot::Sender<Vec<String>>) {
+        let script = self.config.script.clone();
+        let turtles: Vec<Turtle> = self
+            .turtles
+            .iter()
+            .filter(|t| {
+                !matches!(t.get_status(), TurtleStatus::Disconnected)
+                    && script.is_newer_than(t.script_version())
+            })
+            .cloned()
+            .collect();
+
+        let deploys: Vec<_> = turtles
+            .into_iter()
+            .map(|turtle| {
+                let script = script.clone();
+                tokio::spawn(async move {
+                    match turtle.deploy(&script, false).await {
+                        Ok(()) => Some(turtle.get_name().to_string()),
+                        Err(Error::RolledBack { .. }) => {
+                            debug!(
+                                "Not deploying to {} as it was rolled back",
+                                turtle.get_name()
+                            );
+                            None
+                        }
+                        Err(e) => {
+                            error!("Problem deploying to {}: {e}", turtle.get_name());
+                            None
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        tokio::spawn(async move {
+            let deployed = futures_util::future::join_all(deploys)
+                .await
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect();
+            let _ = tx.send(deployed);
+        });
+    }
+
+    /// Rolls a turtle's script back on its own task as it marks the turtle as rolled back in the
+    /// database.
+    fn rollback(&self, name: String, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+
+        tokio::spawn(async move {
+            let _ = tx.send(turtle.rollback().await);
+        });
+    }
+
+    /// Sends `command` to every connected turtle `selector` picks out.
+    async fn broadcast(&mut self, selector: Selector, command: TurtleCommand) {
+        let selected = match self.select(&selector) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Problem selecting {selector} to broadcast to: {e}");
+                return;
+            }
+        };
+
         for turtle in self.turtles.iter() {
-            let _ = turtle.send(command.clone()).await;
+            if !selected.contains(&turtle.get_name()) {
+                continue;
+            }
+
+            // Moves wait for a reservation on their own task as reserving goes through here.
+            if let Some(direction) = command.direction() {
+                let turtle = turtle.clone();
+                let manager = self.own_handle.clone();
+                let config = self.config.reservations.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = turtle.move_reserved(direction, &manager, &config).await {
+                        warn!("Could not move {} {e}", turtle.get_name());
+                    }
+                });
+            } else {
+                let _ = turtle.send(command.clone()).await;
+            }
         }
     }
 
@@ -214,6 +977,44 @@ impl TurtleManagerInner {
         let _ = tx.send(turtle);
     }
 
+    /// Sends the status of every turtle, or only the turtles in `dimension`.
+    /// Gathered on its own task as each turtle's status is read from the database.
+    fn status(&self, dimension: Option<Dimension>, tx: oneshot::Sender<String>) {
+        let turtles: Vec<Turtle> = self
+            .turtles
+            .iter()
+            .filter(|t| {
+                dimension.as_ref().is_none_or(|d| {
+                    self.known
+                        .get(t.get_name())
+                        .is_some_and(|known| known.dimension == *d)
+                })
+            })
+            .cloned()
+            .collect();
+        let script = self.config.script.clone();
+
+        tokio::spawn(async move {
+            let statuses = turtles.iter().map(|t| t.status_string(&script));
+            let statuses = futures_util::future::join_all(statuses).await.join("\n");
+
+            if tx.send(statuses.trim().to_string()).is_err() {
+                error!("Problem sending status");
+            };
+        });
+    }
+
+    /// Sends the last known state of every turtle that is in the database.
+    fn get_turtles(&self, tx: oneshot::Sender<Vec<scheme::Turtle>>) {
+        let turtles = self
+            .turtles
+            .iter()
+            .filter_map(|t| Some(self.known.get(t.get_name())?.to_turtle(t.get_name())))
+            .collect();
+
+        let _ = tx.send(turtles);
+    }
+
     fn get_turtle_by_name(&self, name: &str) -> Option<Turtle> {
         self.turtles
             .iter()
@@ -227,6 +1028,9 @@ impl TurtleManagerInner {
     }
 
     async fn update_turtle_position(&mut self, name: String, position: Coordinates) {
+        if let Some(known) = self.known.get_mut(name.as_str()) {
+            known.position = position;
+        }
         if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
             if let Err(e) = turtle.get_db().set_coordinates(position).await {
                 error!("Problem updating turtle position in db {e}");
@@ -234,7 +1038,32 @@ impl TurtleManagerInner {
         }
     }
 
+    async fn update_turtle_dimension(&mut self, name: String, dimension: Dimension) {
+        if let Some(known) = self.known.get_mut(name.as_str()) {
+            known.dimension = dimension.clone();
+        }
+        if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
+            if let Err(e) = turtle.get_db().set_dimension(&dimension).await {
+                error!("Problem updating turtle dimension in db {e}");
+            }
+        }
+    }
+
+    async fn update_turtle_upgrades(&mut self, name: String, upgrades: Upgrades) {
+        if let Some(known) = self.known.get_mut(name.as_str()) {
+            known.upgrades = upgrades.clone();
+        }
+        if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
+            if let Err(e) = turtle.get_db().set_upgrades(&upgrades).await {
+                error!("Problem updating turtle upgrades in db {e}");
+            }
+        }
+    }
+
     async fn update_turtle_heading(&mut self, name: String, heading: Heading) {
+        if let Some(known) = self.known.get_mut(name.as_str()) {
+            known.heading = heading;
+        }
         if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
             if let Err(e) = turtle.get_db().set_heading(heading).await {
                 error!("Problem updating turtle heading in db {e}");
@@ -242,17 +1071,681 @@ impl TurtleManagerInner {
         }
     }
 
+    /// Stores a turtle's fuel level. An idle turtle with a home is sent back to it when its fuel
+    /// drops below the configured low fuel level.
     async fn update_turtle_fuel(&mut self, name: String, fuel: Fuel) {
-        if let Some(turtle) = self.get_turtle_mut_ref(name.as_str()) {
-            if let Err(e) = turtle.get_db().set_fuel(fuel.level).await {
-                error!("Problem updating turtle fuel in db {e}");
+        self.own_handle
+            .metrics()
+            .set_fuel(name.as_str(), fuel.level);
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let before = self
+            .known
+            .get_mut(name.as_str())
+            .map(|known| std::mem::replace(&mut known.fuel, fuel.level));
+        if let Err(e) = turtle.get_db().set_fuel(fuel.level).await {
+            error!("Problem updating turtle fuel in db {e}");
+            return;
+        }
+
+        let low = self.config.home.low_fuel;
+        if before.is_none_or(|b| b < low) || fuel.level >= low || !self.is_idle(&turtle) {
+            return;
+        }
+        info!("{name} is low on fuel. Sending it home");
+        tokio::spawn(self.send_home(turtle, None));
+    }
+
+    fn update_last_seen(&self, name: String) {
+        if let Some((name, _)) = self.known.get_key_value(name.as_str()) {
+            self.set_last_seen(name);
+        }
+    }
+
+    /// Records that a turtle was heard from just now.
+    /// Written on its own task as every heartbeat of every turtle ends up here.
+    fn set_last_seen(&self, name: &'static str) {
+        let db = TurtleDB::new(name, self.pool.clone(), self.own_handle.metrics().clone());
+        tokio::spawn(async move {
+            if let Err(e) = db.set_last_seen_now().await {
+                error!("Problem updating turtle last seen in db {e}");
+            }
+        });
+    }
+
+    /// Marks a turtle as unresponsive or responding again and tells clients.
+    /// A turtle that responds again is reported to clients as connected.
+    fn set_responsive(&mut self, name: String, responsive: bool) {
+        let turtle = match self.get_turtle_mut_ref(name.as_str()) {
+            Some(t) => t,
+            None => {
+                error!("Unknown turtle {name}");
+                return;
+            }
+        };
+
+        if let Err(e) = turtle.get_connection_mut().set_responsive(responsive) {
+            error!("Problem updating turtle responsiveness {e}");
+            return;
+        }
+
+        let message_type = if responsive {
+            ConnectionMessageType::Connected
+        } else {
+            ConnectionMessageType::Unresponsive
+        };
+        let name = turtle.get_name();
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage { name, message_type },
+        );
+    }
+
+    /// Starts a task that has the turtle attack until its entry in `guards` is removed.
+    /// The task removes the entry itself if it ends first so the turtle can be claimed again.
+    fn guard(&mut self, name: String) -> Result<(), Error> {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => return Err(Error::UnknownTurtle { name }),
+        };
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let interval = self.config.guard_interval;
+        let handle = self.own_handle.clone();
+        tokio::spawn(async move {
+            info!("{} guarding", turtle.get_name());
+            if let Err(e) = turtle.guard(interval, stop_rx, handle.clone()).await {
+                warn!("{} stopped guarding: {e}", turtle.get_name());
+            }
+            handle.guard_stopped(turtle.get_name()).await;
+        });
+        self.guards.insert(name, stop_tx);
+
+        Ok(())
+    }
+
+    fn send_drops(&mut self, name: String, items: Vec<ItemCount>) {
+        let name = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t.get_name(),
+            None => {
+                error!("Drops from unknown turtle {name}");
+                return;
+            }
+        };
+
+        info!("{name} picked up {:?}", items);
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage {
+                name,
+                message_type: ConnectionMessageType::Drops(items),
+            },
+        );
+    }
+
+    /// Evaluates on its own task as the chunk can run for a while.
+    /// Every chunk is logged so there is a history of what was run on which turtle.
+    fn eval(&self, name: String, code: String, tx: oneshot::Sender<Result<String, Error>>) {
+        if !self.config.eval.enabled {
+            let _ = tx.send(Err(Error::EvalDisabled));
+            return;
+        }
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+
+        info!("Evaluating on {name}: {code}");
+        let timeout = self.config.eval.timeout;
+        tokio::spawn(async move {
+            let result = turtle.eval(code, timeout).await;
+            match &result {
+                Ok(r) => info!("{name} evaluated to {r}"),
+                Err(e) => warn!("{name} failed to evaluate: {e}"),
+            }
+            let _ = tx.send(result);
+        });
+    }
+
+    /// Crafts on its own task as the turtle has to list its inventory first.
+    fn craft(&self, name: String, item: String, count: u32, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+        let recipe = match self.config.recipes.get(item.as_str()) {
+            Some(r) => r.clone(),
+            None => {
+                let _ = tx.send(Err(Error::UnknownRecipe { item }));
+                return;
+            }
+        };
+
+        let manager = self.own_handle.clone();
+        tokio::spawn(async move {
+            let mut queued = Some(tx);
+            let result = turtle.craft(&item, &recipe, count, &mut queued).await;
+            match queued {
+                // Nothing was crafted so the error goes back to whoever asked.
+                Some(tx) => {
+                    let _ = tx.send(result);
+                }
+                None => manager.crafted(name, item, result.err()).await,
+            }
+        });
+    }
+
+    /// Tells clients a turtle finished crafting `item`.
+    fn crafted(&mut self, name: String, item: String, error: Option<Error>) {
+        let name = match self.known.get_key_value(name.as_str()) {
+            Some((name, _)) => *name,
+            None => return,
+        };
+        match &error {
+            Some(e) => warn!("{name} did not craft {item}: {e}"),
+            None => info!("{name} crafted {item}"),
+        }
+
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage {
+                name,
+                message_type: ConnectionMessageType::Crafted { item, error },
+            },
+        );
+    }
+
+    /// Moves a formation on its own task as it waits on every turtle to move and gets them
+    /// through this manager. Answers once the turtles set off and tells clients when they stop.
+    fn move_formation(
+        &self,
+        names: Vec<String>,
+        formation: Formation,
+        destination: Coordinates,
+        heading: Heading,
+        tx: ResultSender,
+    ) {
+        let manager = self.own_handle.clone();
+        let config = self.config.formation.clone();
+        tokio::spawn(async move {
+            let mut started = Some(tx);
+            let result = formation::move_formation(
+                &manager,
+                &names,
+                formation,
+                destination,
+                heading,
+                &config,
+                &mut started,
+            )
+            .await;
+            match started {
+                // Nothing moved so the error goes back to whoever asked.
+                Some(tx) => {
+                    let _ = tx.send(result);
+                }
+                None => manager.formation_moved(names, result.err()).await,
             }
+        });
+    }
+
+    /// Tells clients a formation stopped moving. Not about a single turtle so it is sent with an
+    /// empty name.
+    fn formation_moved(&mut self, names: Vec<String>, error: Option<Error>) {
+        match &error {
+            Some(e) => warn!(
+                "Formation of {} turtles did not get in place: {e}",
+                names.len()
+            ),
+            None => info!("Formation of {} turtles is in place", names.len()),
         }
+
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage {
+                name: "",
+                message_type: ConnectionMessageType::FormationMoved { names, error },
+            },
+        );
     }
 
-    async fn send_turtle_position(&self, name: String) {
+    /// Moves a turtle on its own task as it may have to wait for the block it is moving into.
+    fn move_turtle(&self, name: String, direction: Direction, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+
+        let manager = self.own_handle.clone();
+        let config = self.config.reservations.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(turtle.move_reserved(direction, &manager, &config).await);
+        });
+    }
+
+    /// Reserves `position` for a turtle unless it is reserved for something else or another
+    /// turtle is there according to the database.
+    fn reserve(&mut self, name: String, position: Coordinates) -> Result<(), Error> {
+        if self.get_turtle_by_name(name.as_str()).is_none() {
+            return Err(Error::UnknownTurtle { name });
+        }
+        let dimension = self
+            .known
+            .get(name.as_str())
+            .map(|k| k.dimension.clone())
+            .unwrap_or_default();
+
+        let taken_by = self.known.iter().find(|(other, known)| {
+            **other != name && known.position == position && known.dimension == dimension
+        });
+        if let Some((other, _)) = taken_by {
+            return Err(Error::Reserved {
+                position,
+                by: Some(other.to_string()),
+            });
+        }
+
+        self.reservations
+            .reserve(
+                name.as_str(),
+                dimension,
+                position,
+                self.config.reservations.lease,
+            )
+            .map_err(|by| Error::Reserved { position, by })
+    }
+
+    /// Marks the block in front of a turtle as taken after it inspected a turtle there.
+    fn turtle_seen(&mut self, name: String) {
+        if let Some(known) = self.known.get(name.as_str()) {
+            let position = known.position.step(known.heading);
+            debug!("{name} found a turtle at {position}");
+            self.reservations.seen(
+                known.dimension.clone(),
+                position,
+                self.config.reservations.lease,
+            );
+        }
+    }
+
+    /// Starts a job on its own task as it runs until every chunk is done.
+    /// Starts a job for the turtles `target` picks out. Fails if `target` names an unknown turtle.
+    fn start_job(&mut self, job: Job, target: Selector) -> Result<u64, Error> {
+        self.select(&target)?;
+        let id = self.next_job;
+        self.next_job += 1;
+
+        let manager = self.own_handle.clone();
+        let config = self.config.dispatch.clone();
+        tokio::spawn(dispatcher::run(manager, id, job, target, config));
+
+        Ok(id)
+    }
+
+    /// Gets a connected turtle `target` picks out in `dimension` that is not working on a job,
+    /// guarding or going home for each chunk in `needs`, going through the chunks in order. A
+    /// turtle is only given a chunk if it has `min_fuel` left after the chunk's next task. Claimed
+    /// turtles are marked as working on `job` until they are released.
+    fn claim_idle(
+        &mut self,
+        job: u64,
+        target: &Selector,
+        dimension: Option<Dimension>,
+        tool: Option<Tool>,
+        min_fuel: u32,
+        needs: Vec<FuelNeeds>,
+    ) -> Vec<(usize, Turtle)> {
+        // A turtle named by the job may have been removed since it started.
+        let selected = self.select(target).unwrap_or_default();
+        let mut idle: Vec<(&Turtle, &KnownState)> = self
+            .turtles
+            .iter()
+            .filter(|t| selected.contains(&t.get_name()))
+            .filter(|t| self.is_idle(t))
+            .filter_map(|t| Some((t, self.known.get(t.get_name())?)))
+            .filter(|(_, known)| dimension.as_ref().is_none_or(|d| *d == known.dimension))
+            .filter(|(_, known)| tool.is_none_or(|t| known.upgrades.has(t)))
+            .collect();
+
+        let mut claimed = vec![];
+        for (i, needs) in needs.iter().enumerate() {
+            let enough = idle
+                .iter()
+                .position(|(_, known)| known.fuel >= min_fuel + needs.from(known.position));
+            if let Some(found) = enough {
+                let (turtle, _) = idle.remove(found);
+                claimed.push((i, turtle.clone()));
+            }
+        }
+
+        for (_, turtle) in claimed.iter() {
+            self.working.insert(turtle.get_name(), job);
+        }
+        claimed
+    }
+
+    /// Whether a turtle is connected and not working on a job, guarding or going home.
+    fn is_idle(&self, turtle: &Turtle) -> bool {
+        let name = turtle.get_name();
+        matches!(turtle.get_status(), TurtleStatus::Connected)
+            && !self.working.contains_key(name)
+            && !self.guards.contains_key(name)
+            && !self.returning.contains(name)
+    }
+
+    fn report_job(&mut self, progress: JobProgress) {
+        Self::send_subs_message(
+            &mut self.client_subscriptions,
+            TurtleConnectionMessage {
+                name: "",
+                message_type: ConnectionMessageType::JobProgress(progress.clone()),
+            },
+        );
+        self.jobs.insert(progress.id, progress);
+    }
+
+    /// Sets or clears a turtle's home on its own task so the database isn't waited on here.
+    fn set_home(&self, name: String, waypoint: Option<String>, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+
+        let pool = self.pool.clone();
+        let metrics = self.own_handle.metrics().clone();
+        tokio::spawn(async move {
+            let _ = tx.send(set_home(&turtle, waypoint, &pool, &metrics).await);
+        });
+    }
+
+    /// Sends a turtle home on its own task as it has to wait for every move. Answers once the
+    /// turtle sets off.
+    fn return_home(&mut self, name: String, tx: ResultSender) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => {
+                let _ = tx.send(Err(Error::UnknownTurtle { name }));
+                return;
+            }
+        };
+        if self.returning.contains(turtle.get_name()) {
+            info!("{name} is already on its way home");
+            let _ = tx.send(Ok(()));
+            return;
+        }
+
+        tokio::spawn(self.send_home(turtle, Some(tx)));
+    }
+
+    /// Marks a turtle as back from its trip home and tells clients how it went.
+    fn returned(&mut self, name: String, waypoint: Option<String>, error: Option<Error>) {
+        let name = match self.returning.take(name.as_str()) {
+            Some(n) => n,
+            None => return,
+        };
+        if let Some(waypoint) = waypoint {
+            Self::send_subs_message(
+                &mut self.client_subscriptions,
+                TurtleConnectionMessage {
+                    name,
+                    message_type: ConnectionMessageType::ReturnedHome { waypoint, error },
+                },
+            );
+        }
+    }
+
+    /// Sends an idle turtle with a full inventory home so it can be emptied.
+    /// Turtles working on a job are sent home by the dispatcher once they give their chunk back.
+    fn inventory_full(&mut self, name: String) {
+        let turtle = match self.get_turtle_by_name(name.as_str()) {
+            Some(t) => t,
+            None => return,
+        };
+        if !self.is_idle(&turtle) {
+            return;
+        }
+
+        info!("{name} has a full inventory. Sending it home");
+        tokio::spawn(self.send_home(turtle, None));
+    }
+
+    /// Sends every connected turtle with a home back to it before shutting down.
+    /// Answers once they all get there or the shutdown wait runs out.
+    fn return_all_home(&mut self, tx: oneshot::Sender<()>) {
+        let turtles: Vec<Turtle> = self
+            .turtles
+            .iter()
+            .filter(|t| matches!(t.get_status(), TurtleStatus::Connected))
+            .filter(|t| !self.returning.contains(t.get_name()))
+            .cloned()
+            .collect();
+        info!("Sending {} connected turtles home", turtles.len());
+        let trips: Vec<_> = turtles
+            .into_iter()
+            .map(|turtle| self.send_home(turtle, None))
+            .collect();
+
+        let wait = self.config.home.shutdown_wait;
+        tokio::spawn(async move {
+            let trips = futures_util::future::join_all(trips);
+            if tokio::time::timeout(wait, trips).await.is_err() {
+                warn!("Not every turtle got home in {}s", wait.as_secs());
+            }
+            let _ = tx.send(());
+        });
+    }
+
+    /// Marks a turtle as going home and gets the trip there. The turtle's home is looked up on
+    /// the trip. `started` is answered once the turtle sets off or with why it can't. The turtle
+    /// is marked as back once the trip ends whether or not it got there.
+    fn send_home(
+        &mut self,
+        turtle: Turtle,
+        started: Option<ResultSender>,
+    ) -> impl Future<Output = Result<(), Error>> {
+        self.returning.insert(turtle.get_name());
+        let manager = self.own_handle.clone();
+        let pool = self.pool.clone();
+        let config = self.config.home.clone();
+        async move {
+            let name = turtle.get_name();
+            let waypoint = match home_of(&turtle, &pool, manager.metrics()).await {
+                Ok(w) => w,
+                Err(e) => {
+                    debug!("{name} can't go home: {e}");
+                    // Marked as back first so the turtle can be sent again once it is answered.
+                    manager.returned(name, None, None).await;
+                    if let Some(tx) = started {
+                        let _ = tx.send(Err(e.clone()));
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some(tx) = started {
+                let _ = tx.send(Ok(()));
+            }
+
+            let result = home::go_to(&manager, &turtle, &waypoint, &config).await;
+            if let Err(e) = &result {
+                warn!("{name} did not get to {}: {e}", waypoint.name);
+            }
+            manager
+                .returned(name, Some(waypoint.name), result.clone().err())
+                .await;
+            result
+        }
+    }
+
+    /// Sends a turtle its position, checking it with GPS first if the turtle can.
+    /// Runs on its own task as finding the turtle's heading means waiting on it to move.
+    fn send_turtle_position(&self, name: String) {
         if let Some(turtle) = self.get_turtle_by_name(name.as_str()) {
-            turtle.send_position_update().await;
+            // Read before anything else is handled. A turtle that does not know its position
+            // keeps reporting 0, 0, 0 which would overwrite it.
+            let known = self.known.get(name.as_str());
+            let position = known.map(|k| k.position);
+            let heading = known.map(|k| k.heading);
+            let manager = self.own_handle.clone();
+            tokio::spawn(async move {
+                turtle
+                    .send_position_update(&manager, position, heading)
+                    .await
+            });
+        }
+    }
+}
+
+/// Removes a waypoint. Fails if there is no waypoint with the name.
+async fn remove_waypoint(name: String, pool: &SqlitePool, metrics: &Metrics) -> Result<(), Error> {
+    if waypoint_operations::remove_waypoint(name.as_str(), pool, metrics).await? {
+        Ok(())
+    } else {
+        Err(Error::UnknownWaypoint { name })
+    }
+}
+
+/// Sets or clears a turtle's home. The waypoint has to exist.
+async fn set_home(
+    turtle: &Turtle,
+    waypoint: Option<String>,
+    pool: &SqlitePool,
+    metrics: &Metrics,
+) -> Result<(), Error> {
+    if let Some(waypoint) = waypoint.as_deref() {
+        if waypoint_operations::get_waypoint(waypoint, pool, metrics)
+            .await?
+            .is_none()
+        {
+            return Err(Error::UnknownWaypoint {
+                name: waypoint.to_string(),
+            });
+        }
+    }
+
+    // Turtles that connected but have not reported yet are not in the database.
+    if turtle
+        .get_db()
+        .set_home(waypoint.as_deref())
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::UnknownTurtle {
+            name: turtle.get_name().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Gets the waypoint a turtle goes back to.
+async fn home_of(turtle: &Turtle, pool: &SqlitePool, metrics: &Metrics) -> Result<Waypoint, Error> {
+    let home = turtle.get_db().get_home().await.ok_or(Error::NoHome {
+        name: turtle.get_name().to_string(),
+    })?;
+
+    waypoint_operations::get_waypoint(home.as_str(), pool, metrics)
+        .await?
+        .ok_or(Error::UnknownWaypoint { name: home })
+}
+
+/// A turtle as it is in the database. Kept so the manager never has to read it back.
+#[derive(Debug, Clone)]
+struct KnownState {
+    position: Coordinates,
+    heading: Heading,
+    dimension: Dimension,
+    turtle_type: TurtleType,
+    fuel: u32,
+    upgrades: Upgrades,
+}
+
+impl KnownState {
+    fn new(position: Coordinates, heading: Heading, dimension: Dimension) -> Self {
+        KnownState {
+            position,
+            heading,
+            dimension,
+            turtle_type: TurtleType::Normal,
+            fuel: 0,
+            upgrades: Upgrades::default(),
+        }
+    }
+
+    fn to_turtle(&self, name: &str) -> scheme::Turtle {
+        scheme::Turtle {
+            name: name.to_string(),
+            coordinates: self.position,
+            dimension: self.dimension.clone(),
+            heading: self.heading,
+            turtle_type: self.turtle_type,
+            fuel: Fuel {
+                level: self.fuel,
+                max: self.turtle_type.get_max_fuel(),
+            },
+            upgrades: self.upgrades.clone(),
+        }
+    }
+}
+
+impl From<scheme::Turtle> for KnownState {
+    fn from(turtle: scheme::Turtle) -> Self {
+        KnownState {
+            position: turtle.coordinates,
+            heading: turtle.heading,
+            dimension: turtle.dimension,
+            turtle_type: turtle.turtle_type,
+            fuel: turtle.fuel.level,
+            upgrades: turtle.upgrades,
+        }
+    }
+}
+
+/// Writes changes to groups and tags one at a time so the database ends up in the same order as
+/// the manager's labels. Each change is sent back to `manager` once it is saved.
+async fn write_labels(
+    mut rx: mpsc::UnboundedReceiver<LabelWrite>,
+    pool: SqlitePool,
+    manager: TurtleManagerHandle,
+) {
+    while let Some((name, label, add, tx)) = rx.recv().await {
+        let result = match (&label, add) {
+            (Selector::Group(group), true) => {
+                group_operations::add_to_group(group, &name, &pool, manager.metrics()).await
+            }
+            (Selector::Group(group), false) => {
+                group_operations::remove_from_group(group, &name, &pool, manager.metrics()).await
+            }
+            (Selector::Tag(tag), true) => {
+                group_operations::add_tag(&name, tag, &pool, manager.metrics()).await
+            }
+            (Selector::Tag(tag), false) => {
+                group_operations::remove_tag(&name, tag, &pool, manager.metrics()).await
+            }
+            (_, _) => continue,
+        };
+
+        match result {
+            Ok(_) => manager.labelled(name, label, add, tx).await,
+            Err(e) => {
+                error!("Problem saving {name}'s groups and tags {e}");
+                let _ = tx.send(Err(e.into()));
+            }
         }
     }
 }
@@ -1,13 +1,28 @@
 use tokio::sync::{mpsc, oneshot};
 
+use crate::crafting::ItemCount;
+use crate::dispatcher::{FuelNeeds, Job, JobProgress};
+use crate::formation::Formation;
+use crate::selector::{Labels, Selector};
 use crate::turtle_manager::TurtleConnectionMessage;
 use crate::turtle_scheme::TurtleEvents;
 use crate::{
-    scheme::{Coordinates, Fuel, Heading},
-    turtle_scheme::TurtleCommand,
+    error::Error,
+    scheme::{
+        self, Coordinates, Dimension, Direction, Fuel, Heading, Tool, TurtleType, Upgrades,
+        Waypoint,
+    },
+    turtle_scheme::{Inventory, TurtleCommand},
+};
+
+use super::{
+    reservations::Reservation,
+    turtle::Turtle,
+    unknown_turtle_connection::{AuthedTurtle, UnknownTurtleConnection},
 };
 
-use super::{turtle::Turtle, unknown_turtle_connection::UnknownTurtleConnection};
+/// Sends whether the message was handled back to the handle.
+pub type ResultSender = oneshot::Sender<Result<(), Error>>;
 
 /// Types of messages that can be sent from a TurtleManagerHandle to a TurtleManagerInner.
 pub enum TurtleManagerMessage {
@@ -17,25 +32,310 @@ pub enum TurtleManagerMessage {
     /// Sends a new turtle connection to the inner.
     UnknownTurtle(UnknownTurtleConnection),
 
+    /// Sent once a new turtle connection finishes the handshake.
+    Authed(AuthedTurtle),
+
     /// Disconnects a turtle by name.
-    Disconnect(String),
+    Disconnect {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Sent by a turtle connection's sender or receiver when it shuts down.
+    /// Ignored if the turtle has since reconnected with a different connection.
+    ConnectionClosed {
+        name: String,
+        id: u64,
+    },
+
+    /// Sends a command to every turtle `selector` picks out.
+    Broadcast {
+        selector: Selector,
+        command: TurtleCommand,
+    },
+
+    /// Pauses a turtle's sender by name. Queued commands are kept.
+    Pause {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Resumes a paused turtle by name.
+    Resume {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Pauses a turtle by name and stops it from retrying its current command.
+    EmergencyStop {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Pauses every connected turtle `selector` picks out.
+    PauseSelected(Selector),
+
+    /// Resumes every paused turtle `selector` picks out.
+    ResumeSelected(Selector),
+
+    /// Emergency stops every connected turtle `selector` picks out.
+    EmergencyStopSelected(Selector),
+
+    /// Gets the names of the turtles `selector` picks out.
+    Select {
+        selector: Selector,
+        tx: oneshot::Sender<Result<Vec<String>, Error>>,
+    },
+
+    /// Adds a turtle to a group or tags it if `add` is set. Otherwise takes it out of the group or
+    /// removes the tag. `label` has to be a group or tag selector.
+    Label {
+        name: String,
+        label: Selector,
+        add: bool,
+        tx: ResultSender,
+    },
 
-    /// Broadcasts a message.
-    Broadcast(TurtleCommand),
+    /// Sent once a change to a turtle's groups or tags is saved to the database. `tx` is answered
+    /// once selections see it.
+    Labelled {
+        name: String,
+        label: Selector,
+        add: bool,
+        tx: ResultSender,
+    },
+
+    /// Gets every group and tag.
+    GetLabels(oneshot::Sender<Result<Labels, Error>>),
 
     /// Gets the status of the connections as a formatted string.
-    Status(oneshot::Sender<String>),
+    /// Only turtles in `dimension` are included if it is set.
+    Status {
+        dimension: Option<Dimension>,
+        tx: oneshot::Sender<String>,
+    },
 
     GetTurtle {
         name: String,
         tx: oneshot::Sender<Option<Turtle>>,
     },
 
+    /// Gets a turtle's last reported position and heading.
+    GetPosition {
+        name: String,
+        tx: oneshot::Sender<Option<(Coordinates, Heading)>>,
+    },
+
+    /// Gets the last known state of every turtle in the database.
+    GetTurtles(oneshot::Sender<Vec<scheme::Turtle>>),
+
+    /// Has a turtle craft at least `count` of `item` with a recipe from the config.
+    Craft {
+        name: String,
+        item: String,
+        count: u32,
+        tx: ResultSender,
+    },
+
+    /// Tells clients a turtle finished crafting `item`. `error` is None if it made everything.
+    Crafted {
+        name: String,
+        item: String,
+        error: Option<Error>,
+    },
+
+    /// Has a turtle attack whatever is in front of it until it is told to stop.
+    /// Replaces the turtle's guard task if it is already guarding.
+    Guard {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Stops a turtle's guard task. Does nothing if it is not guarding.
+    StopGuard {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Sent by a guard task when it ends because it was stopped, the turtle disconnected or an
+    /// attack failed. Ignored if the turtle has since started guarding again.
+    GuardStopped {
+        name: String,
+    },
+
+    /// Sent by a guard task when its turtle picked up items after attacking.
+    Drops {
+        name: String,
+        items: Vec<ItemCount>,
+    },
+
+    /// Sends a turtle the configured script even if it is already running it.
+    /// Fails for a turtle that was rolled back unless `force` is set.
+    Deploy {
+        name: String,
+        force: bool,
+        tx: ResultSender,
+    },
+
+    /// Sends the configured script to every connected turtle running a different version.
+    /// Sends back the names of the turtles it was sent to.
+    DeployAll(oneshot::Sender<Vec<String>>),
+
+    /// Has a turtle go back to the script it had before its last deploy.
+    Rollback {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Runs Lua on a turtle if eval is turned on in the config.
+    Eval {
+        name: String,
+        code: String,
+        tx: oneshot::Sender<Result<String, Error>>,
+    },
+
+    /// Moves turtles together into a formation at `destination` facing `heading`.
+    MoveFormation {
+        names: Vec<String>,
+        formation: Formation,
+        destination: Coordinates,
+        heading: Heading,
+        tx: ResultSender,
+    },
+
+    /// Tells clients a formation stopped moving. `error` is None if every turtle got in place.
+    FormationMoved {
+        names: Vec<String>,
+        error: Option<Error>,
+    },
+
+    /// Moves a turtle once the block it is moving into is reserved for it.
+    MoveTurtle {
+        name: String,
+        direction: Direction,
+        tx: ResultSender,
+    },
+
+    /// Reserves a block for a turtle that is about to move into it.
+    Reserve {
+        name: String,
+        position: Coordinates,
+        tx: ResultSender,
+    },
+
+    /// Sent by a turtle's receiver when the turtle inspected another turtle in front of it.
+    TurtleSeen {
+        name: String,
+    },
+
+    /// Gets every block that is reserved.
+    GetReservations(oneshot::Sender<Vec<Reservation>>),
+
+    /// Starts splitting a job between the idle turtles `target` picks out. Sends back the job's
+    /// id.
+    StartJob {
+        job: Job,
+        target: Selector,
+        tx: oneshot::Sender<Result<u64, Error>>,
+    },
+
+    /// Marks an idle turtle with enough fuel as working on a job for each chunk in `needs` and
+    /// sends them back with the index of their chunk.
+    ClaimIdle {
+        job: u64,
+        target: Selector,
+        dimension: Option<Dimension>,
+        tool: Option<Tool>,
+        min_fuel: u32,
+        needs: Vec<FuelNeeds>,
+        tx: oneshot::Sender<Vec<(usize, Turtle)>>,
+    },
+
+    /// Marks a turtle as idle again after it stopped working on a job.
+    Release {
+        name: String,
+    },
+
+    /// Stores how far along a job is and tells clients.
+    ReportJob(JobProgress),
+
+    /// Gets the progress of every job that has been started.
+    GetJobs(oneshot::Sender<Vec<JobProgress>>),
+
+    /// Stores a waypoint, replacing any with the same name.
+    SetWaypoint {
+        waypoint: Waypoint,
+        tx: ResultSender,
+    },
+
+    /// Removes a waypoint. Turtles that had it as home are left without one.
+    RemoveWaypoint {
+        name: String,
+        tx: ResultSender,
+    },
+
+    GetWaypoints(oneshot::Sender<Result<Vec<Waypoint>, Error>>),
+
+    /// Sets or clears the waypoint a turtle goes back to.
+    SetHome {
+        name: String,
+        waypoint: Option<String>,
+        tx: ResultSender,
+    },
+
+    /// Sends a turtle to its home. Answers once it sets off.
+    ReturnHome {
+        name: String,
+        tx: ResultSender,
+    },
+
+    /// Marks a turtle as no longer on its way home. Clients are told how the trip to `waypoint`
+    /// went if the turtle had a home to go to.
+    Returned {
+        name: String,
+        waypoint: Option<String>,
+        error: Option<Error>,
+    },
+
+    /// Sends an idle turtle that listed its own inventory and found it full to its home.
+    InventoryFull(String),
+
+    /// Sends every connected turtle with a home back to it. Answers once they all got there or
+    /// the shutdown wait ran out.
+    ReturnAllHome(oneshot::Sender<()>),
+
+    /// Gets the last stored contents of the inventory at a position.
+    GetInventory {
+        position: Coordinates,
+        dimension: Dimension,
+        tx: oneshot::Sender<Option<Inventory>>,
+    },
+
+    /// Adds a new turtle to the database. It stays disconnected until it connects.
+    AddTurtle {
+        name: String,
+        position: Coordinates,
+        dimension: Dimension,
+        heading: Heading,
+        turtle_type: TurtleType,
+        tx: ResultSender,
+    },
+
     UpdatePosition {
         name: String,
         position: Coordinates,
     },
 
+    UpdateDimension {
+        name: String,
+        dimension: Dimension,
+    },
+
+    UpdateUpgrades {
+        name: String,
+        upgrades: Upgrades,
+    },
+
     UpdateHeading {
         name: String,
         heading: Heading,
@@ -48,5 +348,22 @@ pub enum TurtleManagerMessage {
 
     SendTurtlePosition(String),
 
+    /// Checks a turtle's first report with GPS and sets the type of a turtle registered during the
+    /// handshake.
+    Register {
+        name: String,
+        fuel: Fuel,
+        position: Option<(Coordinates, Heading)>,
+    },
+
+    /// Records that a turtle was heard from in the database.
+    UpdateLastSeen(String),
+
+    /// Marks a turtle as unresponsive or as responding again after missing heartbeats.
+    SetResponsive {
+        name: String,
+        responsive: bool,
+    },
+
     ClientSubscription(mpsc::UnboundedSender<TurtleConnectionMessage<'static>>),
 }
@@ -11,8 +11,8 @@ use tokio_tungstenite::WebSocketStream;
 use tracing::error;
 
 use super::{
-    turtle_receiver_inner::TurtleReceiverInner, turtle_receiver_message::TurtleReceiverMessage,
-    turtle_sender_handle::ReceiversSenderHandle, TurtleManagerHandle,
+    turtle_connection::ConnectionContext, turtle_receiver_inner::TurtleReceiverInner,
+    turtle_receiver_message::TurtleReceiverMessage, turtle_sender_handle::ReceiversSenderHandle,
 };
 
 /// Communicates with a TurtleReceiverInner which listens for messages from turtles and forwards
@@ -30,18 +30,19 @@ impl TurtleReceiverHandle {
     /// # Arguments
     ///
     /// * `ws_receiver` - Passed on to TurtleReceiverInner to listen for websocket messages.
-    /// * `manager` - Passed on to TurtleReceiverInner to notify when the receiver has closed.
     /// * `sender` - Handle if of the sender connected to our turtle. Used to pass on ok and ready.
-    /// * `name` - Name of the turtle for logging purposes.
+    /// * `first_message` - Frame read during the handshake that is handled first.
+    /// * `context` - The connection this receiver belongs to and how often to check the turtle is
+    ///   alive and when to give up on it.
     pub fn new(
         ws_receiver: SplitStream<WebSocketStream<TcpStream>>,
-        manager: TurtleManagerHandle,
         sender: ReceiversSenderHandle,
-        name: &'static str,
+        first_message: Option<String>,
+        context: ConnectionContext,
     ) -> Self {
         let (tx, rx) = mpsc::channel(1);
 
-        let inner = TurtleReceiverInner::new(rx, ws_receiver, manager, sender, name);
+        let inner = TurtleReceiverInner::new(rx, ws_receiver, sender, first_message, context);
         tokio::spawn(inner.run());
 
         TurtleReceiverHandle { tx }
@@ -1,13 +1,16 @@
 use crate::turtle_manager::turtle_connection_message::TurtleConnectionMessage;
 use crate::turtle_manager::ConnectionMessageType;
 use futures_util::{stream::SplitStream, StreamExt};
+use tokio::time::{self, Duration, Instant};
 use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::WebSocketStream;
-use tracing::{debug, warn};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tracing::{debug, info, warn};
 
-use crate::turtle_scheme::TurtleEvents;
+use crate::scheme::Tool;
+use crate::turtle_scheme::{ResponseType, TurtleEvents};
 
 use super::{
+    session_recorder::SessionRecorder, turtle_connection::ConnectionContext,
     turtle_receiver_message::TurtleReceiverMessage, turtle_sender_handle::ReceiversSenderHandle,
     TurtleManagerHandle,
 };
@@ -22,16 +25,53 @@ pub struct TurtleReceiverInner {
     clients: Vec<mpsc::UnboundedSender<TurtleConnectionMessage<'static>>>,
 
     name: &'static str,
+
+    /// Id of the connection we belong to so a replaced connection can't disconnect its replacement.
+    connection_id: u64,
+
+    /// Records every message received if the session is being recorded.
+    recorder: Option<SessionRecorder>,
+
+    /// When we last got any frame from the turtle, including pongs.
+    last_seen: Instant,
+
+    heartbeat_interval: Duration,
+
+    /// Heartbeats missed in a row.
+    missed_heartbeats: u32,
+    unresponsive_after: u32,
+    disconnect_after: u32,
+
+    /// Set once the manager has been told the turtle is unresponsive.
+    unresponsive: bool,
+
+    /// Set once the first report has been sent to the manager to register the turtle.
+    registered: bool,
+
+    /// Set once the turtle asked for its position. Sending it checks the position with GPS.
+    asked_position: bool,
+
+    /// Frame the handshake read while waiting for a hello that the turtle did not send.
+    /// Handled before anything else.
+    first_message: Option<String>,
 }
 
 impl TurtleReceiverInner {
     pub fn new(
         rx: mpsc::Receiver<TurtleReceiverMessage>,
         ws_receiver: SplitStream<WebSocketStream<TcpStream>>,
-        manager: TurtleManagerHandle,
         sender: ReceiversSenderHandle,
-        name: &'static str,
+        first_message: Option<String>,
+        context: ConnectionContext,
     ) -> Self {
+        let ConnectionContext {
+            manager,
+            name,
+            id,
+            recorder,
+            config,
+        } = context;
+
         TurtleReceiverInner {
             rx,
             ws_receiver,
@@ -39,22 +79,64 @@ impl TurtleReceiverInner {
             sender,
             clients: vec![],
             name,
+            connection_id: id,
+            recorder,
+            last_seen: Instant::now(),
+            heartbeat_interval: config.heartbeat_interval,
+            missed_heartbeats: 0,
+            unresponsive_after: config.unresponsive_after,
+            disconnect_after: config.disconnect_after,
+            unresponsive: false,
+            registered: false,
+            asked_position: false,
+            first_message,
         }
     }
 
     pub async fn run(mut self) {
         let mut close_tx = None;
+        let mut heartbeat = time::interval_at(
+            Instant::now() + self.heartbeat_interval,
+            self.heartbeat_interval,
+        );
+        heartbeat.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        if let Some(message) = self.first_message.take() {
+            self.handle_turtle_message(message).await;
+        }
 
         loop {
             tokio::select! {
                 message = self.ws_receiver.next() => {
                     if let Some(Ok(message)) = message {
-                        self.handle_turtle_message(message.to_string()).await;
+                        self.last_seen = Instant::now();
+                        match message {
+                            Message::Text(text) => {
+                                if let Some(recorder) = &self.recorder {
+                                    recorder.record_in(text.as_str());
+                                }
+                                self.handle_turtle_message(text).await;
+                            }
+                            Message::Binary(_) => {
+                                self.handle_turtle_message(message.to_string()).await;
+                            }
+                            _ => {}
+                        }
                     } else {
                         break;
                     }
                 }
 
+                _ = heartbeat.tick() => {
+                    if !self.heartbeat().await {
+                        warn!(
+                            "{} missed {} heartbeats. Disconnecting",
+                            self.name, self.missed_heartbeats
+                        );
+                        break;
+                    }
+                }
+
                 message = self.rx.recv() => {
                     if let Some(message) = message {
                         match message {
@@ -72,12 +154,48 @@ impl TurtleReceiverInner {
         }
 
         debug!("Turtle Receiver shutting down for {}", self.name);
-        self.manager.disconnect(self.name).await;
+        self.manager
+            .connection_closed(self.name, self.connection_id)
+            .await;
         if let Some(tx) = close_tx {
             let _ = tx.send(());
         }
     }
 
+    /// Checks whether we have heard from the turtle since the last heartbeat and sends another.
+    /// Returns false if the turtle has missed so many heartbeats that it should be disconnected.
+    async fn heartbeat(&mut self) -> bool {
+        if self.last_seen.elapsed() < self.heartbeat_interval {
+            self.missed_heartbeats = 0;
+            self.manager.update_last_seen(self.name).await;
+
+            if self.unresponsive {
+                info!("{} is responding again", self.name);
+                self.unresponsive = false;
+                self.manager.set_responsive(self.name, true).await;
+            }
+        } else {
+            self.missed_heartbeats += 1;
+            debug!("{} missed heartbeat {}", self.name, self.missed_heartbeats);
+
+            if self.missed_heartbeats >= self.disconnect_after {
+                return false;
+            }
+
+            if self.missed_heartbeats >= self.unresponsive_after && !self.unresponsive {
+                warn!("{} is unresponsive", self.name);
+                self.unresponsive = true;
+                self.manager.set_responsive(self.name, false).await;
+            }
+        }
+
+        // Only ask for a ping response when the turtle has gone quiet. A turtle that is working
+        // through commands reports after every one.
+        self.sender.heartbeat(self.missed_heartbeats > 0).await;
+
+        true
+    }
+
     async fn handle_turtle_message(&mut self, message: String) {
         let event: TurtleEvents = match serde_json::from_str(message.as_str()) {
             Ok(e) => e,
@@ -100,18 +218,61 @@ impl TurtleReceiverInner {
                 position,
                 heading,
                 fuel,
+                dimension,
+                upgrades,
             } => {
+                if !self.registered {
+                    // gps.locate needs a wireless modem. Older ComputerCraft versions can't tell
+                    // so those turtles are checked anyway.
+                    let can_locate = upgrades.as_ref().is_none_or(|u| u.has(Tool::Modem));
+                    let reported =
+                        (can_locate && !self.asked_position).then_some((position, heading));
+                    self.manager
+                        .register_turtle(self.name, fuel, reported)
+                        .await;
+                    self.registered = true;
+                }
+
                 self.manager
                     .update_turtle_position(self.name, position)
                     .await;
+                // Turtles without a dimension file report none so the database is left as it is.
+                if let Some(dimension) = dimension {
+                    self.manager
+                        .update_turtle_dimension(self.name, dimension)
+                        .await;
+                }
+                // Older ComputerCraft versions can't tell which upgrades are equipped.
+                if let Some(upgrades) = upgrades {
+                    self.manager
+                        .update_turtle_upgrades(self.name, upgrades)
+                        .await;
+                }
                 self.manager.update_turtle_heading(self.name, heading).await;
                 self.manager.update_turtle_fuel(self.name, fuel).await;
             }
-            TurtleEvents::Response { response } => self.sender.got_response(response).await,
-            TurtleEvents::Inspection { block: _ } => {}
+            TurtleEvents::Response { response } => {
+                if let ResponseType::Inspection { block } = &response.response {
+                    if block.is_turtle() {
+                        self.manager.turtle_seen(self.name).await;
+                    }
+                }
+                self.sender.got_response(response).await
+            }
+            TurtleEvents::Inspection { block } => {
+                // The turtle in front can't be told apart so the block is marked as taken.
+                if block.is_turtle() {
+                    self.manager.turtle_seen(self.name).await;
+                }
+            }
             TurtleEvents::Ok { id } => self.sender.ok(id).await,
             TurtleEvents::Ready => self.sender.ready().await,
-            TurtleEvents::GetPosition => self.manager.send_turtle_position(self.name).await,
+            TurtleEvents::GetPosition => {
+                self.asked_position = true;
+                self.manager.send_turtle_position(self.name).await
+            }
+            // Only read during the handshake.
+            TurtleEvents::Hello { .. } => warn!("{} said hello after the handshake", self.name),
         }
     }
 
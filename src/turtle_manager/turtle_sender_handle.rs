@@ -9,25 +9,25 @@ use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use tracing::error;
 
 use crate::{
-    scheme::{Coordinates, Heading},
+    error::Error,
+    scheme::{Coordinates, Dimension, Heading},
     turtle_scheme::{RequestType, Response, ResponseType, TurtleCommand},
 };
 
 use super::{
+    turtle_connection::ConnectionContext,
     turtle_sender_inner::TurtleSenderInner,
     turtle_sender_message::{LockedSenderMessage, ReceiversSenderMessage, TurtleSenderMessage},
-    TurtleManagerHandle,
 };
 
 pub fn sender(
     ws_sender: SplitSink<WebSocketStream<TcpStream>, Message>,
-    manager: TurtleManagerHandle,
-    name: &'static str,
+    context: ConnectionContext,
 ) -> (TurtleSenderHandle, ReceiversSenderHandle) {
     let (main_tx, main_rx) = mpsc::channel(1);
     let (receiver_tx, receiver_rx) = mpsc::channel(1);
 
-    let inner = TurtleSenderInner::new(main_rx, receiver_rx, ws_sender, manager, name);
+    let inner = TurtleSenderInner::new(main_rx, receiver_rx, ws_sender, context);
     tokio::spawn(inner.run());
 
     (
@@ -71,13 +71,41 @@ impl TurtleSenderHandle {
         }
     }
 
-    pub async fn send(&self, command: TurtleCommand) {
+    /// Queues a command for the turtle.
+    /// Fails if the sender has shut down because the turtle disconnected.
+    pub async fn send(&self, command: TurtleCommand) -> Result<(), Error> {
         if let Err(m) = self.tx.send(TurtleSenderMessage::Command(command)).await {
             error!("Problem sending message {m}");
+            return Err(Error::Disconnected);
+        }
+
+        Ok(())
+    }
+
+    pub async fn pause(&self) {
+        if self.tx.send(TurtleSenderMessage::Pause).await.is_err() {
+            error!("Problem sending pause message");
         }
     }
 
-    pub async fn request(&self, request: RequestType) -> Result<ResponseType, ()> {
+    pub async fn resume(&self) {
+        if self.tx.send(TurtleSenderMessage::Resume).await.is_err() {
+            error!("Problem sending resume message");
+        }
+    }
+
+    pub async fn emergency_stop(&self) {
+        if self
+            .tx
+            .send(TurtleSenderMessage::EmergencyStop)
+            .await
+            .is_err()
+        {
+            error!("Problem sending emergency stop message");
+        }
+    }
+
+    pub async fn request(&self, request: RequestType) -> Result<ResponseType, Error> {
         let (tx, rx) = oneshot::channel();
 
         if self
@@ -87,17 +115,31 @@ impl TurtleSenderHandle {
             .is_err()
         {
             error!("Problem sending request");
-            return Err(());
+            return Err(Error::Disconnected);
+        }
+
+        match rx.await {
+            Ok(r) => r,
+            Err(_) => Err(Error::Disconnected),
         }
+    }
 
-        if let Ok(r) = rx.await {
-            Ok(r)
-        } else {
-            Err(())
+    /// Fails every outstanding request and returns every command still queued.
+    /// Returns nothing if the sender has already closed.
+    pub async fn drain(&self) -> Vec<TurtleCommand> {
+        let (tx, rx) = oneshot::channel();
+
+        if self.tx.send(TurtleSenderMessage::Drain(tx)).await.is_err() {
+            error!("Problem sending drain message");
+            return vec![];
         }
+
+        rx.await.unwrap_or_default()
     }
 
-    pub async fn lock(&self) -> Result<LockedSenderHandle, ()> {
+    /// Waits for every command before the lock to finish and then gives sole use of the sender.
+    /// Fails if the turtle disconnects or does not answer the ping sent when locking.
+    pub async fn lock(&self) -> Result<LockedSenderHandle, Error> {
         let (mpsc_tx, mpsc_rx) = mpsc::channel(1);
         let (oneshot_tx, oneshot_rx) = oneshot::channel();
 
@@ -107,12 +149,13 @@ impl TurtleSenderHandle {
             .await
             .is_err()
         {
-            return Err(());
+            return Err(Error::Disconnected);
         }
 
         match oneshot_rx.await {
             Ok(Ok(_)) => Ok(LockedSenderHandle { tx: mpsc_tx }),
-            _ => Err(()),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(Error::Disconnected),
         }
     }
 }
@@ -149,6 +192,19 @@ impl ReceiversSenderHandle {
             error!("Problem sending got response");
         }
     }
+
+    /// Tells the sender to ping the turtle.
+    /// If `request` is set a ping request is queued as well as the websocket ping.
+    pub async fn heartbeat(&self, request: bool) {
+        if self
+            .tx
+            .send(ReceiversSenderMessage::Heartbeat(request))
+            .await
+            .is_err()
+        {
+            error!("Problem sending heartbeat");
+        }
+    }
 }
 
 pub struct LockedSenderHandle {
@@ -162,7 +218,7 @@ impl LockedSenderHandle {
         }
     }
 
-    pub async fn request(&self, request: RequestType) -> Result<ResponseType, ()> {
+    pub async fn request(&self, request: RequestType) -> Result<ResponseType, Error> {
         let (tx, rx) = oneshot::channel();
 
         if self
@@ -172,20 +228,34 @@ impl LockedSenderHandle {
             .is_err()
         {
             error!("Problem sending request to locked sender");
-            return Err(());
+            return Err(Error::Disconnected);
         }
 
-        if let Ok(r) = rx.await {
-            Ok(r)
-        } else {
-            Err(())
+        match rx.await {
+            Ok(r) => r,
+            Err(_) => Err(Error::Disconnected),
+        }
+    }
+
+    pub async fn send(&self, command: TurtleCommand) {
+        if let Err(m) = self.tx.send(LockedSenderMessage::Command(command)).await {
+            error!("Problem sending command to locked sender {m}");
         }
     }
 
-    pub async fn send_position_update(&self, position: Coordinates, heading: Heading) {
+    /// Sends the turtle its position. The turtle's dimension file is left alone if `dimension`
+    /// is None.
+    pub async fn send_position_update(
+        &self,
+        position: Coordinates,
+        heading: Heading,
+        dimension: Option<Dimension>,
+    ) {
         if self
             .tx
-            .send(LockedSenderMessage::UpdatePosition(position, heading))
+            .send(LockedSenderMessage::UpdatePosition(
+                position, heading, dimension,
+            ))
             .await
             .is_err()
         {
@@ -1,7 +1,10 @@
+use crate::error::Error;
+use crate::metrics::{Metrics, TimeoutKind};
 use crate::turtle_scheme::{Request, RequestType, Response, ResponseType, TurtleCommand};
 use futures_util::{stream::SplitSink, SinkExt};
 use serde::Serialize;
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::oneshot;
 use tokio::time::MissedTickBehavior;
@@ -10,7 +13,10 @@ use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use tracing::{debug, error, info, warn};
 use turtle_sender_queue::SenderQueue;
 
-use super::turtle_sender_message::{LockedSenderMessage, ReceiversSenderMessage};
+use super::session_recorder::SessionRecorder;
+use super::turtle_connection::ConnectionContext;
+use super::turtle_manager_config::RequestTimeouts;
+use super::turtle_sender_message::{LockedSenderMessage, ReceiversSenderMessage, ResponseSender};
 use super::{turtle_sender_message::TurtleSenderMessage, TurtleManagerHandle};
 
 #[derive(Serialize)]
@@ -19,6 +25,27 @@ struct SentCommand {
     pub command: TurtleCommand,
 }
 
+/// A lock waiting for the sender. Holds where the locked messages come from and where to say
+/// whether the lock was taken.
+type QueuedLock = (
+    mpsc::Receiver<LockedSenderMessage>,
+    oneshot::Sender<Result<(), Error>>,
+);
+
+/// A request sent or queued for the turtle that has not been answered yet.
+struct OutstandingRequest {
+    tx: ResponseSender,
+
+    /// When the request was made.
+    sent: time::Instant,
+
+    /// When the request fails with a timeout if it still has no response.
+    deadline: time::Instant,
+
+    /// Whether the request lists the turtle's own inventory.
+    lists_items: bool,
+}
+
 pub struct TurtleSenderInner {
     rx: mpsc::Receiver<TurtleSenderMessage>,
     receiver_rx: mpsc::Receiver<ReceiversSenderMessage>,
@@ -26,6 +53,7 @@ pub struct TurtleSenderInner {
     manager: TurtleManagerHandle,
 
     name: &'static str,
+    connection_id: u64,
 }
 
 impl TurtleSenderInner {
@@ -33,84 +61,138 @@ impl TurtleSenderInner {
         rx: mpsc::Receiver<TurtleSenderMessage>,
         receiver_rx: mpsc::Receiver<ReceiversSenderMessage>,
         ws_sender: SplitSink<WebSocketStream<TcpStream>, Message>,
-        manager: TurtleManagerHandle,
-        name: &'static str,
+        context: ConnectionContext,
     ) -> Self {
-        let mut command_timeout = time::interval(Duration::from_secs(5));
-        command_timeout.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let ConnectionContext {
+            manager,
+            name,
+            id,
+            recorder,
+            config,
+        } = context;
 
         TurtleSenderInner {
             rx,
             receiver_rx,
-            sender: Sender::new(ws_sender, name),
+            sender: Sender::new(
+                ws_sender,
+                name,
+                recorder,
+                config.request_timeouts,
+                config.lock_timeout,
+                manager.metrics().clone(),
+            ),
             manager,
             name,
+            connection_id: id,
         }
     }
 
     pub async fn run(mut self) {
         debug!("Starting Turtle Sender");
         let mut close_tx = None;
-        let mut lock_queue: VecDeque<(
-            mpsc::Receiver<LockedSenderMessage>,
-            oneshot::Sender<Result<(), ()>>,
-        )> = VecDeque::new();
+        let mut lock_queue: VecDeque<QueuedLock> = VecDeque::new();
 
         loop {
-            if self.sender.sent_command.is_none() {
+            // Locks wait for a paused turtle to be resumed as nothing they send would go out.
+            if self.sender.sent_command.is_none() && !self.sender.sender_queue.is_paused() {
                 if let Some((rx, tx)) = lock_queue.pop_front() {
-                    lock(&mut self.sender, rx, &mut self.receiver_rx, tx, self.name).await;
+                    let deferred = lock(
+                        &mut self.sender,
+                        rx,
+                        &mut self.rx,
+                        &mut self.receiver_rx,
+                        tx,
+                        &self.manager,
+                        self.name,
+                    )
+                    .await;
+                    // Handled in the order they came in now the lock is released.
+                    let mut closed = false;
+                    for message in deferred {
+                        closed |= !self
+                            .handle_message(message, &mut lock_queue, &mut close_tx)
+                            .await;
+                    }
+                    if closed {
+                        break;
+                    }
+                    continue;
                 }
             }
 
+            let request_deadline = self.sender.next_deadline();
             select! {
-                _ = self.sender.command_timeout.tick(), if self.sender.is_sent_command() => {
+                _ = time::sleep_until(request_deadline.unwrap_or_else(time::Instant::now)), if request_deadline.is_some() => {
+                    self.sender.expire_requests();
+                }
+                _ = self.sender.command_timeout.tick(), if self.sender.is_sent_command() && !self.sender.stopped => {
                     warn!("Failed to get ok from turtle {} before timeout. Retrying command", self.name);
+                    self.sender.metrics.timed_out(self.name, TimeoutKind::Command);
                     if let Some(c) = &self.sender.sent_command {
+                        self.sender.metrics.command_retried(self.name);
                         self.sender.send_command(c.command.clone()).await;
                     }
                 }
                 message = self.rx.recv() => {
-                    if let Some(message) = message {
-                        match message {
-                            TurtleSenderMessage::Close(tx) => {
-                                close_tx = Some(tx);
+                    match message {
+                        Some(message) => {
+                            if !self.handle_message(message, &mut lock_queue, &mut close_tx).await {
                                 break;
                             }
-                            TurtleSenderMessage::Request(request, tx) => {
-                                self.sender.request(request, tx).await;
-                            }
-                            TurtleSenderMessage::Command(command) => {
-                                self.sender.send(command).await;
-                            }
-                            TurtleSenderMessage::Lock(rx, tx) => lock_queue.push_back((rx, tx)),
                         }
-                    } else {
-                        break;
+                        None => break,
                     }
                 }
                 message = self.receiver_rx.recv() => {
                     if let Some(message) = message {
-                        self.handle_receiver_message(message).await;
+                        self.sender
+                            .handle_receiver_message(message, &self.manager)
+                            .await;
                     }
                 }
             }
         }
 
         debug!("Turtle sender shutting down for {}", self.name);
-        self.manager.disconnect(self.name).await;
+        self.sender.metrics.set_queue_depth(self.name, 0);
+        self.manager
+            .connection_closed(self.name, self.connection_id)
+            .await;
         self.sender.close().await;
         if let Some(tx) = close_tx {
             let _ = tx.send(());
         }
     }
 
-    async fn handle_receiver_message(&mut self, message: ReceiversSenderMessage) {
+    /// Handles a message from the sender's handle. Returns false once the sender should close.
+    async fn handle_message(
+        &mut self,
+        message: TurtleSenderMessage,
+        lock_queue: &mut VecDeque<QueuedLock>,
+        close_tx: &mut Option<oneshot::Sender<()>>,
+    ) -> bool {
         match message {
-            ReceiversSenderMessage::GotOk(id) => self.sender.ok(id).await,
-            ReceiversSenderMessage::Ready => self.sender.ready().await,
-            ReceiversSenderMessage::Response(response) => self.sender.response(response).await,
+            TurtleSenderMessage::Close(tx) => {
+                *close_tx = Some(tx);
+                return false;
+            }
+            TurtleSenderMessage::Request(request, tx) => {
+                self.sender.request(request, tx).await;
+            }
+            TurtleSenderMessage::Command(command) => {
+                self.sender.send(command).await;
+            }
+            TurtleSenderMessage::Pause => self.sender.pause(),
+            TurtleSenderMessage::Resume => self.sender.resume().await,
+            TurtleSenderMessage::EmergencyStop => self.sender.emergency_stop(),
+            TurtleSenderMessage::Lock(rx, tx) => lock_queue.push_back((rx, tx)),
+            TurtleSenderMessage::Drain(tx) => {
+                let _ = tx.send(self.sender.drain());
+            }
         }
+
+        true
     }
 }
 
@@ -120,12 +202,39 @@ struct Sender<'a> {
     command_timeout: time::Interval,
     sender_queue: SenderQueue<TurtleCommand>,
     next_id: u64,
-    outstanding_requests: HashMap<u64, oneshot::Sender<ResponseType>>,
+    /// Requests waiting on a response.
+    outstanding_requests: HashMap<u64, OutstandingRequest>,
+
+    /// How long each request waits for its response.
+    request_timeouts: RequestTimeouts,
+
+    /// How long a lock is held before it is released for its task.
+    lock_timeout: Duration,
+
+    /// Metrics of the manager this turtle belongs to.
+    metrics: Arc<Metrics>,
+
+    /// Set by an emergency stop. Stops the sent command from being retried until resumed.
+    stopped: bool,
+
+    /// Records every message we send if the session is being recorded.
+    recorder: Option<SessionRecorder>,
+
+    /// Response to the last heartbeat ping request. Stops a quiet turtle's queue filling up with
+    /// pings.
+    heartbeat_response: Option<oneshot::Receiver<Result<ResponseType, Error>>>,
     name: &'a str,
 }
 
 impl<'a> Sender<'a> {
-    pub fn new(ws_sender: SplitSink<WebSocketStream<TcpStream>, Message>, name: &'a str) -> Self {
+    pub fn new(
+        ws_sender: SplitSink<WebSocketStream<TcpStream>, Message>,
+        name: &'a str,
+        recorder: Option<SessionRecorder>,
+        request_timeouts: RequestTimeouts,
+        lock_timeout: Duration,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         let mut command_timeout = time::interval(Duration::from_secs(5));
         command_timeout.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
@@ -136,43 +245,158 @@ impl<'a> Sender<'a> {
             command_timeout,
             next_id: 0,
             outstanding_requests: HashMap::new(),
+            request_timeouts,
+            lock_timeout,
+            metrics,
+            stopped: false,
+            recorder,
+            heartbeat_response: None,
             name,
         }
     }
 
-    async fn handle_receiver_message(&mut self, message: ReceiversSenderMessage) {
+    /// Handles a message from the receiver whether or not the sender is locked. `manager` is
+    /// told when the turtle lists a full inventory.
+    async fn handle_receiver_message(
+        &mut self,
+        message: ReceiversSenderMessage,
+        manager: &TurtleManagerHandle,
+    ) {
         match message {
             ReceiversSenderMessage::GotOk(id) => self.ok(id).await,
             ReceiversSenderMessage::Ready => self.ready().await,
-            ReceiversSenderMessage::Response(response) => self.response(response).await,
+            ReceiversSenderMessage::Response(response) => {
+                if self.is_full_item_list(&response) {
+                    manager.inventory_full(self.name).await;
+                }
+                self.response(response).await
+            }
+            ReceiversSenderMessage::Heartbeat(request) => self.heartbeat(request).await,
         }
     }
 
-    pub async fn request(&mut self, request_type: RequestType, tx: oneshot::Sender<ResponseType>) {
+    pub async fn request(&mut self, request_type: RequestType, tx: ResponseSender) {
         let id = self.next_id;
         self.next_id += 1;
+        let sent = time::Instant::now();
+        let deadline = sent + self.request_timeouts.get(&request_type);
+        let lists_items = matches!(request_type, RequestType::ItemList);
         let request = Request {
             id,
             request: request_type,
         };
 
-        self.outstanding_requests.insert(id, tx);
+        self.outstanding_requests.insert(
+            id,
+            OutstandingRequest {
+                tx,
+                sent,
+                deadline,
+                lists_items,
+            },
+        );
 
         self.send(TurtleCommand::Request(request)).await;
     }
 
     pub async fn response(&mut self, response: Response) {
-        if let Some(tx) = self.outstanding_requests.remove(&response.id) {
-            let _ = tx.send(response.response);
+        if let Some(OutstandingRequest { tx, sent, .. }) =
+            self.outstanding_requests.remove(&response.id)
+        {
+            self.metrics.observe_request(sent.elapsed());
+            let result = match response.response {
+                ResponseType::Error { reason } => Err(Error::TurtleFailed { reason }),
+                r => Ok(r),
+            };
+            let _ = tx.send(result);
         } else {
             warn!("Got response for unknown request {:?}", response);
         }
     }
 
+    /// Whether `response` answers a request for the turtle's own inventory with a full one.
+    pub fn is_full_item_list(&self, response: &Response) -> bool {
+        let full = matches!(&response.response, ResponseType::Inventory { inventory } if inventory.is_full());
+        full && self
+            .outstanding_requests
+            .get(&response.id)
+            .is_some_and(|r| r.lists_items)
+    }
+
+    /// When the next outstanding request times out.
+    pub fn next_deadline(&self) -> Option<time::Instant> {
+        self.outstanding_requests.values().map(|r| r.deadline).min()
+    }
+
+    /// Fails every request past its deadline with a timeout and forgets requests nobody is waiting
+    /// on any more. Expired requests still in the queue are removed so the turtle never runs them.
+    pub fn expire_requests(&mut self) {
+        let now = time::Instant::now();
+        let expired: Vec<u64> = self
+            .outstanding_requests
+            .iter()
+            .filter(|(_, r)| r.deadline <= now || r.tx.is_closed())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired.iter() {
+            if let Some(request) = self.outstanding_requests.remove(id) {
+                if !request.tx.is_closed() {
+                    warn!("Request {id} to {} timed out", self.name);
+                    self.metrics.timed_out(self.name, TimeoutKind::Request);
+                    let _ = request.tx.send(Err(Error::TimedOut));
+                }
+            }
+        }
+
+        self.sender_queue
+            .retain(|c| !matches!(c, TurtleCommand::Request(r) if expired.contains(&r.id)));
+        self.metrics
+            .set_queue_depth(self.name, self.sender_queue.len());
+    }
+
     pub async fn send(&mut self, command: TurtleCommand) {
         if let Some(c) = self.sender_queue.send(command) {
             self.send_command(c).await;
         }
+        self.metrics
+            .set_queue_depth(self.name, self.sender_queue.len());
+    }
+
+    /// Sends a websocket ping so the turtle's websocket answers with a pong.
+    /// If `request` is set also queues a ping request unless the last one is still waiting.
+    pub async fn heartbeat(&mut self, request: bool) {
+        if let Err(e) = self.ws_sender.send(Message::Ping(vec![])).await {
+            error!("Problem sending ping to {} {e}", self.name);
+        }
+
+        let waiting = match &mut self.heartbeat_response {
+            Some(rx) => matches!(rx.try_recv(), Err(oneshot::error::TryRecvError::Empty)),
+            None => false,
+        };
+
+        if request && !waiting {
+            let (tx, rx) = oneshot::channel();
+            self.request(RequestType::Ping, tx).await;
+            self.heartbeat_response = Some(rx);
+        }
+    }
+
+    /// Fails every outstanding request and takes every queued command so they can be sent on a new
+    /// connection. The sent command is dropped as the turtle may have already run it.
+    pub fn drain(&mut self) -> Vec<TurtleCommand> {
+        for (_, request) in self.outstanding_requests.drain() {
+            let _ = request.tx.send(Err(Error::Replaced));
+        }
+        self.sent_command = None;
+        self.metrics.set_queue_depth(self.name, 0);
+
+        // Requests are answered with the id they were sent with so can't be moved.
+        self.sender_queue
+            .drain()
+            .into_iter()
+            .filter(|c| !matches!(c, TurtleCommand::Request(_)))
+            .collect()
     }
 
     pub async fn ready(&mut self) {
@@ -186,6 +410,31 @@ impl<'a> Sender<'a> {
         // }
     }
 
+    /// Stops sending queued commands. A command that has already been sent is still retried.
+    pub fn pause(&mut self) {
+        info!("Pausing sender for {}", self.name);
+        self.sender_queue.pause();
+    }
+
+    /// Pauses and also stops retrying the sent command so nothing more goes over the websocket.
+    pub fn emergency_stop(&mut self) {
+        warn!("Emergency stopping sender for {}", self.name);
+        self.sender_queue.pause();
+        self.stopped = true;
+    }
+
+    pub async fn resume(&mut self) {
+        info!("Resuming sender for {}", self.name);
+        if self.stopped {
+            self.stopped = false;
+            self.command_timeout.reset();
+        }
+
+        if let Some(c) = self.sender_queue.resume() {
+            self.send_command(c).await;
+        }
+    }
+
     pub async fn ok(&mut self, id: u64) {
         if let Some(command) = &self.sent_command {
             if id != command.id {
@@ -218,12 +467,17 @@ impl<'a> Sender<'a> {
         )
         .await;
         self.sent_command = Some(sent_command);
+        self.metrics
+            .set_queue_depth(self.name, self.sender_queue.len());
 
         self.command_timeout.reset();
     }
 
     pub async fn send_message(&mut self, message: String) {
         debug!("Sending message to {}: {message}", self.name);
+        if let Some(recorder) = &self.recorder {
+            recorder.record_out(message.as_str());
+        }
         if let Err(e) = self.ws_sender.send(Message::Text(message)).await {
             error!("Problem sending message to {} {e}", self.name);
         }
@@ -238,56 +492,99 @@ impl<'a> Sender<'a> {
     }
 }
 
+/// Sends only what comes through `rx` until the lock is released or times out.
+/// Pausing, stopping, closing or draining the sender releases the lock straight away, failing
+/// whatever the lock had not queued yet. Every other message from the handle waits until the lock
+/// is released and is returned to be handled then.
 async fn lock<'a>(
     sender: &mut Sender<'a>,
     mut rx: mpsc::Receiver<LockedSenderMessage>,
+    messages: &mut mpsc::Receiver<TurtleSenderMessage>,
     receiver_rx: &mut mpsc::Receiver<ReceiversSenderMessage>,
-    unlock_tx: oneshot::Sender<Result<(), ()>>,
+    unlock_tx: oneshot::Sender<Result<(), Error>>,
+    manager: &TurtleManagerHandle,
     name: &str,
-) {
+) -> Vec<TurtleSenderMessage> {
     debug!("Sender for {} is locking", name);
 
     let (ping_tx, ping_rx) = oneshot::channel();
     sender.request(RequestType::Ping, ping_tx).await;
 
     tokio::spawn(async move {
-        let response = ping_rx.await;
-        if let Ok(response) = response {
-            if !matches!(response, ResponseType::Pong) {
+        let result = match ping_rx.await {
+            Ok(Ok(ResponseType::Pong)) => Ok(()),
+            Ok(Ok(response)) => {
                 error!("Got incorrect response type to ping :{:?}", response);
-                let _ = unlock_tx.send(Err(()));
-                return;
+                Err(Error::Protocol {
+                    message: format!("expected pong got {:?}", response),
+                })
             }
-        } else {
-            let _ = unlock_tx.send(Err(()));
-            return;
-        }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(Error::Disconnected),
+        };
 
-        let _ = unlock_tx.send(Ok(()));
+        let _ = unlock_tx.send(result);
     });
 
     debug!("Starting lock loop");
 
     let start_time = time::Instant::now();
     let mut should_exit = false;
+    let mut deferred = vec![];
 
     loop {
-        let timeout = tokio::time::sleep(Duration::from_secs(10) - start_time.elapsed());
+        let timeout = tokio::time::sleep(sender.lock_timeout.saturating_sub(start_time.elapsed()));
+        let request_deadline = sender.next_deadline();
         tokio::select! {
+            _ = time::sleep_until(request_deadline.unwrap_or_else(time::Instant::now)), if request_deadline.is_some() => {
+                sender.expire_requests();
+            }
             _ = timeout => {
                 warn!("Timeout during lock");
+                sender.metrics.timed_out(name, TimeoutKind::Lock);
+                fail_locked(&mut rx, Error::TimedOut);
                 break;
             }
+            message = messages.recv() => {
+                match message {
+                    // The queue is kept to be sent once the turtle is resumed.
+                    Some(
+                        message @ (TurtleSenderMessage::Pause
+                        | TurtleSenderMessage::EmergencyStop),
+                    ) => {
+                        warn!("Releasing the lock on {name} as it is paused");
+                        fail_locked(&mut rx, Error::Paused);
+                        deferred.push(message);
+                        break;
+                    }
+                    // Draining moves the queue to the new connection so it is left for the drain.
+                    Some(message @ TurtleSenderMessage::Drain(_)) => {
+                        warn!("Releasing the lock on {name} as it is being replaced");
+                        fail_locked(&mut rx, Error::Replaced);
+                        deferred.push(message);
+                        break;
+                    }
+                    Some(message @ TurtleSenderMessage::Close(_)) => {
+                        warn!("Releasing the lock on {name} as it is closing");
+                        fail_locked(&mut rx, Error::Disconnected);
+                        deferred.push(message);
+                        break;
+                    }
+                    Some(message) => deferred.push(message),
+                    None => break,
+                }
+            }
             message = rx.recv(), if !should_exit => {
                 if let Some(message) = message {
                     match message {
                         LockedSenderMessage::Request(request, tx) => {
                             sender.request(request, tx).await;
                         }
-                        LockedSenderMessage::UpdatePosition(position, heading) => {
+                        LockedSenderMessage::UpdatePosition(position, heading, dimension) => {
                             info!("Updating position of {}", name);
-                            sender.send(TurtleCommand::UpdatePosition { coords: position, heading }).await;
+                            sender.send(TurtleCommand::UpdatePosition { coords: position, heading, dimension }).await;
                         }
+                        LockedSenderMessage::Command(command) => sender.send(command).await,
                         LockedSenderMessage::Unlock => {
                             should_exit = true;
                         }
@@ -298,7 +595,7 @@ async fn lock<'a>(
             }
             message = receiver_rx.recv() => {
                 if let Some(message) = message {
-                    sender.handle_receiver_message(message).await;
+                    sender.handle_receiver_message(message, manager).await;
                 } else {
                     break;
                 }
@@ -312,4 +609,16 @@ async fn lock<'a>(
     }
 
     debug!("Sender for {} is unlocking", name);
+    deferred
+}
+
+/// Stops anything more being sent with a lock and fails the requests that were sent with it but
+/// not handled yet.
+fn fail_locked(rx: &mut mpsc::Receiver<LockedSenderMessage>, error: Error) {
+    rx.close();
+    while let Ok(message) = rx.try_recv() {
+        if let LockedSenderMessage::Request(_, tx) = message {
+            let _ = tx.send(Err(error.clone()));
+        }
+    }
 }
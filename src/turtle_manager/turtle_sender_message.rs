@@ -1,17 +1,28 @@
 use crate::{
-    scheme::{Coordinates, Heading},
+    error::Error,
+    scheme::{Coordinates, Dimension, Heading},
     turtle_scheme::{RequestType, Response, ResponseType, TurtleCommand},
 };
 use tokio::sync::{mpsc, oneshot};
 
+/// Sends the response to a request or why there was no response.
+pub type ResponseSender = oneshot::Sender<Result<ResponseType, Error>>;
+
 #[derive(Debug)]
 pub enum TurtleSenderMessage {
-    Request(RequestType, oneshot::Sender<ResponseType>),
+    Request(RequestType, ResponseSender),
     Close(oneshot::Sender<()>),
     Command(TurtleCommand),
+    Pause,
+    Resume,
+    EmergencyStop,
+
+    /// Fails every outstanding request, stops retrying the sent command and returns every command
+    /// still waiting in the queue. Used when the turtle's connection is replaced.
+    Drain(oneshot::Sender<Vec<TurtleCommand>>),
     Lock(
         mpsc::Receiver<LockedSenderMessage>,
-        oneshot::Sender<Result<(), ()>>,
+        oneshot::Sender<Result<(), Error>>,
     ), // UpdatePosition(Position, oneshot::Sender<Result<(), ()>>),
 }
 
@@ -20,11 +31,15 @@ pub enum ReceiversSenderMessage {
     GotOk(u64),
     Ready,
     Response(Response),
+
+    /// Sends a websocket ping. Also queues a ping request if set.
+    Heartbeat(bool),
 }
 
 #[derive(Debug)]
 pub enum LockedSenderMessage {
-    Request(RequestType, oneshot::Sender<ResponseType>),
-    UpdatePosition(Coordinates, Heading),
+    Request(RequestType, ResponseSender),
+    UpdatePosition(Coordinates, Heading, Option<Dimension>),
+    Command(TurtleCommand),
     Unlock,
 }
@@ -1,25 +1,45 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use futures_util::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use super::{turtle_connection::TurtleConnection, TurtleManagerHandle};
+use crate::deploy;
+use crate::turtle_scheme::{Capabilities, TurtleEvents, PROTOCOL_VERSION};
+
+use super::{
+    session_recorder::{start_recording, SessionRecorder},
+    turtle_connection::TurtleConnection,
+    turtle_manager_config::TurtleManagerConfig,
+    TurtleManagerHandle,
+};
 
 pub struct UnknownTurtleConnection {
     ws_stream: WebSocketStream<TcpStream>,
 }
 
+/// A turtle that has finished the handshake. Turned into a TurtleConnection once the manager takes
+/// it so nothing it sends reaches the manager before the turtle is added.
+pub struct AuthedTurtle {
+    ws_stream: WebSocketStream<TcpStream>,
+    pub name: &'static str,
+    script_version: Option<String>,
+    recorder: Option<SessionRecorder>,
+    first_message: Option<String>,
+    pub capabilities: Capabilities,
+}
+
 impl UnknownTurtleConnection {
     pub fn new(ws_stream: WebSocketStream<TcpStream>) -> Self {
         UnknownTurtleConnection { ws_stream }
     }
 
-    pub async fn auth(
-        mut self,
-        manager: TurtleManagerHandle,
-    ) -> Option<(&'static str, TurtleConnection)> {
+    /// Gets the turtle's id and script version, sends it its name and reads what it supports.
+    /// Turtles that speak a different protocol are disconnected.
+    /// If `record_dir` is set the session, including this handshake, is recorded there.
+    pub async fn auth(mut self, record_dir: Option<&PathBuf>) -> Option<AuthedTurtle> {
         let id = if let Ok(Some(Ok(Message::Text(id)))) =
             tokio::time::timeout(Duration::from_millis(500), self.ws_stream.next()).await
         {
@@ -30,26 +50,80 @@ impl UnknownTurtleConnection {
             return None;
         };
 
-        let id: u64 = if let Ok(id) = id.parse::<f64>() {
-            id as u64
+        let id_message = id.clone();
+        let (id, script_version) = if let Some(hello) = deploy::parse_id_message(id.as_str()) {
+            hello
         } else {
             error!("Turtle sent invalid id {id}");
             let _ = self.ws_stream.close(None).await;
             return None;
         };
 
-        debug!("Turtle has id {id}");
+        debug!("Turtle has id {id} and script version {:?}", script_version);
 
         let name = Self::get_name(id);
+        let recorder = start_recording(record_dir, name);
+        if let Some(recorder) = &recorder {
+            recorder.record_in(id_message.as_str());
+            recorder.record_out(name);
+        }
+
         if let Err(e) = self.ws_stream.send(Message::Text(name.to_string())).await {
             error!("Problem sending turtle its name {e}");
             let _ = self.ws_stream.close(None).await;
             return None;
         }
 
+        let (capabilities, first_message) = self.hello(name, recorder.as_ref()).await;
+        if let Some(protocol) = capabilities.protocol.filter(|p| *p != PROTOCOL_VERSION) {
+            error!("{name} speaks protocol {protocol}. Expected {PROTOCOL_VERSION}. Disconnecting");
+            let _ = self.ws_stream.close(None).await;
+            return None;
+        }
+
         info!("{name} connected");
 
-        Some((name, TurtleConnection::new(self.ws_stream, manager, name)))
+        Some(AuthedTurtle {
+            ws_stream: self.ws_stream,
+            name,
+            script_version,
+            recorder,
+            first_message,
+            capabilities,
+        })
+    }
+
+    /// Reads the hello a turtle sends after getting its name.
+    /// Turtles from before the hello go straight to their first report. That frame is returned
+    /// so it can be handled by the receiver. Turtles that send neither in time are treated as
+    /// being from before the hello as well.
+    async fn hello(
+        &mut self,
+        name: &str,
+        recorder: Option<&SessionRecorder>,
+    ) -> (Capabilities, Option<String>) {
+        let message =
+            match tokio::time::timeout(Duration::from_millis(500), self.ws_stream.next()).await {
+                Ok(Some(Ok(Message::Text(message)))) => message,
+                _ => {
+                    warn!("{name} did not say hello. Only sending it the original commands");
+                    return (Capabilities::legacy(), None);
+                }
+            };
+        if let Some(recorder) = recorder {
+            recorder.record_in(message.as_str());
+        }
+
+        let capabilities = serde_json::from_str::<TurtleEvents>(message.as_str())
+            .ok()
+            .and_then(|event| Capabilities::from_hello(&event));
+        match capabilities {
+            Some(capabilities) => (capabilities, None),
+            None => {
+                warn!("{name} is from before the hello. Only sending it the original commands");
+                (Capabilities::legacy(), Some(message))
+            }
+        }
     }
 
     fn get_name(id: u64) -> &'static str {
@@ -63,6 +137,25 @@ impl UnknownTurtleConnection {
     }
 }
 
+impl AuthedTurtle {
+    /// Starts the turtle's sender and receiver.
+    pub fn connect(
+        self,
+        manager: TurtleManagerHandle,
+        config: &TurtleManagerConfig,
+    ) -> TurtleConnection {
+        TurtleConnection::new(
+            self.ws_stream,
+            manager,
+            self.name,
+            self.script_version,
+            self.recorder,
+            self.first_message,
+            config,
+        )
+    }
+}
+
 struct NamesList(&'static str);
 
 impl NamesList {
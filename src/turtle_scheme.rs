@@ -1,5 +1,10 @@
+/// What a turtle said it supports during the handshake.
+mod capabilities;
 mod turtle_commands;
 mod turtle_events;
 
+pub use capabilities::{Capabilities, PROTOCOL_VERSION};
 pub use turtle_commands::{Message, Request, RequestType, TurtleCommand};
-pub use turtle_events::{Response, ResponseType, TurtleEvents};
+pub use turtle_events::{
+    Inventory, ItemSlot, Peripheral, Response, ResponseType, Side, TurtleEvents,
+};
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use super::{RequestType, TurtleCommand, TurtleEvents};
+
+/// Version of the messages in turtle_scheme. Bumped whenever a message changes shape.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Command types startup.lua handled before it said hello.
+const LEGACY_COMMANDS: [&str; 9] = [
+    "request",
+    "move",
+    "forward",
+    "back",
+    "turn_left",
+    "turn_right",
+    "reboot",
+    "inspect",
+    "update_position",
+];
+
+/// Request types startup.lua answered before it said hello.
+const LEGACY_REQUESTS: [&str; 2] = ["inspect", "ping"];
+
+/// What a turtle's startup.lua said it can do during the handshake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Protocol version the turtle speaks. None for turtles from before the handshake had one.
+    pub protocol: Option<u32>,
+
+    /// Command types the turtle handles. None if the turtle did not say so every command is sent.
+    commands: Option<HashSet<String>>,
+
+    /// Request types the turtle answers. None if the turtle did not say.
+    requests: Option<HashSet<String>>,
+}
+
+impl Capabilities {
+    /// Gets the capabilities from a hello event. Returns None for any other event.
+    pub fn from_hello(event: &TurtleEvents) -> Option<Self> {
+        match event {
+            TurtleEvents::Hello {
+                protocol,
+                commands,
+                requests,
+            } => Some(Capabilities {
+                protocol: Some(*protocol),
+                commands: Some(commands.iter().cloned().collect()),
+                requests: Some(requests.iter().cloned().collect()),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Capabilities of a turtle that did not say hello. Only the commands and requests startup.lua
+    /// handled before the hello are sent to it.
+    pub fn legacy() -> Self {
+        Capabilities {
+            protocol: None,
+            commands: Some(LEGACY_COMMANDS.iter().map(|c| c.to_string()).collect()),
+            requests: Some(LEGACY_REQUESTS.iter().map(|r| r.to_string()).collect()),
+        }
+    }
+
+    /// Whether the turtle can handle `command`.
+    /// Always true for turtles that did not list what they support.
+    pub fn supports(&self, command: &TurtleCommand) -> bool {
+        match command {
+            TurtleCommand::Request(request) => self.supports_request(&request.request),
+            command => self
+                .commands
+                .as_ref()
+                .is_none_or(|c| c.contains(command.kind())),
+        }
+    }
+
+    /// Whether the turtle answers `request`.
+    /// Always true for turtles that did not list what they support.
+    pub fn supports_request(&self, request: &RequestType) -> bool {
+        self.requests
+            .as_ref()
+            .is_none_or(|r| r.contains(request.kind()))
+    }
+}
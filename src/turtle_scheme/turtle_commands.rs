@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
-use crate::scheme::{Coordinates, Direction, Heading};
+use crate::scheme::{Coordinates, Dimension, Direction, Heading};
+
+use super::turtle_events::Side;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -14,6 +16,42 @@ pub enum Message {
 pub enum RequestType {
     Inspect,
     Ping,
+
+    /// Gets the turtle's position from `gps.locate`.
+    Locate,
+
+    /// Lists the peripherals attached to each side of the turtle.
+    PeripheralList,
+
+    /// Lists the slots of the inventory on `side` of the turtle.
+    InventoryList {
+        side: Side,
+    },
+
+    /// Lists the slots of the turtle's own inventory.
+    ItemList,
+
+    /// Runs a chunk of Lua in a sandbox on the turtle. The turtle gives up on it after
+    /// `timeout_ms` if it yields.
+    Eval {
+        code: String,
+        timeout_ms: u64,
+    },
+}
+
+impl RequestType {
+    /// Name of the request type as it is sent to the turtle.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RequestType::Inspect => "inspect",
+            RequestType::Ping => "ping",
+            RequestType::Locate => "locate",
+            RequestType::PeripheralList => "peripheral_list",
+            RequestType::InventoryList { .. } => "inventory_list",
+            RequestType::ItemList => "item_list",
+            RequestType::Eval { .. } => "eval",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -37,5 +75,150 @@ pub enum TurtleCommand {
     UpdatePosition {
         coords: Coordinates,
         heading: Heading,
+
+        /// Written to the turtle's dimension file. None leaves the file as it is.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dimension: Option<Dimension>,
+    },
+
+    /// Equips the item in `slot` on the turtle's left side. Anything already equipped there is
+    /// put back in the slot.
+    EquipLeft {
+        slot: u32,
+    },
+
+    /// Equips the item in `slot` on the turtle's right side. Anything already equipped there is
+    /// put back in the slot.
+    EquipRight {
+        slot: u32,
+    },
+
+    /// Moves `count` items from slot `from` to slot `to`.
+    TransferTo {
+        from: u32,
+        to: u32,
+        count: u32,
+    },
+
+    /// Crafts the recipe in the top left of the inventory up to `count` times.
+    /// Needs a crafting table equipped.
+    Craft {
+        count: u32,
+    },
+
+    /// Attacks the entity in front of the turtle. Drops are picked up into the inventory.
+    Attack,
+
+    /// Attacks the entity above the turtle.
+    AttackUp,
+
+    /// Attacks the entity below the turtle.
+    AttackDown,
+
+    /// Mines the block in front of the turtle. What it drops is picked up into the inventory.
+    /// Needs a pickaxe or other digging tool equipped.
+    Dig,
+
+    /// Mines the block above the turtle.
+    DigUp,
+
+    /// Mines the block below the turtle.
+    DigDown,
+
+    /// Places the item in `slot` in front of the turtle. If there is an entity in front the item
+    /// is used on it instead, such as shearing a sheep or filling a bucket from a cow.
+    Place {
+        slot: u32,
+    },
+
+    /// Places or uses the item in `slot` above the turtle.
+    PlaceUp {
+        slot: u32,
+    },
+
+    /// Places or uses the item in `slot` below the turtle.
+    PlaceDown {
+        slot: u32,
+    },
+
+    /// Drops every item in `slot` into the inventory in front of the turtle, or on the ground if
+    /// there is no inventory.
+    Drop {
+        slot: u32,
     },
+
+    /// Drops every item in `slot` into the inventory above the turtle.
+    DropUp {
+        slot: u32,
+    },
+
+    /// Drops every item in `slot` into the inventory below the turtle.
+    DropDown {
+        slot: u32,
+    },
+
+    /// Replaces startup.lua with `script`. The old script and version are kept for a rollback.
+    /// Takes effect after a reboot.
+    Deploy {
+        script: String,
+        version: String,
+    },
+
+    /// Swaps startup.lua back to the script it had before the last deploy.
+    /// Takes effect after a reboot.
+    Rollback,
+}
+
+impl TurtleCommand {
+    /// Name of the command type as it is sent to the turtle.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TurtleCommand::Request(_) => "request",
+            TurtleCommand::Move { .. } => "move",
+            TurtleCommand::Forward => "forward",
+            TurtleCommand::Back => "back",
+            TurtleCommand::TurnLeft => "turn_left",
+            TurtleCommand::TurnRight => "turn_right",
+            TurtleCommand::Reboot => "reboot",
+            TurtleCommand::Inspect => "inspect",
+            TurtleCommand::UpdatePosition { .. } => "update_position",
+            TurtleCommand::EquipLeft { .. } => "equip_left",
+            TurtleCommand::EquipRight { .. } => "equip_right",
+            TurtleCommand::TransferTo { .. } => "transfer_to",
+            TurtleCommand::Craft { .. } => "craft",
+            TurtleCommand::Attack => "attack",
+            TurtleCommand::AttackUp => "attack_up",
+            TurtleCommand::AttackDown => "attack_down",
+            TurtleCommand::Dig => "dig",
+            TurtleCommand::DigUp => "dig_up",
+            TurtleCommand::DigDown => "dig_down",
+            TurtleCommand::Place { .. } => "place",
+            TurtleCommand::PlaceUp { .. } => "place_up",
+            TurtleCommand::PlaceDown { .. } => "place_down",
+            TurtleCommand::Drop { .. } => "drop",
+            TurtleCommand::DropUp { .. } => "drop_up",
+            TurtleCommand::DropDown { .. } => "drop_down",
+            TurtleCommand::Deploy { .. } => "deploy",
+            TurtleCommand::Rollback => "rollback",
+        }
+    }
+
+    /// Gets which way the command moves the turtle. Returns None for commands that don't move it.
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            TurtleCommand::Move { direction } => Some(*direction),
+            TurtleCommand::Forward => Some(Direction::Forward),
+            TurtleCommand::Back => Some(Direction::Back),
+            _ => None,
+        }
+    }
+
+    /// Whether only admins can send the command. Raw requests, deploys and rollbacks would get
+    /// around the admin only eval and deploy commands.
+    pub fn needs_admin(&self) -> bool {
+        matches!(
+            self,
+            TurtleCommand::Request(_) | TurtleCommand::Deploy { .. } | TurtleCommand::Rollback
+        )
+    }
 }
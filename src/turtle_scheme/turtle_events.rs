@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     blocks::Block,
-    scheme::{Coordinates, Fuel, Heading},
+    scheme::{Coordinates, Dimension, Fuel, Heading, Upgrades},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -15,8 +15,23 @@ pub enum TurtleEvents {
         position: Coordinates,
         heading: Heading,
         fuel: Fuel,
+
+        /// Contents of the turtle's dimension file. None if the turtle does not have one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dimension: Option<Dimension>,
+
+        /// Equipped items. None on ComputerCraft versions without `turtle.getEquippedLeft`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        upgrades: Option<Upgrades>,
     },
     GetPosition,
+
+    /// Sent once after the turtle gets its name. Lists the command and request types it handles.
+    Hello {
+        protocol: u32,
+        commands: Vec<String>,
+        requests: Vec<String>,
+    },
     Inspection {
         block: crate::blocks::Block,
     },
@@ -35,11 +50,40 @@ pub struct Response {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ResponseType {
-    Inspection { block: Block },
+    Inspection {
+        block: Block,
+    },
     Pong,
+
+    /// The turtle could not do the request.
+    Error {
+        reason: String,
+    },
+
+    /// The turtle's GPS fix. None if the turtle is out of range of enough GPS hosts.
+    Location {
+        position: Option<Coordinates>,
+    },
+
+    /// Every peripheral attached directly to the turtle.
+    Peripherals {
+        peripherals: Vec<Peripheral>,
+    },
+
+    /// Contents of an inventory next to the turtle.
+    Inventory {
+        inventory: Inventory,
+    },
+
+    /// Values returned by an evaluated chunk serialized with `textutils.serialize`.
+    Evaluated {
+        result: String,
+    },
 }
 
+/// Sides of a turtle relative to the way it faces. Named the same as ComputerCraft's sides.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
     Front,
     Back,
@@ -48,3 +92,98 @@ pub enum Side {
     Top,
     Bottom,
 }
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Front => "front",
+            Side::Back => "back",
+            Side::Left => "left",
+            Side::Right => "right",
+            Side::Top => "top",
+            Side::Bottom => "bottom",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Side> {
+        match s {
+            "front" => Some(Side::Front),
+            "back" => Some(Side::Back),
+            "left" => Some(Side::Left),
+            "right" => Some(Side::Right),
+            "top" => Some(Side::Top),
+            "bottom" => Some(Side::Bottom),
+            _ => None,
+        }
+    }
+
+    /// Gets the position of the block on this side of a turtle at `position` facing `heading`.
+    pub fn position_from(&self, position: Coordinates, heading: Heading) -> Coordinates {
+        match self {
+            Side::Front => position.step(heading),
+            Side::Back => position.step(heading.opposite()),
+            Side::Left => position.step(heading.left()),
+            Side::Right => position.step(heading.right()),
+            Side::Top => Coordinates {
+                y: position.y + 1,
+                ..position
+            },
+            Side::Bottom => Coordinates {
+                y: position.y - 1,
+                ..position
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A peripheral on one side of a turtle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peripheral {
+    pub side: Side,
+
+    /// Every type the peripheral has such as `minecraft:chest` and `inventory`.
+    pub types: Vec<String>,
+}
+
+/// Contents of an inventory peripheral such as a chest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    /// Number of slots in the inventory.
+    pub size: u32,
+
+    /// Slots that have items in them. Empty slots are left out.
+    pub slots: Vec<ItemSlot>,
+}
+
+impl Inventory {
+    /// Whether every slot has items in it.
+    pub fn is_full(&self) -> bool {
+        self.slots.len() as u32 >= self.size
+    }
+
+    /// Total number of `item` across every slot.
+    pub fn count(&self, item: &str) -> u32 {
+        self.slots
+            .iter()
+            .filter(|s| s.name == item)
+            .map(|s| s.count)
+            .sum()
+    }
+}
+
+/// A stack of items in one slot of an inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemSlot {
+    /// Slot number starting from 1 like ComputerCraft.
+    pub slot: u32,
+
+    /// Minecraft identifier of the item.
+    pub name: String,
+    pub count: u32,
+}
@@ -18,6 +18,9 @@ pub struct SenderQueue<T> {
 
     /// State of the SenderQueue.
     state: QueueState,
+
+    /// While paused no messages are yielded, they are only queued.
+    paused: bool,
 }
 
 impl<T> SenderQueue<T> {
@@ -27,6 +30,7 @@ impl<T> SenderQueue<T> {
         SenderQueue {
             queue: VecDeque::new(),
             state: QueueState::Waiting,
+            paused: false,
         }
     }
 
@@ -36,7 +40,7 @@ impl<T> SenderQueue<T> {
     }
 
     /// If the sender is ready to send a message then return the next message in queue.
-    /// If the sender is waiting then add the message to queue and return None.
+    /// If the sender is waiting or paused then add the message to queue and return None.
     pub fn send(&mut self, message: T) -> Option<T> {
         self.queue.push_back(message);
 
@@ -53,10 +57,31 @@ impl<T> SenderQueue<T> {
         self.pop_send()
     }
 
-    /// Used internally by ready() and send().
-    /// If there is a message in queue then set state to waiting and return the message.
+    /// Stops the queue from yielding messages until resume() is called.
+    /// Messages sent while paused are kept in the queue.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Unpauses the queue and returns the next message in queue if the queue is ready.
+    pub fn resume(&mut self) -> Option<T> {
+        self.paused = false;
+
+        match self.state {
+            QueueState::Ready => self.pop_send(),
+            QueueState::Waiting => None,
+        }
+    }
+
+    /// Used internally by ready(), send() and resume().
+    /// If the queue is not paused and there is a message in queue then set state to waiting and
+    /// return the message.
     /// Otherwise return None.
     fn pop_send(&mut self) -> Option<T> {
+        if self.paused {
+            return None;
+        }
+
         let message = self.queue.pop_front();
         if message.is_some() {
             self.state = QueueState::Waiting;
@@ -69,9 +94,30 @@ impl<T> SenderQueue<T> {
         matches!(self.state, QueueState::Ready)
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
+
+    /// Number of messages waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Removes and returns every message waiting in the queue.
+    /// The state of the queue is not changed.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.queue.drain(..).collect()
+    }
+
+    /// Removes every message waiting in the queue that `f` returns false for.
+    /// The state of the queue is not changed.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        self.queue.retain(f);
+    }
 }
 
 impl<T> Default for SenderQueue<T> {
@@ -141,4 +187,80 @@ mod tests {
         assert_eq!(queue.state, QueueState::Ready);
         assert!(queue.queue.is_empty());
     }
+
+    // Checks that a paused queue keeps messages even when it is ready.
+    #[test]
+    fn check_paused_ready() {
+        let message = "test_message";
+
+        let mut queue = SenderQueue::new();
+        queue.pause();
+        assert_eq!(queue.send(message), None);
+        assert_eq!(queue.ready(), None);
+        assert_eq!(queue.state, QueueState::Ready);
+        assert_eq!(queue.queue.to_owned(), vec![message]);
+    }
+
+    // Checks that resuming a ready queue returns the next message in queue.
+    #[test]
+    fn check_resume_ready() {
+        let message = "test_message";
+
+        let mut queue = SenderQueue::new();
+        queue.pause();
+        queue.send(message);
+        queue.ready();
+        assert_eq!(queue.resume(), Some(message));
+        assert_eq!(queue.state, QueueState::Waiting);
+        assert!(!queue.is_paused());
+    }
+
+    // Checks that resuming a waiting queue does not return a message.
+    #[test]
+    fn check_resume_waiting() {
+        let message = "test_message";
+
+        let mut queue = SenderQueue::new();
+        queue.pause();
+        queue.send(message);
+        assert_eq!(queue.resume(), None);
+        assert_eq!(queue.queue.to_owned(), vec![message]);
+    }
+
+    // Check that len counts queued messages but not the ones that were sent.
+    #[test]
+    fn check_len() {
+        let mut queue = SenderQueue::new();
+        queue.send("first");
+        queue.send("second");
+        assert_eq!(queue.len(), 2);
+
+        queue.ready();
+        assert_eq!(queue.len(), 1);
+    }
+
+    // Check that drain empties the queue in order without changing its state.
+    #[test]
+    fn check_drain() {
+        let mut queue = SenderQueue::new();
+        queue.send("first");
+        queue.send("second");
+
+        assert_eq!(queue.drain(), vec!["first", "second"]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.state, QueueState::Waiting);
+    }
+
+    // Check that retain only keeps the messages that match without changing the state.
+    #[test]
+    fn check_retain() {
+        let mut queue = SenderQueue::new();
+        queue.send("first");
+        queue.send("second");
+        queue.send("third");
+
+        queue.retain(|m| *m != "second");
+        assert_eq!(queue.drain(), vec!["first", "third"]);
+        assert_eq!(queue.state, QueueState::Waiting);
+    }
 }
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use crate::protocol::{
+    Coordinates, Dimension, Heading, Upgrades, COMMANDS, PROTOCOL_VERSION, REQUESTS,
+};
+
+/// Number of inventory slots a turtle has.
+pub const INVENTORY_SIZE: usize = 16;
+
+/// Most items of one kind that fit in a slot.
+pub(crate) const STACK_SIZE: u32 = 64;
+
+/// A stack of items in a turtle's inventory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemStack {
+    pub name: String,
+    pub count: u32,
+}
+
+impl ItemStack {
+    /// Merges the stack into the first `slots` with room for it like a hopper would.
+    /// Returns how many items did not fit.
+    pub(crate) fn merge_into(mut self, slots: &mut [Option<ItemStack>]) -> u32 {
+        for slot in slots.iter_mut() {
+            let room = match slot {
+                Some(s) if s.name == self.name => STACK_SIZE.saturating_sub(s.count),
+                Some(_) => 0,
+                None => STACK_SIZE,
+            };
+            let moved = room.min(self.count);
+            if moved == 0 {
+                continue;
+            }
+
+            match slot {
+                Some(s) => s.count += moved,
+                None => {
+                    *slot = Some(ItemStack {
+                        name: self.name.clone(),
+                        count: moved,
+                    })
+                }
+            }
+            self.count -= moved;
+        }
+
+        self.count
+    }
+}
+
+/// A shaped recipe the simulated crafting table knows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CraftingRecipe {
+    /// Rows of the crafting grid from the top left slot of the inventory. Missing cells are empty.
+    pub pattern: Vec<Vec<Option<String>>>,
+
+    /// What one craft makes.
+    pub output: ItemStack,
+}
+
+/// Faults that can be injected into a simulated turtle's connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Closes the websocket after handling this many commands.
+    DisconnectAfter(u64),
+
+    /// Stops reading and sending after handling this many commands but keeps the socket open,
+    /// like a turtle whose chunk unloaded.
+    HangAfter(u64),
+}
+
+/// Everything needed to start a simulated turtle.
+#[derive(Debug, Clone)]
+pub struct TurtleConfig {
+    /// Computer id sent during the handshake. Decides the turtle's name.
+    pub id: u64,
+
+    /// Where the turtle actually is in the world.
+    pub position: Coordinates,
+
+    /// Which way the turtle is actually facing.
+    pub heading: Heading,
+
+    /// Contents of the turtle's `/position` file.
+    /// None means the file does not exist and the turtle will ask the wrangler for its position.
+    pub position_file: Option<(Coordinates, Heading)>,
+
+    /// Contents of the turtle's `/dimension` file.
+    /// None means the file does not exist and the turtle reports no dimension.
+    pub dimension_file: Option<Dimension>,
+
+    pub fuel: u32,
+
+    /// 20000 for a normal turtle and 100000 for an advanced turtle.
+    pub fuel_limit: u32,
+
+    pub inventory: [Option<ItemStack>; INVENTORY_SIZE],
+
+    /// Items equipped when the turtle starts.
+    pub upgrades: Upgrades,
+
+    /// Delay before each command from the wrangler is handled.
+    pub latency: Duration,
+
+    pub fault: Option<Fault>,
+
+    /// Whether the turtle is in range of GPS hosts so `gps.locate` finds its real position.
+    pub gps: bool,
+
+    /// How long to wait before reconnecting after the connection drops, like startup.lua does.
+    /// None means the turtle stays disconnected. A reboot always reconnects straight away.
+    pub reconnect: Option<Duration>,
+
+    /// Contents of the turtle's `/version` file sent during the handshake.
+    /// None means startup.lua was installed by hand and the turtle only sends its id.
+    pub script_version: Option<String>,
+
+    /// Protocol version sent in the hello.
+    pub protocol: u32,
+
+    /// Command types listed in the hello after the turtle gets its name.
+    /// None means the turtle is from before the hello and goes straight to its first report.
+    pub commands: Option<Vec<String>>,
+
+    /// Request types listed in the hello.
+    pub requests: Vec<String>,
+
+    /// Recipes `turtle.craft` can make. Crafting anything else fails.
+    pub recipes: Vec<CraftingRecipe>,
+}
+
+impl TurtleConfig {
+    pub const NORMAL_FUEL_LIMIT: u32 = 20000;
+    pub const ADVANCED_FUEL_LIMIT: u32 = 100000;
+
+    /// A normal turtle at the origin facing north that knows its position.
+    pub fn new(id: u64) -> Self {
+        TurtleConfig {
+            id,
+            position: Coordinates::default(),
+            heading: Heading::North,
+            position_file: Some((Coordinates::default(), Heading::North)),
+            dimension_file: None,
+            fuel: Self::NORMAL_FUEL_LIMIT,
+            fuel_limit: Self::NORMAL_FUEL_LIMIT,
+            inventory: Default::default(),
+            upgrades: Upgrades::default(),
+            latency: Duration::ZERO,
+            fault: None,
+            gps: false,
+            reconnect: None,
+            script_version: None,
+            protocol: PROTOCOL_VERSION,
+            commands: Some(COMMANDS.iter().map(|c| c.to_string()).collect()),
+            requests: REQUESTS.iter().map(|r| r.to_string()).collect(),
+            recipes: vec![],
+        }
+    }
+
+    pub fn is_advanced(&self) -> bool {
+        self.fuel_limit == Self::ADVANCED_FUEL_LIMIT
+    }
+}
@@ -0,0 +1,28 @@
+//! Simulates ComputerCraft turtles running startup.lua so the wrangler can be run and tested
+//! without Minecraft.
+
+/// Options for starting a simulated turtle and the faults it can have.
+mod config;
+
+/// Messages sent to and from the wrangler over the websocket.
+pub mod protocol;
+
+/// State of a simulated turtle and how it reacts to commands.
+mod sim_turtle;
+
+/// Communicates with a SimTurtleInner.
+mod sim_turtle_handle;
+
+/// The logic behind connecting a simulated turtle to the wrangler.
+mod sim_turtle_inner;
+
+/// Messages that can be sent from a SimTurtleHandle to a SimTurtleInner.
+mod sim_turtle_message;
+
+/// In memory voxel world the simulated turtles move around in.
+mod world;
+
+pub use config::{CraftingRecipe, Fault, ItemStack, TurtleConfig, INVENTORY_SIZE};
+pub use sim_turtle::SimState;
+pub use sim_turtle_handle::SimTurtleHandle;
+pub use world::{Entity, SharedWorld, TurtleBlock, World};
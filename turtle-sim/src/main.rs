@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use tracing::{error, info};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use turtle_sim::{Fault, SimTurtleHandle, TurtleConfig, World};
+
+/// Reads an option of the form `--name value` from the command line.
+fn read_option<T: std::str::FromStr>(args: &[String], name: &str) -> Option<T> {
+    let i = args.iter().position(|a| a == name)?;
+    args.get(i + 1)?.parse().ok()
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "turtle_sim=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!(
+            "Usage: turtle-sim [url] [--count n] [--first-id id] [--latency ms] [--fuel level] \
+            [--advanced] [--disconnect-after n] [--hang-after n]"
+        );
+        return;
+    }
+
+    let url = match args.get(1) {
+        Some(u) if !u.starts_with("--") => u.clone(),
+        _ => "ws://127.0.0.1:8080".to_string(),
+    };
+    let count: u64 = read_option(&args, "--count").unwrap_or(1);
+    let first_id: u64 = read_option(&args, "--first-id").unwrap_or(0);
+    let latency: u64 = read_option(&args, "--latency").unwrap_or(0);
+    let fuel: Option<u32> = read_option(&args, "--fuel");
+    let advanced = args.iter().any(|a| a == "--advanced");
+    let fault = match (
+        read_option(&args, "--disconnect-after"),
+        read_option(&args, "--hang-after"),
+    ) {
+        (Some(n), _) => Some(Fault::DisconnectAfter(n)),
+        (None, Some(n)) => Some(Fault::HangAfter(n)),
+        (None, None) => None,
+    };
+
+    let world = World::shared();
+    let mut turtles = vec![];
+    for i in 0..count {
+        let mut config = TurtleConfig::new(first_id + i);
+        // Space the turtles out so they don't start inside each other.
+        config.position.x = i as i64;
+        config.position_file = Some((config.position, config.heading));
+        if advanced {
+            config.fuel_limit = TurtleConfig::ADVANCED_FUEL_LIMIT;
+        }
+        config.fuel = fuel.unwrap_or(config.fuel_limit);
+        config.latency = Duration::from_millis(latency);
+        config.fault = fault;
+        config.reconnect = Some(Duration::from_secs(5));
+
+        turtles.push(SimTurtleHandle::new(url.clone(), config, world.clone()));
+    }
+
+    info!("Started {count} simulated turtles connecting to {url}");
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Problem waiting for ctrl-c {e}");
+    }
+
+    for turtle in turtles.iter() {
+        turtle.close().await;
+    }
+}
@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Direction a turtle can be told to move in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "f")]
+    Forward,
+
+    #[serde(rename = "b")]
+    Back,
+
+    #[serde(rename = "l")]
+    Left,
+
+    #[serde(rename = "r")]
+    Right,
+
+    #[serde(rename = "u")]
+    Up,
+
+    #[serde(rename = "d")]
+    Down,
+}
+
+/// Protocol version startup.lua sends in its hello.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Command types startup.lua lists in its hello.
+pub const COMMANDS: [&str; 27] = [
+    "request",
+    "move",
+    "forward",
+    "back",
+    "turn_left",
+    "turn_right",
+    "reboot",
+    "update_position",
+    "inspect",
+    "equip_left",
+    "equip_right",
+    "transfer_to",
+    "craft",
+    "attack",
+    "attack_up",
+    "attack_down",
+    "place",
+    "place_up",
+    "place_down",
+    "deploy",
+    "rollback",
+    "dig",
+    "dig_up",
+    "dig_down",
+    "drop",
+    "drop_up",
+    "drop_down",
+];
+
+/// Request types startup.lua lists in its hello.
+pub const REQUESTS: [&str; 7] = [
+    "inspect",
+    "ping",
+    "locate",
+    "peripheral_list",
+    "inventory_list",
+    "item_list",
+    "eval",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Heading {
+    #[default]
+    #[serde(rename = "n")]
+    North,
+
+    #[serde(rename = "s")]
+    South,
+
+    #[serde(rename = "e")]
+    East,
+
+    #[serde(rename = "w")]
+    West,
+}
+
+impl Heading {
+    pub fn left(&self) -> Heading {
+        match self {
+            Heading::North => Heading::West,
+            Heading::South => Heading::East,
+            Heading::East => Heading::North,
+            Heading::West => Heading::South,
+        }
+    }
+
+    pub fn right(&self) -> Heading {
+        match self {
+            Heading::North => Heading::East,
+            Heading::South => Heading::West,
+            Heading::East => Heading::South,
+            Heading::West => Heading::North,
+        }
+    }
+
+    /// Name Minecraft uses for the facing block state.
+    pub fn facing(&self) -> &'static str {
+        match self {
+            Heading::North => "north",
+            Heading::South => "south",
+            Heading::East => "east",
+            Heading::West => "west",
+        }
+    }
+}
+
+impl Coordinates {
+    /// Gets the coordinates one block away in the direction of `heading`.
+    pub fn step(&self, heading: Heading) -> Coordinates {
+        match heading {
+            Heading::North => Coordinates {
+                z: self.z - 1,
+                ..*self
+            },
+            Heading::South => Coordinates {
+                z: self.z + 1,
+                ..*self
+            },
+            Heading::East => Coordinates {
+                x: self.x + 1,
+                ..*self
+            },
+            Heading::West => Coordinates {
+                x: self.x - 1,
+                ..*self
+            },
+        }
+    }
+
+    pub fn up(&self) -> Coordinates {
+        Coordinates {
+            y: self.y + 1,
+            ..*self
+        }
+    }
+
+    pub fn down(&self) -> Coordinates {
+        Coordinates {
+            y: self.y - 1,
+            ..*self
+        }
+    }
+}
+
+/// Contents of a turtle's `/dimension` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimension {
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world: Option<String>,
+}
+
+/// Items equipped on each side of a turtle.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upgrades {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fuel {
+    pub level: u32,
+    pub max: u32,
+}
+
+/// A command as it is sent over the websocket by the wrangler.
+/// Every command is wrapped with an id that is acknowledged with an ok event.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SentCommand {
+    pub id: u64,
+    pub command: Value,
+}
+
+/// Commands the simulator understands.
+/// Anything else is acknowledged and ignored just like startup.lua does.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    Request {
+        id: u64,
+        request: Value,
+    },
+    Move {
+        direction: Direction,
+    },
+    Forward,
+    Back,
+    TurnLeft,
+    TurnRight,
+    Reboot,
+    Inspect,
+    UpdatePosition {
+        coords: Coordinates,
+        heading: Heading,
+
+        #[serde(default)]
+        dimension: Option<Dimension>,
+    },
+    EquipLeft {
+        slot: u32,
+    },
+    EquipRight {
+        slot: u32,
+    },
+    TransferTo {
+        from: u32,
+        to: u32,
+        count: u32,
+    },
+    Craft {
+        count: u32,
+    },
+    Attack,
+    AttackUp,
+    AttackDown,
+    Dig,
+    DigUp,
+    DigDown,
+    Drop {
+        slot: u32,
+    },
+    DropUp {
+        slot: u32,
+    },
+    DropDown {
+        slot: u32,
+    },
+    Deploy {
+        script: String,
+        version: String,
+    },
+    Rollback,
+}
+
+/// Events the simulator sends back to the wrangler.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Response {
+        response: Response,
+    },
+    Report {
+        position: Coordinates,
+        heading: Heading,
+        fuel: Fuel,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dimension: Option<Dimension>,
+
+        upgrades: Upgrades,
+    },
+    GetPosition,
+    Hello {
+        protocol: u32,
+        commands: Vec<String>,
+        requests: Vec<String>,
+    },
+    Inspection {
+        block: Value,
+    },
+    Ok {
+        id: u64,
+    },
+    Ready,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Response {
+    pub id: u64,
+    pub response: Value,
+}
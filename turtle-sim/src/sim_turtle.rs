@@ -0,0 +1,883 @@
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+use crate::config::{CraftingRecipe, ItemStack, TurtleConfig, INVENTORY_SIZE, STACK_SIZE};
+use crate::protocol::{
+    Command, Coordinates, Dimension, Direction, Event, Fuel, Heading, Response, Upgrades,
+};
+use crate::world::{SharedWorld, TurtleBlock};
+
+/// ComputerCraft's sides in the order `redstone.getSides` gives them.
+const SIDES: [&str; 6] = ["top", "bottom", "left", "right", "front", "back"];
+
+/// Slots across the inventory. The crafting grid is the top left 3 by 3 slots.
+const INVENTORY_WIDTH: usize = 4;
+
+/// Turns a slot number starting from 1 like ComputerCraft into an index into the inventory.
+fn slot_index(slot: u32) -> Result<usize, String> {
+    (slot as usize)
+        .checked_sub(1)
+        .filter(|i| *i < INVENTORY_SIZE)
+        .ok_or_else(|| format!("Slot {slot} out of range"))
+}
+
+/// Result of handling a single command.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Events to send back to the wrangler after the ok.
+    pub events: Vec<Event>,
+
+    /// The command asked the turtle to reboot.
+    pub reboot: bool,
+}
+
+/// Everything the simulator knows about a turtle.
+/// Mirrors what startup.lua and the turtle itself would know.
+#[derive(Debug, Clone)]
+pub struct SimState {
+    /// Name the wrangler gave the turtle during the handshake.
+    pub name: Option<String>,
+
+    /// Where the turtle actually is.
+    pub position: Coordinates,
+
+    /// Which way the turtle is actually facing.
+    pub heading: Heading,
+
+    /// Contents of the `/position` file. Moves update it by dead reckoning so it can drift from the
+    /// real position if the wrangler sends a wrong position update.
+    pub position_file: Option<(Coordinates, Heading)>,
+
+    /// Contents of the `/dimension` file. Only changed by position updates.
+    pub dimension_file: Option<Dimension>,
+
+    pub fuel: u32,
+    pub fuel_limit: u32,
+    pub inventory: [Option<ItemStack>; INVENTORY_SIZE],
+    pub upgrades: Upgrades,
+    pub recipes: Vec<CraftingRecipe>,
+
+    /// Whether `gps.locate` gets a fix.
+    pub gps: bool,
+
+    /// Version of startup.lua from the `/version` file.
+    pub script_version: Option<String>,
+
+    /// Version of the script the last deploy kept for a rollback.
+    pub old_script_version: Option<String>,
+
+    /// Whether a deploy has kept an old script to roll back to.
+    pub can_rollback: bool,
+
+    /// Every command received from the wrangler in order.
+    pub commands: Vec<Value>,
+
+    pub connected: bool,
+}
+
+impl SimState {
+    pub fn new(config: &TurtleConfig) -> Self {
+        SimState {
+            name: None,
+            position: config.position,
+            heading: config.heading,
+            position_file: config.position_file,
+            dimension_file: config.dimension_file.clone(),
+            fuel: config.fuel,
+            fuel_limit: config.fuel_limit,
+            inventory: config.inventory.clone(),
+            upgrades: config.upgrades.clone(),
+            recipes: config.recipes.clone(),
+            gps: config.gps,
+            script_version: config.script_version.clone(),
+            old_script_version: None,
+            can_rollback: false,
+            commands: vec![],
+            connected: false,
+        }
+    }
+
+    /// First message of the handshake. The computer id followed by the script version if there is
+    /// one, like startup.lua's connect.
+    pub fn hello(&self, id: u64) -> String {
+        match &self.script_version {
+            Some(version) => format!("{id} {version}"),
+            None => id.to_string(),
+        }
+    }
+
+    /// Builds the report startup.lua sends before every ready.
+    pub fn report(&self) -> Event {
+        let (position, heading) = self
+            .position_file
+            .unwrap_or((Coordinates::default(), Heading::North));
+
+        Event::Report {
+            position,
+            heading,
+            fuel: Fuel {
+                level: self.fuel,
+                max: self.fuel_limit,
+            },
+            dimension: self.dimension_file.clone(),
+            upgrades: self.upgrades.clone(),
+        }
+    }
+
+    /// Handles a command the same way startup.lua's interpretCommand does.
+    /// Unknown commands are ignored.
+    pub fn handle_command(&mut self, command: Value, world: &SharedWorld) -> Outcome {
+        self.commands.push(command.clone());
+
+        let command: Command = match serde_json::from_value(command.clone()) {
+            Ok(c) => c,
+            Err(_) => {
+                warn!("Unknown command {command}");
+                return Outcome::default();
+            }
+        };
+
+        let mut outcome = Outcome::default();
+        match command {
+            Command::Request { id, request } => {
+                let response = self.handle_request(&request, world);
+                outcome.events.push(Event::Response {
+                    response: Response { id, response },
+                });
+            }
+            Command::Move { direction } => {
+                let _ = self.move_turtle(direction, world);
+            }
+            Command::Forward => {
+                let _ = self.move_turtle(Direction::Forward, world);
+            }
+            Command::Back => {
+                let _ = self.move_turtle(Direction::Back, world);
+            }
+            Command::TurnLeft => {
+                let _ = self.move_turtle(Direction::Left, world);
+            }
+            Command::TurnRight => {
+                let _ = self.move_turtle(Direction::Right, world);
+            }
+            Command::Reboot => outcome.reboot = true,
+            Command::Inspect => outcome.events.push(Event::Inspection {
+                block: self.inspect(world),
+            }),
+            Command::UpdatePosition {
+                coords,
+                heading,
+                dimension,
+            } => {
+                self.position_file = Some((coords, heading));
+                if dimension.is_some() {
+                    self.dimension_file = dimension;
+                }
+            }
+            Command::EquipLeft { slot } => {
+                let _ = self.equip(slot, true);
+            }
+            Command::EquipRight { slot } => {
+                let _ = self.equip(slot, false);
+            }
+            Command::TransferTo { from, to, count } => {
+                let _ = self.transfer_to(from, to, count);
+            }
+            Command::Deploy { version, .. } => {
+                self.old_script_version = self.script_version.replace(version);
+                self.can_rollback = true;
+            }
+            Command::Rollback => {
+                if self.can_rollback {
+                    std::mem::swap(&mut self.script_version, &mut self.old_script_version);
+                }
+            }
+            Command::Attack => {
+                self.attack(self.position.step(self.heading), world);
+            }
+            Command::AttackUp => {
+                self.attack(self.position.up(), world);
+            }
+            Command::AttackDown => {
+                self.attack(self.position.down(), world);
+            }
+            Command::Dig => {
+                self.dig(self.position.step(self.heading), world);
+            }
+            Command::DigUp => {
+                self.dig(self.position.up(), world);
+            }
+            Command::DigDown => {
+                self.dig(self.position.down(), world);
+            }
+            Command::Drop { slot } => {
+                let _ = self.drop_items(slot, self.position.step(self.heading), world);
+            }
+            Command::DropUp { slot } => {
+                let _ = self.drop_items(slot, self.position.up(), world);
+            }
+            Command::DropDown { slot } => {
+                let _ = self.drop_items(slot, self.position.down(), world);
+            }
+            Command::Craft { count } => {
+                let _ = self.craft(count);
+            }
+        }
+
+        outcome
+    }
+
+    /// Answers a request like startup.lua's interpretRequest.
+    /// Unknown requests are answered with an error.
+    fn handle_request(&mut self, request: &Value, world: &SharedWorld) -> Value {
+        match request["type"].as_str() {
+            Some("inspect") => json!({ "type": "inspection", "block": self.inspect(world) }),
+            Some("ping") => json!({ "type": "pong" }),
+            Some("locate") if self.gps => json!({ "type": "location", "position": self.position }),
+            Some("locate") => json!({ "type": "location" }),
+            Some("peripheral_list") => self.list_peripherals(world),
+            Some("item_list") => self.list_items(),
+            Some("inventory_list") => self.list_inventory(request["side"].as_str(), world),
+            // There is no Lua to run. Fails the same way a chunk that errors does.
+            Some("eval") => json!({ "type": "error", "reason": "eval is not simulated" }),
+            _ => {
+                warn!("Unknown request {request}");
+                json!({
+                    "type": "error",
+                    "reason": format!("unknown request {}", request["type"]),
+                })
+            }
+        }
+    }
+
+    /// Swaps the item in `slot` with the upgrade on one side like `turtle.equipLeft`.
+    /// Only a single item can be equipped so the slot can't hold a bigger stack.
+    pub fn equip(&mut self, slot: u32, left: bool) -> Result<(), String> {
+        let index = slot_index(slot)?;
+        if self.inventory[index].as_ref().is_some_and(|i| i.count > 1) {
+            return Err("Not enough space".to_string());
+        }
+
+        let side = if left {
+            &mut self.upgrades.left
+        } else {
+            &mut self.upgrades.right
+        };
+        let equipped = self.inventory[index].take().map(|i| i.name);
+        self.inventory[index] = side.take().map(|name| ItemStack { name, count: 1 });
+        *side = equipped;
+
+        Ok(())
+    }
+
+    /// Moves up to `count` items from slot `from` to slot `to` like `turtle.transferTo`.
+    /// Fails if `to` has a different item or is full.
+    pub fn transfer_to(&mut self, from: u32, to: u32, count: u32) -> Result<(), String> {
+        let from = slot_index(from)?;
+        let to = slot_index(to)?;
+        let item = self.inventory[from].clone().ok_or("No items to transfer")?;
+        let room = match &self.inventory[to] {
+            Some(i) if i.name != item.name => return Err("Slot has a different item".to_string()),
+            Some(i) => STACK_SIZE.saturating_sub(i.count),
+            None => STACK_SIZE,
+        };
+        let moved = count.min(item.count).min(room);
+        if moved == 0 {
+            return Err("No space for items".to_string());
+        }
+
+        self.inventory[to] = Some(ItemStack {
+            name: item.name.clone(),
+            count: self.inventory[to].as_ref().map_or(0, |i| i.count) + moved,
+        });
+        self.inventory[from] = (item.count > moved).then(|| ItemStack {
+            name: item.name,
+            count: item.count - moved,
+        });
+
+        Ok(())
+    }
+
+    /// Kills the entity at `position` and picks up what it drops like `turtle.attack`.
+    /// Drops that do not fit in the inventory are lost. Returns whether there was an entity.
+    pub fn attack(&mut self, position: Coordinates, world: &SharedWorld) -> bool {
+        let entity = match world.lock().unwrap().kill_entity(position) {
+            Some(e) => e,
+            None => return false,
+        };
+
+        self.pick_up(entity.drops);
+        true
+    }
+
+    /// Mines the block at `position` and picks it up like `turtle.dig`. Every block drops itself
+    /// and turtles can't be mined. Returns whether there was a block.
+    pub fn dig(&mut self, position: Coordinates, world: &SharedWorld) -> bool {
+        let block = match world.lock().unwrap().remove_block(position) {
+            Some(b) => b,
+            None => return false,
+        };
+
+        self.pick_up(vec![ItemStack {
+            name: block,
+            count: 1,
+        }]);
+        true
+    }
+
+    /// Merges items into the inventory. Items that do not fit are lost.
+    fn pick_up(&mut self, items: Vec<ItemStack>) {
+        for drop in items {
+            drop.merge_into(&mut self.inventory);
+        }
+    }
+
+    /// Drops the items in `slot` into the inventory at `position` like `turtle.drop`. Items that
+    /// do not fit stay in the slot. Without an inventory the items fall on the ground and are lost.
+    pub fn drop_items(
+        &mut self,
+        slot: u32,
+        position: Coordinates,
+        world: &SharedWorld,
+    ) -> Result<(), String> {
+        let index = slot_index(slot)?;
+        let item = self.inventory[index].take().ok_or("No items to drop")?;
+        let name = item.name.clone();
+        let left = world
+            .lock()
+            .unwrap()
+            .insert_items(position, item)
+            .unwrap_or_default();
+        self.inventory[index] = (left > 0).then_some(ItemStack { name, count: left });
+
+        Ok(())
+    }
+
+    /// Crafts the recipe in the crafting grid up to `count` times like `turtle.craft`.
+    /// Needs a crafting table and every slot outside the grid to be empty. What it makes is merged
+    /// into the inventory.
+    pub fn craft(&mut self, count: u32) -> Result<(), String> {
+        let table = Some("minecraft:crafting_table");
+        if self.upgrades.left.as_deref() != table && self.upgrades.right.as_deref() != table {
+            return Err("No crafting table".to_string());
+        }
+
+        let in_grid = |i: usize| i % INVENTORY_WIDTH < 3 && i / INVENTORY_WIDTH < 3;
+        if (0..INVENTORY_SIZE).any(|i| !in_grid(i) && self.inventory[i].is_some()) {
+            return Err("No matching recipes".to_string());
+        }
+
+        let matches = |recipe: &&CraftingRecipe| {
+            (0..INVENTORY_SIZE).filter(|i| in_grid(*i)).all(|i| {
+                let wanted = recipe
+                    .pattern
+                    .get(i / INVENTORY_WIDTH)
+                    .and_then(|row| row.get(i % INVENTORY_WIDTH))
+                    .cloned()
+                    .flatten();
+                wanted.as_deref() == self.inventory[i].as_ref().map(|s| s.name.as_str())
+            })
+        };
+        let recipe = self
+            .recipes
+            .iter()
+            .find(matches)
+            .cloned()
+            .ok_or("No matching recipes")?;
+
+        let crafts = self
+            .inventory
+            .iter()
+            .flatten()
+            .map(|s| s.count)
+            .min()
+            .unwrap_or_default()
+            .min(count);
+        if crafts == 0 {
+            return Err("No matching recipes".to_string());
+        }
+
+        for slot in self.inventory.iter_mut() {
+            if let Some(s) = slot {
+                s.count -= crafts;
+                if s.count == 0 {
+                    *slot = None;
+                }
+            }
+        }
+        self.pick_up(vec![ItemStack {
+            name: recipe.output.name,
+            count: recipe.output.count * crafts,
+        }]);
+
+        Ok(())
+    }
+
+    /// Lists the turtle's own inventory like startup.lua's listItems.
+    fn list_items(&self) -> Value {
+        let slots: Vec<Value> = self
+            .inventory
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let item = item.as_ref()?;
+                Some(json!({ "slot": i + 1, "name": item.name, "count": item.count }))
+            })
+            .collect();
+
+        json!({
+            "type": "inventory",
+            "inventory": { "size": INVENTORY_SIZE, "slots": slots },
+        })
+    }
+
+    /// Lists the peripherals next to the turtle in the same order as `redstone.getSides`.
+    fn list_peripherals(&self, world: &SharedWorld) -> Value {
+        let world = world.lock().unwrap();
+        let peripherals: Vec<Value> = SIDES
+            .iter()
+            .filter_map(|side| {
+                let types = world.peripheral_types(self.side_position(side)?)?;
+                Some(json!({ "side": side, "types": types }))
+            })
+            .collect();
+
+        json!({ "type": "peripherals", "peripherals": peripherals })
+    }
+
+    /// Lists the slots with items in the inventory on `side` like startup.lua's listInventory.
+    fn list_inventory(&self, side: Option<&str>, world: &SharedWorld) -> Value {
+        let world = world.lock().unwrap();
+        let slots = match side
+            .and_then(|s| self.side_position(s))
+            .and_then(|p| world.get_inventory(p))
+        {
+            Some(s) => s,
+            None => {
+                return json!({
+                    "type": "error",
+                    "reason": format!("no inventory on {}", side.unwrap_or("nil")),
+                })
+            }
+        };
+
+        let items: Vec<Value> = slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let item = item.as_ref()?;
+                Some(json!({ "slot": i + 1, "name": item.name, "count": item.count }))
+            })
+            .collect();
+
+        json!({
+            "type": "inventory",
+            "inventory": { "size": slots.len(), "slots": items },
+        })
+    }
+
+    /// Gets the position of the block on a ComputerCraft side of the turtle.
+    fn side_position(&self, side: &str) -> Option<Coordinates> {
+        match side {
+            "front" => Some(self.position.step(self.heading)),
+            "back" => Some(self.position.step(self.heading.left().left())),
+            "left" => Some(self.position.step(self.heading.left())),
+            "right" => Some(self.position.step(self.heading.right())),
+            "top" => Some(self.position.up()),
+            "bottom" => Some(self.position.down()),
+            _ => None,
+        }
+    }
+
+    fn inspect(&self, world: &SharedWorld) -> Value {
+        let front = self.position.step(self.heading);
+        world.lock().unwrap().inspect(front)
+    }
+
+    /// Moves or turns the turtle.
+    /// Like startup.lua moving fails if the position file is missing.
+    pub fn move_turtle(&mut self, direction: Direction, world: &SharedWorld) -> Result<(), String> {
+        let (file_position, file_heading) = match self.position_file {
+            Some(p) => p,
+            None => return Err("unknown position".to_string()),
+        };
+
+        let (target, file_target) = match direction {
+            Direction::Left => {
+                self.heading = self.heading.left();
+                self.position_file = Some((file_position, file_heading.left()));
+                world
+                    .lock()
+                    .unwrap()
+                    .set_turtle_heading(self.position, self.heading);
+                return Ok(());
+            }
+            Direction::Right => {
+                self.heading = self.heading.right();
+                self.position_file = Some((file_position, file_heading.right()));
+                world
+                    .lock()
+                    .unwrap()
+                    .set_turtle_heading(self.position, self.heading);
+                return Ok(());
+            }
+            Direction::Forward => (
+                self.position.step(self.heading),
+                file_position.step(file_heading),
+            ),
+            Direction::Back => (
+                self.position.step(self.heading.left().left()),
+                file_position.step(file_heading.left().left()),
+            ),
+            Direction::Up => (self.position.up(), file_position.up()),
+            Direction::Down => (self.position.down(), file_position.down()),
+        };
+
+        if self.fuel == 0 {
+            return Err("Out of fuel".to_string());
+        }
+
+        if !world.lock().unwrap().move_turtle(self.position, target) {
+            debug!("Movement obstructed at {:?}", target);
+            return Err("Movement obstructed".to_string());
+        }
+
+        self.fuel -= 1;
+        self.position = target;
+        self.position_file = Some((file_target, file_heading));
+
+        Ok(())
+    }
+
+    pub fn turtle_block(&self) -> TurtleBlock {
+        TurtleBlock {
+            heading: self.heading,
+            advanced: self.fuel_limit == TurtleConfig::ADVANCED_FUEL_LIMIT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Entity, World};
+
+    fn setup() -> (SimState, SharedWorld) {
+        let world = World::shared();
+        let state = SimState::new(&TurtleConfig::new(0));
+        world
+            .lock()
+            .unwrap()
+            .place_turtle(state.position, state.turtle_block());
+
+        (state, world)
+    }
+
+    // Check that moving forward updates the world, the position file and fuel.
+    #[test]
+    fn check_forward() {
+        let (mut state, world) = setup();
+
+        state.handle_command(json!({ "type": "forward" }), &world);
+        let expected = Coordinates { x: 0, y: 0, z: -1 };
+        assert_eq!(state.position, expected);
+        assert_eq!(state.position_file, Some((expected, Heading::North)));
+        assert_eq!(state.fuel, TurtleConfig::NORMAL_FUEL_LIMIT - 1);
+        assert!(world.lock().unwrap().get_turtle(expected).is_some());
+    }
+
+    // Check that an obstacle stops the turtle without using fuel.
+    #[test]
+    fn check_obstructed() {
+        let (mut state, world) = setup();
+        let front = state.position.step(Heading::North);
+        world.lock().unwrap().set_block(front, "minecraft:stone");
+
+        assert!(state.move_turtle(Direction::Forward, &world).is_err());
+        assert_eq!(state.position, Coordinates::default());
+        assert_eq!(state.fuel, TurtleConfig::NORMAL_FUEL_LIMIT);
+    }
+
+    // Check that a turtle without a position file refuses to move like startup.lua does.
+    #[test]
+    fn check_unknown_position() {
+        let (mut state, world) = setup();
+        state.position_file = None;
+
+        assert!(state.move_turtle(Direction::Up, &world).is_err());
+        assert_eq!(state.position, Coordinates::default());
+    }
+
+    // Check that requests are answered with the id they were sent with.
+    #[test]
+    fn check_ping_request() {
+        let (mut state, world) = setup();
+
+        let outcome = state.handle_command(
+            json!({ "type": "request", "id": 7, "request": { "type": "ping" } }),
+            &world,
+        );
+        assert_eq!(
+            outcome.events,
+            vec![Event::Response {
+                response: Response {
+                    id: 7,
+                    response: json!({ "type": "pong" })
+                }
+            }]
+        );
+    }
+
+    // Check that position updates only replace the dimension file when they have a dimension.
+    #[test]
+    fn check_update_dimension() {
+        let (mut state, world) = setup();
+        let coords = json!({ "x": 1, "y": 2, "z": 3 });
+
+        state.handle_command(
+            json!({
+                "type": "update_position",
+                "coords": coords,
+                "heading": "e",
+                "dimension": { "name": "minecraft:the_nether" },
+            }),
+            &world,
+        );
+        let nether = Some(Dimension {
+            name: "minecraft:the_nether".to_string(),
+            world: None,
+        });
+        assert_eq!(state.dimension_file, nether);
+
+        state.handle_command(
+            json!({ "type": "update_position", "coords": coords, "heading": "e" }),
+            &world,
+        );
+        assert_eq!(state.dimension_file, nether);
+        assert!(matches!(state.report(), Event::Report { dimension, .. } if dimension == nether));
+    }
+
+    // Check that unknown requests are answered with an error instead of being dropped.
+    #[test]
+    fn check_unknown_request() {
+        let (mut state, world) = setup();
+
+        let outcome = state.handle_command(
+            json!({ "type": "request", "id": 3, "request": { "type": "dance" } }),
+            &world,
+        );
+        assert!(
+            matches!(&outcome.events[0], Event::Response { response } if response.id == 3 && response.response["type"] == "error")
+        );
+    }
+
+    // Check that locate only finds the real position when the turtle has gps.
+    #[test]
+    fn check_locate_request() {
+        let (mut state, world) = setup();
+        let locate = json!({ "type": "request", "id": 0, "request": { "type": "locate" } });
+
+        let outcome = state.handle_command(locate.clone(), &world);
+        assert!(
+            matches!(&outcome.events[0], Event::Response { response } if response.response["position"].is_null())
+        );
+
+        state.gps = true;
+        state.position = Coordinates { x: 1, y: 2, z: 3 };
+        let outcome = state.handle_command(locate, &world);
+        assert!(
+            matches!(&outcome.events[0], Event::Response { response } if response.response["position"] == json!({ "x": 1, "y": 2, "z": 3 }))
+        );
+    }
+
+    // Check that only slots with items are listed and missing inventories are an error.
+    #[test]
+    fn check_inventory_request() {
+        let (mut state, world) = setup();
+        let list = json!({
+            "type": "request",
+            "id": 0,
+            "request": { "type": "inventory_list", "side": "front" },
+        });
+
+        let outcome = state.handle_command(list.clone(), &world);
+        assert!(
+            matches!(&outcome.events[0], Event::Response { response } if response.response["type"] == "error")
+        );
+
+        let mut slots = vec![None; 27];
+        slots[2] = Some(ItemStack {
+            name: "minecraft:cobblestone".to_string(),
+            count: 64,
+        });
+        let front = state.position.step(state.heading);
+        world
+            .lock()
+            .unwrap()
+            .set_inventory(front, "minecraft:chest", slots);
+
+        let outcome = state.handle_command(list, &world);
+        assert!(matches!(
+            &outcome.events[0],
+            Event::Response { response } if response.response["inventory"] == json!({
+                "size": 27,
+                "slots": [{ "slot": 3, "name": "minecraft:cobblestone", "count": 64 }],
+            })
+        ));
+    }
+
+    // Check that equipping swaps the slot with the upgrade on that side.
+    #[test]
+    fn check_equip() {
+        let (mut state, world) = setup();
+        state.upgrades.left = Some("minecraft:diamond_pickaxe".to_string());
+        state.inventory[0] = Some(ItemStack {
+            name: "computercraft:wireless_modem_normal".to_string(),
+            count: 1,
+        });
+
+        state.handle_command(json!({ "type": "equip_left", "slot": 1 }), &world);
+        assert_eq!(
+            state.upgrades.left.as_deref(),
+            Some("computercraft:wireless_modem_normal")
+        );
+        assert_eq!(
+            state.inventory[0].as_ref().map(|i| i.name.as_str()),
+            Some("minecraft:diamond_pickaxe")
+        );
+
+        state.inventory[1] = Some(ItemStack {
+            name: "minecraft:diamond_axe".to_string(),
+            count: 2,
+        });
+        assert!(state.equip(2, false).is_err());
+        assert!(state.upgrades.right.is_none());
+    }
+
+    // Check that transfers merge into matching stacks and never overfill a slot.
+    #[test]
+    fn check_transfer_to() {
+        let (mut state, world) = setup();
+        let planks = |count| {
+            Some(ItemStack {
+                name: "minecraft:oak_planks".to_string(),
+                count,
+            })
+        };
+        state.inventory[0] = planks(40);
+        state.inventory[1] = planks(30);
+
+        state.handle_command(
+            json!({ "type": "transfer_to", "from": 1, "to": 2, "count": 40 }),
+            &world,
+        );
+        assert_eq!(state.inventory[0], planks(6));
+        assert_eq!(state.inventory[1], planks(64));
+
+        assert!(state.transfer_to(1, 3, 6).is_ok());
+        assert!(state.inventory[0].is_none());
+        assert_eq!(state.inventory[2], planks(6));
+    }
+
+    // Check that attacking kills the entity in front and merges its drops into the inventory.
+    #[test]
+    fn check_attack() {
+        let (mut state, world) = setup();
+        let bones = |count| ItemStack {
+            name: "minecraft:bone".to_string(),
+            count,
+        };
+        state.inventory[0] = Some(bones(63));
+        let front = state.position.step(state.heading);
+        world.lock().unwrap().spawn_entity(
+            front,
+            Entity {
+                name: "minecraft:skeleton".to_string(),
+                drops: vec![bones(2)],
+            },
+        );
+
+        state.handle_command(json!({ "type": "attack" }), &world);
+        assert_eq!(state.inventory[0], Some(bones(64)));
+        assert_eq!(state.inventory[1], Some(bones(1)));
+        assert!(!state.attack(front, &world));
+    }
+
+    // Check that digging removes the block in front and picks it up.
+    #[test]
+    fn check_dig() {
+        let (mut state, world) = setup();
+        let below = state.position.down();
+        world.lock().unwrap().set_block(below, "minecraft:dirt");
+
+        state.handle_command(json!({ "type": "dig_down" }), &world);
+        assert!(world.lock().unwrap().is_free(below));
+        assert_eq!(
+            state.inventory[0],
+            Some(ItemStack {
+                name: "minecraft:dirt".to_string(),
+                count: 1,
+            })
+        );
+        assert!(!state.dig(below, &world));
+    }
+
+    // Check that a deploy keeps the old version and a rollback swaps back to it.
+    #[test]
+    fn check_deploy_rollback() {
+        let (mut state, world) = setup();
+        assert_eq!(state.hello(3), "3");
+
+        state.handle_command(json!({ "type": "rollback" }), &world);
+        assert_eq!(state.script_version, None);
+
+        state.handle_command(
+            json!({ "type": "deploy", "script": "print('hi')", "version": "abc" }),
+            &world,
+        );
+        assert_eq!(state.hello(3), "3 abc");
+
+        state.handle_command(json!({ "type": "rollback" }), &world);
+        assert_eq!(state.script_version, None);
+        assert_eq!(state.old_script_version.as_deref(), Some("abc"));
+    }
+
+    // Check that crafting needs everything outside the grid to be empty and a drop puts the items
+    // in the chest in front.
+    #[test]
+    fn check_craft_and_drop() {
+        let (mut state, world) = setup();
+        let stack = |name: &str, count| {
+            Some(ItemStack {
+                name: name.to_string(),
+                count,
+            })
+        };
+        state.upgrades.right = Some("minecraft:crafting_table".to_string());
+        state.recipes = vec![CraftingRecipe {
+            pattern: vec![vec![Some("minecraft:oak_log".to_string())]],
+            output: ItemStack {
+                name: "minecraft:oak_planks".to_string(),
+                count: 4,
+            },
+        }];
+        state.inventory[0] = stack("minecraft:oak_log", 2);
+        state.inventory[3] = stack("minecraft:dirt", 5);
+        let chest = state.position.step(state.heading);
+        world
+            .lock()
+            .unwrap()
+            .set_inventory(chest, "minecraft:chest", vec![None; 27]);
+
+        assert!(state.craft(64).is_err());
+        assert!(state.drop_items(4, chest, &world).is_ok());
+        assert_eq!(state.inventory[3], None);
+        assert_eq!(
+            world.lock().unwrap().get_inventory(chest).unwrap()[0],
+            stack("minecraft:dirt", 5)
+        );
+
+        assert!(state.craft(64).is_ok());
+        assert_eq!(state.inventory[0], stack("minecraft:oak_planks", 8));
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::error;
+
+use crate::config::TurtleConfig;
+use crate::sim_turtle::SimState;
+use crate::sim_turtle_inner::SimTurtleInner;
+use crate::sim_turtle_message::SimTurtleMessage;
+use crate::world::SharedWorld;
+
+/// Handle for communicating with a SimTurtleInner.
+#[derive(Debug, Clone)]
+pub struct SimTurtleHandle {
+    tx: mpsc::Sender<SimTurtleMessage>,
+    state: watch::Receiver<SimState>,
+}
+
+impl SimTurtleHandle {
+    /// Places a simulated turtle in the world and connects it to a wrangler.
+    ///
+    /// # Arguments
+    /// * `url` - Websocket url of the wrangler. I.E. "ws://127.0.0.1:8080".
+    /// * `config` - Starting state of the turtle and the faults to inject.
+    /// * `world` - World shared with the other simulated turtles.
+    pub fn new(url: impl Into<String>, config: TurtleConfig, world: SharedWorld) -> Self {
+        let (tx, rx) = mpsc::channel(1);
+        let (state_tx, state) = watch::channel(SimState::new(&config));
+
+        let inner = SimTurtleInner::new(rx, state_tx, url.into(), config, world);
+        tokio::spawn(inner.run());
+
+        SimTurtleHandle { tx, state }
+    }
+
+    /// Gets a snapshot of the turtle's current state.
+    pub fn get_state(&self) -> SimState {
+        self.state.borrow().clone()
+    }
+
+    /// Waits until the turtle's state matches `predicate`.
+    /// Returns the matching state or None if it did not match before `timeout`.
+    pub async fn wait_for(
+        &self,
+        timeout: Duration,
+        predicate: impl FnMut(&SimState) -> bool,
+    ) -> Option<SimState> {
+        let mut state = self.state.clone();
+        let result = match tokio::time::timeout(timeout, state.wait_for(predicate)).await {
+            Ok(Ok(s)) => Some(s.clone()),
+            _ => None,
+        };
+
+        result
+    }
+
+    /// Closes the turtle's connection and waits for it to stop.
+    pub async fn close(&self) {
+        let (tx, rx) = oneshot::channel();
+
+        if self.tx.send(SimTurtleMessage::Close(tx)).await.is_err() {
+            error!("Problem closing simulated turtle");
+            return;
+        }
+
+        let _ = rx.await;
+    }
+}
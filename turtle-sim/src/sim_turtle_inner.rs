@@ -0,0 +1,251 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+use crate::config::{Fault, TurtleConfig};
+use crate::protocol::{Event, SentCommand};
+use crate::sim_turtle::SimState;
+use crate::sim_turtle_message::SimTurtleMessage;
+use crate::world::SharedWorld;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Why a connection to the wrangler ended.
+enum SessionEnd {
+    /// A handle asked the turtle to close.
+    Closed,
+
+    /// The connection dropped or could not be made.
+    Disconnected,
+
+    /// The wrangler told the turtle to reboot.
+    Reboot,
+}
+
+/// Runs a simulated turtle. Connects to the wrangler and answers commands the same way
+/// startup.lua does.
+pub struct SimTurtleInner {
+    rx: mpsc::Receiver<SimTurtleMessage>,
+
+    /// Publishes the turtle's state to its handles after every change.
+    state_tx: watch::Sender<SimState>,
+    state: SimState,
+
+    url: String,
+    config: TurtleConfig,
+    world: SharedWorld,
+
+    /// Set when a handle asks us to close so we can report when we have.
+    close_tx: Option<oneshot::Sender<()>>,
+}
+
+impl SimTurtleInner {
+    pub fn new(
+        rx: mpsc::Receiver<SimTurtleMessage>,
+        state_tx: watch::Sender<SimState>,
+        url: String,
+        config: TurtleConfig,
+        world: SharedWorld,
+    ) -> Self {
+        let state = SimState::new(&config);
+
+        SimTurtleInner {
+            rx,
+            state_tx,
+            state,
+            url,
+            config,
+            world,
+            close_tx: None,
+        }
+    }
+
+    pub async fn run(mut self) {
+        if !self
+            .world
+            .lock()
+            .unwrap()
+            .place_turtle(self.state.position, self.state.turtle_block())
+        {
+            warn!("Simulated turtle {} spawned inside a block", self.config.id);
+        }
+
+        loop {
+            let end = self.session().await;
+            self.state.connected = false;
+            self.publish();
+
+            match end {
+                SessionEnd::Closed => break,
+                SessionEnd::Reboot => info!("Simulated turtle {} rebooting", self.config.id),
+                SessionEnd::Disconnected => match self.config.reconnect {
+                    Some(delay) => {
+                        debug!("Reconnecting in {:?}", delay);
+                        if self.wait_for_close(delay).await {
+                            break;
+                        }
+                    }
+                    None => {
+                        self.wait_for_close(std::time::Duration::MAX).await;
+                        break;
+                    }
+                },
+            }
+        }
+
+        self.world
+            .lock()
+            .unwrap()
+            .remove_turtle(self.state.position);
+
+        debug!("Simulated turtle {} closing", self.config.id);
+        if let Some(tx) = self.close_tx.take() {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Waits for a close message from a handle for up to `duration`.
+    /// Returns true if we should close.
+    async fn wait_for_close(&mut self, duration: std::time::Duration) -> bool {
+        let sleep = tokio::time::sleep(duration);
+        tokio::select! {
+            _ = sleep => false,
+            message = self.rx.recv() => {
+                if let Some(SimTurtleMessage::Close(tx)) = message {
+                    self.close_tx = Some(tx);
+                }
+                true
+            }
+        }
+    }
+
+    /// Connects to the wrangler and handles commands until the connection ends.
+    async fn session(&mut self) -> SessionEnd {
+        let mut ws = match tokio_tungstenite::connect_async(self.url.as_str()).await {
+            Ok((ws, _)) => ws,
+            Err(e) => {
+                warn!("Simulated turtle {} failed to connect {e}", self.config.id);
+                return SessionEnd::Disconnected;
+            }
+        };
+
+        if ws
+            .send(Message::Text(self.state.hello(self.config.id)))
+            .await
+            .is_err()
+        {
+            return SessionEnd::Disconnected;
+        }
+
+        match ws.next().await {
+            Some(Ok(Message::Text(name))) => {
+                info!("Simulated turtle {} is named {name}", self.config.id);
+                self.state.name = Some(name);
+            }
+            _ => return SessionEnd::Disconnected,
+        }
+
+        if let Some(commands) = &self.config.commands {
+            let hello = Event::Hello {
+                protocol: self.config.protocol,
+                commands: commands.clone(),
+                requests: self.config.requests.clone(),
+            };
+            if !send_event(&mut ws, &hello).await {
+                return SessionEnd::Disconnected;
+            }
+        }
+
+        self.state.connected = true;
+        self.publish();
+
+        if self.state.position_file.is_none() && !send_event(&mut ws, &Event::GetPosition).await {
+            return SessionEnd::Disconnected;
+        }
+
+        let mut handled = 0;
+        loop {
+            if !send_event(&mut ws, &self.state.report()).await
+                || !send_event(&mut ws, &Event::Ready).await
+            {
+                return SessionEnd::Disconnected;
+            }
+
+            let message = loop {
+                tokio::select! {
+                    message = self.rx.recv() => {
+                        if let Some(SimTurtleMessage::Close(tx)) = message {
+                            self.close_tx = Some(tx);
+                        }
+                        let _ = ws.close(None).await;
+                        return SessionEnd::Closed;
+                    }
+                    message = ws.next() => match message {
+                        Some(Ok(Message::Text(text))) => break text,
+                        Some(Ok(_)) => continue,
+                        _ => return SessionEnd::Disconnected,
+                    }
+                }
+            };
+
+            tokio::time::sleep(self.config.latency).await;
+
+            let command: SentCommand = match serde_json::from_str(message.as_str()) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Got invalid command {message} {e}");
+                    continue;
+                }
+            };
+
+            if !send_event(&mut ws, &Event::Ok { id: command.id }).await {
+                return SessionEnd::Disconnected;
+            }
+
+            let outcome = self.state.handle_command(command.command, &self.world);
+            self.publish();
+
+            for event in outcome.events.iter() {
+                if !send_event(&mut ws, event).await {
+                    return SessionEnd::Disconnected;
+                }
+            }
+
+            if outcome.reboot {
+                let _ = ws.close(None).await;
+                return SessionEnd::Reboot;
+            }
+
+            handled += 1;
+            match self.config.fault {
+                Some(Fault::DisconnectAfter(n)) if handled >= n => {
+                    info!("Simulated turtle {} dropping connection", self.config.id);
+                    self.config.fault = None;
+                    drop(ws);
+                    return SessionEnd::Disconnected;
+                }
+                Some(Fault::HangAfter(n)) if handled >= n => {
+                    info!("Simulated turtle {} hanging", self.config.id);
+                    self.config.fault = None;
+                    // Keep the socket open without reading from it until we are told to close.
+                    self.wait_for_close(std::time::Duration::MAX).await;
+                    drop(ws);
+                    return SessionEnd::Closed;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn publish(&self) {
+        self.state_tx.send_replace(self.state.clone());
+    }
+}
+
+/// Sends an event to the wrangler. Returns false if the connection is closed.
+async fn send_event(ws: &mut WsStream, event: &Event) -> bool {
+    let message = serde_json::to_string(event).expect("Problem serializing event");
+    ws.send(Message::Text(message)).await.is_ok()
+}
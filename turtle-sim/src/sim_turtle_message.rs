@@ -0,0 +1,8 @@
+use tokio::sync::oneshot;
+
+/// Messages that can be sent from a SimTurtleHandle to a SimTurtleInner.
+#[derive(Debug)]
+pub enum SimTurtleMessage {
+    /// Closes the connection and removes the turtle from the world.
+    Close(oneshot::Sender<()>),
+}
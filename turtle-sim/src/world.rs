@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+
+use crate::config::ItemStack;
+use crate::protocol::{Coordinates, Heading};
+
+/// World shared between every simulated turtle so they can see and block each other.
+pub type SharedWorld = Arc<Mutex<World>>;
+
+/// A turtle standing in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurtleBlock {
+    pub heading: Heading,
+    pub advanced: bool,
+}
+
+/// A mob in the world that a turtle can attack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entity {
+    pub name: String,
+
+    /// Items the entity drops when it is killed.
+    pub drops: Vec<ItemStack>,
+}
+
+/// In memory voxel world.
+/// Any position without a block or a turtle is air.
+#[derive(Debug, Default)]
+pub struct World {
+    /// Minecraft identifiers of the solid blocks in the world.
+    blocks: HashMap<Coordinates, String>,
+
+    /// Turtles in the world keyed by their position.
+    turtles: HashMap<Coordinates, TurtleBlock>,
+
+    /// Slots of the blocks that are inventories such as chests. Each has a block in `blocks`.
+    inventories: HashMap<Coordinates, Vec<Option<ItemStack>>>,
+
+    /// Mobs keyed by the position they stand in. They do not block turtles.
+    entities: HashMap<Coordinates, Entity>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    /// Creates a new empty world that can be shared between turtles.
+    pub fn shared() -> SharedWorld {
+        Arc::new(Mutex::new(World::new()))
+    }
+
+    /// Places a block. Used to build obstacles.
+    pub fn set_block(&mut self, position: Coordinates, name: impl Into<String>) {
+        self.blocks.insert(position, name.into());
+    }
+
+    pub fn remove_block(&mut self, position: Coordinates) -> Option<String> {
+        self.inventories.remove(&position);
+        self.blocks.remove(&position)
+    }
+
+    /// Places a block that is an inventory such as a chest. The inventory has as many slots as
+    /// `slots` is long.
+    pub fn set_inventory(
+        &mut self,
+        position: Coordinates,
+        name: impl Into<String>,
+        slots: Vec<Option<ItemStack>>,
+    ) {
+        self.blocks.insert(position, name.into());
+        self.inventories.insert(position, slots);
+    }
+
+    pub fn get_inventory(&self, position: Coordinates) -> Option<&[Option<ItemStack>]> {
+        self.inventories.get(&position).map(Vec::as_slice)
+    }
+
+    /// Puts items into the inventory at `position`. Returns None if there is no inventory there,
+    /// otherwise how many items did not fit.
+    pub fn insert_items(&mut self, position: Coordinates, items: ItemStack) -> Option<u32> {
+        let slots = self.inventories.get_mut(&position)?;
+        Some(items.merge_into(slots))
+    }
+
+    /// Gets the peripheral types of the block at `position` like `peripheral.getType`.
+    /// Returns None if the block is not a peripheral.
+    pub fn peripheral_types(&self, position: Coordinates) -> Option<Vec<String>> {
+        if !self.inventories.contains_key(&position) {
+            return None;
+        }
+
+        let name = self.blocks.get(&position)?;
+        Some(vec![name.clone(), "inventory".to_string()])
+    }
+
+    pub fn spawn_entity(&mut self, position: Coordinates, entity: Entity) {
+        self.entities.insert(position, entity);
+    }
+
+    /// Removes the entity at `position` as if it was killed in one hit.
+    pub fn kill_entity(&mut self, position: Coordinates) -> Option<Entity> {
+        self.entities.remove(&position)
+    }
+
+    pub fn get_block(&self, position: Coordinates) -> Option<&str> {
+        self.blocks.get(&position).map(String::as_str)
+    }
+
+    pub fn get_turtle(&self, position: Coordinates) -> Option<&TurtleBlock> {
+        self.turtles.get(&position)
+    }
+
+    /// Whether a turtle could move into `position`.
+    pub fn is_free(&self, position: Coordinates) -> bool {
+        !self.blocks.contains_key(&position) && !self.turtles.contains_key(&position)
+    }
+
+    /// Places a turtle. Returns false if the position is already taken.
+    pub fn place_turtle(&mut self, position: Coordinates, turtle: TurtleBlock) -> bool {
+        if !self.is_free(position) {
+            return false;
+        }
+
+        self.turtles.insert(position, turtle);
+        true
+    }
+
+    pub fn remove_turtle(&mut self, position: Coordinates) -> Option<TurtleBlock> {
+        self.turtles.remove(&position)
+    }
+
+    /// Moves a turtle from `from` to `to`. Returns false if `to` is blocked.
+    pub fn move_turtle(&mut self, from: Coordinates, to: Coordinates) -> bool {
+        if !self.is_free(to) {
+            return false;
+        }
+
+        match self.turtles.remove(&from) {
+            Some(turtle) => {
+                self.turtles.insert(to, turtle);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_turtle_heading(&mut self, position: Coordinates, heading: Heading) {
+        if let Some(turtle) = self.turtles.get_mut(&position) {
+            turtle.heading = heading;
+        }
+    }
+
+    /// Describes the block at `position` in the same shape startup.lua sends from
+    /// `turtle.inspect()`.
+    pub fn inspect(&self, position: Coordinates) -> Value {
+        if let Some(turtle) = self.turtles.get(&position) {
+            let name = if turtle.advanced {
+                "computercraft:turtle_advanced"
+            } else {
+                "computercraft:turtle_normal"
+            };
+
+            return json!({
+                "type": name,
+                "name": name,
+                "state": { "facing": turtle.heading.facing(), "waterlogged": false },
+                "tags": {},
+            });
+        }
+
+        match self.blocks.get(&position) {
+            Some(name) => json!({
+                "type": "other",
+                "name": name,
+                "state": {},
+                "tags": {},
+            }),
+            None => json!({ "type": "air" }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Coordinates = Coordinates { x: 0, y: 0, z: 0 };
+
+    fn turtle() -> TurtleBlock {
+        TurtleBlock {
+            heading: Heading::North,
+            advanced: false,
+        }
+    }
+
+    // Check that turtles cannot be moved into blocks or other turtles.
+    #[test]
+    fn check_move_blocked() {
+        let mut world = World::new();
+        let north = ORIGIN.step(Heading::North);
+        world.set_block(north, "minecraft:stone");
+        assert!(world.place_turtle(ORIGIN, turtle()));
+
+        assert!(!world.move_turtle(ORIGIN, north));
+        assert!(world.get_turtle(ORIGIN).is_some());
+
+        world.remove_block(north);
+        assert!(world.place_turtle(north, turtle()));
+        assert!(!world.move_turtle(ORIGIN, north));
+    }
+
+    // Check that a moved turtle leaves air behind.
+    #[test]
+    fn check_move_free() {
+        let mut world = World::new();
+        assert!(world.place_turtle(ORIGIN, turtle()));
+
+        assert!(world.move_turtle(ORIGIN, ORIGIN.up()));
+        assert!(world.is_free(ORIGIN));
+        assert_eq!(world.inspect(ORIGIN), json!({ "type": "air" }));
+    }
+
+    // Check that removing an inventory's block removes the inventory too.
+    #[test]
+    fn check_remove_inventory() {
+        let mut world = World::new();
+        world.set_inventory(ORIGIN, "minecraft:chest", vec![None; 27]);
+        assert_eq!(
+            world.peripheral_types(ORIGIN),
+            Some(vec!["minecraft:chest".to_string(), "inventory".to_string()])
+        );
+
+        world.remove_block(ORIGIN);
+        assert!(world.get_inventory(ORIGIN).is_none());
+        assert!(world.peripheral_types(ORIGIN).is_none());
+    }
+
+    // Check that inspecting a turtle matches the shape of a real inspection.
+    #[test]
+    fn check_inspect_turtle() {
+        let mut world = World::new();
+        world.place_turtle(ORIGIN, turtle());
+
+        let block = world.inspect(ORIGIN);
+        assert_eq!(block["type"], "computercraft:turtle_normal");
+        assert_eq!(block["state"]["facing"], "north");
+    }
+}